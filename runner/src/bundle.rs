@@ -0,0 +1,211 @@
+//! Package the results of one or more experiments into a single tarball suitable for artifact
+//! evaluation submissions.
+//!
+//! The bundle contains the results directory, a `manifest.json` describing its contents (the
+//! settings of each run, including git hashes and runner invocations, and the git hash of the
+//! runner doing the bundling), and optionally a fingerprint of the host the experiments ran on.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::clap_app;
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+/// Commands run on the remote to fingerprint it, along with the name of the section in the
+/// fingerprint file.
+const HOST_FINGERPRINT_CMDS: &[(&str, &str)] = &[
+    ("Kernel", "uname -a"),
+    ("Kernel Command Line", "cat /proc/cmdline"),
+    ("CPU", "lscpu"),
+    ("Memory", "cat /proc/meminfo"),
+    ("Block Devices", "lsblk"),
+    ("Swap", "cat /proc/swaps"),
+    ("Research Settings", "cat research-settings.json"),
+];
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    clap_app! { bundle =>
+        (about: "Package a results directory into a tarball with a manifest for artifact \
+                 evaluation.")
+        (@arg RESULTS_DIR: +required +takes_value
+         "The local directory containing the results (and .params files) to bundle.")
+        (@arg OUTPUT: -o --output +takes_value
+         "(Optional) The name of the tarball to produce (defaults to <RESULTS_DIR>.tar.gz).")
+        (@arg HOSTNAME: --host +takes_value requires[USERNAME]
+         "(Optional) The domain name of the remote the experiments ran on, to be fingerprinted \
+          (e.g. c240g2-031321.wisc.cloudlab.us:22)")
+        (@arg USERNAME: --user +takes_value requires[HOSTNAME]
+         "(Optional) The username on the remote (e.g. markm)")
+    }
+}
+
+/// A single run found in the results directory, identified by its `.params` file.
+#[derive(Debug, Serialize)]
+struct BundledRun {
+    /// The name of the params file.
+    params_file: String,
+
+    /// All of the settings of the run, as recorded in the params file.
+    settings: BTreeMap<String, serde_json::Value>,
+}
+
+/// The temporary directory in which the bundle is staged. It is removed when dropped, so that it
+/// does not leak if bundling fails partway through.
+struct StagingDir(PathBuf);
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The `manifest.json` at the root of the bundle.
+#[derive(Debug, Serialize)]
+struct Manifest {
+    /// When the bundle was created.
+    created: String,
+
+    /// The git hash of the runner that created the bundle.
+    runner_git_hash: String,
+
+    /// The runs found in the results directory.
+    runs: Vec<BundledRun>,
+
+    /// All files in the bundle along with their sizes in bytes.
+    files: BTreeMap<String, u64>,
+
+    /// The name of the host fingerprint file, if the host was fingerprinted.
+    host_fingerprint: Option<String>,
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let results_dir = Path::new(sub_m.value_of("RESULTS_DIR").unwrap());
+    let results_name = results_dir
+        .file_name()
+        .ok_or_else(|| failure::format_err!("Invalid results directory: {:?}", results_dir))?
+        .to_string_lossy()
+        .into_owned();
+    let output = sub_m
+        .value_of("OUTPUT")
+        .map(String::from)
+        .unwrap_or_else(|| format!("{}.tar.gz", results_name));
+
+    // Stage everything in a temporary directory so that the bundle has a clean layout.
+    let staging =
+        StagingDir(std::env::temp_dir().join(format!("runner-bundle-{}", std::process::id())));
+    let staging_results = staging.0.join(&results_name);
+    std::fs::create_dir_all(&staging.0)?;
+
+    let status = Command::new("cp")
+        .arg("-r")
+        .arg(results_dir)
+        .arg(&staging_results)
+        .status()?;
+    if !status.success() {
+        return Err(failure::format_err!("Unable to copy {:?}", results_dir));
+    }
+
+    // Fingerprint the host if requested.
+    let host_fingerprint = if let (Some(host), Some(username)) =
+        (sub_m.value_of("HOSTNAME"), sub_m.value_of("USERNAME"))
+    {
+        const FINGERPRINT_FILE: &str = "host-fingerprint.txt";
+        let shell = SshShell::with_default_key(username, host)?;
+        std::fs::write(staging.0.join(FINGERPRINT_FILE), fingerprint_host(&shell)?)?;
+        Some(FINGERPRINT_FILE.to_owned())
+    } else {
+        None
+    };
+
+    // Find all runs and files.
+    let mut files = BTreeMap::new();
+    let mut runs = Vec::new();
+    collect_files(&staging.0, &staging.0, &mut files, &mut runs)?;
+
+    let manifest = Manifest {
+        created: chrono::offset::Local::now().to_rfc3339(),
+        runner_git_hash: crate::common::local_research_workspace_git_hash()?,
+        runs,
+        files,
+        host_fingerprint,
+    };
+
+    std::fs::write(
+        staging.0.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    // Create the tarball.
+    let status = Command::new("tar")
+        .arg("czf")
+        .arg(std::env::current_dir()?.join(&output))
+        .arg("-C")
+        .arg(&staging.0)
+        .arg(".")
+        .status()?;
+    if !status.success() {
+        return Err(failure::format_err!("Unable to create tarball {}", output));
+    }
+
+    println!("BUNDLE: {}", output);
+
+    Ok(())
+}
+
+/// Recursively walk `dir`, recording the size of every file (relative to `root`) and parsing any
+/// `.params` files. A `.params` file that cannot be parsed (e.g. from an interrupted run) is still
+/// bundled, but is not listed as a run.
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<String, u64>,
+    runs: &mut Vec<BundledRun>,
+) -> Result<(), failure::Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let path: PathBuf = entry?.path();
+
+        if path.is_dir() {
+            collect_files(root, &path, files, runs)?;
+            continue;
+        }
+
+        let relative = path
+            .strip_prefix(root)
+            .expect("file not under staging dir")
+            .to_string_lossy()
+            .into_owned();
+        files.insert(relative.clone(), std::fs::metadata(&path)?.len());
+
+        if path.extension().map(|ext| ext == "params").unwrap_or(false) {
+            match crate::common::output::read_params_file(&path) {
+                Ok(settings) => runs.push(BundledRun {
+                    params_file: relative,
+                    settings,
+                }),
+                Err(err) => println!(
+                    "WARNING: skipping unreadable params file {}: {}",
+                    relative, err
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect a human-readable fingerprint of the remote host.
+fn fingerprint_host(shell: &SshShell) -> Result<String, failure::Error> {
+    let mut fingerprint = String::new();
+
+    for (section, command) in HOST_FINGERPRINT_CMDS.iter() {
+        let out = shell.run(cmd!("{}", command).allow_error())?;
+
+        fingerprint.push_str(&format!("{}\n=====\n{}\n\n", section, out.stdout.trim()));
+    }
+
+    Ok(fingerprint)
+}
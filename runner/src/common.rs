@@ -14,8 +14,46 @@ mod macros;
 #[macro_use]
 pub mod output;
 
+pub mod config;
+
 pub mod exp_0sim;
 
+pub mod background;
+
+pub mod deadline;
+
+pub mod drift;
+
+pub mod distro;
+
+pub mod guest_kernel;
+
+pub mod boot;
+
+pub mod boot_params;
+
+pub mod cgroups;
+
+pub mod network;
+
+pub mod perf;
+
+pub mod reconnect;
+
+pub mod snapshot;
+
+pub mod sim_stats;
+
+pub mod index;
+
+pub mod size;
+
+pub mod swap_profile;
+
+pub mod telemetry;
+
+pub mod tracing;
+
 pub mod hadoop;
 
 use failure::ResultExt;
@@ -105,6 +143,26 @@ pub struct Login<'u, 'h, A: std::net::ToSocketAddrs + std::fmt::Display + Clone>
     pub username: &'u str,
 }
 
+/// Parse a hosts file: one `HOSTNAME USERNAME` pair per line. Blank lines and lines starting with
+/// `#` are ignored.
+pub fn parse_hosts_file(contents: &str) -> Result<Vec<(String, String)>, failure::Error> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let parts: Vec<_> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [hostname, username] => Ok(((*hostname).to_owned(), (*username).to_owned())),
+                _ => Err(failure::format_err!(
+                    "Expected `HOSTNAME USERNAME` in hosts file, found {:?}",
+                    line
+                )),
+            }
+        })
+        .collect()
+}
+
 /// Common paths.
 pub mod paths {
     /// The path at which `clone_research_workspace` clones the workspace.
@@ -134,6 +192,12 @@ pub mod paths {
     /// Path the to nullfs submodule
     pub const ZEROSIM_NULLFS_SUBMODULE: &str = "bmks/nullfs";
 
+    /// Path to the YCSB installation, which is downloaded by `setup00000 --guest_bmks`.
+    pub const ZEROSIM_YCSB_PATH: &str = "bmks/ycsb";
+
+    /// Path to the GAP benchmark suite, which is cloned by `setup00000 --host_bmks`.
+    pub const ZEROSIM_GAPBS_PATH: &str = "bmks/gapbs";
+
     /// Path to redis.conf.
     pub const REDIS_CONF: &str = "bmks/redis.conf";
 
@@ -162,6 +226,12 @@ pub mod paths {
         /// The shared directory for results on the guest.
         pub const VAGRANT_RESULTS_DIR: &str = "/vagrant/vm_shared/results/";
 
+        /// Where SPEC CPU 2017 is installed in the guest, if it is.
+        pub const SPEC_2017_GUEST_PATH: &str = "/home/vagrant/spec2017";
+
+        /// The name of the SPEC CPU 2017 config with which the benchmarks are built and run.
+        pub const SPEC_2017_CONFIG: &str = "zerosim";
+
         /// The URL of the tarball used to build the guest kernel.
         /// **NOTE** Also update the next constant.
         pub const KERNEL_RECENT_TARBALL: &str =
@@ -177,15 +247,6 @@ pub mod paths {
     }
 }
 
-/// Given an array of timings, generate a human-readable string.
-pub fn timings_str(timings: &[(&str, std::time::Duration)]) -> String {
-    let mut s = String::new();
-    for (label, d) in timings.iter() {
-        s.push_str(&format!("{}: {:?}\n", label, d));
-    }
-    s
-}
-
 /// Clone the 0sim-workspace and checkout the given submodules.
 ///
 /// `secret` is a GitHub personal access token or password that is needed if a private repo is
@@ -259,6 +320,12 @@ pub fn local_research_workspace_git_hash() -> Result<String, failure::Error> {
     Ok(output.into())
 }
 
+/// Returns the command line with which the runner was invoked, so that it can be recorded with
+/// the results of an experiment.
+pub fn runner_invocation() -> Vec<String> {
+    config::expanded_args().unwrap_or_else(|| std::env::args().collect())
+}
+
 /// Get the path of the user's home directory.
 pub fn get_user_home_dir(ushell: &SshShell) -> Result<String, failure::Error> {
     let user_home = ushell
@@ -349,13 +416,11 @@ pub fn gen_local_version(branch: &str, hash: &str) -> String {
     )
 }
 
-/// Generate a new vagrant domain name and update the Vagrantfile.
-pub fn gen_new_vagrantdomain(shell: &SshShell) -> Result<(), failure::Error> {
-    let vagrant_path = &format!("{}/{}", RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
+/// Generate a new, unique vagrant domain name.
+pub fn gen_new_vagrantdomain(shell: &SshShell) -> Result<String, failure::Error> {
     let uniq = shell.run(cmd!("date | sha256sum | head -c 10"))?;
     let uniq = uniq.stdout.trim();
-    shell.run(cmd!("sed -i 's/:test_vm/:test_vm_{}/' Vagrantfile", uniq).cwd(vagrant_path))?;
-    Ok(())
+    Ok(format!("test_vm_{}", uniq))
 }
 
 /// Returns the number of processor cores on the machine.
@@ -430,7 +495,6 @@ pub fn turn_on_thp(
 /// What type of package to produce from the kernel build?
 pub enum KernelPkgType {
     /// `bindeb-pkg`
-    #[allow(dead_code)]
     Deb,
     /// `binrpm-pkg`
     Rpm,
@@ -453,6 +517,18 @@ pub enum KernelSrc {
     /// or `.tgz` extension.
     #[allow(dead_code)]
     Tar { tarball_path: String },
+
+    /// The given tarball, which will be untarred, after which the series of patches in
+    /// `patch_dir` is applied with `patch -p1`. If `patch_dir` contains a quilt-style `series`
+    /// file, the patches it lists are applied in that order. Otherwise, the `*.patch` and `*.diff`
+    /// files are applied in lexicographic order of file name (e.g. as produced by `git
+    /// format-patch`). Any previously unpacked source directory is deleted first so that the
+    /// patches apply to a pristine tree. As with `Tar`, we assume that the name of the unpacked
+    /// source directory is the tarball name without the extension.
+    Patched {
+        base_tarball: String,
+        patch_dir: String,
+    },
 }
 
 /// Where to get the base config (on top of which we will apply additional changes)?
@@ -481,6 +557,42 @@ pub struct KernelConfig<'a> {
     pub extra_options: &'a [(&'a str, bool)],
 }
 
+/// The name of the directory a kernel source tarball unpacks to: the tarball name without the
+/// `.tar.gz`, `.tar.xz`, or `.tgz` extension.
+fn tarball_source_dir(tarball_path: &str) -> &str {
+    tarball_path
+        .trim_end_matches(".tar.gz")
+        .trim_end_matches(".tar.xz")
+        .trim_end_matches(".tgz")
+}
+
+/// List the patches in `patch_dir` in the order they should be applied: the order of its `series`
+/// file if there is one, or else the `*.patch` and `*.diff` files sorted by name.
+fn list_patches(shell: &SshShell, patch_dir: &str) -> Result<Vec<String>, failure::Error> {
+    let has_series = shell.run(cmd!("test -f {}/series", patch_dir)).is_ok();
+
+    if has_series {
+        // As in quilt, each line names a patch (possibly followed by options, which we ignore),
+        // and `#` starts a comment.
+        let series = shell.run(cmd!("cat {}/series", patch_dir))?.stdout;
+        Ok(series
+            .lines()
+            .filter_map(|line| line.split('#').next()?.split_whitespace().next())
+            .map(String::from)
+            .collect())
+    } else {
+        let files = shell.run(cmd!("ls -1 {}", patch_dir))?.stdout;
+        let mut patches: Vec<String> = files
+            .lines()
+            .map(str::trim)
+            .filter(|file| file.ends_with(".patch") || file.ends_with(".diff"))
+            .map(String::from)
+            .collect();
+        patches.sort();
+        Ok(patches)
+    }
+}
+
 pub fn get_absolute_path(shell: &SshShell, path: &str) -> Result<String, failure::Error> {
     Ok(shell.run(cmd!("pwd").cwd(path))?.stdout.trim().into())
 }
@@ -515,13 +627,38 @@ pub fn build_kernel(
         KernelSrc::Tar { tarball_path } => {
             ushell.run(cmd!("tar xvf {}", tarball_path))?;
 
-            get_absolute_path(
-                ushell,
-                tarball_path
-                    .trim_end_matches(".tar.gz")
-                    .trim_end_matches(".tar.xz")
-                    .trim_end_matches(".tgz"),
-            )?
+            get_absolute_path(ushell, tarball_source_dir(&tarball_path))?
+        }
+
+        KernelSrc::Patched {
+            base_tarball,
+            patch_dir,
+        } => {
+            let source_dir = tarball_source_dir(&base_tarball);
+
+            ushell.run(cmd!("rm -rf {}", source_dir))?;
+            ushell.run(cmd!("tar xvf {}", base_tarball))?;
+
+            let source_path = get_absolute_path(ushell, source_dir)?;
+            let patch_dir = get_absolute_path(ushell, &patch_dir)?;
+
+            for patch in list_patches(ushell, &patch_dir)? {
+                ushell
+                    .run(
+                        cmd!("patch -p1 --forward --batch < {}/{}", patch_dir, patch)
+                            .cwd(&source_path),
+                    )
+                    .map_err(|err| {
+                        failure::format_err!(
+                            "Patch {} does not apply to {}: {}",
+                            patch,
+                            base_tarball,
+                            err
+                        )
+                    })?;
+            }
+
+            source_path
         }
     };
 
@@ -625,8 +762,6 @@ pub fn build_kernel(
 pub enum ServiceAction {
     /// Start the service if it is not active. Otherwise, do nothing.
     Start,
-    /// Stop the service if it is active. Otherwise, do nothing.
-    Stop,
     /// Restart the service, or start it if it is not active. Requires that the service exist.
     Restart,
     /// Disable and stop the service if it is active. Otherwise, do nothing.
@@ -657,11 +792,6 @@ pub fn service(
                 shell.run(cmd!("sudo systemctl start {}", service))?;
             }
         }
-        ServiceAction::Stop => {
-            if is_active {
-                shell.run(cmd!("sudo systemctl stop {}", service))?;
-            }
-        }
         ServiceAction::Enable => {
             shell.run(cmd!("sudo systemctl enable {}", service))?;
         }
@@ -727,3 +857,34 @@ pub fn get_device_id(shell: &SshShell, dev_name: &str) -> Result<String, failure
         Ok(name)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hosts_file() {
+        let hosts = parse_hosts_file(
+            "# cluster\n\
+             c240g5-110107.wisc.cloudlab.us:22 markm\n\
+             \n   \n\
+             \tc240g5-110108.wisc.cloudlab.us:22\t markm  \n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            hosts,
+            vec![
+                ("c240g5-110107.wisc.cloudlab.us:22".into(), "markm".into()),
+                ("c240g5-110108.wisc.cloudlab.us:22".into(), "markm".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn hosts_file_rejects_malformed_lines() {
+        assert!(parse_hosts_file("host:22\n").is_err());
+        assert!(parse_hosts_file("host:22 markm extra\n").is_err());
+        assert!(parse_hosts_file("").unwrap().is_empty());
+    }
+}
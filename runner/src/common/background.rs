@@ -0,0 +1,66 @@
+//! Stopping background loops on a remote.
+//!
+//! Samplers like `ConsoleScreenshots` run a loop on the remote for as long as a marker file exists.
+//! `LoopMarker` owns such a file: it has a unique name, so that loops left over from an earlier
+//! run can never be kept alive by a later one, and it is removed when the `LoopMarker` is dropped,
+//! so that the loops also stop when an experiment fails before it stops them explicitly.
+
+use spurs::{cmd, Execute, SshShell};
+
+/// A file on a remote whose existence keeps one or more background loops running.
+pub struct LoopMarker {
+    /// The path of the marker on the remote.
+    path: String,
+
+    /// A separate connection to the remote to remove the marker with, since the shells of the
+    /// caller may already be gone when the marker is dropped.
+    shell: SshShell,
+
+    /// True once the marker has been removed.
+    removed: bool,
+}
+
+impl LoopMarker {
+    /// Create a new marker on the remote of `shell`. `name` identifies what the marker is for
+    /// (e.g. `screenshots`); a unique suffix is added to it.
+    pub fn create(shell: &SshShell, name: &str) -> Result<Self, failure::Error> {
+        let unique = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos();
+        let path = format!("/tmp/runner-{}-{}-{}", name, std::process::id(), unique);
+
+        shell.run(cmd!("touch {}", path))?;
+
+        Ok(LoopMarker {
+            path,
+            shell: SshShell::from_existing(shell)?,
+            removed: false,
+        })
+    }
+
+    /// The path of the marker on the remote, for use in the loop condition.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Remove the marker, so that the loops exit after their current iteration.
+    pub fn remove(&mut self) -> Result<(), failure::Error> {
+        if !self.removed {
+            self.shell.run(cmd!("rm -f {}", self.path))?;
+            self.removed = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LoopMarker {
+    fn drop(&mut self) {
+        if let Err(err) = self.remove() {
+            println!(
+                "WARNING: unable to remove {}; background loops may still be running: {}",
+                self.path, err
+            );
+        }
+    }
+}
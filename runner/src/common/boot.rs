@@ -0,0 +1,100 @@
+//! Detection of unexpected host reboots during an experiment.
+//!
+//! If the host reboots in the middle of a run (e.g. a power event or a watchdog), the next SSH
+//! command just fails, which looks like any other error. Instead, we remember the boot id of the
+//! host after each intentional reboot. If the experiment fails and the host's boot id has changed,
+//! the run is marked invalid in its `results.json`, and the host is cleaned up so that it can be
+//! used for the next experiment.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::{exp_0sim::vagrant_halt, output::OutputSink, Login};
+
+/// How many times to try to reconnect to the host after a failure, and how long to wait between
+/// attempts. The host may still be rebooting.
+const RECONNECT_ATTEMPTS: usize = 60;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+
+thread_local! {
+    /// The boot id of the host as of the last intentional reboot.
+    static EXPECTED_BOOT_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Returns the boot id of the remote, which changes every time it boots.
+pub fn boot_id(shell: &SshShell) -> Result<String, failure::Error> {
+    Ok(shell
+        .run(cmd!("cat /proc/sys/kernel/random/boot_id"))?
+        .stdout
+        .trim()
+        .to_owned())
+}
+
+/// Record the current boot of the host as expected. Call this after any intentional reboot.
+pub fn expect_current_boot(shell: &SshShell) -> Result<(), failure::Error> {
+    let id = boot_id(shell)?;
+    EXPECTED_BOOT_ID.with(|expected| *expected.borrow_mut() = Some(id));
+    Ok(())
+}
+
+/// Check the result `res` of an experiment. If it failed and the host has rebooted since the last
+/// call to `expect_current_boot`, mark the run invalid by writing `results_file` to the results
+/// directory on the host, halt any VM left over, and return an error saying so. Otherwise, return
+/// `res` unchanged.
+pub fn invalidate_if_rebooted<A>(
+    login: &Login<A>,
+    results_file: &str,
+    res: Result<(), failure::Error>,
+) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let err = match res {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+
+    let expected = match EXPECTED_BOOT_ID.with(|expected| expected.borrow().clone()) {
+        Some(expected) => expected,
+        None => return Err(err),
+    };
+
+    // Wait for the host to come back up, in case it is still rebooting.
+    let mut ushell = None;
+    for _ in 0..RECONNECT_ATTEMPTS {
+        match SshShell::with_default_key(login.username, &login.host) {
+            Ok(shell) if shell.run(cmd!("whoami")).is_ok() => {
+                ushell = Some(shell);
+                break;
+            }
+            _ => std::thread::sleep(RECONNECT_INTERVAL),
+        }
+    }
+    let ushell = match ushell {
+        Some(ushell) => ushell,
+        None => return Err(err),
+    };
+
+    let current = boot_id(&ushell)?;
+    if current == expected {
+        return Err(err);
+    }
+
+    let reason = format!(
+        "Host rebooted unexpectedly during the experiment (boot id {} -> {}): {}",
+        expected, current, err
+    );
+
+    let results = serde_json::json!({
+        "invalid": true,
+        "reason": reason,
+    });
+    OutputSink::auto(&ushell, None)?.write(results_file, &serde_json::to_string(&results)?)?;
+
+    // Clean up so that the machine can be used again. If there is no VM, ignore errors.
+    let _ = vagrant_halt(&ushell);
+
+    Err(failure::format_err!("{}", reason))
+}
@@ -0,0 +1,191 @@
+//! Running the host and guest with a given set of kernel boot parameters.
+//!
+//! Experiments that sweep kernel configuration (e.g. THP, NUMA balancing, or CPU mitigations) take
+//! the boot parameters of the host and guest as settings. Any parameter that the running kernel
+//! wasn't booted with is added with `set_kernel_boot_param`, and the machine is rebooted: the host
+//! by the experiment's initial reboot (see `set_host`), and the guest by `start_vm` (see
+//! `set_guest`). `Cmdlines::collect` then checks that the parameters took effect and records the
+//! `/proc/cmdline` of both, so that the results say exactly which configuration was measured.
+
+use std::cell::{Cell, RefCell};
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::exp_0sim::set_kernel_boot_param;
+
+/// A kernel boot parameter, with an optional value (e.g. `mitigations=off` or `nokaslr`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootParam {
+    pub param: String,
+    pub value: Option<String>,
+}
+
+impl BootParam {
+    /// Returns true if `cmdline` (e.g. the contents of `/proc/cmdline`) contains this parameter
+    /// with the same value.
+    pub fn is_in(&self, cmdline: &str) -> bool {
+        let param = self.to_string();
+        cmdline.split_whitespace().any(|p| p == param)
+    }
+}
+
+impl std::fmt::Display for BootParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.param, value),
+            None => write!(f, "{}", self.param),
+        }
+    }
+}
+
+impl std::str::FromStr for BootParam {
+    type Err = failure::Error;
+
+    /// Parse `param` or `param=value`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let param = parts.next().unwrap_or("");
+        let value = parts.next();
+
+        // These would break the sed in `set_kernel_boot_param` or the grub config.
+        let is_bad = |s: &str| {
+            s.is_empty() || s.contains(|c: char| c.is_whitespace() || "\"'/\\".contains(c))
+        };
+
+        if is_bad(param) || value.map(is_bad).unwrap_or(false) {
+            Err(failure::format_err!(
+                "Expected a boot parameter like `nokaslr` or `mitigations=off`, not {:?}",
+                s
+            ))
+        } else {
+            Ok(BootParam {
+                param: param.into(),
+                value: value.map(Into::into),
+            })
+        }
+    }
+}
+
+thread_local! {
+    /// The boot parameters requested for the guest.
+    static GUEST_BOOT_PARAMS: RefCell<Vec<BootParam>> = const { RefCell::new(vec![]) };
+
+    /// True if the boot parameters were just set and the VM is being restarted to apply them.
+    static REBOOTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Boot the guest with the given parameters for the rest of the run.
+pub fn set_guest(params: Vec<BootParam>) {
+    GUEST_BOOT_PARAMS.with(|p| *p.borrow_mut() = params);
+}
+
+/// Returns true if the VM is being restarted to apply boot parameters that were just set. The
+/// guest disk must not be restored from its snapshot in that case, since that would discard them.
+pub fn rebooting() -> bool {
+    REBOOTING.with(|r| r.get())
+}
+
+/// Returns the command line the remote's running kernel was booted with.
+pub fn cmdline(shell: &SshShell) -> Result<String, failure::Error> {
+    Ok(shell
+        .run(cmd!("cat /proc/cmdline"))?
+        .stdout
+        .trim()
+        .to_owned())
+}
+
+/// Add any of `params` that the running kernel wasn't booted with to the grub config of the
+/// remote. Returns true if anything was added, in which case the remote must be rebooted for the
+/// parameters to take effect. Requires `sudo`.
+fn set_missing(shell: &SshShell, params: &[BootParam]) -> Result<bool, failure::Error> {
+    let current = cmdline(shell)?;
+    let mut changed = false;
+
+    for param in params.iter().filter(|param| !param.is_in(&current)) {
+        println!("Setting kernel boot parameter {}", param);
+        set_kernel_boot_param(shell, &param.param, param.value.as_deref())?;
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
+/// Make sure that the host boots with `params`. Call this before the initial reboot of the
+/// experiment, which then applies them. Returns true if the host needs that reboot, i.e. if it is
+/// not already running with all of `params`.
+pub fn set_host(ushell: &SshShell, params: &[BootParam]) -> Result<bool, failure::Error> {
+    set_missing(ushell, params)
+}
+
+/// Make sure that the guest runs with the parameters passed to `set_guest`, if any. `vrshell` must
+/// be a root shell in the guest. If any are missing, they are added to its grub config, and `true`
+/// is returned: the caller must then restart the VM to apply them. Returns an error if any are
+/// still missing after such a restart.
+pub fn set_guest_if_needed(vrshell: &SshShell) -> Result<bool, failure::Error> {
+    let params = GUEST_BOOT_PARAMS.with(|p| p.borrow().clone());
+    let rebooting = REBOOTING.with(|r| r.replace(false));
+
+    if rebooting {
+        check(vrshell, &params, "guest")?;
+        return Ok(false);
+    }
+
+    let changed = set_missing(vrshell, &params)?;
+    REBOOTING.with(|r| r.set(changed));
+
+    Ok(changed)
+}
+
+/// Returns the command line of the remote's running kernel, or an error if it is missing any of
+/// `params`. `machine` is the name of the remote for the error message.
+fn check(shell: &SshShell, params: &[BootParam], machine: &str) -> Result<String, failure::Error> {
+    let current = cmdline(shell)?;
+
+    let missing: Vec<_> = params
+        .iter()
+        .filter(|param| !param.is_in(&current))
+        .map(ToString::to_string)
+        .collect();
+
+    if missing.is_empty() {
+        Ok(current)
+    } else {
+        Err(failure::format_err!(
+            "The {} did not boot with {} (cmdline: {})",
+            machine,
+            missing.join(" "),
+            current
+        ))
+    }
+}
+
+/// The command lines the host and guest kernels were booted with.
+#[derive(Debug, Serialize)]
+pub struct Cmdlines {
+    pub host: String,
+
+    /// `None` if there is no guest (e.g. a baseline run on the host).
+    pub guest: Option<String>,
+}
+
+impl Cmdlines {
+    /// Check that the host (`ushell`) runs with `host_params` and the guest (`vshell`, if any)
+    /// with the parameters passed to `set_guest`, and return their command lines.
+    pub fn collect(
+        ushell: &SshShell,
+        vshell: Option<&SshShell>,
+        host_params: &[BootParam],
+    ) -> Result<Self, failure::Error> {
+        let guest_params = GUEST_BOOT_PARAMS.with(|p| p.borrow().clone());
+
+        Ok(Cmdlines {
+            host: check(ushell, host_params, "host")?,
+            guest: match vshell {
+                Some(vshell) => Some(check(vshell, &guest_params, "guest")?),
+                None => None,
+            },
+        })
+    }
+}
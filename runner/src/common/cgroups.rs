@@ -0,0 +1,175 @@
+//! Memory cgroups for limiting the memory of workloads.
+//!
+//! Most experiments create memory pressure by sizing the VM, which also limits everything else
+//! running in the guest. A `MemoryCgroup` limits just the workload instead. It works on the host or
+//! the guest and with both cgroup v1 and v2, and records how close the workload came to the limit
+//! so that the results show whether the limit actually mattered.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::size::Size;
+
+/// The root of the cgroup filesystem.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The version of the cgroup interface on a machine.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum CgroupVersion {
+    V1,
+    V2,
+}
+
+impl CgroupVersion {
+    /// Detect which cgroup version the remote uses. Machines with the hybrid layout are treated as
+    /// v1, since the memory controller is only available there.
+    pub fn detect(shell: &SshShell) -> Result<Self, failure::Error> {
+        let fs = shell.run(cmd!("stat -fc %T {}", CGROUP_ROOT))?.stdout;
+
+        Ok(if fs.trim() == "cgroup2fs" {
+            CgroupVersion::V2
+        } else {
+            CgroupVersion::V1
+        })
+    }
+}
+
+/// A memory cgroup on a remote.
+#[derive(Clone, Debug)]
+pub struct MemoryCgroup {
+    name: String,
+    version: CgroupVersion,
+    limit: Size,
+}
+
+/// The memory usage of a cgroup, as recorded in the results of an experiment.
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryCgroupStats {
+    /// The name of the cgroup.
+    pub name: String,
+    /// The cgroup version of the remote.
+    pub version: CgroupVersion,
+    /// The memory limit in bytes.
+    pub limit_bytes: usize,
+    /// The most memory used by the cgroup in bytes, if the kernel reports it.
+    pub max_usage_bytes: Option<u64>,
+    /// Counts of memory events in the cgroup. For v2, these are the contents of `memory.events`
+    /// (e.g. `max` and `oom_kill`). For v1, these are `failcnt` and the counters from
+    /// `memory.oom_control`.
+    pub events: BTreeMap<String, u64>,
+}
+
+impl MemoryCgroup {
+    /// Create a memory cgroup called `name` on the remote, limited to `limit` of memory (including
+    /// the page cache). If the cgroup already exists, it is recreated so that its stats start
+    /// fresh; this fails if processes are still running in it. Requires `sudo`.
+    pub fn create(shell: &SshShell, name: &str, limit: Size) -> Result<Self, failure::Error> {
+        let version = CgroupVersion::detect(shell)?;
+        let cgroup = MemoryCgroup {
+            name: name.into(),
+            version,
+            limit,
+        };
+        let path = cgroup.path();
+
+        shell.run(cmd!("if [ -d {0} ] ; then sudo rmdir {0} ; fi", path))?;
+
+        match version {
+            CgroupVersion::V1 => {
+                with_shell! { shell =>
+                    cmd!("sudo mkdir -p {}", path),
+                    cmd!(
+                        "echo {} | sudo tee {}/memory.limit_in_bytes",
+                        limit.bytes(),
+                        path
+                    ),
+                }
+            }
+            CgroupVersion::V2 => {
+                with_shell! { shell =>
+                    cmd!("echo +memory | sudo tee {}/cgroup.subtree_control", CGROUP_ROOT),
+                    cmd!("sudo mkdir -p {}", path),
+                    cmd!("echo {} | sudo tee {}/memory.max", limit.bytes(), path),
+                }
+            }
+        }
+
+        Ok(cgroup)
+    }
+
+    /// The path of the cgroup on the remote.
+    pub fn path(&self) -> String {
+        match self.version {
+            CgroupVersion::V1 => dir!(CGROUP_ROOT, "memory", self.name.as_str()),
+            CgroupVersion::V2 => dir!(CGROUP_ROOT, self.name.as_str()),
+        }
+    }
+
+    /// Returns a prefix for a command line that runs the command in the cgroup. For example,
+    ///
+    /// ```rust,ignore
+    /// format!("{} taskset -c 0 ./workload > out", cgroup.exec_prefix())
+    /// ```
+    ///
+    /// runs `taskset -c 0 ./workload` (and its children) in the cgroup. Only the first command of
+    /// a pipeline is in the cgroup. The command runs as the same user as without the prefix.
+    pub fn exec_prefix(&self) -> String {
+        format!(
+            "sh -c 'echo $$ | sudo tee {}/cgroup.procs > /dev/null && exec \"$0\" \"$@\"'",
+            self.path()
+        )
+    }
+
+    /// Read the memory usage of the cgroup so far.
+    pub fn stats(&self, shell: &SshShell) -> Result<MemoryCgroupStats, failure::Error> {
+        let path = self.path();
+        // Returns the contents of the given file in the cgroup, or "" if it does not exist.
+        let read = |file: &str| -> Result<String, failure::Error> {
+            Ok(shell
+                .run(cmd!("cat {}/{}", path, file).allow_error())?
+                .stdout)
+        };
+        let number = |contents: String| contents.trim().parse::<u64>().ok();
+
+        let (max_usage_bytes, events) = match self.version {
+            CgroupVersion::V1 => {
+                let mut events = parse_flat_keyed(&read("memory.oom_control")?);
+                if let Some(failcnt) = number(read("memory.failcnt")?) {
+                    events.insert("failcnt".into(), failcnt);
+                }
+                (number(read("memory.max_usage_in_bytes")?), events)
+            }
+            CgroupVersion::V2 => {
+                // `memory.peak` is only available on newer kernels.
+                (
+                    number(read("memory.peak")?),
+                    parse_flat_keyed(&read("memory.events")?),
+                )
+            }
+        };
+
+        Ok(MemoryCgroupStats {
+            name: self.name.clone(),
+            version: self.version,
+            limit_bytes: self.limit.bytes(),
+            max_usage_bytes,
+            events,
+        })
+    }
+}
+
+/// Parse a cgroup file with lines of the form `key value`, skipping values that are not numbers.
+fn parse_flat_keyed(contents: &str) -> BTreeMap<String, u64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let key = parts.next()?;
+            let value = parts.next()?.parse().ok()?;
+            Some((key.to_owned(), value))
+        })
+        .collect()
+}
@@ -0,0 +1,316 @@
+//! Loading subcommand parameters from a file.
+//!
+//! Experiments take a long list of flags, which are tedious to type and easy to get wrong.
+//! `runner --config exp.toml <subcommand>` reads the subcommand's parameters from a TOML file
+//! instead. The file is expanded into ordinary command line arguments before `clap` sees them, so
+//! every subcommand supports it without any changes, and all of the usual validation applies.
+//!
+//! Each key in the file is the long name of a flag of the subcommand:
+//!
+//! ```toml
+//! # Positional arguments, in order. These can be given either here or on the command line, but
+//! # not both.
+//! args = ["c240g5-110107.wisc.cloudlab.us:22", "markm"]
+//!
+//! vm_size = "512G"  # --vm_size 512G
+//! cores = 8         # --cores 8
+//! warmup = true     # --warmup (`false` omits the flag)
+//!
+//! # Parameters of a nested subcommand (e.g. `exp00010 ... memcached 64G`).
+//! [memcached]
+//! args = ["64G"]
+//! ```
+//!
+//! Flags given on the command line override the file. Overrides must use the long form of the
+//! flag (e.g. `--vm_size`, not `-v`).
+
+use std::cell::RefCell;
+
+use toml::{value::Table, Value};
+
+thread_local! {
+    /// The command line after expanding the config file, if there was one.
+    static EXPANDED_ARGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Returns the command line after expanding the config file, if any.
+pub fn expanded_args() -> Option<Vec<String>> {
+    EXPANDED_ARGS.with(|args| args.borrow().clone())
+}
+
+/// If `args` contains `--config FILE` before the subcommand, expand the contents of `FILE` into
+/// the arguments of the subcommand. Otherwise, return `args` unchanged. `value_opts` are the long
+/// names of the global options that take a value, so that their values are not mistaken for the
+/// subcommand.
+pub fn expand_config(
+    args: Vec<String>,
+    value_opts: &[&str],
+) -> Result<Vec<String>, failure::Error> {
+    let mut config = None;
+    let mut subcommand = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+
+        if arg == "--config" {
+            config = args.get(i + 1).cloned();
+            i += 2;
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(path.to_owned());
+            i += 1;
+        } else if arg.starts_with("--") && !arg.contains('=') && value_opts.contains(&&arg[2..]) {
+            i += 2;
+        } else if arg.starts_with('-') {
+            i += 1;
+        } else {
+            subcommand = Some(i);
+            break;
+        }
+    }
+
+    let (config, subcommand) = match (config, subcommand) {
+        (Some(config), Some(subcommand)) => (config, subcommand),
+        // Let `clap` report any errors.
+        _ => return Ok(args),
+    };
+
+    let contents = std::fs::read_to_string(&config)
+        .map_err(|e| failure::format_err!("Unable to read config file {}: {}", config, e))?;
+    let table = match contents.parse::<Value>()? {
+        Value::Table(table) => table,
+        _ => unreachable!("a TOML document is always a table"),
+    };
+
+    let mut expanded = args[..=subcommand].to_vec();
+    expanded.extend(expand_table(&table, &args[subcommand + 1..])?);
+
+    EXPANDED_ARGS.with(|args| *args.borrow_mut() = Some(expanded.clone()));
+
+    Ok(expanded)
+}
+
+/// Returns true if the flag `--key` is given in `cli`.
+fn overridden(cli: &[String], key: &str) -> bool {
+    let flag = format!("--{}", key);
+    let flag_eq = format!("--{}=", key);
+    cli.iter()
+        .any(|arg| *arg == flag || arg.starts_with(&flag_eq))
+}
+
+/// Convert a TOML scalar to a command line argument.
+fn scalar(key: &str, value: &Value) -> Result<String, failure::Error> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Datetime(d) => Ok(d.to_string()),
+        _ => Err(failure::format_err!(
+            "Config key {:?} must be a string or number, found {}",
+            key,
+            value
+        )),
+    }
+}
+
+/// Expand the parameters in `table` into arguments for a subcommand whose remaining arguments on
+/// the command line are `cli`.
+fn expand_table(table: &Table, cli: &[String]) -> Result<Vec<String>, failure::Error> {
+    let mut expanded = vec![];
+    let mut positionals = vec![];
+    let mut nested = None;
+
+    for (key, value) in table.iter() {
+        match value {
+            Value::Table(table) => {
+                if let Some((other, _)) = nested {
+                    return Err(failure::format_err!(
+                        "Config file gives parameters for both {:?} and {:?}, but only one \
+                         subcommand can run",
+                        other,
+                        key
+                    ));
+                }
+                nested = Some((key, table));
+            }
+
+            Value::Array(values) if key == "args" => {
+                for value in values.iter() {
+                    positionals.push(scalar(key, value)?);
+                }
+            }
+
+            _ if overridden(cli, key) => {}
+
+            Value::Boolean(true) => expanded.push(format!("--{}", key)),
+            Value::Boolean(false) => {}
+
+            Value::Array(values) => {
+                for value in values.iter() {
+                    expanded.push(format!("--{}", key));
+                    expanded.push(scalar(key, value)?);
+                }
+            }
+
+            value => {
+                expanded.push(format!("--{}", key));
+                expanded.push(scalar(key, value)?);
+            }
+        }
+    }
+
+    expanded.extend(positionals);
+
+    match nested {
+        None => expanded.extend(cli.iter().cloned()),
+
+        // The nested subcommand is always the last positional argument, so its arguments go after
+        // it, whether it is named on the command line or only in the file.
+        Some((name, table)) => {
+            if let Some(i) = cli.iter().position(|arg| arg == name) {
+                expanded.extend(cli[..=i].iter().cloned());
+                expanded.extend(expand_table(table, &cli[i + 1..])?);
+            } else {
+                expanded.extend(cli.iter().cloned());
+                expanded.push(name.clone());
+                expanded.extend(expand_table(table, &[])?);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+
+    /// Write `contents` to a config file unique to `name` and return its path.
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "runner-config-test-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn no_config() {
+        let cli = args(&["runner", "exp00000", "host:22", "markm"]);
+        assert_eq!(expand_config(cli.clone(), &[]).unwrap(), cli);
+    }
+
+    #[test]
+    fn expands_flags_and_args() {
+        let config = config_file(
+            "flags",
+            r#"
+            args = ["host:22", "markm"]
+            vm_size = "512G"
+            cores = 8
+            warmup = true
+            baseline = false
+            antagonist = ["cpu", "io:1G"]
+            "#,
+        );
+
+        let expanded = expand_config(
+            args(&["runner", "--config", &config, "exp00000", "--cores", "4"]),
+            &[],
+        )
+        .unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(
+            expanded,
+            args(&[
+                "runner",
+                "--config",
+                &config,
+                "exp00000",
+                "--antagonist",
+                "cpu",
+                "--antagonist",
+                "io:1G",
+                "--vm_size",
+                "512G",
+                "--warmup",
+                "host:22",
+                "markm",
+                "--cores",
+                "4",
+            ])
+        );
+    }
+
+    #[test]
+    fn expands_nested_subcommand() {
+        let config = config_file(
+            "nested",
+            r#"
+            cores = 8
+            [memcached]
+            args = ["64G"]
+            "#,
+        );
+
+        let expanded = expand_config(
+            args(&[
+                "runner",
+                "--dry_run",
+                &format!("--config={}", config),
+                "exp00010",
+            ]),
+            &[],
+        )
+        .unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(
+            expanded,
+            args(&[
+                "runner",
+                "--dry_run",
+                &format!("--config={}", config),
+                "exp00010",
+                "--cores",
+                "8",
+                "memcached",
+                "64G",
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_values_of_global_options() {
+        let config = config_file("global", "cores = 8\n");
+
+        let expanded = expand_config(
+            args(&["runner", "--log", "debug", "--config", &config, "exp00000"]),
+            &["log"],
+        )
+        .unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(
+            expanded,
+            args(&["runner", "--log", "debug", "--config", &config, "exp00000", "--cores", "8"])
+        );
+    }
+
+    #[test]
+    fn rejects_two_nested_subcommands() {
+        let config = config_file("two", "[memcached]\n[redis]\n");
+
+        let res = expand_config(args(&["runner", "--config", &config, "exp00010"]), &[]);
+        std::fs::remove_file(&config).unwrap();
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! A global deadline for experiments.
+//!
+//! The deadline is set once at startup via `runner --deadline DURATION`. Experiments check it
+//! before each major phase (e.g. warmup, workload). If the deadline has passed, the remaining
+//! phases are skipped, whatever results exist are still collected, and the run is marked partial
+//! by writing a `.partial` file next to the other output files. This lets a scheduler (e.g. the
+//! jobserver) ask for a graceful stop rather than killing the runner outright.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The deadline as seconds since the UNIX epoch, or 0 if there is no deadline.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before UNIX epoch")
+        .as_secs()
+}
+
+/// Parse a human-readable duration, such as `90`, `90s`, `30m`, `4h`, `1d`, or `1h30m`. A bare
+/// number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, failure::Error> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return Err(failure::format_err!("Empty string is not a valid duration"));
+    }
+
+    let mut total: u64 = 0;
+    let mut num = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+
+        let mult = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => {
+                return Err(failure::format_err!(
+                    "Invalid duration unit {:?} in {:?}",
+                    c,
+                    s
+                ))
+            }
+        };
+
+        if num.is_empty() {
+            return Err(failure::format_err!(
+                "Missing number before {:?} in {:?}",
+                c,
+                s
+            ));
+        }
+
+        total = num
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(mult))
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| failure::format_err!("Duration too long: {:?}", s))?;
+        num.clear();
+    }
+
+    if !num.is_empty() {
+        total = num
+            .parse::<u64>()
+            .ok()
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| failure::format_err!("Duration too long: {:?}", s))?;
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Set the global deadline to `duration` from now.
+pub fn set(duration: Duration) {
+    DEADLINE.store(
+        now_secs().saturating_add(duration.as_secs()),
+        Ordering::SeqCst,
+    );
+}
+
+/// Returns the global deadline as seconds since the UNIX epoch, if there is one.
+pub fn get() -> Option<u64> {
+    match DEADLINE.load(Ordering::SeqCst) {
+        0 => None,
+        deadline => Some(deadline),
+    }
+}
+
+/// Returns true if there is a deadline and it has passed.
+pub fn exceeded() -> bool {
+    get()
+        .map(|deadline| now_secs() >= deadline)
+        .unwrap_or(false)
+}
+
+/// Keeps track of which phases of an experiment were skipped because the deadline passed.
+#[derive(Debug, Default)]
+pub struct PhaseGuard {
+    skipped: Vec<String>,
+}
+
+impl PhaseGuard {
+    pub fn new() -> Self {
+        PhaseGuard::default()
+    }
+
+    /// Returns true if the phase called `label` should run. If the deadline has passed, the phase
+    /// is recorded as skipped and `false` is returned.
+    pub fn should_run<S: Into<String>>(&mut self, label: S) -> bool {
+        if exceeded() {
+            let label = label.into();
+            println!("Deadline exceeded. Skipping phase: {}", label);
+            self.skipped.push(label);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Returns true if any phase was skipped.
+    pub fn is_partial(&self) -> bool {
+        !self.skipped.is_empty()
+    }
+
+    /// A human-readable report of the skipped phases, suitable for the `.partial` file.
+    pub fn report(&self) -> String {
+        let mut s = String::from("Deadline exceeded. Skipped phases:\n");
+        for label in self.skipped.iter() {
+            s.push_str(label);
+            s.push('\n');
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("4h").unwrap(), Duration::from_secs(4 * 3600));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(
+            parse_duration(" 1h30m ").unwrap(),
+            Duration::from_secs(5400)
+        );
+        assert_eq!(parse_duration("1m30").unwrap(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        for s in &["", "h", "1x", "1.5h", "-1h", "1 h"] {
+            assert!(parse_duration(s).is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_overflow() {
+        for s in &[
+            "99999999999999999999",
+            "18446744073709551615d",
+            "18446744073709551615s1s",
+        ] {
+            assert!(parse_duration(s).is_err(), "{:?} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Differences between the Linux distributions we set up hosts on.
+//!
+//! Setup was originally written for CentOS, but some CloudLab images only come with Ubuntu. Rather
+//! than sprinkling checks throughout the setup routines, they ask the `Distro` of the host how to
+//! do things that differ: installing packages, updating grub, and packaging kernels. Packages are
+//! named by their CentOS names and mapped to the equivalent Debian/Ubuntu names here.
+
+use spurs::{cmd, Execute, SshCommand, SshShell};
+
+use super::KernelPkgType;
+
+/// A family of Linux distributions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Distro {
+    /// CentOS, RHEL, and Fedora.
+    CentOS,
+    /// Debian and Ubuntu.
+    Debian,
+}
+
+/// CentOS package names that are different on Debian/Ubuntu. A `None` means the package is not
+/// needed or not available there (e.g. because the default compiler is already new enough).
+const DEBIAN_PACKAGE_NAMES: &[(&str, Option<&str>)] = &[
+    ("libunwind-devel", Some("libunwind-dev")),
+    ("centos-release-scl", None),
+    ("libfdt-devel", Some("libfdt-dev")),
+    ("devtoolset-7", None),
+    ("libguestfs-tools-c", Some("libguestfs-tools")),
+    ("libxslt-devel", Some("libxslt1-dev")),
+    ("libxml2-devel", Some("libxml2-dev")),
+    ("gcc-gfortran", Some("gfortran")),
+    ("gcc-c++", Some("g++")),
+    ("ruby-devel", Some("ruby-dev")),
+    ("openssl-devel", Some("libssl-dev")),
+    ("libvirt", Some("libvirt-daemon-system")),
+    ("nfs-utils", Some("nfs-kernel-server")),
+    ("libvirt-devel", Some("libvirt-dev")),
+    ("pciutils-devel", Some("libpci-dev")),
+    ("elfutils-devel", Some("libelf-dev")),
+    ("audit-libs-devel", Some("libaudit-dev")),
+    ("slang-devel", Some("libslang2-dev")),
+    ("perl-ExtUtils-Embed", Some("libperl-dev")),
+    ("binutils-devel", Some("binutils-dev")),
+    ("xz-devel", Some("liblzma-dev")),
+    ("numactl-devel", Some("libnuma-dev")),
+    ("java-1.8.0-openjdk", Some("openjdk-8-jdk")),
+    ("scl-utils", None),
+    ("glib2-devel", Some("libglib2.0-dev")),
+    ("pixman-devel", Some("libpixman-1-dev")),
+    ("zlib-devel", Some("zlib1g-dev")),
+    ("fuse-devel", Some("libfuse-dev")),
+    ("libcgroup", Some("cgroup-tools")),
+    ("libcgroup-tools", None),
+    ("redis", Some("redis-server")),
+    ("perf", Some("linux-tools-generic")),
+    ("libevent", Some("libevent-2.1-6")),
+    ("libevent-devel", Some("libevent-dev")),
+];
+
+impl Distro {
+    /// Detect the distribution of the remote from `/etc/os-release`.
+    pub fn detect(shell: &SshShell) -> Result<Self, failure::Error> {
+        let os_release = shell
+            .run(cmd!("grep -E '^(ID|ID_LIKE)=' /etc/os-release"))?
+            .stdout;
+
+        let ids: Vec<_> = os_release
+            .lines()
+            .flat_map(|line| line.split_once('=').map(|(_, ids)| ids))
+            .flat_map(|ids| ids.trim_matches('"').split_whitespace())
+            .collect();
+
+        if ids
+            .iter()
+            .any(|id| ["centos", "rhel", "fedora"].contains(id))
+        {
+            Ok(Distro::CentOS)
+        } else if ids.iter().any(|id| ["debian", "ubuntu"].contains(id)) {
+            Ok(Distro::Debian)
+        } else {
+            Err(failure::format_err!(
+                "Unsupported distribution: {}",
+                os_release.trim()
+            ))
+        }
+    }
+
+    /// Returns the name of the CentOS package `pkg` on this distribution, if it is needed.
+    fn package_name(self, pkg: &str) -> Option<&str> {
+        match self {
+            Distro::CentOS => Some(pkg),
+            Distro::Debian => DEBIAN_PACKAGE_NAMES
+                .iter()
+                .find(|(centos, _)| *centos == pkg)
+                .map(|(_, debian)| *debian)
+                .unwrap_or(Some(pkg)),
+        }
+    }
+
+    /// Returns a command that installs the given packages, named by their CentOS names.
+    pub fn install(self, pkgs: &[&str]) -> SshCommand {
+        match self {
+            Distro::CentOS => spurs_util::centos::yum_install(pkgs),
+            Distro::Debian => {
+                let pkgs: Vec<_> = pkgs
+                    .iter()
+                    .filter_map(|pkg| self.package_name(pkg))
+                    .collect();
+                cmd!(
+                    "sudo DEBIAN_FRONTEND=noninteractive apt-get -y install {}",
+                    pkgs.join(" ")
+                )
+            }
+        }
+    }
+
+    /// Returns a command that installs the basic compiler toolchain.
+    pub fn install_dev_tools(self) -> SshCommand {
+        match self {
+            Distro::CentOS => cmd!("sudo yum group install -y 'Development Tools'"),
+            Distro::Debian => cmd!(
+                "sudo apt-get update && \
+                 sudo DEBIAN_FRONTEND=noninteractive apt-get -y install build-essential"
+            )
+            .use_bash(),
+        }
+    }
+
+    /// Returns a command that installs a package from the given URL, which should point to an
+    /// `.rpm` on CentOS and a `.deb` on Debian.
+    pub fn install_url(self, url: &str) -> SshCommand {
+        match self {
+            Distro::CentOS => cmd!("sudo yum -y install {}", url),
+            Distro::Debian => cmd!(
+                "wget -O /tmp/pkg.deb {} && sudo apt-get -y install /tmp/pkg.deb",
+                url
+            )
+            .use_bash(),
+        }
+    }
+
+    /// Returns true if the given package is installed, named by its name on this distribution.
+    pub fn is_installed(self, shell: &SshShell, pkg: &str) -> bool {
+        match self {
+            Distro::CentOS => shell
+                .run(cmd!("yum list installed {} | grep -q {}", pkg, pkg))
+                .is_ok(),
+            Distro::Debian => shell
+                .run(cmd!("dpkg -s {} | grep -q 'Status: install ok'", pkg))
+                .is_ok(),
+        }
+    }
+
+    /// The group that the QEMU processes started by libvirt run as.
+    pub fn qemu_group(self) -> &'static str {
+        match self {
+            Distro::CentOS => "qemu",
+            Distro::Debian => "libvirt-qemu",
+        }
+    }
+
+    /// The user that the QEMU processes started by libvirt run as.
+    pub fn qemu_user(self) -> &'static str {
+        match self {
+            Distro::CentOS => "qemu",
+            Distro::Debian => "libvirt-qemu",
+        }
+    }
+
+    /// The name of the NFS id mapping service.
+    pub fn nfs_idmap_service(self) -> &'static str {
+        match self {
+            Distro::CentOS => "nfs-idmap",
+            Distro::Debian => "nfs-idmapd",
+        }
+    }
+
+    /// The type of package to build kernels as.
+    pub fn kernel_pkg_type(self) -> KernelPkgType {
+        match self {
+            Distro::CentOS => KernelPkgType::Rpm,
+            Distro::Debian => KernelPkgType::Deb,
+        }
+    }
+
+    /// Returns a command that regenerates the grub config after `/etc/default/grub` changes.
+    pub fn update_grub(self) -> SshCommand {
+        match self {
+            Distro::CentOS => cmd!("sudo grub2-mkconfig -o /boot/grub2/grub.cfg"),
+            Distro::Debian => cmd!("sudo update-grub"),
+        }
+    }
+
+    /// Returns a command that makes the first grub entry (i.e. the newest kernel) the default.
+    pub fn set_default_kernel_to_newest(self) -> SshCommand {
+        match self {
+            Distro::CentOS => cmd!("sudo grub2-set-default 0"),
+            Distro::Debian => cmd!(
+                "sudo sed -i 's/^GRUB_DEFAULT=.*/GRUB_DEFAULT=0/' /etc/default/grub && \
+                 sudo update-grub"
+            )
+            .use_bash(),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Detecting time-drift anomalies in a simulation.
+//!
+//! 0sim hides the time the hypervisor spends simulating from the guest by offsetting its TSC. When
+//! that goes wrong, the guest sees its clock jump, and the results of timing-sensitive workloads
+//! are meaningless, but the run otherwise looks fine. `GuestOffsetSampler` records
+//! `/proc/zerosim_guest_offset` periodically during a run, and `DriftReport` checks those samples
+//! and the `dmesg` of the host and guest for signs of trouble, with a pass/fail verdict. When a
+//! `GuestOffsetSampler` was started, only the `dmesg` lines logged after it started are checked, so
+//! that messages from boot or from earlier runs on the same host are not blamed on this run.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+
+use super::background::LoopMarker;
+
+/// The file on the host to which `GuestOffsetSampler` appends samples.
+const OFFSET_SAMPLES_FILE: &str = "/tmp/runner-guest-offset-samples";
+
+/// The line that `GuestOffsetSampler` writes to the kernel log of the host and guest when it
+/// starts. Only the `dmesg` lines after the last such line are checked.
+const DMESG_START_MARKER: &str = "runner: checking for time drift from here";
+
+/// How often experiments sample `/proc/zerosim_guest_offset`, in seconds.
+pub const GUEST_OFFSET_SAMPLE_INTERVAL: usize = 10;
+
+/// Patterns in `dmesg` output that indicate the clock of the machine misbehaved, along with the
+/// kind of anomaly they indicate.
+const DRIFT_DMESG_PATTERNS: &[(&str, &str)] = &[
+    ("soft lockup", "soft_lockup"),
+    ("hard LOCKUP", "hard_lockup"),
+    ("rcu_sched self-detected stall", "rcu_stall"),
+    ("rcu_sched detected stalls", "rcu_stall"),
+    ("timekeeping watchdog", "clock_jump"),
+    ("Marking clocksource", "clock_jump"),
+    ("went backwards", "clock_jump"),
+    ("hrtimer: interrupt took", "slow_hrtimer"),
+    ("Marking TSC unstable", "tsc_unstable"),
+    ("TSC found unstable", "tsc_unstable"),
+];
+
+/// Periodically samples `/proc/zerosim_guest_offset` on the host in the background.
+pub struct GuestOffsetSampler {
+    /// Keeps the sampling loop running. The loop also stops if this is dropped without calling
+    /// `stop` (e.g. because the workload failed).
+    marker: LoopMarker,
+
+    /// The shell running the sampling loop.
+    handle: (SshShell, SshSpawnHandle),
+}
+
+impl GuestOffsetSampler {
+    /// Start sampling every `interval` seconds on the host (`ushell`), and mark the kernel logs of
+    /// the host and guest (`vshell`) so that only later `dmesg` lines are checked. The samples are
+    /// analyzed by `DriftReport::collect`, which is called by `gen_standard_sim_output`. Requires
+    /// `sudo`.
+    pub fn start(
+        ushell: &SshShell,
+        vshell: &SshShell,
+        interval: usize,
+    ) -> Result<Self, failure::Error> {
+        for shell in &[ushell, vshell] {
+            shell.run(cmd!("echo {} | sudo tee /dev/kmsg", DMESG_START_MARKER))?;
+        }
+
+        ushell.run(cmd!("rm -f {}", OFFSET_SAMPLES_FILE))?;
+        let marker = LoopMarker::create(ushell, "guest-offset")?;
+
+        // Each line of the file is prefixed with the time of the sample.
+        let handle = ushell.spawn(
+            cmd!(
+                "while [ -e {} ] ; do \
+                 t=$(date +%s.%N) ; \
+                 sed \"s/^/$t /\" /proc/zerosim_guest_offset ; \
+                 sleep {} ; \
+                 done >> {} ; \
+                 echo offset sampling done",
+                marker.path(),
+                interval,
+                OFFSET_SAMPLES_FILE,
+            )
+            .use_bash(),
+        )?;
+
+        Ok(GuestOffsetSampler { marker, handle })
+    }
+
+    /// Stop sampling and wait for the sampling loop to exit.
+    pub fn stop(mut self) -> Result<(), failure::Error> {
+        self.marker.remove()?;
+        (self.handle.1).join()?;
+        Ok(())
+    }
+}
+
+/// Whether a run is free of time-drift anomalies.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum DriftVerdict {
+    Pass,
+    Fail,
+}
+
+/// A sign that the clock of the host or guest misbehaved during the run.
+#[derive(Debug, Serialize)]
+pub struct DriftAnomaly {
+    /// Where the anomaly was found: `host_dmesg`, `guest_dmesg`, or `guest_offset`.
+    pub source: &'static str,
+
+    /// The kind of anomaly (e.g. `soft_lockup` or `offset_backwards`).
+    pub kind: &'static str,
+
+    /// The line of `dmesg` or a description of the bad samples.
+    pub detail: String,
+}
+
+/// The time-drift anomalies of a run.
+#[derive(Debug, Serialize)]
+pub struct DriftReport {
+    /// `Fail` if any anomalies were found.
+    pub verdict: DriftVerdict,
+
+    /// The number of samples of `/proc/zerosim_guest_offset` analyzed. This is 0 if the
+    /// experiment did not run a `GuestOffsetSampler`.
+    pub offset_samples: usize,
+
+    /// All anomalies found.
+    pub anomalies: Vec<DriftAnomaly>,
+}
+
+impl DriftReport {
+    /// Collect the `dmesg` of the host (`ushell`) and guest (`vshell`) and any samples taken by a
+    /// `GuestOffsetSampler`, and analyze them. The samples are removed afterwards so that they are
+    /// not mistaken for those of a later run.
+    pub fn collect(ushell: &SshShell, vshell: &SshShell) -> Result<Self, failure::Error> {
+        let host_dmesg = ushell.run(cmd!("dmesg"))?.stdout;
+        let guest_dmesg = vshell.run(cmd!("dmesg"))?.stdout;
+        let samples = ushell
+            .run(cmd!("cat {}", OFFSET_SAMPLES_FILE).allow_error())?
+            .stdout;
+        ushell.run(cmd!("rm -f {}", OFFSET_SAMPLES_FILE))?;
+
+        Ok(Self::analyze(&host_dmesg, &guest_dmesg, &samples))
+    }
+
+    /// Analyze the `dmesg` of the host and guest and the contents of the samples file of a
+    /// `GuestOffsetSampler` (which may be empty).
+    pub fn analyze(host_dmesg: &str, guest_dmesg: &str, samples: &str) -> Self {
+        let mut anomalies = find_drift_dmesg(host_dmesg, "host_dmesg");
+        anomalies.extend(find_drift_dmesg(guest_dmesg, "guest_dmesg"));
+
+        let samples = parse_offset_samples(samples);
+        let offset_samples = samples.values().map(Vec::len).sum();
+        anomalies.extend(find_offset_anomalies(&samples));
+
+        DriftReport {
+            verdict: if anomalies.is_empty() {
+                DriftVerdict::Pass
+            } else {
+                DriftVerdict::Fail
+            },
+            offset_samples,
+            anomalies,
+        }
+    }
+
+    /// A human-readable summary of the report for the sim output.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Verdict: {}\nOffset samples: {}\nAnomalies: {}\n",
+            match self.verdict {
+                DriftVerdict::Pass => "PASS",
+                DriftVerdict::Fail => "FAIL",
+            },
+            self.offset_samples,
+            self.anomalies.len()
+        );
+
+        for anomaly in self.anomalies.iter() {
+            summary.push_str(&format!(
+                "{} {}: {}\n",
+                anomaly.source, anomaly.kind, anomaly.detail
+            ));
+        }
+
+        summary
+    }
+}
+
+/// Returns the lines of `dmesg` after the last `DMESG_START_MARKER` (or all lines, if there is
+/// none) that indicate the clock misbehaved.
+fn find_drift_dmesg(dmesg: &str, source: &'static str) -> Vec<DriftAnomaly> {
+    let dmesg = match dmesg.rfind(DMESG_START_MARKER) {
+        Some(start) => &dmesg[start..],
+        None => dmesg,
+    };
+
+    dmesg
+        .lines()
+        .filter_map(|line| {
+            DRIFT_DMESG_PATTERNS
+                .iter()
+                .find(|(pattern, _)| line.contains(pattern))
+                .map(|(_, kind)| DriftAnomaly {
+                    source,
+                    kind,
+                    detail: line.to_owned(),
+                })
+        })
+        .collect()
+}
+
+/// Parse the samples file of a `GuestOffsetSampler`. Lines look like `<time> <line of
+/// /proc/zerosim_guest_offset>`, where the last field of the line is the offset and anything
+/// before it identifies the vcpu. Returns the `(time, offset)` samples of each vcpu in order.
+fn parse_offset_samples(samples: &str) -> BTreeMap<String, Vec<(f64, i64)>> {
+    let mut parsed: BTreeMap<String, Vec<(f64, i64)>> = BTreeMap::new();
+
+    for line in samples.lines() {
+        let mut parts = line.trim().splitn(2, ' ');
+        let (time, rest) = match (parts.next(), parts.next()) {
+            (Some(time), Some(rest)) => (time, rest.trim()),
+            _ => continue,
+        };
+        let time = match time.parse() {
+            Ok(time) => time,
+            Err(_) => continue,
+        };
+        let (vcpu, offset) = match rest.rfind(|c: char| c.is_whitespace() || c == ':') {
+            Some(i) => (rest[..i].trim(), rest[i + 1..].trim()),
+            None => ("", rest),
+        };
+        if let Ok(offset) = offset.parse() {
+            parsed
+                .entry(vcpu.to_owned())
+                .or_default()
+                .push((time, offset));
+        }
+    }
+
+    parsed
+}
+
+/// Returns an anomaly for each time the offset of a vcpu decreased between samples, which means
+/// the clock of the guest jumped.
+fn find_offset_anomalies(samples: &BTreeMap<String, Vec<(f64, i64)>>) -> Vec<DriftAnomaly> {
+    let mut anomalies = vec![];
+
+    for (vcpu, samples) in samples.iter() {
+        for pair in samples.windows(2) {
+            let ((t0, offset0), (t1, offset1)) = (pair[0], pair[1]);
+            if offset1 < offset0 {
+                anomalies.push(DriftAnomaly {
+                    source: "guest_offset",
+                    kind: "offset_backwards",
+                    detail: format!(
+                        "vcpu {:?}: offset {} at {} -> {} at {}",
+                        vcpu, offset0, t0, offset1, t1
+                    ),
+                });
+            }
+        }
+    }
+
+    anomalies
+}
@@ -1,9 +1,13 @@
 //! Routines used for 0sim-related experiments
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use spurs::{cmd, Execute, SshError, SshShell};
+use serde::{Deserialize, Serialize};
 
+use spurs::{cmd, Execute, SshError, SshShell, SshSpawnHandle};
+
+use super::background::LoopMarker;
 use super::paths::*;
 
 pub use super::{Login, ServiceAction};
@@ -25,6 +29,149 @@ pub const ZEROSIM_SKIP_HALT: bool = false;
 /// The default value for /proc/zerosim_lapic_adjust.
 pub const ZEROSIM_LAPIC_ADJUST: bool = true;
 
+/// The block size of the thin swap pool in 512B sectors (256000 = 128MB).
+const THIN_POOL_BLOCK_SECTORS: usize = 256000;
+
+/// The libvirt domain name of the VM started by the `qemu` backend.
+const QEMU_DOMAIN: &str = "zerosim_guest";
+
+/// The NFS exports file for the shared directories of the VM started by the `qemu` backend.
+const QEMU_EXPORTS_FILE: &str = "/etc/exports.d/zerosim-qemu.exports";
+
+/// The address of the host as seen by a guest using QEMU user-mode networking.
+const QEMU_USER_NET_HOST: &str = "10.0.2.2";
+
+/// How long to wait for the guest to boot or shut down with the `qemu` backend, in seconds.
+const QEMU_TIMEOUT: usize = 600;
+
+/// The directory on the host in which the copy-on-write disk images of named VMs are created.
+const QEMU_OVERLAY_DIR: &str = "/var/lib/libvirt/images";
+
+/// The name of the internal qcow2 snapshot of the guest disk taken at the end of setup.
+const GUEST_SNAPSHOT: &str = "zerosim-setup";
+
+/// How the 0sim guest is launched on the host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum VmBackend {
+    /// `vagrant up` with the vagrant-libvirt plugin, using a generated Vagrantfile.
+    Vagrant,
+
+    /// A libvirt domain defined directly from generated XML, bypassing vagrant. The guest disk is
+    /// the image created by `vagrant up` during setup, so setup still needs vagrant. The guest
+    /// uses QEMU user-mode networking, with the host's SSH port forwarded to the guest, and mounts
+    /// the shared directories from the host via NFS itself.
+    Qemu,
+}
+
+/// The selected `VmBackend`, as its index in `VmBackend::ALL`.
+static VM_BACKEND: AtomicUsize = AtomicUsize::new(0);
+
+impl VmBackend {
+    const ALL: &'static [VmBackend] = &[VmBackend::Vagrant, VmBackend::Qemu];
+
+    /// The names of the backends, as accepted by `runner --vm_backend`.
+    pub const NAMES: &'static [&'static str] = &["vagrant", "qemu"];
+}
+
+impl std::str::FromStr for VmBackend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .position(|name| *name == s)
+            .map(|i| Self::ALL[i])
+            .ok_or_else(|| failure::format_err!("Unknown VM backend: {}", s))
+    }
+}
+
+/// Select the backend used to start and stop the VM for the rest of the run.
+pub fn set_vm_backend(backend: VmBackend) {
+    let i = VmBackend::ALL
+        .iter()
+        .position(|b| *b == backend)
+        .expect("backend not in VmBackend::ALL");
+    VM_BACKEND.store(i, Ordering::SeqCst);
+}
+
+/// Returns the backend used to start and stop the VM (by default, `Vagrant`).
+pub fn vm_backend() -> VmBackend {
+    VmBackend::ALL[VM_BACKEND.load(Ordering::SeqCst)]
+}
+
+/// Whether to restore the guest disk from the snapshot taken at the end of setup before starting
+/// the VM.
+static FRESH_GUEST: AtomicBool = AtomicBool::new(false);
+
+/// Restore the guest disk from the snapshot taken at the end of setup every time the VM is started
+/// for the rest of the run, so that state left over from previous workloads (e.g. swapfile
+/// contents, the page cache, HDFS data) cannot contaminate the results.
+pub fn set_fresh_guest(fresh: bool) {
+    FRESH_GUEST.store(fresh, Ordering::SeqCst);
+}
+
+/// Returns true if the guest disk is restored from its snapshot before starting the VM.
+pub fn fresh_guest() -> bool {
+    FRESH_GUEST.load(Ordering::SeqCst)
+}
+
+/// The default zpool of zswap: the one 0sim adds to the host kernel.
+pub const DEFAULT_ZSWAP_ZPOOL: &str = "ztier";
+
+/// The default maximum percentage of host memory that zswap can use.
+pub const DEFAULT_ZSWAP_MAX_POOL_PERCENT: usize = 50;
+
+/// How zswap is configured on the host. This is recorded in the settings of experiments, so that
+/// sweeps over zswap parameters are reproducible. Apply it with `ZeroSim::zswap`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZswapConfig {
+    /// Whether zswap is on. If not, the hypervisor actually swaps to disk (not recommended).
+    pub enabled: bool,
+
+    /// The zpool allocator (e.g. `ztier`, `zbud`, `z3fold`, or `zsmalloc`).
+    pub zpool: String,
+
+    /// The maximum percentage of host memory that zswap can use.
+    pub max_pool_percent: usize,
+
+    /// The compression algorithm (e.g. `lzo` or `lz4`), or the kernel's default if `None`.
+    pub compressor: Option<String>,
+}
+
+impl Default for ZswapConfig {
+    fn default() -> Self {
+        ZswapConfig {
+            enabled: true,
+            zpool: DEFAULT_ZSWAP_ZPOOL.into(),
+            max_pool_percent: DEFAULT_ZSWAP_MAX_POOL_PERCENT,
+            compressor: None,
+        }
+    }
+}
+
+impl ZswapConfig {
+    /// Build the config from the `--disable_zswap`, `--zswap_zpool`, `--zswap_max_pool_percent`,
+    /// and `--zswap_compressor` options of an experiment (named `DISABLE_ZSWAP`, `ZSWAP_ZPOOL`,
+    /// `ZSWAP_MAX_POOL_PERCENT`, and `ZSWAP_COMPRESSOR`). Options the experiment doesn't have are
+    /// left at their defaults.
+    pub fn from_args(sub_m: &clap::ArgMatches<'_>) -> Self {
+        let default = Self::default();
+
+        ZswapConfig {
+            enabled: !sub_m.is_present("DISABLE_ZSWAP"),
+            zpool: sub_m
+                .value_of("ZSWAP_ZPOOL")
+                .map(String::from)
+                .unwrap_or(default.zpool),
+            max_pool_percent: sub_m
+                .value_of("ZSWAP_MAX_POOL_PERCENT")
+                .map(|value| value.parse::<usize>().unwrap())
+                .unwrap_or(default.max_pool_percent),
+            compressor: sub_m.value_of("ZSWAP_COMPRESSOR").map(String::from),
+        }
+    }
+}
+
 /// Sets various settings on 0sim.
 pub struct ZeroSim;
 
@@ -87,26 +234,19 @@ impl ZeroSim {
         Ok(())
     }
 
-    /// Set the Zswap max_pool_percent.
-    pub fn zswap_max_pool_percent(shell: &SshShell, pct: usize) -> Result<(), failure::Error> {
-        assert!(pct <= 100);
-
-        shell.run(
-            cmd!(
-                "echo {} | sudo tee /sys/module/zswap/parameters/max_pool_percent",
-                pct
-            )
-            .use_bash(),
-        )?;
-
-        Ok(())
-    }
+    /// Configure zswap according to `config`. If it is enabled, this also turns off THP and KSM on
+    /// the host, which don't work well with it.
+    pub fn zswap(shell: &mut SshShell, config: &ZswapConfig) -> Result<(), failure::Error> {
+        assert!(config.max_pool_percent <= 100);
 
-    /// Turn on Zswap with some default parameters.
-    pub fn turn_on_zswap(shell: &mut SshShell) -> Result<(), failure::Error> {
         // apparently permissions can get weird
         shell.run(cmd!("sudo chmod +w /sys/module/zswap/parameters/*").use_bash())?;
 
+        if !config.enabled {
+            shell.run(cmd!("echo n | sudo tee /sys/module/zswap/parameters/enabled").use_bash())?;
+            return Ok(());
+        }
+
         // THP is buggy with frontswap until later kernels
         shell.run(
             cmd!("echo never | sudo tee /sys/kernel/mm/transparent_hugepage/enabled").use_bash(),
@@ -116,7 +256,29 @@ impl ZeroSim {
         crate::common::service(shell, "ksm", ServiceAction::Disable)?;
         crate::common::service(shell, "ksmtuned", ServiceAction::Disable)?;
 
-        shell.run(cmd!("echo ztier | sudo tee /sys/module/zswap/parameters/zpool").use_bash())?;
+        if let Some(compressor) = &config.compressor {
+            shell.run(
+                cmd!(
+                    "echo {} | sudo tee /sys/module/zswap/parameters/compressor",
+                    compressor
+                )
+                .use_bash(),
+            )?;
+        }
+        shell.run(
+            cmd!(
+                "echo {} | sudo tee /sys/module/zswap/parameters/zpool",
+                config.zpool
+            )
+            .use_bash(),
+        )?;
+        shell.run(
+            cmd!(
+                "echo {} | sudo tee /sys/module/zswap/parameters/max_pool_percent",
+                config.max_pool_percent
+            )
+            .use_bash(),
+        )?;
         shell.run(cmd!("echo y | sudo tee /sys/module/zswap/parameters/enabled").use_bash())?;
         shell.run(cmd!("sudo tail /sys/module/zswap/parameters/*").use_bash())?;
 
@@ -270,15 +432,93 @@ where
 
     set_kernel_printk_level(&ushell, 4)?;
 
+    crate::common::boot::expect_current_boot(&ushell)?;
+
     Ok(ushell)
 }
 
+/// The file on the remote where `save_host_fingerprint` records the state of the host.
+const HOST_FINGERPRINT_FILE: &str = "/tmp/runner-host-fingerprint";
+
+/// Returns a fingerprint of the state of the host that experiments set up: the current boot, the
+/// kernel and its command line, the active swap devices, and the zswap settings.
+pub fn host_fingerprint(shell: &SshShell) -> Result<String, failure::Error> {
+    Ok(shell
+        .run(
+            cmd!(
+                "cat /proc/sys/kernel/random/boot_id ; \
+                 uname -r ; \
+                 cat /proc/cmdline ; \
+                 awk 'NR > 1 {{ print $1 }}' /proc/swaps ; \
+                 cat /sys/module/zswap/parameters/{{enabled,zpool,max_pool_percent,compressor}} || true"
+            )
+            .use_bash(),
+        )?
+        .stdout)
+}
+
+/// Record the current state of the host (see `host_fingerprint`) so that later `--dev` runs can
+/// tell whether they can skip setting it up again. Call this after the host is fully set up.
+pub fn save_host_fingerprint(shell: &SshShell) -> Result<(), failure::Error> {
+    let fingerprint = host_fingerprint(shell)?;
+    shell.run(cmd!(
+        "echo {} > {}",
+        spurs_util::escape_for_bash(&fingerprint),
+        HOST_FINGERPRINT_FILE
+    ))?;
+    Ok(())
+}
+
+/// For fast iteration during development (`--dev`): connect to the host and, if it is still in
+/// the state recorded by `save_host_fingerprint`, return a shell to it without rebooting or
+/// setting up swapping again. Otherwise, return `None`, and the caller should set up the host
+/// normally.
+///
+/// Timing results from such runs are not comparable to those from a freshly rebooted host.
+pub fn connect_to_prepared_host<A>(login: &Login<A>) -> Result<Option<SshShell>, failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Debug + std::fmt::Display + Clone,
+{
+    let ushell = SshShell::with_default_key(login.username, &login.host)?;
+
+    let saved = ushell
+        .run(cmd!("cat {}", HOST_FINGERPRINT_FILE))
+        .map(|out| out.stdout);
+    let current = host_fingerprint(&ushell)?;
+
+    match saved {
+        Ok(ref saved) if saved.trim() == current.trim() => {
+            println!("Host is already set up. Skipping reboot and swap setup (--dev).");
+
+            // Force the VM off if it was left running. If there is no VM, then ignore errors.
+            let _ = vagrant_halt(&ushell);
+
+            crate::common::boot::expect_current_boot(&ushell)?;
+
+            Ok(Some(ushell))
+        }
+        _ => {
+            println!("Host state has changed since the last run. Doing a full setup.");
+            Ok(None)
+        }
+    }
+}
+
 pub fn connect_to_vagrant_user<A: std::net::ToSocketAddrs + std::fmt::Display>(
     hostname: A,
     user: &str,
+) -> Result<SshShell, SshError> {
+    connect_to_vm_user(hostname, user, VAGRANT_PORT)
+}
+
+/// Connect to the VM whose SSH port is forwarded from `port` on the host.
+pub fn connect_to_vm_user<A: std::net::ToSocketAddrs + std::fmt::Display>(
+    hostname: A,
+    user: &str,
+    port: u16,
 ) -> Result<SshShell, SshError> {
     let (host, _) = spurs_util::get_host_ip(hostname);
-    SshShell::with_default_key(user, (host, VAGRANT_PORT))
+    SshShell::with_default_key(user, (host, port))
 }
 
 pub fn connect_to_vagrant_as_root<A: std::net::ToSocketAddrs + std::fmt::Display>(
@@ -293,12 +533,17 @@ pub fn connect_to_vagrant_as_user<A: std::net::ToSocketAddrs + std::fmt::Display
     connect_to_vagrant_user(hostname, "vagrant")
 }
 
+/// Shut down the VM using the selected `VmBackend`.
 pub fn vagrant_halt(shell: &SshShell) -> Result<(), failure::Error> {
     let vagrant_path = &dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
 
     // Speed things up...
     ZeroSim::tsc_offsetting(shell, false)?;
 
+    if vm_backend() == VmBackend::Qemu {
+        return qemu_halt(shell);
+    }
+
     let res = shell.run(cmd!("vagrant halt").cwd(vagrant_path));
 
     if res.is_err() {
@@ -309,32 +554,179 @@ pub fn vagrant_halt(shell: &SshShell) -> Result<(), failure::Error> {
     Ok(())
 }
 
-/// Start the VM with the given amount of memory and core. If `fast` is `true`, TSC offsetting
-/// is disabled during the VM boot (and re-enabled afterwards), which is much faster.
+/// Start the VM with the given amount of memory and core using the selected `VmBackend` and the
+/// default `VmConfig`. See `start_vm`.
+pub fn start_vagrant<A: std::net::ToSocketAddrs + std::fmt::Display + Clone>(
+    shell: &SshShell,
+    hostname: A,
+    memgb: usize,
+    cores: usize,
+    fast: bool,
+    skip_halt: bool,
+    lapic_adjust: bool,
+) -> Result<SshShell, failure::Error> {
+    start_vm(
+        shell,
+        hostname,
+        &VmConfig::new(memgb, cores),
+        fast,
+        skip_halt,
+        lapic_adjust,
+    )
+}
+
+/// Start the VM described by `config` using the selected `VmBackend`. If `fast` is `true`, TSC
+/// offsetting is disabled during the VM boot (and re-enabled afterwards), which is much faster.
 ///
 /// After starting the VM, we attempt to disable soft lockup detectors in the guest because they
 /// can produce timing anomalies.
-pub fn start_vagrant<A: std::net::ToSocketAddrs + std::fmt::Display>(
+///
+/// If a guest kernel was requested (`--guest_kernel`) and the guest is not running it, it is
+/// installed and the VM is restarted to boot into it.
+pub fn start_vm<A: std::net::ToSocketAddrs + std::fmt::Display + Clone>(
     shell: &SshShell,
     hostname: A,
-    memgb: usize,
-    cores: usize,
+    config: &VmConfig,
     fast: bool,
     skip_halt: bool,
     lapic_adjust: bool,
 ) -> Result<SshShell, failure::Error> {
-    crate::common::service(shell, "firewalld", ServiceAction::Stop)?;
-    crate::common::service(shell, "nfs-idmap", ServiceAction::Restart)?;
-    crate::common::service(shell, "libvirtd", ServiceAction::Restart)?;
+    let backend = vm_backend();
+    if backend == VmBackend::Vagrant {
+        // Keep the same VM domain name though...
+        let domain = current_vagrant_domain(shell)?;
+        write_vagrantfile(shell, &domain, config)?;
+    }
+
+    let vagrant_path = &dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
+
+    prepare_host_for_vms(shell, config.mem_gb, fast, lapic_adjust)?;
+
+    if fresh_guest()
+        && !crate::common::guest_kernel::rebooting()
+        && !crate::common::boot_params::rebooting()
+    {
+        stop_vagrant_domain(shell)?;
+        restore_guest_disk(shell)?;
+    }
+
+    // We want to pin the vCPUs as soon as possible because otherwise, they tend to switch
+    // around a lot, causing a lot of printk overhead. The qemu backend pins them in the domain
+    // XML.
+    match backend {
+        VmBackend::Vagrant => {
+            virsh_vcpupin(shell, &config.vcpu_pinning)?;
+            shell.run(cmd!("vagrant up").no_pty().cwd(vagrant_path))?;
+        }
+        VmBackend::Qemu => {
+            stop_vagrant_domain(shell)?;
+            let disk = vagrant_disk_image(shell)?;
+            qemu_up(shell, QEMU_DOMAIN, &disk, config)?;
+        }
+    }
+
+    shell.run(cmd!("sudo lsof -i -P -n | grep LISTEN").use_bash())?;
+    let vshell = connect_to_vagrant_as_root(hostname.clone())?;
+
+    prepare_guest(shell, &vshell, config)?;
+
+    if crate::common::guest_kernel::install_if_needed(shell, &vshell)?
+        || crate::common::boot_params::set_guest_if_needed(&vshell)?
+    {
+        vagrant_halt(shell)?;
+        return start_vm(shell, hostname, config, fast, skip_halt, lapic_adjust);
+    }
+
+    // Enable TSC offsetting (regardless of whether it was already off).
+    ZeroSim::tsc_offsetting(shell, true)?;
+
+    // Can turn skip_halt back on now.
+    ZeroSim::skip_halt(shell, skip_halt)?;
+
+    Ok(vshell)
+}
+
+/// Start several named VMs on the same host (e.g. a victim and an antagonist), each with its own
+/// `VmConfig`, and return root shells to them by name. Otherwise, this is like `start_vm`.
+///
+/// Requires the `qemu` backend. Each VM boots from its own copy-on-write overlay of the guest disk
+/// image created during setup, which is discarded the next time the VMs are started, and the SSH
+/// port of the `i`-th VM is forwarded from `VAGRANT_PORT + i`. The caller should pin the vCPUs of
+/// different VMs to different host CPUs (e.g. with `VmConfig::pin_from`).
+pub fn start_vms<A: std::net::ToSocketAddrs + std::fmt::Display>(
+    shell: &SshShell,
+    hostname: A,
+    vms: &[(&str, VmConfig)],
+    fast: bool,
+    skip_halt: bool,
+    lapic_adjust: bool,
+) -> Result<BTreeMap<String, SshShell>, failure::Error> {
+    if vm_backend() != VmBackend::Qemu {
+        return Err(failure::format_err!(
+            "Multiple VMs per host require `--vm_backend qemu`, since vagrant would create a \
+             fresh guest for each VM rather than reusing the one prepared during setup."
+        ));
+    }
+
+    let total_mem_gb = vms.iter().map(|(_, config)| config.mem_gb).sum();
+    prepare_host_for_vms(shell, total_mem_gb, fast, lapic_adjust)?;
+
+    stop_vagrant_domain(shell)?;
+    if fresh_guest() {
+        restore_guest_disk(shell)?;
+    }
+    let base = vagrant_disk_image(shell)?;
+
+    let mut configs = Vec::new();
+    for (i, (name, config)) in vms.iter().enumerate() {
+        let domain = format!("{}_{}", QEMU_DOMAIN, name);
+        let disk = format!("{}/{}.qcow2", QEMU_OVERLAY_DIR, domain);
+        let config = config.clone().forward_ssh_from(VAGRANT_PORT + i as u16);
+
+        shell.run(cmd!(
+            "sudo rm -f {} && sudo qemu-img create -f qcow2 -F qcow2 -b {} {}",
+            disk,
+            base,
+            disk
+        ))?;
+        qemu_up(shell, &domain, &disk, &config)?;
+
+        configs.push((name.to_string(), config));
+    }
+
+    shell.run(cmd!("sudo lsof -i -P -n | grep LISTEN").use_bash())?;
+
+    let mut vshells = BTreeMap::new();
+    for (name, config) in configs.into_iter() {
+        let port = config.ssh_port().expect("SSH port was just set");
+        let vshell = connect_to_vm_user(&hostname, "root", port)?;
+        prepare_guest(shell, &vshell, &config)?;
+        vshells.insert(name, vshell);
+    }
+
+    // Enable TSC offsetting (regardless of whether it was already off).
+    ZeroSim::tsc_offsetting(shell, true)?;
+
+    // Can turn skip_halt back on now.
+    ZeroSim::skip_halt(shell, skip_halt)?;
+
+    Ok(vshells)
+}
+
+/// Prepare the host to start VMs with a total of `mem_gb` GB of memory and shut down any VMs that
+/// are running. If `fast` is `true`, TSC offsetting is disabled until the caller turns it back on.
+fn prepare_host_for_vms(
+    shell: &SshShell,
+    mem_gb: usize,
+    fast: bool,
+    lapic_adjust: bool,
+) -> Result<(), failure::Error> {
+    crate::common::network::prepare_host(shell)?;
 
     // Disable KSM because it creates a lot of overhead when the host is oversubscribed
     crate::common::service(shell, "ksm", ServiceAction::Disable)?;
     crate::common::service(shell, "ksmtuned", ServiceAction::Disable)?;
 
-    gen_vagrantfile(shell, memgb, cores)?;
-
-    let vagrant_path = &dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
-
     // Make sure to turn off skip_halt, which breaks multi-core boot.
     ZeroSim::skip_halt(shell, false)?;
 
@@ -346,25 +738,28 @@ pub fn start_vagrant<A: std::net::ToSocketAddrs + std::fmt::Display>(
 
     vagrant_halt(&shell)?;
 
-    // We want to pin the vCPUs as soon as possible because otherwise, they tend to switch
-    // around a lot, causing a lot of printk overhead.
-    let pin = {
-        let mut pin = HashMap::new();
-        for c in 0..cores {
-            pin.insert(c, c);
-        }
-        pin
-    };
-    virsh_vcpupin(shell, &pin)?;
+    // Make sure there is enough memory + swap to back the VMs before spending hours on them.
+    check_swap_capacity(shell, mem_gb)?;
 
-    shell.run(cmd!("vagrant up").no_pty().cwd(vagrant_path))?;
+    Ok(())
+}
 
-    shell.run(cmd!("sudo lsof -i -P -n | grep LISTEN").use_bash())?;
-    let vshell = connect_to_vagrant_as_root(hostname)?;
+/// Check and prepare a guest that just booted with the given `config`.
+fn prepare_guest(
+    shell: &SshShell,
+    vshell: &SshShell,
+    config: &VmConfig,
+) -> Result<(), failure::Error> {
+    if vm_backend() == VmBackend::Qemu {
+        qemu_mount_shared_dirs(shell, vshell, config)?;
+    }
+
+    crate::common::network::verify_guest(shell, vshell, config.ssh_port().unwrap_or(VAGRANT_PORT))?;
+    verify_guest_resources(shell, vshell, config.mem_gb, config.cores)?;
 
-    turn_off_watchdogs(&vshell)?;
+    turn_off_watchdogs(vshell)?;
 
-    dump_sys_info(&vshell)?;
+    dump_sys_info(vshell)?;
 
     // Don't let the OOM killer kill ssh
     vshell.run(cmd!(
@@ -373,13 +768,405 @@ pub fn start_vagrant<A: std::net::ToSocketAddrs + std::fmt::Display>(
         done"
     ))?;
 
-    // Enable TSC offsetting (regardless of whether it was already off).
-    ZeroSim::tsc_offsetting(shell, true)?;
+    Ok(())
+}
 
-    // Can turn skip_halt back on now.
-    ZeroSim::skip_halt(shell, skip_halt)?;
+/// Returns the libvirt domain name of the VM created by `vagrant up`.
+fn vagrant_libvirt_domain(shell: &SshShell) -> Result<String, failure::Error> {
+    let domain = current_vagrant_domain(shell)?;
 
-    Ok(vshell)
+    // vagrant-libvirt prefixes the domain name with the name of the vagrant directory.
+    Ok(shell
+        .run(cmd!("sudo virsh list --all --name | grep -m1 '{}$'", domain).use_bash())?
+        .stdout
+        .trim()
+        .to_owned())
+}
+
+/// Returns the path on the host of the disk image of the VM created by `vagrant up`, which the
+/// `qemu` backend boots from.
+fn vagrant_disk_image(shell: &SshShell) -> Result<String, failure::Error> {
+    let domain = current_vagrant_domain(shell)?;
+    let libvirt_domain = vagrant_libvirt_domain(shell)?;
+
+    let disk = shell
+        .run(
+            cmd!(
+                "sudo virsh domblklist --details {} | awk '$2 == \"disk\" {{print $4}}' | head -n1",
+                libvirt_domain
+            )
+            .use_bash(),
+        )?
+        .stdout
+        .trim()
+        .to_owned();
+
+    if disk.is_empty() {
+        Err(failure::format_err!(
+            "Unable to find the disk image of VM {}. The qemu backend boots from the image \
+             created by `vagrant up`, so the VM must have been set up with vagrant first.",
+            domain
+        ))
+    } else {
+        Ok(disk)
+    }
+}
+
+/// Wait for the libvirt domain `domain` to shut off. Returns an error if it does not within
+/// `QEMU_TIMEOUT` seconds.
+fn wait_for_shutoff(shell: &SshShell, domain: &str) -> Result<(), failure::Error> {
+    shell.run(
+        cmd!(
+            "timeout {} bash -c 'until sudo virsh domstate {} | grep -q \"shut off\" ; \
+             do sleep 5 ; done'",
+            QEMU_TIMEOUT,
+            domain
+        )
+        .use_bash(),
+    )?;
+
+    Ok(())
+}
+
+/// Snapshot the guest disk, replacing any previous snapshot, so that it can later be restored by
+/// `restore_guest_disk`. This should be done right after setting up the guest, when it is in a
+/// known clean state. The VM is shut down first (with TSC offsetting off, which makes it much
+/// faster), since the disk must not change while it is being snapshotted.
+pub fn snapshot_guest_disk(shell: &SshShell) -> Result<(), failure::Error> {
+    vagrant_halt(shell)?;
+
+    // The guest may already be shutting down on its own (e.g. after `poweroff`).
+    wait_for_shutoff(shell, &vagrant_libvirt_domain(shell)?)?;
+
+    let disk = vagrant_disk_image(shell)?;
+    shell.run(cmd!("sudo qemu-img snapshot -d {} {}", GUEST_SNAPSHOT, disk).allow_error())?;
+    shell.run(cmd!(
+        "sudo qemu-img snapshot -c {} {}",
+        GUEST_SNAPSHOT,
+        disk
+    ))?;
+
+    Ok(())
+}
+
+/// Restore the guest disk from the snapshot taken by `snapshot_guest_disk`, discarding any changes
+/// since. All VMs using the disk must be shut down.
+pub fn restore_guest_disk(shell: &SshShell) -> Result<(), failure::Error> {
+    let disk = vagrant_disk_image(shell)?;
+
+    let has_snapshot = shell
+        .run(
+            cmd!(
+                "sudo qemu-img snapshot -l {} | grep -q ' {} '",
+                disk,
+                GUEST_SNAPSHOT
+            )
+            .use_bash(),
+        )
+        .is_ok();
+    if !has_snapshot {
+        return Err(failure::format_err!(
+            "The guest disk {} has no {} snapshot to restore. Rerun the guest setup in \
+             setup00000 to create one.",
+            disk,
+            GUEST_SNAPSHOT
+        ));
+    }
+
+    shell.run(cmd!(
+        "sudo qemu-img snapshot -a {} {}",
+        GUEST_SNAPSHOT,
+        disk
+    ))?;
+
+    Ok(())
+}
+
+/// Stop the VM created by vagrant if it is running, since the `qemu` backend boots from the same
+/// disk image.
+fn stop_vagrant_domain(shell: &SshShell) -> Result<(), failure::Error> {
+    if let Ok((domain, true)) = virsh_domain_name(shell) {
+        if !domain.starts_with(QEMU_DOMAIN) {
+            shell.run(cmd!("sudo virsh destroy {}", domain))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Start the VM described by `config` as the libvirt domain `domain` booting from `disk` with the
+/// `qemu` backend, and wait for its SSH server to come up.
+fn qemu_up(
+    shell: &SshShell,
+    domain: &str,
+    disk: &str,
+    config: &VmConfig,
+) -> Result<(), failure::Error> {
+    let mut context = config.template_context(shell)?;
+    context.insert("domain", domain);
+    context.insert("disk", disk);
+    let xml = tera::Tera::one_off(QEMU_DOMAIN_TEMPLATE, &context, false)
+        .map_err(|e| failure::format_err!("Unable to render libvirt domain XML: {}", e))?;
+    let xml_file = format!("/tmp/{}.xml", domain);
+    shell.run(cmd!(
+        "echo {} > {}",
+        spurs_util::escape_for_bash(&xml),
+        xml_file
+    ))?;
+
+    // Redefine the domain so that any changes to the memory or cores take effect.
+    shell.run(cmd!("sudo virsh undefine {}", domain).allow_error())?;
+    with_shell! { shell =>
+        cmd!("sudo virsh define {}", xml_file),
+        cmd!("sudo virsh start {}", domain),
+        cmd!("sudo virsh vcpupin {}", domain),
+    }
+
+    // QEMU listens on the forwarded port immediately, so wait for the guest's SSH server instead.
+    shell.run(
+        cmd!(
+            "timeout {} bash -c 'until ssh-keyscan -p {} localhost 2>/dev/null | grep -q ssh ; \
+             do sleep 5 ; done'",
+            QEMU_TIMEOUT,
+            config.ssh_port().unwrap_or(VAGRANT_PORT)
+        )
+        .use_bash(),
+    )?;
+
+    Ok(())
+}
+
+/// Export the shared directories of `config` from the host via NFS and mount them in the guest,
+/// as vagrant does. Guest traffic using user-mode networking comes from the host's loopback
+/// address and unprivileged ports, hence `insecure`.
+fn qemu_mount_shared_dirs(
+    ushell: &SshShell,
+    vshell: &SshShell,
+    config: &VmConfig,
+) -> Result<(), failure::Error> {
+    let user_home = crate::common::get_user_home_dir(ushell)?;
+    let uid = ushell.run(cmd!("id -u"))?.stdout.trim().to_owned();
+    let gid = ushell.run(cmd!("id -g"))?.stdout.trim().to_owned();
+
+    let shared_dirs: Vec<_> = config
+        .shared_dirs
+        .iter()
+        .map(|dir| (dir.host_path(&user_home), dir.guest.clone()))
+        .collect();
+
+    let exports: String = shared_dirs
+        .iter()
+        .map(|(host_dir, _)| {
+            format!(
+                "{} 127.0.0.1(rw,no_subtree_check,all_squash,insecure,anonuid={},anongid={})\n",
+                host_dir, uid, gid
+            )
+        })
+        .collect();
+
+    with_shell! { ushell =>
+        cmd!("sudo mkdir -p /etc/exports.d"),
+        cmd!(
+            "echo {} | sudo tee {}",
+            spurs_util::escape_for_bash(&exports),
+            QEMU_EXPORTS_FILE
+        ),
+        cmd!("sudo exportfs -ra"),
+    }
+
+    for (host_dir, guest_dir) in shared_dirs.iter() {
+        with_shell! { vshell =>
+            cmd!("mkdir -p {}", guest_dir),
+            cmd!("mountpoint -q {} || mount -t nfs -o vers=3,tcp {}:{} {}",
+                 guest_dir, QEMU_USER_NET_HOST, host_dir, guest_dir),
+        }
+    }
+
+    Ok(())
+}
+
+/// Shut down all VMs started by the `qemu` backend that are running. If a guest does not shut
+/// down cleanly in time, it is forcibly stopped.
+fn qemu_halt(shell: &SshShell) -> Result<(), failure::Error> {
+    let running = shell
+        .run(cmd!("sudo virsh list --name"))?
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|domain| domain.starts_with(QEMU_DOMAIN))
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    for domain in running.iter() {
+        shell.run(cmd!("sudo virsh shutdown {}", domain))?;
+    }
+
+    for domain in running.iter() {
+        if wait_for_shutoff(shell, domain).is_err() {
+            shell.run(cmd!("sudo virsh destroy {}", domain))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the amount of free swap space on the host in KB. Thinly-provisioned swap devices
+/// report a (fake) huge size, so for them we use the free space in the thin pool instead.
+pub fn available_swap_kb(shell: &SshShell) -> Result<usize, failure::Error> {
+    let swaps = shell.run(cmd!("cat /proc/swaps"))?.stdout;
+
+    // The thin swap device (if `setup00000` created one) shows up in `/proc/swaps` as some
+    // `/dev/dm-N`, like any other device-mapper device (e.g. LVM swap), so find out which one.
+    let settings = crate::common::get_remote_research_settings(shell)?;
+    let thin_dev =
+        if crate::common::get_remote_research_setting::<String>(&settings, "dm-data")?.is_some() {
+            Some(
+                shell
+                    .run(cmd!("readlink -f /dev/mapper/mythin"))?
+                    .stdout
+                    .trim()
+                    .to_owned(),
+            )
+        } else {
+            None
+        };
+
+    let mut available = 0;
+    let mut counted_thin_pool = false;
+
+    // Skip the header line. Each line is `Filename Type Size Used Priority`.
+    for line in swaps.lines().skip(1) {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            continue;
+        }
+
+        let is_thin = match &thin_dev {
+            Some(thin_dev) => fields[0] == thin_dev || fields[0] == "/dev/mapper/mythin",
+            None => false,
+        };
+
+        if is_thin {
+            // All thin devices share the pool, so only count its free space once.
+            if !counted_thin_pool {
+                available += thin_pool_free_kb(shell)?;
+                counted_thin_pool = true;
+            }
+        } else {
+            let size = fields[2].parse::<usize>()?;
+            let used = fields[3].parse::<usize>()?;
+            available += size - used;
+        }
+    }
+
+    Ok(available)
+}
+
+/// Returns the amount of free data space in the thin pool `mypool` in KB.
+fn thin_pool_free_kb(shell: &SshShell) -> Result<usize, failure::Error> {
+    // The output looks like `0 <len> thin-pool <txid> <used>/<total meta> <used>/<total data> ...`
+    let status = shell.run(cmd!("sudo dmsetup status mypool"))?.stdout;
+    let data = status
+        .split_whitespace()
+        .nth(5)
+        .ok_or_else(|| failure::format_err!("Unable to parse thin pool status: {}", status))?;
+    let mut data = data.split('/');
+    let used = data.next().unwrap().parse::<usize>()?;
+    let total = data
+        .next()
+        .ok_or_else(|| failure::format_err!("Unable to parse thin pool status: {}", status))?
+        .parse::<usize>()?;
+
+    // Blocks are `THIN_POOL_BLOCK_SECTORS` 512B sectors.
+    Ok((total - used) * THIN_POOL_BLOCK_SECTORS / 2)
+}
+
+/// Check that the host has enough available memory and swap space to back a VM with `memgb` GB
+/// of memory. Otherwise, the guest will be OOM killed or thrash partway through the experiment,
+/// which is much harder to diagnose than this error.
+pub fn check_swap_capacity(shell: &SshShell, memgb: usize) -> Result<(), failure::Error> {
+    let swap_kb = available_swap_kb(shell)?;
+    let mem_kb = shell
+        .run(cmd!("grep MemAvailable /proc/meminfo | awk '{{print $2}}'").use_bash())?
+        .stdout
+        .trim()
+        .parse::<usize>()?;
+
+    let needed_kb = memgb << 20;
+
+    if swap_kb + mem_kb < needed_kb {
+        return Err(failure::format_err!(
+            "Insufficient swap space for a {}GB VM: {}KB available memory + {}KB available swap \
+             < {}KB needed. Add more swap devices (or a larger thin pool) or use a smaller VM.",
+            memgb,
+            mem_kb,
+            swap_kb,
+            needed_kb
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check from inside the guest that it has the requested amount of memory and number of vCPUs.
+/// If not (e.g. because the edits to the Vagrantfile did not take effect), return an error with
+/// the relevant parts of the libvirt domain XML and the Vagrantfile.
+///
+/// The guest kernel reserves some memory for itself, so a guest with at least 85% of the
+/// requested memory is considered correct.
+pub fn verify_guest_resources(
+    shell: &SshShell,
+    vshell: &SshShell,
+    memgb: usize,
+    cores: usize,
+) -> Result<(), failure::Error> {
+    let guest_cores = vshell.run(cmd!("nproc"))?.stdout.trim().parse::<usize>()?;
+    let guest_mem_kb = vshell
+        .run(cmd!("grep MemTotal /proc/meminfo | awk '{{print $2}}'").use_bash())?
+        .stdout
+        .trim()
+        .parse::<usize>()?;
+
+    let requested_mem_kb = memgb << 20;
+    let mem_ok = guest_mem_kb <= requested_mem_kb && guest_mem_kb >= requested_mem_kb / 100 * 85;
+
+    if guest_cores == cores && mem_ok {
+        return Ok(());
+    }
+
+    let (domain, _) = virsh_domain_name(shell)?;
+    let xml = shell
+        .run(
+            cmd!(
+                "sudo virsh dumpxml {} | grep -E '<(memory|currentMemory|vcpu)'",
+                domain
+            )
+            .use_bash()
+            .allow_error(),
+        )?
+        .stdout;
+    let vagrantfile = shell
+        .run(
+            cmd!("grep -E 'libvirt\\.(memory|cpus)' Vagrantfile")
+                .cwd(dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY))
+                .allow_error(),
+        )?
+        .stdout;
+
+    Err(failure::format_err!(
+        "The guest does not have the requested resources.\n\
+         Requested: {} vCPUs, {}KB memory\n\
+         Guest has: {} vCPUs, {}KB memory\n\
+         libvirt domain {}:\n{}\n\
+         Vagrantfile:\n{}",
+        cores,
+        requested_mem_kb,
+        guest_cores,
+        guest_mem_kb,
+        domain,
+        xml.trim(),
+        vagrantfile.trim()
+    ))
 }
 
 /// Turn off soft lockup and NMI watchdogs if possible in the shell.
@@ -477,14 +1264,15 @@ fn create_and_turn_on_thin_swap_inner(
         // - `mapper_device_size` is the end sector of the pool. This should be the size of the data device.
         // - `loopback` is the metadata device
         // - `mapper_device` is the data device
-        // - 256000 = 128MB is the block size
+        // - `THIN_POOL_BLOCK_SECTORS` is the block size
         // - 0 indicates no dm event on low-watermark
         shell.run(cmd!(
             "sudo dmsetup create mypool --table \
-             '0 {} thin-pool {} {} 256000 0'",
+             '0 {} thin-pool {} {} {} 0'",
             mapper_device_size,
             loopback,
             data_dev,
+            THIN_POOL_BLOCK_SECTORS,
         ))?;
 
         if new {
@@ -688,51 +1476,229 @@ pub fn virsh_vcpupin(
     Ok(())
 }
 
-/// Generate a Vagrantfile for a VM with the given amount of memory and number of cores. A
-/// Vagrantfile should already exist containing the correct domain name.
-pub fn gen_vagrantfile(shell: &SshShell, memgb: usize, cores: usize) -> Result<(), failure::Error> {
-    let vagrant_path = &format!("{}/{}", RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
+/// Periodically captures screenshots of the guest console via `virsh screenshot` in the
+/// background. This is useful for post-mortem debugging of a guest that hangs during a workload.
+pub struct ConsoleScreenshots {
+    /// Keeps the capture loop running. The loop also stops if this is dropped without calling
+    /// `stop` (e.g. because the workload failed).
+    marker: LoopMarker,
 
-    // Keep the same VM domain name though...
-    let current_name =
-        shell.run(cmd!("grep -oE ':test_vm[0-9a-zA-Z_]+' Vagrantfile").cwd(vagrant_path))?;
-    let current_name = current_name.stdout.trim();
+    /// The shell running the capture loop.
+    handle: (SshShell, SshSpawnHandle),
+}
+
+impl ConsoleScreenshots {
+    /// Start capturing a screenshot of the running VM's console every `interval` seconds into the
+    /// directory `dir` on the host, which is created if needed. Requires `sudo`.
+    pub fn start(shell: &SshShell, interval: usize, dir: &str) -> Result<Self, failure::Error> {
+        let (domain, _) = virsh_domain_name(shell)?;
+
+        shell.run(cmd!("mkdir -p {}", dir))?;
+        let marker = LoopMarker::create(shell, "screenshots")?;
+
+        let handle = shell.spawn(
+            cmd!(
+                "while [ -e {} ] ; do \
+                 sudo virsh screenshot {} {}/screenshot-$(date +%s).ppm ; \
+                 sleep {} ; \
+                 done ; \
+                 echo screenshots done",
+                marker.path(),
+                domain,
+                dir,
+                interval,
+            )
+            .use_bash(),
+        )?;
 
-    with_shell! { shell in vagrant_path =>
-        cmd!("cp Vagrantfile.bk Vagrantfile"),
-        cmd!("sed -i 's/:test_vm/{}/' Vagrantfile", current_name),
-        cmd!("sed -i 's/memory = 1023/memory = {}/' Vagrantfile", memgb),
-        cmd!("sed -i 's/cpus = 1/cpus = {}/' Vagrantfile", cores),
+        Ok(ConsoleScreenshots { marker, handle })
     }
 
-    let user_home = crate::common::get_user_home_dir(shell)?;
-    let vagrant_full_path = &format!("{}/{}", user_home, vagrant_path).replace("/", r#"\/"#);
-    let vm_shared_full_path = &format!(
-        "{}/{}",
-        user_home,
-        crate::common::setup00000::HOSTNAME_SHARED_DIR
-    )
-    .replace("/", r#"\/"#);
-    let research_workspace_full_path =
-        &format!("{}/{}", user_home, RESEARCH_WORKSPACE_PATH).replace("/", r#"\/"#);
+    /// Stop capturing screenshots and wait for the capture loop to exit.
+    pub fn stop(mut self) -> Result<(), failure::Error> {
+        self.marker.remove()?;
+        (self.handle.1).join()?;
+        Ok(())
+    }
+}
 
-    with_shell! { shell in vagrant_path =>
-        cmd!(
-            r#"sed -i 's/vagrant_dir = ''/vagrant_dir = "{}"/' Vagrantfile"#,
-            vagrant_full_path
-        ),
-        cmd!(
-            r#"sed -i 's/vm_shared_dir = ''/vm_shared_dir = "{}"/' Vagrantfile"#,
-            vm_shared_full_path
-        ),
-        cmd!(
-            r#"sed -i 's/zerosim_workspace_dir = ''/zerosim_workspace_dir = "{}"/' Vagrantfile"#,
-            research_workspace_full_path
-        ),
+/// The template from which Vagrantfiles are rendered. It is compiled into the runner so that the
+/// Vagrantfile always matches the version of the runner that generated it.
+const VAGRANTFILE_TEMPLATE: &str = include_str!("../../../vagrant/Vagrantfile.tera");
+
+/// The template from which the libvirt domain XML of the `qemu` backend is rendered.
+const QEMU_DOMAIN_TEMPLATE: &str = include_str!("../../../vagrant/zerosim-guest.xml.tera");
+
+/// A directory on the host shared with the guest via NFS.
+#[derive(Clone, Debug, Serialize)]
+pub struct SharedDir {
+    /// The path on the host. Relative paths are relative to the user's home directory.
+    pub host: String,
+
+    /// The absolute path in the guest at which to mount the directory.
+    pub guest: String,
+}
+
+impl SharedDir {
+    /// Returns the absolute path of the directory on the host, given the user's home directory.
+    fn host_path(&self, user_home: &str) -> String {
+        if self.host.starts_with('/') {
+            self.host.clone()
+        } else {
+            format!("{}/{}", user_home, self.host)
+        }
     }
+}
+
+/// A network interface of the guest.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind")]
+pub enum VmNic {
+    /// A NIC bridged to the given host interface, giving the guest access to the host's network.
+    /// If `iface` is `None`, the interface with a route to the internet is used. Only supported
+    /// by the vagrant backend.
+    Bridge { iface: Option<String> },
+
+    /// Forward `host_port` on the host to the guest's SSH port.
+    SshForward { host_port: u16 },
+}
+
+/// The configuration of the VM, from which the Vagrantfile (or, for the `qemu` backend, the libvirt
+/// domain XML) is rendered. `VmConfig::new` has the configuration that all experiments use; VMs
+/// started together with `start_vms` are pinned to different host CPUs with `pin_from`.
+#[derive(Clone, Debug)]
+pub struct VmConfig {
+    /// The amount of memory of the VM in GB.
+    pub mem_gb: usize,
+
+    /// The number of vCPUs of the VM.
+    pub cores: usize,
+
+    /// For `(v, p)` in `vcpu_pinning`, vCPU `v` is pinned to host CPU `p`.
+    pub vcpu_pinning: HashMap<usize, usize>,
+
+    /// Directories shared with the guest.
+    pub shared_dirs: Vec<SharedDir>,
+
+    /// The network interfaces of the guest, in order.
+    pub nics: Vec<VmNic>,
+
+    /// Extra arguments passed to QEMU.
+    pub extra_qemu_args: Vec<String>,
+}
+
+impl VmConfig {
+    /// The default configuration of a VM with the given amount of memory and number of vCPUs for
+    /// the selected `VmBackend`: vCPU `i` is pinned to host CPU `i`, the shared results directory
+    /// and the workspace are shared with the guest, and the guest's SSH port is forwarded from
+    /// `VAGRANT_PORT`.
+    pub fn new(mem_gb: usize, cores: usize) -> Self {
+        let mut nics = vec![];
+        if vm_backend() == VmBackend::Vagrant {
+            nics.push(VmNic::Bridge { iface: None });
+        }
+        nics.push(VmNic::SshForward {
+            host_port: VAGRANT_PORT,
+        });
+
+        VmConfig {
+            mem_gb,
+            cores,
+            vcpu_pinning: (0..cores).map(|c| (c, c)).collect(),
+            shared_dirs: vec![
+                SharedDir {
+                    host: setup00000::HOSTNAME_SHARED_DIR.into(),
+                    guest: setup00000::VAGRANT_SHARED_DIR.into(),
+                },
+                SharedDir {
+                    host: RESEARCH_WORKSPACE_PATH.into(),
+                    guest: format!("/home/vagrant/{}", RESEARCH_WORKSPACE_PATH),
+                },
+            ],
+            nics,
+
+            // HACK: libvirt has no way to specify phys-bits, so we just force it to pass QEMU the
+            // -cpu flag twice. QEMU happens to take the second one.
+            extra_qemu_args: vec!["-cpu".into(), "Skylake-Client,phys-bits=48".into()],
+        }
+    }
+
+    /// Pin vCPU `i` to host CPU `first_cpu + i` for all vCPUs, e.g. so that several VMs on the
+    /// same host use different CPUs.
+    pub fn pin_from(mut self, first_cpu: usize) -> Self {
+        self.vcpu_pinning = (0..self.cores).map(|c| (c, first_cpu + c)).collect();
+        self
+    }
+
+    /// Forward the guest's SSH port from `port` on the host instead of the default.
+    pub fn forward_ssh_from(mut self, port: u16) -> Self {
+        self.nics
+            .retain(|nic| !matches!(nic, VmNic::SshForward { .. }));
+        self.nics.push(VmNic::SshForward { host_port: port });
+        self
+    }
+
+    /// Returns the port on the host from which the guest's SSH port is forwarded, if any.
+    pub fn ssh_port(&self) -> Option<u16> {
+        self.nics.iter().find_map(|nic| match nic {
+            VmNic::SshForward { host_port } => Some(*host_port),
+            _ => None,
+        })
+    }
+
+    /// Returns the context for rendering the Vagrantfile or domain XML templates, with all paths
+    /// made absolute and all bridged interfaces resolved.
+    fn template_context(&self, shell: &SshShell) -> Result<tera::Context, failure::Error> {
+        let user_home = crate::common::get_user_home_dir(shell)?;
+
+        let shared_dirs: Vec<_> = self
+            .shared_dirs
+            .iter()
+            .map(|dir| SharedDir {
+                host: dir.host_path(&user_home),
+                guest: dir.guest.clone(),
+            })
+            .collect();
+
+        let mut nics = vec![];
+        for nic in self.nics.iter() {
+            nics.push(match nic {
+                VmNic::Bridge { iface: None } => VmNic::Bridge {
+                    iface: Some(default_iface(shell)?),
+                },
+                nic => nic.clone(),
+            });
+        }
+
+        let mut vcpu_pinning: Vec<_> = self.vcpu_pinning.iter().collect();
+        vcpu_pinning.sort();
+        let vcpu_pinning: Vec<_> = vcpu_pinning
+            .into_iter()
+            .map(|(vcpu, cpu)| serde_json::json!({ "vcpu": vcpu, "cpu": cpu }))
+            .collect();
+
+        let mut context = tera::Context::new();
+        context.insert("mem_gb", &self.mem_gb);
+        context.insert("cores", &self.cores);
+        context.insert("vcpu_pinning", &vcpu_pinning);
+        context.insert("shared_dirs", &shared_dirs);
+        context.insert("nics", &nics);
+        context.insert("extra_qemu_args", &self.extra_qemu_args);
+        context.insert(
+            "vagrant_dir",
+            &format!(
+                "{}/{}/{}",
+                user_home, RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY
+            ),
+        );
+
+        Ok(context)
+    }
+}
 
-    // Choose the interface that actually gives network access. We do this by looking for the
-    // interface that gives a route 1.1.1.1 (Cloudflare DNS).
+/// Returns the host interface that actually gives network access. We do this by looking for the
+/// interface that gives a route 1.1.1.1 (Cloudflare DNS).
+fn default_iface(shell: &SshShell) -> Result<String, failure::Error> {
     let iface = shell.run(
         cmd!(
             r#"/usr/sbin/ip route get 1.1.1.1 |\
@@ -741,15 +1707,44 @@ pub fn gen_vagrantfile(shell: &SshShell, memgb: usize, cores: usize) -> Result<(
         )
         .use_bash(),
     )?;
-    let iface = iface.stdout.trim();
+    Ok(iface.stdout.trim().to_owned())
+}
+
+/// Returns the name of the VM domain in the current Vagrantfile (e.g. `test_vm_abcdef0123`).
+pub fn current_vagrant_domain(shell: &SshShell) -> Result<String, failure::Error> {
+    let vagrant_path = &format!("{}/{}", RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
+    let current_name =
+        shell.run(cmd!("grep -oE ':test_vm[0-9a-zA-Z_]+' Vagrantfile").cwd(vagrant_path))?;
+    Ok(current_name
+        .stdout
+        .trim()
+        .trim_start_matches(':')
+        .to_owned())
+}
+
+/// Render the Vagrantfile template for the VM described by `config` with the given domain name,
+/// and write it to the `vagrant` subdirectory of the workspace.
+pub fn write_vagrantfile(
+    shell: &SshShell,
+    domain: &str,
+    config: &VmConfig,
+) -> Result<(), failure::Error> {
+    let vagrant_path = &format!("{}/{}", RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
+
+    let mut context = config.template_context(shell)?;
+    context.insert("domain", domain);
+
+    let vagrantfile = tera::Tera::one_off(VAGRANTFILE_TEMPLATE, &context, false)
+        .map_err(|e| failure::format_err!("Unable to render Vagrantfile: {}", e))?;
 
     shell.run(
         cmd!(
-            r#"sed -i 's/iface = "eno1"/iface = "{}"/' Vagrantfile"#,
-            iface
+            "echo {} > Vagrantfile",
+            spurs_util::escape_for_bash(&vagrantfile)
         )
         .cwd(vagrant_path),
     )?;
+
     Ok(())
 }
 
@@ -795,7 +1790,7 @@ pub fn set_kernel_boot_param(
     ))?;
 
     // Rebuild grub conf
-    shell.run(cmd!("sudo grub2-mkconfig -o /boot/grub2/grub.cfg"))?;
+    shell.run(crate::common::distro::Distro::detect(shell)?.update_grub())?;
 
     // Sync to help avoid corruption
     shell.run(cmd!("sync"))?;
@@ -807,7 +1802,9 @@ pub fn set_kernel_boot_param(
 /// simulation.
 ///
 /// `sim_file` should be just the file name, not the directory path. This function will cause the
-/// output to be in the standard locations.
+/// output to be in the standard locations. In addition to the raw dump in `sim_file`, the parsed
+/// `SimStats` are saved as JSON in `<sim_file>.json`. If the `DriftReport` of the stats fails, the
+/// run is marked suspect by also writing the report to `<sim_file>.suspect`.
 ///
 /// Requires `sudo`.
 pub fn gen_standard_sim_output(
@@ -863,6 +1860,33 @@ pub fn gen_standard_sim_output(
     ))?;
     vshell.run(cmd!("dmesg >> {}", guest_sim_file))?;
 
+    let stats = crate::common::sim_stats::SimStats::collect(ushell, vshell)?;
+    ushell.run(cmd!(
+        "echo {} > {}.json",
+        spurs_util::escape_for_bash(&serde_json::to_string(&stats)?),
+        host_sim_file
+    ))?;
+
+    ushell.run(cmd!("echo -e '\nTime Drift\n=====' >> {}", host_sim_file))?;
+    ushell.run(cmd!(
+        "echo {} >> {}",
+        spurs_util::escape_for_bash(&stats.drift.summary()),
+        host_sim_file
+    ))?;
+
+    // Flag the run as suspect so that it stands out in the index.
+    if stats.drift.verdict == crate::common::drift::DriftVerdict::Fail {
+        println!(
+            "WARNING: {} time-drift anomalies found; marking the run suspect",
+            stats.drift.anomalies.len()
+        );
+        ushell.run(cmd!(
+            "echo {} > {}.suspect",
+            spurs_util::escape_for_bash(&stats.drift.summary()),
+            host_sim_file
+        ))?;
+    }
+
     vshell.run(cmd!("sync"))?;
     ushell.run(cmd!("sync"))?;
 
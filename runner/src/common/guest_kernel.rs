@@ -0,0 +1,199 @@
+//! Selecting the kernel the guest runs during an experiment.
+//!
+//! Normally, the guest runs whichever kernel `setup00000` installed (see `KERNEL_RECENT_TARBALL`).
+//! With `--guest_kernel`, `start_vm` makes sure that the guest runs the requested kernel before the
+//! experiment starts: if it doesn't, the kernel is installed in the guest (building it on the host
+//! with `build_kernel` first, if needed), and the VM is restarted to boot into it.
+
+use std::cell::{Cell, RefCell};
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+use crate::common::{get_user_home_dir, paths::setup00000::*};
+
+/// The kernel to run in the guest.
+#[derive(Clone, Debug, Serialize)]
+pub enum GuestKernel {
+    /// A mainline kernel version (e.g. `5.4.1`). If no RPM of it was built on the host before, it
+    /// is built from the tarball on kernel.org with the config of the guest.
+    Version(String),
+
+    /// The path of a kernel RPM on the host.
+    Rpm(String),
+}
+
+impl std::str::FromStr for GuestKernel {
+    type Err = failure::Error;
+
+    /// Parse either a kernel version (e.g. `5.4.1`) or the path of an RPM (e.g.
+    /// `rpmbuild/RPMS/x86_64/kernel-5.4.1-1.x86_64.rpm`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.ends_with(".rpm") {
+            Ok(GuestKernel::Rpm(s.into()))
+        } else if !s.is_empty()
+            && s.starts_with(|c: char| c.is_ascii_digit())
+            && s.chars().all(|c| c.is_ascii_digit() || c == '.')
+        {
+            Ok(GuestKernel::Version(s.into()))
+        } else {
+            Err(failure::format_err!(
+                "Expected a kernel version (e.g. 5.4.1) or the path of an RPM, not {:?}",
+                s
+            ))
+        }
+    }
+}
+
+thread_local! {
+    /// The kernel requested with `--guest_kernel`, if any.
+    static GUEST_KERNEL: RefCell<Option<GuestKernel>> = const { RefCell::new(None) };
+
+    /// True if the requested kernel was just installed and the VM is being restarted to boot it.
+    static REBOOTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run the guest with the given kernel for the rest of the run, or with whichever kernel is
+/// installed if `None`.
+pub fn set(kernel: Option<GuestKernel>) {
+    GUEST_KERNEL.with(|k| *k.borrow_mut() = kernel);
+}
+
+/// Returns the kernel requested for the guest, if any.
+pub fn get() -> Option<GuestKernel> {
+    GUEST_KERNEL.with(|k| k.borrow().clone())
+}
+
+/// Returns true if the VM is being restarted to boot into a kernel that was just installed. The
+/// guest disk must not be restored from its snapshot in that case, since that would discard the
+/// new kernel.
+pub fn rebooting() -> bool {
+    REBOOTING.with(|r| r.get())
+}
+
+/// Make sure that the guest runs the requested kernel, if any. `vrshell` must be a root shell in
+/// the guest. If the kernel needs to be installed, it is installed and made the default, and
+/// `true` is returned: the caller must then restart the VM to boot into it. Returns an error if
+/// the guest still doesn't run the kernel after such a restart.
+pub fn install_if_needed(ushell: &SshShell, vrshell: &SshShell) -> Result<bool, failure::Error> {
+    let kernel = match get() {
+        Some(kernel) => kernel,
+        None => return Ok(false),
+    };
+
+    let rebooting = REBOOTING.with(|r| r.replace(false));
+    let running = vrshell.run(cmd!("uname -r"))?.stdout.trim().to_owned();
+
+    // Avoid building the kernel if the guest already runs the requested version.
+    if let GuestKernel::Version(version) = &kernel {
+        if running == *version || running.starts_with(&format!("{}-", version)) {
+            return Ok(false);
+        }
+    }
+
+    let kernel_rpm = match &kernel {
+        GuestKernel::Version(version) => guest_kernel_rpm_for_version(ushell, vrshell, version)?,
+        GuestKernel::Rpm(path) => copy_rpm_to_shared_dir(ushell, path)?,
+    };
+    let kernel_rpm_path = dir!(VAGRANT_SHARED_DIR, kernel_rpm.as_str());
+
+    // The release of a kernel RPM is the suffix of the `vmlinuz` it installs.
+    let vmlinuz = vrshell
+        .run(cmd!("rpm -qlp {} | grep '^/boot/vmlinuz-'", kernel_rpm_path).use_bash())?
+        .stdout;
+    let vmlinuz = vmlinuz.trim();
+    let release = vmlinuz.trim_start_matches("/boot/vmlinuz-");
+
+    if running == release {
+        return Ok(false);
+    }
+
+    if rebooting {
+        return Err(failure::format_err!(
+            "The guest did not boot into kernel {} (running {})",
+            release,
+            running
+        ));
+    }
+
+    println!(
+        "Installing kernel {} in the guest (running {})",
+        release, running
+    );
+
+    crate::setup00000::install_guest_kernel(vrshell, &kernel_rpm)?;
+
+    // The new kernel is not necessarily the newest one installed.
+    vrshell.run(cmd!("grubby --set-default {}", vmlinuz))?;
+    vrshell.run(cmd!("sync"))?;
+
+    REBOOTING.with(|r| r.set(true));
+
+    Ok(true)
+}
+
+/// Returns the name of an RPM of the given kernel version in the shared directory, building it on
+/// the host first if there isn't one in `~/rpmbuild` already.
+fn guest_kernel_rpm_for_version(
+    ushell: &SshShell,
+    vrshell: &SshShell,
+    version: &str,
+) -> Result<String, failure::Error> {
+    let user_home = get_user_home_dir(ushell)?;
+
+    let built = ushell
+        .run(
+            cmd!(
+                "ls -Art {}/rpmbuild/RPMS/x86_64/ | grep -v headers | \
+                 grep 'kernel-{}-' | tail -n 1",
+                user_home,
+                version
+            )
+            .use_bash()
+            .allow_error(),
+        )?
+        .stdout;
+    let built = built.trim();
+
+    if !built.is_empty() {
+        return copy_rpm_to_shared_dir(
+            ushell,
+            &dir!(user_home.as_str(), "rpmbuild/RPMS/x86_64", built),
+        );
+    }
+
+    // Build with the config of the guest, like `setup00000` does.
+    let guest_config = vrshell
+        .run(cmd!("ls -1 /boot/config-* | head -n1").use_bash())?
+        .stdout;
+    let guest_config = guest_config.trim();
+    vrshell.run(cmd!("cp {} {}", guest_config, VAGRANT_SHARED_DIR))?;
+
+    let guest_config_base_name = guest_config.trim_start_matches("/boot/");
+
+    let major = version.split('.').next().unwrap();
+    let tarball = format!(
+        "https://cdn.kernel.org/pub/linux/kernel/v{}.x/linux-{}.tar.xz",
+        major, version
+    );
+
+    crate::setup00000::build_guest_kernel(ushell, &tarball, guest_config_base_name, None)
+}
+
+/// Copy the kernel RPM at `path` on the host to the shared directory, so that the guest can
+/// install it. Returns the name of the RPM.
+fn copy_rpm_to_shared_dir(ushell: &SshShell, path: &str) -> Result<String, failure::Error> {
+    let user_home = get_user_home_dir(ushell)?;
+
+    let kernel_rpm = path
+        .rsplit('/')
+        .next()
+        .expect("rsplit returns at least one item");
+    let shared_rpm = dir!(user_home.as_str(), HOSTNAME_SHARED_DIR, kernel_rpm);
+
+    // The RPM may already be in the shared directory.
+    ushell.run(cmd!("[ -e {} ] || cp {} {}", shared_rpm, path, shared_rpm).use_bash())?;
+
+    Ok(kernel_rpm.into())
+}
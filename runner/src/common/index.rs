@@ -0,0 +1,125 @@
+//! An index of all runs in a results directory.
+//!
+//! Finding the output of a particular run used to mean grepping through `.params` files. After
+//! every experiment, `append` adds a record describing the run to `index.jsonl` in the results
+//! directory, one JSON object per line, which `runner results` can then query.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::{
+    output::{OutputManager, Progress},
+    paths::setup00000::HOSTNAME_SHARED_RESULTS_DIR,
+};
+
+/// The name of the index file in the results directory.
+pub const INDEX_FILE: &str = "index.jsonl";
+
+/// A record of a single run in the index.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexRecord {
+    /// When the run finished, in RFC 3339 format.
+    pub timestamp: String,
+
+    /// The experiment that was run (e.g. `exp00000`).
+    pub experiment: String,
+
+    /// The hash of the settings of the run, which is the same for runs with the same
+    /// configuration. See `OutputManager::settings_hash`.
+    pub settings_hash: String,
+
+    /// The git hash of the workspace on the machine running the runner.
+    pub local_git_hash: Option<String>,
+
+    /// The git hash of the workspace on the remote.
+    pub remote_git_hash: Option<String>,
+
+    /// The remote the run was on.
+    pub host: Option<String>,
+
+    /// The name of the `.params` file of the run.
+    pub params_file: String,
+
+    /// The names of all output files of the run, including the params file.
+    pub output_files: Vec<String>,
+
+    /// How long the experiment took, in seconds.
+    pub duration_secs: f64,
+
+    /// Whether the run was marked suspect because of time-drift anomalies (see
+    /// `gen_standard_sim_output`).
+    #[serde(default)]
+    pub suspect: bool,
+}
+
+/// Append a record of the run with the given settings and timers to the index in the results
+/// directory on the remote. This should be called at the very end of the experiment, after all
+/// output files are written.
+pub fn append(
+    ushell: &SshShell,
+    settings: &OutputManager,
+    timers: &Progress,
+) -> Result<(), failure::Error> {
+    let as_string = |setting| {
+        settings
+            .get_json(setting)
+            .and_then(|value| value.as_str().map(String::from))
+    };
+
+    let output_files = ushell
+        .run(
+            cmd!("ls -1 {}", settings.gen_file_name("*"))
+                .cwd(HOSTNAME_SHARED_RESULTS_DIR)
+                .allow_error(),
+        )?
+        .stdout
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let suspect = output_files.iter().any(|file| file.ends_with(".suspect"));
+
+    let record = IndexRecord {
+        timestamp: chrono::offset::Local::now().to_rfc3339(),
+        experiment: timers.experiment().into(),
+        settings_hash: settings.settings_hash(),
+        local_git_hash: as_string("local_git_hash"),
+        remote_git_hash: as_string("remote_git_hash"),
+        host: as_string("host"),
+        params_file: settings.gen_file_name("params"),
+        output_files,
+        duration_secs: timers.elapsed().as_secs_f64(),
+        suspect,
+    };
+
+    ushell.run(
+        cmd!(
+            "echo {} >> {}",
+            spurs_util::escape_for_bash(&serde_json::to_string(&record)?),
+            INDEX_FILE
+        )
+        .cwd(HOSTNAME_SHARED_RESULTS_DIR),
+    )?;
+
+    Ok(())
+}
+
+/// Read all records from the index file at `path`. Lines that cannot be parsed are skipped with a
+/// warning.
+pub fn read(path: &std::path::Path) -> Result<Vec<IndexRecord>, failure::Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| failure::format_err!("Unable to read index {:?}: {}", path, e))?;
+
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                println!("WARNING: skipping line {} of {:?}: {}", i + 1, path, e);
+                None
+            }
+        })
+        .collect())
+}
@@ -1,12 +1,16 @@
 //! Useful macros.
 
-/// Time the given operations and push the time to the given `Vec<(String, Duration)>`.
+/// Announce the phase `$label`, time the given operations, and record the time in the given
+/// `Progress`. `$label` is usually a literal, but can be any string (e.g. to tell apart the phases
+/// of each point of a sweep).
 macro_rules! time {
-    ($timers:ident, $label:literal, $expr:expr) => {{
+    ($timers:ident, $label:expr, $expr:expr) => {{
+        let label = $label;
+        $timers.begin(&label);
         let start = std::time::Instant::now();
         let result = $expr;
         let duration = std::time::Instant::now() - start;
-        $timers.push(($label, duration));
+        $timers.push((label, duration));
         result
     }};
 }
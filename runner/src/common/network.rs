@@ -0,0 +1,88 @@
+//! Preparation and verification of the host's firewall and network for running the VM.
+//!
+//! The VM shares a directory with the host via NFS and is reached via a forwarded SSH port, both
+//! of which break in confusing ways if firewalld or libvirt's networking is misconfigured. All
+//! such preparation lives here so that setup and experiments do the same thing.
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::{
+    distro::Distro,
+    get_user_home_dir,
+    paths::setup00000::{HOSTNAME_SHARED_DIR, VAGRANT_SHARED_DIR},
+    service, ServiceAction,
+};
+
+/// Firewalld services needed for the guest to mount the shared directory via NFS.
+const NFS_FIREWALL_SERVICES: &[&str] = &["nfs", "rpc-bind", "mountd"];
+
+/// Prepare the host's firewall and network for the VM. This is idempotent.
+///
+/// Firewalld causes VM issues, so we disable it. In case it is ever re-enabled, we first open the
+/// ports needed by NFS. When we do that, we need to restart libvirtd so that it recreates its
+/// firewall rules.
+pub fn prepare_host(shell: &SshShell) -> Result<(), failure::Error> {
+    // `firewall-cmd --state` returns 252 if not running.
+    let firewall_up = shell.run(cmd!("sudo firewall-cmd --state")).is_ok();
+    if firewall_up {
+        for svc in NFS_FIREWALL_SERVICES.iter() {
+            shell.run(cmd!("sudo firewall-cmd --permanent --add-service={}", svc))?;
+        }
+        shell.run(cmd!("sudo firewall-cmd --reload"))?;
+        service(shell, "firewalld", ServiceAction::Disable)?;
+    }
+
+    let distro = Distro::detect(shell)?;
+    service(shell, distro.nfs_idmap_service(), ServiceAction::Restart)?;
+    service(shell, "libvirtd", ServiceAction::Restart)?;
+
+    Ok(())
+}
+
+/// Verify that the VM's networking actually works: the SSH port forwarded from `port` is
+/// listening on the host, and the shared directory is mounted in the guest and writes to it are
+/// visible on the host. Returns an error describing the first problem found.
+pub fn verify_guest(ushell: &SshShell, vshell: &SshShell, port: u16) -> Result<(), failure::Error> {
+    // The forwarded port should be listening on the host.
+    let listening = ushell
+        .run(cmd!("sudo ss -ltn | grep -q ':{} '", port).use_bash())
+        .is_ok();
+    if !listening {
+        return Err(failure::format_err!(
+            "The VM's SSH port {} is not being forwarded on the host. Check that libvirtd \
+             is running and that `vagrant up` succeeded.",
+            port
+        ));
+    }
+
+    // The shared directory should be mounted in the guest.
+    let mounted = vshell
+        .run(cmd!("mountpoint -q {}", VAGRANT_SHARED_DIR))
+        .is_ok();
+    if !mounted {
+        return Err(failure::format_err!(
+            "The shared directory is not mounted at {} in the guest. Check that the NFS \
+             server is running on the host and that the firewall allows {:?}.",
+            VAGRANT_SHARED_DIR,
+            NFS_FIREWALL_SERVICES
+        ));
+    }
+
+    // Writes from the guest should be visible on the host.
+    let marker = "network-check";
+    vshell.run(cmd!("date > {}", dir!(VAGRANT_SHARED_DIR, marker)))?;
+    let user_home = get_user_home_dir(ushell)?;
+    let host_marker = dir!(user_home.as_str(), HOSTNAME_SHARED_DIR, marker);
+    let visible = ushell.run(cmd!("test -e {}", host_marker)).is_ok();
+    if !visible {
+        return Err(failure::format_err!(
+            "A file written to {} in the guest is not visible at {} on the host. The shared \
+             directory may be mounted from the wrong place.",
+            VAGRANT_SHARED_DIR,
+            host_marker
+        ));
+    }
+    ushell.run(cmd!("rm -f {}", host_marker))?;
+
+    Ok(())
+}
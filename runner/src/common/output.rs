@@ -3,6 +3,34 @@
 use chrono::{offset::Local, DateTime};
 use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
 
+use spurs::{cmd, Execute, SshShell};
+use spurs_util::escape_for_bash;
+
+use super::paths::setup00000::{HOSTNAME_SHARED_RESULTS_DIR, VAGRANT_RESULTS_DIR};
+
+/// Settings that are expected to differ between otherwise identical runs, and so do not identify
+/// the configuration of a run.
+pub const VOLATILE_SETTINGS: &[&str] = &[
+    "local_git_hash",
+    "remote_git_hash",
+    "runner_invocation",
+    "remote_research_settings",
+    "deadline",
+];
+
+/// The setting recording the unit in which a params file stores sizes. Params files written before
+/// sizes were stored in bytes do not have it, and store the `LEGACY_GB_SETTINGS` in GB instead.
+const SIZE_UNIT_SETTING: &str = "size_unit";
+
+/// The settings that params files without a `SIZE_UNIT_SETTING` store in GB, along with the JSON
+/// pointer to the size within the setting (empty if the setting is the size itself).
+const LEGACY_GB_SETTINGS: &[(&str, &str)] = &[
+    ("vm_size", ""),
+    ("size", ""),
+    ("workload_settings", "/TimeMmapTouch/size"),
+    ("workload_settings", "/Memcached/size"),
+];
+
 /// `OutputManager` manages all things regarding naming and tagging output with settings and
 /// properties of its data.
 ///
@@ -21,13 +49,15 @@ pub struct OutputManager {
 }
 
 impl OutputManager {
-    /// Create a new empty `OutputManager` containing now settings.
+    /// Create a new `OutputManager` containing no settings other than the unit of sizes.
     pub fn new() -> Self {
-        OutputManager {
+        let mut manager = OutputManager {
             settings: std::collections::BTreeMap::new(),
             important: Vec::new(),
             timestamp: Local::now(),
-        }
+        };
+        manager.register(SIZE_UNIT_SETTING, &"bytes", false);
+        manager
     }
 
     /// Register a new setting called `name` with value `value`. The boolean value `important`
@@ -121,6 +151,38 @@ impl OutputManager {
         serde_json::from_str(self.settings.get(setting).expect("no such setting"))
             .expect("unable to deserialize")
     }
+
+    /// Returns the value of setting `setting` as JSON, or `None` if it is not registered.
+    pub fn get_json(&self, setting: &str) -> Option<serde_json::Value> {
+        self.settings
+            .get(setting)
+            .and_then(|value| serde_json::from_str(value).ok())
+    }
+
+    /// Returns a hash of all settings except the `VOLATILE_SETTINGS`, which is the same for any two
+    /// runs with the same configuration. The hash (64-bit FNV-1a) is stable across runner builds.
+    pub fn settings_hash(&self) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+        for (name, value) in self.settings.iter() {
+            if VOLATILE_SETTINGS.contains(&name.as_str()) {
+                continue;
+            }
+
+            for byte in name
+                .bytes()
+                .chain(b"=".iter().copied())
+                .chain(value.bytes())
+            {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+            hash ^= u64::from(b'\n');
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+
+        format!("{:016x}", hash)
+    }
 }
 
 impl Serialize for OutputManager {
@@ -152,6 +214,257 @@ impl<'de> Deserialize<'de> for OutputManager {
     }
 }
 
+/// Read a `.params` file generated by an `OutputManager`, returning each setting's value. Params
+/// files are a map from setting names to JSON-serialized values; values that are not valid JSON
+/// are returned as strings. Sizes in params files that predate `SIZE_UNIT_SETTING` are converted
+/// from GB to bytes, so that sizes are always in bytes.
+pub fn read_params_file(
+    path: &std::path::Path,
+) -> Result<std::collections::BTreeMap<String, serde_json::Value>, failure::Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let raw: std::collections::BTreeMap<String, String> = serde_json::from_str(contents.trim())
+        .map_err(|e| failure::format_err!("Unable to parse params file {:?}: {}", path, e))?;
+
+    let mut settings: std::collections::BTreeMap<_, _> = raw
+        .into_iter()
+        .map(|(k, v)| {
+            let v = serde_json::from_str(&v).unwrap_or(serde_json::Value::String(v));
+            (k, v)
+        })
+        .collect();
+
+    if !settings.contains_key(SIZE_UNIT_SETTING) {
+        for (setting, pointer) in LEGACY_GB_SETTINGS {
+            let size = settings
+                .get_mut(*setting)
+                .and_then(|value| value.pointer_mut(pointer));
+
+            if let Some(size) = size {
+                if let Some(gb) = size.as_u64() {
+                    *size = serde_json::Value::from(gb << 30);
+                }
+            }
+        }
+    }
+
+    Ok(settings)
+}
+
+/// The file on the machine running the runner in which `Progress` remembers how long each phase
+/// of each experiment took, relative to `$HOME`.
+const PHASE_HISTORY_FILE: &str = ".runner-phase-history.json";
+
+/// `Progress` keeps track of the phases of an experiment (e.g. reboot, VM boot, warmup, workload,
+/// collection) as they run.
+///
+/// Experiments announce each phase with the `time!` macro. At the beginning of each phase, its
+/// name is printed along with an estimate of when it will finish, based on how long it took the
+/// last time the experiment ran on this machine. At the end, the time it took is recorded, and
+/// `report` produces a machine-readable summary for the experiment's `time_file`.
+#[derive(Debug)]
+pub struct Progress {
+    /// The name of the experiment (e.g. `exp00000`).
+    experiment: String,
+    /// The duration of each phase the last time the experiment ran, keyed by
+    /// `<experiment>/<phase>`, in seconds.
+    history: std::collections::BTreeMap<String, f64>,
+    /// The time each phase took so far, in order.
+    timings: Vec<(String, std::time::Duration)>,
+    /// When the experiment started.
+    started: std::time::Instant,
+}
+
+impl Progress {
+    /// Start tracking the phases of the given experiment.
+    pub fn new(experiment: &str) -> Self {
+        let history = Self::history_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|history| serde_json::from_str(&history).ok())
+            .unwrap_or_default();
+
+        Progress {
+            experiment: experiment.into(),
+            history,
+            timings: Vec::new(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// The name of the experiment.
+    pub fn experiment(&self) -> &str {
+        &self.experiment
+    }
+
+    /// The number of phases timed so far.
+    pub fn num_phases(&self) -> usize {
+        self.timings.len()
+    }
+
+    /// Forget the timings of all but the first `n` phases (e.g. because those phases are about to
+    /// be rerun).
+    pub fn truncate(&mut self, n: usize) {
+        self.timings.truncate(n);
+    }
+
+    /// How long the experiment has been running, including time outside of any phase.
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.started.elapsed()
+    }
+
+    fn history_path() -> Option<std::path::PathBuf> {
+        std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(PHASE_HISTORY_FILE))
+    }
+
+    fn history_key(&self, phase: &str) -> String {
+        format!("{}/{}", self.experiment, phase)
+    }
+
+    /// Announce the beginning of the given phase, with an estimate of when it will finish if the
+    /// phase has run before.
+    pub fn begin(&self, phase: &str) {
+        match self.history.get(&self.history_key(phase)) {
+            Some(&secs) => {
+                let eta = Local::now() + chrono::Duration::milliseconds((secs * 1000.0) as i64);
+                println!(
+                    "== Phase: {} (took {:.0}s last time; ETA {}) ==",
+                    phase,
+                    secs,
+                    eta.format("%H:%M:%S")
+                );
+            }
+            None => println!("== Phase: {} ==", phase),
+        }
+    }
+
+    /// Record that the given phase finished after `duration`. Takes a tuple so that `Progress`
+    /// can be used like the `Vec` of timings it replaces.
+    pub fn push<S: Into<String>>(&mut self, (phase, duration): (S, std::time::Duration)) {
+        let phase = phase.into();
+        println!("== Phase: {} done in {:?} ==", phase, duration);
+
+        self.history
+            .insert(self.history_key(&phase), duration.as_secs_f64());
+        self.timings.push((phase, duration));
+
+        // Save the history after every phase so that it is not lost if the experiment fails.
+        if let (Some(path), Ok(history)) =
+            (Self::history_path(), serde_json::to_string(&self.history))
+        {
+            let _ = std::fs::write(path, history);
+        }
+    }
+
+    /// Returns a machine-readable (JSON) summary of the phases so far, suitable for the
+    /// `time_file` of the experiment.
+    pub fn report(&self) -> String {
+        let phases: Vec<_> = self
+            .timings
+            .iter()
+            .map(|(phase, duration)| {
+                serde_json::json!({
+                    "phase": phase,
+                    "secs": duration.as_secs_f64(),
+                })
+            })
+            .collect();
+        let total: f64 = self.timings.iter().map(|(_, d)| d.as_secs_f64()).sum();
+
+        serde_json::json!({
+            "experiment": self.experiment,
+            "phases": phases,
+            "total_secs": total,
+        })
+        .to_string()
+    }
+}
+
+/// The most bytes of a file that `OutputSink::write` passes on one command line. Each byte is
+/// escaped as 4 characters, so this keeps commands well under the kernel's `ARG_MAX`.
+const WRITE_CHUNK_BYTES: usize = 16 * 1024;
+
+/// `OutputSink` determines where the output files of an experiment are written.
+///
+/// Experiments should use `OutputSink::auto` to pick the sink based on how they are run, then use
+/// `path` to generate the path of an output file (e.g. to pass to a workload) and `write` to write
+/// files like the params or timing files.
+///
+/// Every sink writes through a remote shell, since the workloads that produce the output files
+/// run remotely too. There is no local or SFTP sink: `spurs` does not expose the SSH session of a
+/// shell, so files cannot be copied back to the machine running the runner.
+pub enum OutputSink<'s> {
+    /// The shared results directory as seen from the guest. Files are written by the guest.
+    Guest { shell: &'s SshShell },
+
+    /// The shared results directory on the host, for when there is no guest (e.g. bare-metal
+    /// runs) or the shared directory is not mounted in the guest. Files are written by the host.
+    Host { shell: &'s SshShell, home: String },
+}
+
+impl<'s> OutputSink<'s> {
+    /// Choose a sink based on the run mode: if there is a guest (`vshell`) and the shared
+    /// directory is mounted in it, write via the guest. Otherwise, write directly on the host.
+    pub fn auto(
+        ushell: &'s SshShell,
+        vshell: Option<&'s SshShell>,
+    ) -> Result<Self, failure::Error> {
+        if let Some(vshell) = vshell {
+            let mounted = vshell.run(cmd!("test -d {}", VAGRANT_RESULTS_DIR)).is_ok();
+
+            if mounted {
+                return Ok(OutputSink::Guest { shell: vshell });
+            }
+        }
+
+        let home = crate::common::get_user_home_dir(ushell)?;
+        ushell.run(cmd!("mkdir -p {}/{}", home, HOSTNAME_SHARED_RESULTS_DIR))?;
+
+        Ok(OutputSink::Host {
+            shell: ushell,
+            home,
+        })
+    }
+
+    /// Returns the full path of the output file with the given name, as seen by whoever writes to
+    /// the sink.
+    pub fn path(&self, file: &str) -> String {
+        match self {
+            OutputSink::Guest { .. } => dir!(VAGRANT_RESULTS_DIR, file),
+            OutputSink::Host { home, .. } => {
+                dir!(home.as_str(), HOSTNAME_SHARED_RESULTS_DIR, file)
+            }
+        }
+    }
+
+    /// Returns the shell that writes to the sink.
+    pub fn shell(&self) -> &'s SshShell {
+        match self {
+            OutputSink::Guest { shell } | OutputSink::Host { shell, .. } => shell,
+        }
+    }
+
+    /// Write `contents` to the output file with the given name, replacing any existing contents.
+    ///
+    /// `contents` may be arbitrarily large and need not be text: every byte is passed to `printf`
+    /// as an escape, in chunks of at most `WRITE_CHUNK_BYTES`. The chunks are written to a
+    /// temporary file, which replaces the output file only once it is complete.
+    pub fn write<C: AsRef<[u8]>>(&self, file: &str, contents: C) -> Result<(), failure::Error> {
+        let path = self.path(file);
+        let tmp = format!("{}.tmp", path);
+        let shell = self.shell();
+
+        shell.run(cmd!(": > {}", tmp))?;
+
+        for chunk in contents.as_ref().chunks(WRITE_CHUNK_BYTES) {
+            let escaped: String = chunk.iter().map(|b| format!("\\x{:02x}", b)).collect();
+            shell.run(cmd!("printf {} >> {}", escape_for_bash(&escaped), tmp).use_bash())?;
+        }
+
+        shell.run(cmd!("mv {} {}", tmp, path))?;
+
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __settings_helper {
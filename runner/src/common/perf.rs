@@ -0,0 +1,171 @@
+//! Running `perf` on the host or guest alongside a workload.
+//!
+//! `PerfSession::start` runs `perf stat` or `perf record` in the background for a fixed amount of
+//! time. `PerfSession::finish` waits for it, copies its output into the results directory, and
+//! optionally generates a `perf report`.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+
+/// The events measured by default. These are the ones we usually care about for 0sim: TLB and
+/// cache behavior, page faults, and what reclamation and KVM are doing.
+pub const DEFAULT_PERF_EVENTS: &[&str] = &[
+    "cycles",
+    "cache-misses",
+    "dTLB-load-misses",
+    "dTLB-store-misses",
+    "page-faults",
+    "context-switches",
+    "vmscan:*",
+    "kvm:*",
+];
+
+/// Which `perf` subcommand to run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PerfMode {
+    /// `perf stat`, printing counts every `interval_ms` (or once at the end).
+    Stat,
+    /// `perf record`, sampling the given events.
+    Record,
+}
+
+impl PerfMode {
+    /// The names of the modes, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["stat", "record"];
+}
+
+impl std::str::FromStr for PerfMode {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stat" => Ok(PerfMode::Stat),
+            "record" => Ok(PerfMode::Record),
+            _ => Err(failure::format_err!("Unknown perf mode: {}", s)),
+        }
+    }
+}
+
+/// Configuration for a `perf` session.
+pub struct PerfConfig<'a> {
+    /// The path of the `perf` binary (e.g. `perf` or one built in the kernel tree).
+    pub perf: &'a str,
+
+    /// Whether to run `perf stat` or `perf record`.
+    pub mode: PerfMode,
+
+    /// The events to measure (e.g. `DEFAULT_PERF_EVENTS`).
+    pub events: &'a [String],
+
+    /// The CPUs to measure (e.g. `0` or `0-3`), or all CPUs if `None`.
+    pub cpus: Option<&'a str>,
+
+    /// For `PerfMode::Stat`, print counts every `interval_ms` milliseconds.
+    pub interval_ms: Option<usize>,
+
+    /// Wait this many milliseconds before starting to measure.
+    pub delay_ms: Option<usize>,
+
+    /// How long to measure in seconds (not counting `delay_ms`).
+    pub duration: usize,
+
+    /// The core to pin `perf` itself to, so that it stays out of the way of the workload.
+    pub pin_core: usize,
+
+    /// The file to write the output to on the remote (usually in the results directory).
+    pub output_file: &'a str,
+}
+
+/// A `perf` session running in the background.
+pub struct PerfSession {
+    /// The shell running `perf`.
+    handle: (SshShell, SshSpawnHandle),
+
+    /// The path of the `perf` binary.
+    perf: String,
+
+    /// Whether this is `perf stat` or `perf record`.
+    mode: PerfMode,
+
+    /// The file `perf` is writing to.
+    tmp_file: String,
+
+    /// The final location of the output.
+    output_file: String,
+}
+
+impl PerfSession {
+    /// Start `perf` in the background. Requires `sudo`.
+    ///
+    /// `perf` writes to a file in `/tmp` rather than directly to `output_file` so that it doesn't
+    /// generate IO on the shared results directory while measuring.
+    pub fn start(shell: &SshShell, cfg: &PerfConfig<'_>) -> Result<Self, failure::Error> {
+        let file_name = cfg
+            .output_file
+            .rsplit('/')
+            .next()
+            .expect("rsplit returns at least one item");
+        let tmp_file = dir!("/tmp", file_name);
+
+        let (subcommand, mode_args) = match cfg.mode {
+            PerfMode::Stat => (
+                "stat",
+                cfg.interval_ms
+                    .map(|interval| format!("-I {}", interval))
+                    .unwrap_or_default(),
+            ),
+            PerfMode::Record => ("record", "-g".to_owned()),
+        };
+
+        let handle = shell.spawn(cmd!(
+            "sudo taskset -c {} {} {} {} {} {} -e '{}' -o {} sleep {}",
+            cfg.pin_core,
+            cfg.perf,
+            subcommand,
+            cfg.cpus
+                .map(|cpus| format!("-C {}", cpus))
+                .unwrap_or_else(|| "-a".to_owned()),
+            mode_args,
+            cfg.delay_ms
+                .map(|delay| format!("-D {}", delay))
+                .unwrap_or_default(),
+            cfg.events.join(","),
+            tmp_file,
+            // `perf` measures `sleep`, including the delay.
+            cfg.duration + cfg.delay_ms.unwrap_or(0) / 1000,
+        ))?;
+
+        Ok(PerfSession {
+            handle,
+            perf: cfg.perf.to_owned(),
+            mode: cfg.mode,
+            tmp_file,
+            output_file: cfg.output_file.to_owned(),
+        })
+    }
+
+    /// Wait for `perf` to finish and copy its output to the output file. If `report_file` is
+    /// given and this is a `perf record` session, also write the output of `perf report --stdio`
+    /// to it.
+    pub fn finish(self, shell: &SshShell, report_file: Option<&str>) -> Result<(), failure::Error> {
+        (self.handle.1).join()?;
+
+        with_shell! { shell =>
+            cmd!("sudo cp {} {}", self.tmp_file, self.output_file),
+            cmd!("sudo chown $(whoami) {}", self.output_file),
+            cmd!("sudo rm -f {}", self.tmp_file),
+        }
+
+        if let (PerfMode::Record, Some(report_file)) = (self.mode, report_file) {
+            shell.run(cmd!(
+                "sudo {} report --stdio -i {} > {}",
+                self.perf,
+                self.output_file,
+                report_file
+            ))?;
+        }
+
+        Ok(())
+    }
+}
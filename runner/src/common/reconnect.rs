@@ -0,0 +1,94 @@
+//! Recovering from dropped SSH connections in the middle of an experiment.
+//!
+//! Long experiments run over flaky links (e.g. a VPN) fail outright if the SSH connection drops.
+//! The number of reconnection attempts is set once at startup via `runner --reconnect N`.
+//! Experiments wrap phases that can safely be rerun in `with_reconnect`: if the connection drops
+//! during such a phase, the shell passed to `with_reconnect` is reconnected and the phase is
+//! retried from its beginning, rather than failing the whole run.
+//!
+//! Only that one shell is protected. Other shells, and anything running over them (e.g. background
+//! telemetry loops), are not reconnected, so a drop that breaks them still fails the run. This is
+//! why experiments give the protected phase a dedicated shell.
+//!
+//! `spurs` uses its own libssh2 sessions rather than the `ssh` binary, and does not expose them, so
+//! there is no OpenSSH `ControlMaster` to multiplex over, no way to set keepalives, and no way to
+//! run over `mosh`; each `SshShell` reconnects independently.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use spurs::{cmd, Execute, SshError, SshShell};
+
+/// The maximum number of times to reconnect during a single phase. 0 means never reconnect.
+static MAX_RECONNECTS: AtomicUsize = AtomicUsize::new(0);
+
+/// How long to wait before the first reconnection attempt. Each subsequent attempt waits twice as
+/// long as the previous one.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Set the maximum number of times to reconnect during a single phase.
+pub fn set_max_reconnects(n: usize) {
+    MAX_RECONNECTS.store(n, Ordering::SeqCst);
+}
+
+/// Returns true if `err` indicates that the connection itself failed, as opposed to a command
+/// exiting with an error.
+pub fn is_connection_error(err: &failure::Error) -> bool {
+    match err.downcast_ref::<SshError>() {
+        Some(SshError::NonZeroExit { .. }) | None => false,
+        Some(_) => true,
+    }
+}
+
+/// Reconnect `shell`, backing off between attempts, until it can run commands again or all
+/// attempts are used up.
+fn reconnect(shell: &mut SshShell, attempts: usize) -> Result<(), failure::Error> {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=attempts {
+        println!(
+            "Reconnecting (attempt {}/{}) in {:?}...",
+            attempt, attempts, backoff
+        );
+        std::thread::sleep(backoff);
+        backoff *= 2;
+
+        if shell.reconnect().is_ok() && shell.run(cmd!("whoami")).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(failure::format_err!(
+        "Unable to reconnect after {} attempts",
+        attempts
+    ))
+}
+
+/// Run the phase `f` with `shell`. If the connection drops during the phase, reconnect and rerun
+/// the phase from the beginning, up to the number of times set by `set_max_reconnects`. `f`
+/// should be safe to rerun after being interrupted partway through.
+pub fn with_reconnect<T, F>(
+    shell: &mut SshShell,
+    label: &str,
+    mut f: F,
+) -> Result<T, failure::Error>
+where
+    F: FnMut(&SshShell) -> Result<T, failure::Error>,
+{
+    let max = MAX_RECONNECTS.load(Ordering::SeqCst);
+    let mut reconnects = 0;
+
+    loop {
+        match f(shell) {
+            Err(ref err) if is_connection_error(err) && reconnects < max => {
+                println!("Connection lost during phase {}: {}", label, err);
+
+                reconnect(shell, max - reconnects)?;
+                reconnects += 1;
+
+                println!("Reconnected. Restarting phase {}.", label);
+            }
+            res => return res,
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Structured statistics about a simulation, collected after it finishes.
+//!
+//! `gen_standard_sim_output` dumps a bunch of `/proc` files and `dmesg` into a free-form text file,
+//! which analysis scripts then have to scrape with regexes. `SimStats` collects the same
+//! information (memory usage, zswap stats, CPU info, and anything alarming in `dmesg`) from the
+//! host and guest into typed fields that are saved as JSON next to the raw dump, along with a
+//! `DriftReport` on the clocks of both.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::drift::DriftReport;
+
+/// Patterns in `dmesg` output that indicate something went wrong during the simulation, along
+/// with the kind of anomaly they indicate.
+const DMESG_ANOMALIES: &[(&str, &str)] = &[
+    ("soft lockup", "soft_lockup"),
+    ("hard LOCKUP", "hard_lockup"),
+    ("rcu_sched self-detected stall", "rcu_stall"),
+    ("rcu_sched detected stalls", "rcu_stall"),
+    ("blocked for more than", "hung_task"),
+    ("Out of memory", "oom"),
+    ("invoked oom-killer", "oom"),
+    // Every boot logs clocksource registration and switching, so only match the watchdog giving up
+    // on an unstable clocksource.
+    ("Marking clocksource", "clocksource"),
+    ("unstable (delta", "clocksource"),
+    ("BUG:", "bug"),
+    ("WARNING:", "warning"),
+    ("Call Trace", "call_trace"),
+];
+
+/// Statistics about the simulation from both the host and the guest.
+#[derive(Debug, Serialize)]
+pub struct SimStats {
+    /// The host running the simulation.
+    pub host: MachineStats,
+
+    /// The simulated guest.
+    pub guest: MachineStats,
+
+    /// The zswap stats of the host from debugfs (e.g. `stored_pages`), if zswap is enabled.
+    pub zswap: BTreeMap<String, u64>,
+
+    /// The contents of `/proc/zerosim_guest_offset` on the host, if it exists.
+    pub zerosim_guest_offset: Option<String>,
+
+    /// Time-drift anomalies during the run, with a pass/fail verdict.
+    pub drift: DriftReport,
+}
+
+/// Statistics about a single machine (host or guest).
+#[derive(Debug, Serialize)]
+pub struct MachineStats {
+    /// The fields of `/proc/meminfo`, in KB (or pages for the `HugePages_*` fields).
+    pub meminfo: BTreeMap<String, u64>,
+
+    /// A summary of `/proc/cpuinfo`.
+    pub cpuinfo: CpuInfo,
+
+    /// Lines of `dmesg` that indicate something went wrong.
+    pub dmesg_anomalies: Vec<DmesgAnomaly>,
+}
+
+/// A summary of `/proc/cpuinfo`.
+#[derive(Debug, Default, Serialize)]
+pub struct CpuInfo {
+    /// The number of logical processors.
+    pub processors: usize,
+
+    /// The model name of the first processor.
+    pub model_name: Option<String>,
+
+    /// The clock speed of each processor in MHz.
+    pub mhz: Vec<f64>,
+
+    /// The feature flags of the first processor.
+    pub flags: Vec<String>,
+}
+
+/// A line of `dmesg` that indicates something went wrong.
+#[derive(Debug, Serialize)]
+pub struct DmesgAnomaly {
+    /// The kind of anomaly (e.g. `soft_lockup` or `oom`).
+    pub kind: &'static str,
+
+    /// The line of `dmesg`.
+    pub line: String,
+}
+
+impl SimStats {
+    /// Collect statistics from the host (`ushell`) and guest (`vshell`). Requires `sudo` on the
+    /// host for the zswap stats.
+    pub fn collect(ushell: &SshShell, vshell: &SshShell) -> Result<Self, failure::Error> {
+        let zswap = ushell
+            .run(cmd!("sudo grep -r . /sys/kernel/debug/zswap/").allow_error())?
+            .stdout;
+        let zerosim_guest_offset = ushell
+            .run(cmd!("cat /proc/zerosim_guest_offset"))
+            .ok()
+            .map(|out| out.stdout.trim().to_owned());
+
+        Ok(SimStats {
+            host: MachineStats::collect(ushell)?,
+            guest: MachineStats::collect(vshell)?,
+            zswap: parse_zswap(&zswap),
+            zerosim_guest_offset,
+            drift: DriftReport::collect(ushell, vshell)?,
+        })
+    }
+}
+
+impl MachineStats {
+    /// Collect statistics from the remote.
+    pub fn collect(shell: &SshShell) -> Result<Self, failure::Error> {
+        let meminfo = shell.run(cmd!("cat /proc/meminfo"))?.stdout;
+        let cpuinfo = shell.run(cmd!("cat /proc/cpuinfo"))?.stdout;
+        let dmesg = shell.run(cmd!("dmesg"))?.stdout;
+
+        Ok(MachineStats {
+            meminfo: parse_meminfo(&meminfo),
+            cpuinfo: parse_cpuinfo(&cpuinfo),
+            dmesg_anomalies: find_dmesg_anomalies(&dmesg),
+        })
+    }
+}
+
+/// Parse `/proc/meminfo`. Lines look like `MemFree:        1234 kB`.
+pub fn parse_meminfo(meminfo: &str) -> BTreeMap<String, u64> {
+    meminfo
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.trim_end_matches(':');
+            let value = parts.next()?.parse().ok()?;
+            Some((name.to_owned(), value))
+        })
+        .collect()
+}
+
+/// Parse `/proc/cpuinfo`. Lines look like `model name<TAB>: Intel(R) Xeon(R) ...`, with a blank line
+/// between processors.
+pub fn parse_cpuinfo(cpuinfo: &str) -> CpuInfo {
+    let mut info = CpuInfo::default();
+
+    for line in cpuinfo.lines() {
+        let mut parts = line.splitn(2, ':');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+
+        match key {
+            "processor" => info.processors += 1,
+            "model name" if info.model_name.is_none() => info.model_name = Some(value.to_owned()),
+            "cpu MHz" => {
+                if let Ok(mhz) = value.parse() {
+                    info.mhz.push(mhz);
+                }
+            }
+            "flags" if info.flags.is_empty() => {
+                info.flags = value.split_whitespace().map(String::from).collect()
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+/// Parse the output of `grep -r . /sys/kernel/debug/zswap/`. Lines look like
+/// `/sys/kernel/debug/zswap/stored_pages:1234`.
+pub fn parse_zswap(zswap: &str) -> BTreeMap<String, u64> {
+    zswap
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.rsplitn(2, ':');
+            let value = parts.next()?.trim().parse().ok()?;
+            let name = parts.next()?.rsplit('/').next()?;
+            Some((name.to_owned(), value))
+        })
+        .collect()
+}
+
+/// Returns the lines of `dmesg` that indicate something went wrong.
+pub fn find_dmesg_anomalies(dmesg: &str) -> Vec<DmesgAnomaly> {
+    dmesg
+        .lines()
+        .filter_map(|line| {
+            DMESG_ANOMALIES
+                .iter()
+                .find(|(pattern, _)| line.contains(pattern))
+                .map(|(_, kind)| DmesgAnomaly {
+                    kind,
+                    line: line.to_owned(),
+                })
+        })
+        .collect()
+}
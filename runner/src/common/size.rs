@@ -0,0 +1,235 @@
+//! Memory and workload sizes.
+//!
+//! Sizes on the command line can be given with a suffix (e.g. `512M`, `64G`, `1T`). A bare number
+//! is interpreted as GB, which is what all experiments historically took. Sizes are stored in
+//! settings as a number of bytes, so there is never any question about the unit.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// A size in bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Size(usize);
+
+impl Size {
+    pub fn from_bytes(bytes: usize) -> Self {
+        Size(bytes)
+    }
+
+    pub fn from_kb(kb: usize) -> Self {
+        Size(kb << 10)
+    }
+
+    pub fn from_mb(mb: usize) -> Self {
+        Size(mb << 20)
+    }
+
+    pub fn from_gb(gb: usize) -> Self {
+        Size(gb << 30)
+    }
+
+    pub fn bytes(self) -> usize {
+        self.0
+    }
+
+    /// The size in KB, rounded down.
+    pub fn kb(self) -> usize {
+        self.0 >> 10
+    }
+
+    /// The size in MB, rounded down.
+    pub fn mb(self) -> usize {
+        self.0 >> 20
+    }
+
+    /// The size in GB, rounded down.
+    pub fn gb(self) -> usize {
+        self.0 >> 30
+    }
+
+    /// The number of 4KB pages, rounded down.
+    pub fn pages(self) -> usize {
+        self.0 >> 12
+    }
+}
+
+impl FromStr for Size {
+    type Err = failure::Error;
+
+    /// Parse a size such as `512M`, `64G`, `1T`, or `4096K`. The suffix is case-insensitive and
+    /// may optionally be followed by `B` (e.g. `64GB`). A bare number is interpreted as GB, and a
+    /// number followed only by `B` as bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let upper = s.to_uppercase();
+        let (upper, bytes) = match upper.strip_suffix('B') {
+            Some(num) if num.ends_with(|c: char| c.is_ascii_digit()) => (num, true),
+            Some(num) if !num.is_empty() => (num, false),
+            _ => (upper.as_str(), false),
+        };
+
+        let (num, shift) = match upper.chars().last() {
+            _ if bytes => (upper, 0),
+            Some('K') => (&upper[..upper.len() - 1], 10),
+            Some('M') => (&upper[..upper.len() - 1], 20),
+            Some('G') => (&upper[..upper.len() - 1], 30),
+            Some('T') => (&upper[..upper.len() - 1], 40),
+            Some(c) if c.is_ascii_digit() => (upper, 30),
+            _ => return Err(failure::format_err!("Invalid size: {:?}", s)),
+        };
+
+        let num = num
+            .parse::<usize>()
+            .map_err(|e| failure::format_err!("Invalid size {:?}: {}", s, e))?;
+
+        num.checked_mul(1 << shift)
+            .map(Size)
+            .ok_or_else(|| failure::format_err!("Size too large: {:?}", s))
+    }
+}
+
+impl fmt::Display for Size {
+    /// Display the size in the largest unit that represents it exactly (e.g. `512M`), in a form
+    /// that can be parsed back.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: &[(char, usize)] = &[('T', 40), ('G', 30), ('M', 20), ('K', 10)];
+
+        for &(unit, shift) in UNITS {
+            if self.0 != 0 && self.0.is_multiple_of(1 << shift) {
+                return write!(f, "{}{}", self.0 >> shift, unit);
+            }
+        }
+
+        write!(f, "{}B", self.0)
+    }
+}
+
+impl Serialize for Size {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0 as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    /// Sizes are deserialized from a number of bytes, or from a string like `512M`. Params files
+    /// written before sizes were stored in bytes recorded some sizes in GB; those are converted by
+    /// `output::read_params_file`, not here.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SizeVisitor;
+
+        impl<'de> Visitor<'de> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a number of bytes or a size such as \"512M\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, bytes: u64) -> Result<Size, E> {
+                usize::try_from(bytes)
+                    .map(Size)
+                    .map_err(|_| E::custom(format!("size too large: {}", bytes)))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Size, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+/// A `clap` validator for sizes.
+pub fn is_size(s: String) -> Result<(), String> {
+    s.parse::<Size>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// A `clap` validator for VM sizes. The VM's memory is configured in GB, so the size must be a
+/// whole, non-zero number of GB.
+pub fn is_vm_size(s: String) -> Result<(), String> {
+    let size = s.parse::<Size>().map_err(|e| e.to_string())?;
+
+    if size.bytes() == 0 || size.bytes() % (1 << 30) != 0 {
+        Err(format!("VM size must be a whole number of GB: {}", s))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        assert_eq!("4096K".parse::<Size>().unwrap(), Size::from_kb(4096));
+        assert_eq!("512M".parse::<Size>().unwrap(), Size::from_mb(512));
+        assert_eq!("64G".parse::<Size>().unwrap(), Size::from_gb(64));
+        assert_eq!("1T".parse::<Size>().unwrap(), Size::from_gb(1024));
+        assert_eq!("100B".parse::<Size>().unwrap(), Size::from_bytes(100));
+    }
+
+    #[test]
+    fn parse_bare_number_is_gb() {
+        assert_eq!("64".parse::<Size>().unwrap(), Size::from_gb(64));
+    }
+
+    #[test]
+    fn parse_is_lenient() {
+        assert_eq!("64gb".parse::<Size>().unwrap(), Size::from_gb(64));
+        assert_eq!(" 512m ".parse::<Size>().unwrap(), Size::from_mb(512));
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        for s in &["", "B", "G", "12X", "1.5G", "-1G", "99999999999999999999T"] {
+            assert!(s.parse::<Size>().is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn display_uses_largest_exact_unit() {
+        assert_eq!(Size::from_mb(512).to_string(), "512M");
+        assert_eq!(Size::from_mb(1024).to_string(), "1G");
+        assert_eq!(Size::from_mb(1536).to_string(), "1536M");
+        assert_eq!(Size::from_gb(2048).to_string(), "2T");
+        assert_eq!(Size::from_bytes(100).to_string(), "100B");
+        assert_eq!(Size::from_bytes(0).to_string(), "0B");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for &size in &[
+            Size::from_bytes(0),
+            Size::from_bytes(100),
+            Size::from_kb(3),
+            Size::from_mb(512),
+            Size::from_gb(64),
+        ] {
+            assert_eq!(size.to_string().parse::<Size>().unwrap(), size);
+        }
+    }
+
+    #[test]
+    fn serde() {
+        let size = Size::from_mb(512);
+        assert_eq!(serde_json::to_string(&size).unwrap(), "536870912");
+        assert_eq!(serde_json::from_str::<Size>("536870912").unwrap(), size);
+        assert_eq!(serde_json::from_str::<Size>("\"512M\"").unwrap(), size);
+    }
+
+    #[test]
+    fn vm_size() {
+        assert!(is_vm_size("64G".into()).is_ok());
+        assert!(is_vm_size("2048M".into()).is_ok());
+        assert!(is_vm_size("512M".into()).is_err());
+        assert!(is_vm_size("1536M".into()).is_err());
+        assert!(is_vm_size("0".into()).is_err());
+    }
+}
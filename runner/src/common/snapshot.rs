@@ -0,0 +1,175 @@
+//! Before/after snapshots of system counters around the phases of an experiment.
+//!
+//! Continuous sampling (e.g. of `/proc/vmstat`) has overhead and produces a lot of data. Often,
+//! we just want to know how much memory and IO activity each phase caused. `PhaseSnapshots`
+//! snapshots a few key counter files before and after each phase and keeps only the differences,
+//! which are then stored with the results.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+/// Block devices that are not interesting for IO accounting.
+const IGNORED_DISK_PREFIXES: &[&str] = &["loop", "ram", "sr"];
+
+/// A snapshot of system counters on a remote at a point in time. Counters are named
+/// `<source>.<name>` (e.g. `vmstat.pswpout` or `diskstats.sda.sectors_written`).
+#[derive(Debug, Clone)]
+pub struct ResourceSnapshot {
+    counters: BTreeMap<String, i64>,
+}
+
+impl ResourceSnapshot {
+    /// Snapshot `/proc/meminfo`, `/proc/vmstat`, `/proc/diskstats`, and zswap stats (if
+    /// available) on the remote. Requires `sudo` for the zswap stats.
+    pub fn take(shell: &SshShell) -> Result<Self, failure::Error> {
+        let mut counters = BTreeMap::new();
+
+        // Lines look like `MemFree:        1234 kB`.
+        let meminfo = shell.run(cmd!("cat /proc/meminfo"))?.stdout;
+        for line in meminfo.lines() {
+            let mut parts = line.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                if let Ok(value) = value.parse() {
+                    counters.insert(format!("meminfo.{}", name.trim_end_matches(':')), value);
+                }
+            }
+        }
+
+        // Lines look like `pswpout 1234`.
+        let vmstat = shell.run(cmd!("cat /proc/vmstat"))?.stdout;
+        for line in vmstat.lines() {
+            let mut parts = line.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                if let Ok(value) = value.parse() {
+                    counters.insert(format!("vmstat.{}", name), value);
+                }
+            }
+        }
+
+        // Lines look like `major minor name reads reads_merged sectors_read ms_reading writes
+        // writes_merged sectors_written ...`.
+        let diskstats = shell.run(cmd!("cat /proc/diskstats"))?.stdout;
+        for line in diskstats.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.len() < 10
+                || IGNORED_DISK_PREFIXES
+                    .iter()
+                    .any(|prefix| fields[2].starts_with(prefix))
+            {
+                continue;
+            }
+
+            for (name, idx) in &[
+                ("reads", 3),
+                ("sectors_read", 5),
+                ("writes", 7),
+                ("sectors_written", 9),
+            ] {
+                if let Ok(value) = fields[*idx].parse() {
+                    counters.insert(format!("diskstats.{}.{}", fields[2], name), value);
+                }
+            }
+        }
+
+        // Lines look like `/sys/kernel/debug/zswap/stored_pages:1234`.
+        let zswap = shell
+            .run(cmd!("sudo grep -r . /sys/kernel/debug/zswap/").allow_error())?
+            .stdout;
+        for line in zswap.lines() {
+            let mut parts = line.rsplitn(2, ':');
+            if let (Some(value), Some(path)) = (parts.next(), parts.next()) {
+                if let (Ok(value), Some(name)) = (value.trim().parse(), path.rsplit('/').next()) {
+                    counters.insert(format!("zswap.{}", name), value);
+                }
+            }
+        }
+
+        Ok(ResourceSnapshot { counters })
+    }
+
+    /// Returns the change in each counter from `self` to `after`, omitting counters that did not
+    /// change.
+    pub fn diff(&self, after: &ResourceSnapshot) -> BTreeMap<String, i64> {
+        self.counters
+            .iter()
+            .filter_map(|(name, before)| {
+                after
+                    .counters
+                    .get(name)
+                    .map(|after| (name.clone(), after - before))
+            })
+            .filter(|(_, delta)| *delta != 0)
+            .collect()
+    }
+}
+
+/// The change in system counters over one phase of an experiment.
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseResources {
+    /// The name of the phase.
+    pub phase: String,
+    /// The change in each counter that changed during the phase.
+    pub diff: BTreeMap<String, i64>,
+}
+
+/// Keeps track of the resource usage of each phase of an experiment.
+///
+/// ```rust,ignore
+/// let mut snapshots = PhaseSnapshots::new();
+///
+/// snapshots.begin(&ushell)?;
+/// run_workload(...)?;
+/// snapshots.end(&ushell, "Workload")?;
+/// ```
+#[derive(Debug, Default)]
+pub struct PhaseSnapshots {
+    current: Option<ResourceSnapshot>,
+    phases: Vec<PhaseResources>,
+}
+
+impl PhaseSnapshots {
+    pub fn new() -> Self {
+        PhaseSnapshots::default()
+    }
+
+    /// The number of phases recorded so far.
+    pub fn num_phases(&self) -> usize {
+        self.phases.len()
+    }
+
+    /// Forget all but the first `n` phases recorded (e.g. because those phases are about to be
+    /// rerun).
+    pub fn truncate(&mut self, n: usize) {
+        self.phases.truncate(n);
+    }
+
+    /// Take the "before" snapshot of a phase.
+    pub fn begin(&mut self, shell: &SshShell) -> Result<(), failure::Error> {
+        self.current = Some(ResourceSnapshot::take(shell)?);
+        Ok(())
+    }
+
+    /// Take the "after" snapshot of the phase called `label` and record the difference.
+    pub fn end(&mut self, shell: &SshShell, label: &str) -> Result<(), failure::Error> {
+        let before = self
+            .current
+            .take()
+            .expect("PhaseSnapshots::end called without begin");
+        let after = ResourceSnapshot::take(shell)?;
+
+        self.phases.push(PhaseResources {
+            phase: label.into(),
+            diff: before.diff(&after),
+        });
+
+        Ok(())
+    }
+
+    /// The resource usage of all phases so far, suitable for storing with the results.
+    pub fn phases(&self) -> &[PhaseResources] {
+        &self.phases
+    }
+}
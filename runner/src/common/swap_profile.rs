@@ -0,0 +1,129 @@
+//! Characterizing the performance of the host's swap devices with `fio`.
+//!
+//! 0sim's performance depends heavily on the backing storage of the host, and a slow or failing
+//! swap device has caused confusing results before. `profile_swap_devices` measures the bandwidth
+//! and latency of each configured swap device under swap-like access patterns, so that results
+//! can be correlated with the storage they ran on.
+
+use serde::Serialize;
+
+use spurs::{cmd, Execute, SshShell};
+
+use super::{exp_0sim::list_swapdevs, size::Size};
+
+/// The `fio` jobs run against each device: (name, `--rw`, `--bs`). Swapping in is mostly small
+/// random reads, and swapping out is random writes, often clustered into larger sequential ones.
+const FIO_JOBS: &[(&str, &str, &str)] = &[
+    ("randread-4k", "randread", "4k"),
+    ("randwrite-4k", "randwrite", "4k"),
+    ("seqwrite-128k", "write", "128k"),
+];
+
+/// The performance profile of a single swap device.
+#[derive(Debug, Serialize)]
+pub struct SwapDeviceProfile {
+    /// The path of the device (e.g. `/dev/sdb`).
+    pub device: String,
+
+    /// The results of each `fio` job.
+    pub jobs: Vec<FioResult>,
+}
+
+/// The result of a single `fio` job.
+#[derive(Debug, Serialize)]
+pub struct FioResult {
+    /// The name of the job (e.g. `randread-4k`).
+    pub job: String,
+    /// The bandwidth in KiB/s.
+    pub bw_kib_s: f64,
+    /// The number of IO operations per second.
+    pub iops: f64,
+    /// The mean completion latency in microseconds.
+    pub mean_lat_us: f64,
+    /// The 99th percentile completion latency in microseconds, if `fio` reported it.
+    pub p99_lat_us: Option<f64>,
+}
+
+/// Returns the paths of the configured swap devices of the host, in the same way that
+/// `turn_on_swapdevs` chooses them.
+pub fn configured_swap_devices(shell: &SshShell) -> Result<Vec<String>, failure::Error> {
+    let settings = crate::common::get_remote_research_settings(shell)?;
+
+    if crate::common::get_remote_research_setting::<String>(&settings, "dm-data")?.is_some() {
+        return Ok(vec!["/dev/mapper/mythin".into()]);
+    }
+
+    let devs: Vec<String> = if let Some(devs) =
+        crate::common::get_remote_research_setting(&settings, "swap-devices")?
+    {
+        devs
+    } else {
+        list_swapdevs(shell)?
+    };
+
+    Ok(devs.into_iter().map(|dev| dir!("/dev", dev)).collect())
+}
+
+/// Run the `fio` jobs against each configured swap device for `runtime` seconds each, using the
+/// first `size` of the device. Requires `sudo` and `fio`.
+///
+/// *NOTE*: This overwrites the devices. Each device is turned off as swap before it is profiled
+/// and reformatted as swap afterwards, but the caller needs to turn swapping back on (e.g. with
+/// `setup_swapping`).
+pub fn profile_swap_devices(
+    shell: &SshShell,
+    size: Size,
+    runtime: usize,
+) -> Result<Vec<SwapDeviceProfile>, failure::Error> {
+    let mut profiles = vec![];
+
+    for device in configured_swap_devices(shell)? {
+        shell.run(cmd!("sudo swapoff {}", device).allow_error())?;
+
+        let mut jobs = vec![];
+        for (job, rw, bs) in FIO_JOBS.iter() {
+            let out = shell.run(cmd!(
+                "sudo fio --name={} --filename={} --rw={} --bs={} --size={} --runtime={} \
+                 --time_based --direct=1 --ioengine=libaio --iodepth=1 --output-format=json",
+                job,
+                device,
+                rw,
+                bs,
+                size.bytes(),
+                runtime
+            ))?;
+
+            jobs.push(parse_fio_json(job, &out.stdout)?);
+        }
+
+        shell.run(cmd!("sudo mkswap {}", device))?;
+
+        profiles.push(SwapDeviceProfile { device, jobs });
+    }
+
+    Ok(profiles)
+}
+
+/// Parse the JSON output of a single `fio` job.
+fn parse_fio_json(job: &str, json: &str) -> Result<FioResult, failure::Error> {
+    let json: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| failure::format_err!("Unable to parse fio output of {}: {}", job, e))?;
+
+    // Whichever of the read and write stats the job actually did.
+    let stats = &json["jobs"][0];
+    let stats = if stats["read"]["io_bytes"].as_u64().unwrap_or(0) > 0 {
+        &stats["read"]
+    } else {
+        &stats["write"]
+    };
+
+    Ok(FioResult {
+        job: job.into(),
+        bw_kib_s: stats["bw"].as_f64().unwrap_or(0.0),
+        iops: stats["iops"].as_f64().unwrap_or(0.0),
+        mean_lat_us: stats["clat_ns"]["mean"].as_f64().unwrap_or(0.0) / 1000.0,
+        p99_lat_us: stats["clat_ns"]["percentile"]["99.000000"]
+            .as_f64()
+            .map(|ns| ns / 1000.0),
+    })
+}
@@ -0,0 +1,128 @@
+//! Telemetry from the host and guest.
+//!
+//! Thermal throttling on the host has contaminated long simulation runs before, and there was no
+//! record of it to check afterwards. `HostTelemetry` samples the RAPL energy counters and thermal
+//! zone temperatures of the host in the background while a workload runs and saves them as a
+//! timeline next to the other results.
+//!
+//! Similarly, `SystemTelemetry` samples the memory, swapping, and CPU frequency stats of either
+//! machine, so that the behavior of the simulation over time can be seen without attaching `perf`
+//! or watching `/proc` by hand.
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+
+use super::background::LoopMarker;
+
+/// Periodically samples host energy counters and temperatures in the background.
+pub struct HostTelemetry {
+    /// Keeps the sampling loop running. The loop also stops if this is dropped without calling
+    /// `stop` (e.g. because the workload failed).
+    marker: LoopMarker,
+
+    /// The shell running the sampling loop.
+    handle: (SshShell, SshSpawnHandle),
+}
+
+impl HostTelemetry {
+    /// Start sampling every `interval` seconds into the CSV file `file` on the host. Each line has
+    /// the form `time,sensor,value`, where `time` is a unix timestamp and `sensor` is either
+    /// `energy_uj.<rapl domain>` (cumulative energy in microjoules) or `temp_mc.<thermal zone>`
+    /// (temperature in millidegrees Celsius). Sensors the host does not have are skipped. Requires
+    /// `sudo` to read the RAPL counters.
+    pub fn start(shell: &SshShell, interval: usize, file: &str) -> Result<Self, failure::Error> {
+        shell.run(cmd!("echo time,sensor,value > {}", file))?;
+        let marker = LoopMarker::create(shell, "telemetry")?;
+
+        let handle = shell.spawn(
+            cmd!(
+                "while [ -e {} ] ; do \
+                 t=$(date +%s.%N) ; \
+                 for d in /sys/class/powercap/intel-rapl:* ; do \
+                 if [ -e $d/energy_uj ] ; then \
+                 echo \"$t,energy_uj.$(cat $d/name)-${{d##*:}},$(sudo cat $d/energy_uj)\" ; \
+                 fi ; \
+                 done ; \
+                 for z in /sys/class/thermal/thermal_zone* ; do \
+                 if [ -e $z/temp ] ; then \
+                 echo \"$t,temp_mc.$(cat $z/type)-${{z##*zone}},$(cat $z/temp)\" ; \
+                 fi ; \
+                 done ; \
+                 sleep {} ; \
+                 done >> {} ; \
+                 echo telemetry done",
+                marker.path(),
+                interval,
+                file,
+            )
+            .use_bash(),
+        )?;
+
+        Ok(HostTelemetry { marker, handle })
+    }
+
+    /// Stop sampling and wait for the sampling loop to exit.
+    pub fn stop(mut self) -> Result<(), failure::Error> {
+        self.marker.remove()?;
+        (self.handle.1).join()?;
+        Ok(())
+    }
+}
+
+/// Periodically samples the memory, swapping, and CPU frequency stats of a remote (host or guest)
+/// in the background.
+pub struct SystemTelemetry {
+    /// Keeps the sampling loop running. The loop also stops if this is dropped without calling
+    /// `stop` (e.g. because the workload failed).
+    marker: LoopMarker,
+
+    /// The shell running the sampling loop.
+    handle: (SshShell, SshSpawnHandle),
+}
+
+impl SystemTelemetry {
+    /// Start sampling every `interval` seconds into the CSV file `file` on the remote. Each line
+    /// has the form `time,stat,value`, where `time` is a unix timestamp and `stat` is one of
+    /// - `meminfo.<field>`: a field of `/proc/meminfo`, in KB (or pages for `HugePages_*`),
+    /// - `vmstat.<field>`: a field of `/proc/vmstat`,
+    /// - `zswap.<stat>`: a zswap stat from debugfs (e.g. `stored_pages`), if zswap is enabled,
+    /// - `ksm.<stat>`: a KSM stat from sysfs (e.g. `pages_shared`), or
+    /// - `cpu_mhz.<n>`: the current clock speed of the `n`th processor in `/proc/cpuinfo`.
+    ///
+    /// Requires `sudo` to read the zswap stats.
+    pub fn start(shell: &SshShell, interval: usize, file: &str) -> Result<Self, failure::Error> {
+        shell.run(cmd!("echo time,stat,value > {}", file))?;
+        let marker = LoopMarker::create(shell, "system-telemetry")?;
+
+        let handle = shell.spawn(
+            cmd!(
+                "while [ -e {} ] ; do \
+                 t=$(date +%s.%N) ; \
+                 awk -v t=$t '{{ sub(\":\", \"\", $1) ; print t \",meminfo.\" $1 \",\" $2 }}' \
+                 /proc/meminfo ; \
+                 awk -v t=$t '{{ print t \",vmstat.\" $1 \",\" $2 }}' /proc/vmstat ; \
+                 sudo grep -r . /sys/kernel/debug/zswap/ 2>/dev/null | \
+                 awk -F: -v t=$t '{{ n = split($1, p, \"/\") ; print t \",zswap.\" p[n] \",\" $2 }}' ; \
+                 grep -r . /sys/kernel/mm/ksm/ 2>/dev/null | \
+                 awk -F: -v t=$t '{{ n = split($1, p, \"/\") ; print t \",ksm.\" p[n] \",\" $2 }}' ; \
+                 awk -F: -v t=$t '/cpu MHz/ {{ gsub(/ /, \"\", $2) ; print t \",cpu_mhz.\" n++ \",\" $2 }}' \
+                 /proc/cpuinfo ; \
+                 sleep {} ; \
+                 done >> {} ; \
+                 echo system telemetry done",
+                marker.path(),
+                interval,
+                file,
+            )
+            .use_bash(),
+        )?;
+
+        Ok(SystemTelemetry { marker, handle })
+    }
+
+    /// Stop sampling and wait for the sampling loop to exit.
+    pub fn stop(mut self) -> Result<(), failure::Error> {
+        self.marker.remove()?;
+        (self.handle.1).join()?;
+        Ok(())
+    }
+}
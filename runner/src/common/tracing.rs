@@ -0,0 +1,144 @@
+//! Running the 0sim trace tool (`zerosim-trace`) on the host alongside a workload.
+//!
+//! `ZerosimTrace::start` checks the configuration and starts the tracer in the background.
+//! `ZerosimTrace::stop` waits for it to finish, copies the trace into the results directory, and
+//! optionally compresses it.
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+
+use super::paths::*;
+
+/// The default sampling interval of the tracer.
+pub const DEFAULT_TRACE_INTERVAL: usize = 500;
+
+/// The default number of trace records the tracer buffers in the kernel.
+pub const DEFAULT_TRACE_BUFFER_SIZE: usize = 100_000;
+
+/// The largest buffer size we allow. The buffer is allocated in the host kernel, so a typo here
+/// can take the host down.
+const MAX_TRACE_BUFFER_SIZE: usize = 10_000_000;
+
+/// Configuration for a run of `zerosim-trace`.
+pub struct ZerosimTraceConfig<'a> {
+    /// The sampling interval of the tracer (e.g. `DEFAULT_TRACE_INTERVAL`).
+    pub interval: usize,
+
+    /// The number of trace records to buffer (e.g. `DEFAULT_TRACE_BUFFER_SIZE`).
+    pub buffer_size: usize,
+
+    /// The file to write the trace to on the host (usually in the results directory).
+    pub output_file: &'a str,
+
+    /// Stop tracing after this many seconds. If `None`, tracing continues until
+    /// `ZerosimTrace::stop` is called.
+    pub timeout: Option<u64>,
+
+    /// The core to pin the tracer to.
+    pub pin_core: usize,
+
+    /// Compress the trace with `xz` after it is copied, leaving `<output_file>.xz` instead.
+    pub compress: bool,
+}
+
+/// A `zerosim-trace` session running in the background.
+pub struct ZerosimTrace {
+    /// The shell running the tracer.
+    handle: (SshShell, SshSpawnHandle),
+
+    /// Whether the tracer stops by itself.
+    timeout: bool,
+
+    /// The file the tracer is writing to.
+    tmp_file: String,
+
+    /// The final location of the trace.
+    output_file: String,
+
+    /// Whether to compress the trace.
+    compress: bool,
+}
+
+impl ZerosimTrace {
+    /// Start tracing in the background. Requires `sudo` and the trace tool to be built (by
+    /// `setup00000`).
+    ///
+    /// The tracer writes to a file in `/tmp` rather than directly to `output_file` so that it
+    /// doesn't generate IO on the shared results directory while tracing.
+    pub fn start(shell: &SshShell, cfg: &ZerosimTraceConfig<'_>) -> Result<Self, failure::Error> {
+        if cfg.interval == 0 {
+            return Err(failure::format_err!(
+                "zerosim-trace interval must be positive"
+            ));
+        }
+        if cfg.buffer_size == 0 || cfg.buffer_size > MAX_TRACE_BUFFER_SIZE {
+            return Err(failure::format_err!(
+                "zerosim-trace buffer size must be between 1 and {}, not {}",
+                MAX_TRACE_BUFFER_SIZE,
+                cfg.buffer_size
+            ));
+        }
+        if cfg.timeout == Some(0) {
+            return Err(failure::format_err!(
+                "zerosim-trace timeout must be positive"
+            ));
+        }
+
+        let tracer = dir!(
+            RESEARCH_WORKSPACE_PATH,
+            ZEROSIM_TRACE_SUBMODULE,
+            "target/release/zerosim-trace"
+        );
+        shell
+            .run(cmd!("test -x {}", tracer))
+            .map_err(|_| failure::format_err!("{} is not built; run setup00000", tracer))?;
+
+        let file_name = cfg
+            .output_file
+            .rsplit('/')
+            .next()
+            .expect("rsplit returns at least one item");
+        let tmp_file = dir!("/tmp", file_name);
+
+        let handle = shell.spawn(cmd!(
+            "sudo taskset -c {} {} trace {} {} {} {}",
+            cfg.pin_core,
+            tracer,
+            cfg.interval,
+            cfg.buffer_size,
+            tmp_file,
+            cfg.timeout
+                .map(|timeout| format!("-t {}", timeout))
+                .unwrap_or_default(),
+        ))?;
+
+        Ok(ZerosimTrace {
+            handle,
+            timeout: cfg.timeout.is_some(),
+            tmp_file,
+            output_file: cfg.output_file.to_owned(),
+            compress: cfg.compress,
+        })
+    }
+
+    /// Wait for the tracer to finish (interrupting it if it has no timeout), copy the trace to the
+    /// output file, and compress it if needed.
+    pub fn stop(self, shell: &SshShell) -> Result<(), failure::Error> {
+        if !self.timeout {
+            shell.run(cmd!("sudo pkill -INT -f 'zerosim-trace trace'").allow_error())?;
+        }
+
+        (self.handle.1).join()?;
+
+        with_shell! { shell =>
+            cmd!("sudo cp {} {}", self.tmp_file, self.output_file),
+            cmd!("sudo chown $(whoami) {}", self.output_file),
+            cmd!("sudo rm -f {}", self.tmp_file),
+        }
+
+        if self.compress {
+            shell.run(cmd!("xz -T0 -f {}", self.output_file))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+//! Compare the results of several batches of experiments side by side.
+//!
+//! Each directory given to `runner compare` is a results directory from one batch (e.g. one run
+//! per kernel or per host). Runs are found by their `.params` files, and the runs in different
+//! directories are aligned by their settings, ignoring settings that differ from run to run
+//! regardless of the experiment (e.g. git hashes) and settings that usually differ between the
+//! directories being compared (e.g. the host or guest kernel). For each group of aligned runs, the report has
+//! one row per metric and one column per directory, along with the difference relative to the
+//! first directory.
+//!
+//! The metrics are the time of each phase from the `.time` file and every number in the
+//! `results.json` and `.sim.json` files of the run. If a directory contains several runs with the same settings,
+//! their metrics are averaged.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use clap::clap_app;
+
+use failure::ResultExt;
+
+use serde_json::Value;
+
+use crate::common::output::VOLATILE_SETTINGS;
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    clap_app! { compare =>
+        (about: "Compare the results of runs in two or more results directories, aligned by \
+                 their settings.")
+        (@arg DIRS: +required +takes_value +multiple min_values(2)
+         "The local results directories to compare. The first one is the baseline.")
+        (@arg FORMAT: --format +takes_value possible_values(&["markdown", "csv"])
+         "(Optional) The format of the report (default: markdown).")
+        (@arg OUTPUT: -o --output +takes_value
+         "(Optional) Write the report to the given file instead of stdout.")
+        (@arg IGNORE: --ignore +takes_value ... number_of_values(1)
+         "(Optional) Do not align runs by the given setting, in addition to the host, username, \
+          and guest kernel. Can be given multiple times.")
+    }
+}
+
+/// Settings that usually differ between the directories being compared (e.g. one batch per host or
+/// per guest kernel), and so are not used to align runs.
+const IGNORED_SETTINGS: &[&str] = &["host", "username", "guest_kernel"];
+
+/// The settings that identify a group of aligned runs, with JSON-serialized values.
+type Key = BTreeMap<String, String>;
+
+/// The metrics of a single run, or the average of several runs with the same settings.
+#[derive(Debug, Default)]
+struct Metrics {
+    /// The number of runs averaged.
+    runs: usize,
+
+    /// The sum of each metric over all runs, and the number of runs that had it.
+    sums: BTreeMap<String, (f64, usize)>,
+}
+
+impl Metrics {
+    fn add(&mut self, metrics: BTreeMap<String, f64>) {
+        self.runs += 1;
+        for (metric, value) in metrics.into_iter() {
+            let sum = self.sums.entry(metric).or_insert((0.0, 0));
+            sum.0 += value;
+            sum.1 += 1;
+        }
+    }
+
+    fn get(&self, metric: &str) -> Option<f64> {
+        self.sums.get(metric).map(|(sum, n)| sum / *n as f64)
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let dirs: Vec<_> = sub_m
+        .values_of("DIRS")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
+    let csv = sub_m.value_of("FORMAT") == Some("csv");
+    let ignored: Vec<&str> = IGNORED_SETTINGS
+        .iter()
+        .copied()
+        .chain(sub_m.values_of("IGNORE").into_iter().flatten())
+        .collect();
+
+    // For each directory, the metrics of the runs in that directory grouped by settings.
+    let mut groups: Vec<BTreeMap<Key, Metrics>> = Vec::new();
+    for dir in dirs.iter() {
+        let mut runs = BTreeMap::new();
+        for params in find_params_files(dir)? {
+            let (key, metrics) = load_run(&params, &ignored)
+                .with_context(|_| format!("loading run {:?}", params))?;
+            runs.entry(key)
+                .or_insert_with(Metrics::default)
+                .add(metrics);
+        }
+
+        if runs.is_empty() {
+            return Err(failure::format_err!("No runs found in {:?}", dir));
+        }
+
+        groups.push(runs);
+    }
+
+    let keys: BTreeSet<&Key> = groups.iter().flat_map(|runs| runs.keys()).collect();
+    let names: Vec<_> = dirs.iter().map(|dir| dir.to_string_lossy()).collect();
+    let report = if csv {
+        csv_report(&names, &keys, &groups)
+    } else {
+        markdown_report(&names, &keys, &groups)
+    };
+
+    match sub_m.value_of("OUTPUT") {
+        Some(output) => std::fs::write(output, report)?,
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of all `.params` files in `dir` and its subdirectories.
+fn find_params_files(dir: &Path) -> Result<Vec<PathBuf>, failure::Error> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir).with_context(|_| format!("reading {:?}", dir))? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            files.extend(find_params_files(&path)?);
+        } else if path.extension().map(|ext| ext == "params").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Load the settings and metrics of the run with the given `.params` file. The other files of the
+/// run share its name, differing only in extension. The `ignored` settings are left out of the key.
+fn load_run(
+    params: &Path,
+    ignored: &[&str],
+) -> Result<(Key, BTreeMap<String, f64>), failure::Error> {
+    let key = crate::common::output::read_params_file(params)?
+        .into_iter()
+        .filter(|(setting, _)| {
+            !VOLATILE_SETTINGS.contains(&setting.as_str()) && !ignored.contains(&setting.as_str())
+        })
+        .map(|(setting, value)| (setting, value.to_string()))
+        .collect();
+
+    let mut metrics = BTreeMap::new();
+
+    // Older runs have a free-form `.time` file rather than JSON, so they have no phase timings.
+    if let Some(time) = read_json(&params.with_extension("time")) {
+        if let Some(phases) = time["phases"].as_array() {
+            for phase in phases.iter() {
+                if let (Some(name), Some(secs)) = (phase["phase"].as_str(), phase["secs"].as_f64())
+                {
+                    metrics.insert(format!("time.{}", name), secs);
+                }
+            }
+        }
+        if let Some(total) = time["total_secs"].as_f64() {
+            metrics.insert("time.total".into(), total);
+        }
+    }
+
+    if let Some(results) = read_json(&params.with_extension("results.json")) {
+        flatten("results", &results, &mut metrics);
+    }
+
+    if let Some(sim) = read_json(&params.with_extension("sim.json")) {
+        flatten("sim", &sim, &mut metrics);
+    }
+
+    Ok((key, metrics))
+}
+
+/// Read and parse the given JSON file, if it exists and is valid.
+fn read_json(path: &Path) -> Option<Value> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+}
+
+/// Add every number in `value` to `metrics`, named by its path from the root joined with dots.
+/// Elements of arrays are named by their `phase` or `name` field if they have one and by their
+/// index otherwise.
+fn flatten(prefix: &str, value: &Value, metrics: &mut BTreeMap<String, f64>) {
+    match value {
+        Value::Number(n) => {
+            if let Some(n) = n.as_f64() {
+                metrics.insert(prefix.to_owned(), n);
+            }
+        }
+
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                flatten(&format!("{}.{}", prefix, k), v, metrics);
+            }
+        }
+
+        Value::Array(values) => {
+            for (i, v) in values.iter().enumerate() {
+                let name = v["phase"]
+                    .as_str()
+                    .or_else(|| v["name"].as_str())
+                    .map(String::from)
+                    .unwrap_or_else(|| i.to_string());
+                flatten(&format!("{}.{}", prefix, name), v, metrics);
+            }
+        }
+
+        Value::Null | Value::Bool(_) | Value::String(_) => {}
+    }
+}
+
+/// Returns a short description of the group of runs with settings `key`, consisting of only the
+/// settings that differ between groups.
+fn describe(key: &Key, keys: &BTreeSet<&Key>) -> String {
+    let varying: Vec<_> = key
+        .iter()
+        .filter(|(setting, value)| keys.iter().any(|other| other.get(*setting) != Some(value)))
+        .map(|(setting, value)| format!("{}={}", setting, value))
+        .collect();
+
+    if varying.is_empty() {
+        "all runs".into()
+    } else {
+        varying.join(" ")
+    }
+}
+
+/// Returns the union of the names of the metrics of the runs with settings `key` in any directory.
+fn metric_names<'a>(key: &Key, groups: &'a [BTreeMap<Key, Metrics>]) -> BTreeSet<&'a str> {
+    groups
+        .iter()
+        .filter_map(|runs| runs.get(key))
+        .flat_map(|metrics| metrics.sums.keys().map(String::as_str))
+        .collect()
+}
+
+/// Format the difference of `value` relative to `baseline` as a percentage.
+fn delta(baseline: Option<f64>, value: Option<f64>) -> String {
+    match (baseline, value) {
+        (Some(baseline), Some(value)) if baseline != 0.0 => {
+            format!("{:+.1}%", (value - baseline) / baseline * 100.0)
+        }
+        _ => String::new(),
+    }
+}
+
+fn fmt_value(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.3}", v)).unwrap_or_default()
+}
+
+fn markdown_report(
+    names: &[std::borrow::Cow<'_, str>],
+    keys: &BTreeSet<&Key>,
+    groups: &[BTreeMap<Key, Metrics>],
+) -> String {
+    let mut report = String::new();
+
+    for key in keys.iter() {
+        report.push_str(&format!("## {}\n\n", describe(key, keys)));
+
+        // Header
+        report.push_str("| metric |");
+        for (i, name) in names.iter().enumerate() {
+            let runs = groups[i].get(*key).map(|m| m.runs).unwrap_or(0);
+            report.push_str(&format!(" {} (n={}) |", name, runs));
+            if i > 0 {
+                report.push_str(" Δ |");
+            }
+        }
+        report.push_str("\n|---|");
+        for i in 0..names.len() {
+            report.push_str(if i > 0 { "---:|---:|" } else { "---:|" });
+        }
+        report.push('\n');
+
+        for metric in metric_names(key, groups).into_iter() {
+            let values: Vec<_> = groups
+                .iter()
+                .map(|runs| runs.get(*key).and_then(|m| m.get(metric)))
+                .collect();
+
+            report.push_str(&format!("| {} |", metric));
+            for (i, value) in values.iter().enumerate() {
+                report.push_str(&format!(" {} |", fmt_value(*value)));
+                if i > 0 {
+                    report.push_str(&format!(" {} |", delta(values[0], *value)));
+                }
+            }
+            report.push('\n');
+        }
+
+        report.push('\n');
+    }
+
+    report
+}
+
+fn csv_report(
+    names: &[std::borrow::Cow<'_, str>],
+    keys: &BTreeSet<&Key>,
+    groups: &[BTreeMap<Key, Metrics>],
+) -> String {
+    // Quote a field if needed.
+    let field = |s: &str| {
+        if s.contains([',', '"', '\n']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_owned()
+        }
+    };
+
+    let mut report = String::from("settings,metric");
+    for (i, name) in names.iter().enumerate() {
+        report.push_str(&format!(",{}", field(name)));
+        if i > 0 {
+            report.push_str(&format!(",{}", field(&format!("{} delta", name))));
+        }
+    }
+    report.push('\n');
+
+    for key in keys.iter() {
+        let description = field(&describe(key, keys));
+
+        for metric in metric_names(key, groups).into_iter() {
+            let values: Vec<_> = groups
+                .iter()
+                .map(|runs| runs.get(*key).and_then(|m| m.get(metric)))
+                .collect();
+
+            report.push_str(&format!("{},{}", description, field(metric)));
+            for (i, value) in values.iter().enumerate() {
+                report.push_str(&format!(",{}", fmt_value(*value)));
+                if i > 0 {
+                    report.push_str(&format!(",{}", delta(values[0], *value)));
+                }
+            }
+            report.push('\n');
+        }
+    }
+
+    report
+}
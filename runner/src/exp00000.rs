@@ -1,5 +1,9 @@
 //! Run the given workload on the remote machine in simulation and record its results.
 //!
+//! With `--baseline`, the same workload runs directly on the host instead, without 0sim or a VM,
+//! with the same instrumentation and output format, so that simulated and native results can be
+//! compared directly.
+//!
 //! Requires `setup00000`.
 
 use clap::clap_app;
@@ -7,19 +11,33 @@ use clap::clap_app;
 use serde::{Deserialize, Serialize};
 
 use spurs::{cmd, Execute, SshShell};
-use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        boot_params::{BootParam, Cmdlines},
+        cgroups::MemoryCgroup,
+        deadline::PhaseGuard,
+        drift::{GuestOffsetSampler, GUEST_OFFSET_SAMPLE_INTERVAL},
         exp_0sim::*,
-        get_cpu_freq,
-        output::OutputManager,
+        get_cpu_freq, get_user_home_dir,
+        output::{OutputManager, OutputSink, Progress},
         paths::{setup00000::*, *},
+        perf::{PerfConfig, PerfMode, PerfSession, DEFAULT_PERF_EVENTS},
+        reconnect::with_reconnect,
+        size::{is_size, is_vm_size, Size},
+        snapshot::PhaseSnapshots,
+        telemetry::{HostTelemetry, SystemTelemetry},
     },
     settings,
     workloads::{
-        run_memcached_gen_data, run_metis_matrix_mult, run_redis_gen_data, run_time_mmap_touch,
-        MemcachedWorkloadConfig, RedisWorkloadConfig, TimeMmapTouchConfig, TimeMmapTouchPattern,
+        gapbs_scale_for_size, kill_servers, run_gapbs, run_memcached_gen_data,
+        run_memcached_gen_data_client, run_metis_matrix_mult, run_mongo_gen_data,
+        run_redis_gen_data, run_redis_gen_data_client, run_spec_2017, run_stage_dataset,
+        run_time_mmap_touch, run_ycsb, start_memcached, start_redis, Antagonist, Antagonists,
+        GapbsKernel, GapbsWorkloadConfig, MemcachedWorkloadConfig, MongoWorkloadConfig,
+        RedisWorkloadConfig, Spec2017WorkloadConfig, StagingTarget, TimeMmapTouchConfig,
+        TimeMmapTouchPattern, WorkloadOptions, YcsbDistribution, YcsbMix, YcsbServer,
+        YcsbWorkloadConfig,
     },
 };
 
@@ -29,8 +47,22 @@ enum Workload {
     Redis,
     MatrixMult2,
     TimeMmapTouch,
+    YcsbMemcached,
+    YcsbRedis,
+    Mongo,
+    Gapbs,
+    Spec2017,
 }
 
+/// The number of times to run a GAP kernel on the same graph.
+const GAPBS_TRIALS: usize = 8;
+
+/// The name of the memory cgroup in the guest that `--mem_limit` puts the workload in.
+const WORKLOAD_CGROUP: &str = "zerosim_workload";
+
+/// The TCP port of the redis server for YCSB, which cannot use the unix socket.
+const YCSB_REDIS_PORT: u16 = 6379;
+
 pub fn cli_options() -> clap::App<'static, 'static> {
     fn is_usize(s: String) -> Result<(), String> {
         s.as_str()
@@ -39,14 +71,35 @@ pub fn cli_options() -> clap::App<'static, 'static> {
             .map_err(|e| format!("{:?}", e))
     }
 
+    fn is_antagonist(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<Antagonist>()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn is_boot_param(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<BootParam>()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn is_ycsb_mix(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<YcsbMix>()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
     clap_app! { exp00000 =>
         (about: "Run experiment 00000. Requires `sudo`.")
         (@arg HOSTNAME: +required +takes_value
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg VMSIZE: +required +takes_value {is_usize}
-         "The number of GBs of the VM (e.g. 500)")
+        (@arg VMSIZE: +required +takes_value {is_vm_size}
+         "The size of the VM (e.g. 500G; a bare number is GB). Must be a multiple of 1GB.")
         (@arg CORES: +required +takes_value {is_usize}
          "The number of cores of the VM")
         (@group PATTERN =>
@@ -56,14 +109,47 @@ pub fn cli_options() -> clap::App<'static, 'static> {
             (@arg memcached: -m "Run a memcached workload")
             (@arg redis: -r "Run a redis workload")
             (@arg matrixmult: -M "Run the Metis matrix_mult2 workload")
+            (@arg mongo: --mongo
+             "Run a mongodb workload (requires `setup00000 --guest_bmks`)")
+            (@arg gapbs: --gapbs +takes_value possible_values(GapbsKernel::NAMES)
+             "Run the given GAP benchmark suite graph kernel (requires `setup00000 --host_bmks`)")
+            (@arg spec17: --spec17 +takes_value
+             "Run the given comma-separated SPEC CPU 2017 benchmarks with the ref inputs (e.g. \
+              505.mcf_r,531.deepsjeng_r; requires `setup00000 --spec_2017`)")
+            (@arg ycsb: --ycsb +takes_value possible_values(&["memcached", "redis"])
+             "Run YCSB against the given server (requires `setup00000 --guest_bmks`)")
         )
+        (@arg GAPBS_SCALE: --gapbs_scale +takes_value {is_usize} requires[gapbs]
+         "(Optional) The scale of the graph for the GAP kernel (2^scale vertices). The default \
+          is the largest graph that fits in the workload size.")
+        (@arg YCSB_RECORDS: --ycsb_records +takes_value {is_usize} requires[ycsb]
+         "(Optional) The number of records YCSB loads. The default is enough 1KB records to \
+          fill the workload size.")
+        (@arg YCSB_OPS: --ycsb_ops +takes_value {is_usize} requires[ycsb]
+         "(Optional) The number of operations in the measured YCSB run. The default is the \
+          number of records.")
+        (@arg YCSB_DISTRIBUTION: --ycsb_distribution +takes_value requires[ycsb]
+         possible_values(YcsbDistribution::NAMES)
+         "(Optional) The YCSB request distribution (default: zipfian).")
+        (@arg YCSB_MIX: --ycsb_mix +takes_value requires[ycsb] {is_ycsb_mix}
+         "(Optional) The YCSB operation mix, as the proportions of reads, updates, and inserts \
+          (default: 0.5,0.5,0).")
+        (@arg MEM_LIMIT: --mem_limit +takes_value {is_size}
+         "(Optional) Limit the memory (including page cache) of the workload to the given size \
+          with a memory cgroup in the guest, rather than relying only on the size of the VM. \
+          Applies to the memcached and redis servers and to metis.")
+        (@arg ANTAGONIST: --antagonist +takes_value ... number_of_values(1) {is_antagonist}
+         "(Optional) Run the given background antagonist in the guest for the duration of the \
+          workload: `memhog:SIZE` (touch SIZE of memory repeatedly), `cpu` (spin on a core), or \
+          `io:SIZE` (random direct IO to a file of SIZE with fio). Each antagonist gets its own \
+          core. Can be given multiple times.")
         (@arg WARMUP: -w --warmup
          "Pass this flag to warmup the VM before running the main workload.")
         (@arg PREFAULT: -p --prefault
          "Pass this flag to prefault memory before running the main workload \
          (ignored for memcached).")
-        (@arg SIZE: -s --size +takes_value {is_usize}
-         "The number of GBs of the workload (e.g. 500)")
+        (@arg SIZE: -s --size +takes_value {is_size}
+         "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
         (@arg MULTICORE_OFFSETTING: --multicore_offsetting
          "(Optional) Enable multicore offsetting for greater accuracy at a performance cost")
         (@arg DRIFT_THRESHOLD: --drift_thresh +takes_value {is_usize} requires[MULTICORE_OFFSETTING]
@@ -73,6 +159,59 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         (@arg DISABLE_ZSWAP: --disable_zswap
          "(Optional; not recommended) Disable zswap, forcing the hypervisor to \
          actually swap to disk")
+        (@arg ZSWAP_ZPOOL: --zswap_zpool +takes_value
+         "(Optional) The zpool allocator of zswap (e.g. zbud or z3fold; default: ztier).")
+        (@arg ZSWAP_MAX_POOL_PERCENT: --zswap_max_pool_percent +takes_value {is_usize}
+         "(Optional) Set the maximum percentage of memory that zswap can use (default: 50).")
+        (@arg ZSWAP_COMPRESSOR: --zswap_compressor +takes_value
+         "(Optional) The compression algorithm of zswap (e.g. lz4; default: the kernel's \
+          default).")
+        (@arg SCREENSHOTS: --screenshots +takes_value {is_usize}
+         "(Optional) Capture a screenshot of the guest console every given number of seconds \
+          during the workload (for debugging hangs).")
+        (@arg TELEMETRY: --telemetry +takes_value {is_usize}
+         "(Optional) Sample the host's RAPL energy counters and temperatures every given number \
+          of seconds during the workload (to detect thermal throttling).")
+        (@arg SYS_TELEMETRY: --sys_telemetry +takes_value {is_usize}
+         "(Optional) Sample the memory, vmstat, zswap, KSM, and CPU frequency stats of the host \
+          and guest every given number of seconds during the workload.")
+        (@arg PERF: --perf +takes_value possible_values(PerfMode::NAMES)
+         "(Optional) Run `perf stat` or `perf record` on the host during the workload.")
+        (@arg PERF_EVENTS: --perf_events +takes_value requires[PERF]
+         "(Optional) A comma-separated list of events for `perf` (default: cycles, cache and TLB \
+          misses, page faults, context switches, vmscan:*, and kvm:*).")
+        (@arg PERF_DURATION: --perf_duration +takes_value {is_usize} requires[PERF]
+         "(Optional) How long to run `perf` in seconds from the start of the workload \
+          (default: 60).")
+        (@arg PERF_GUEST: --perf_guest requires[PERF]
+         "(Optional) Run `perf` in the guest rather than on the host.")
+        (@arg PERF_REPORT: --perf_report requires[PERF]
+         "(Optional) With `--perf record`, also save the output of `perf report --stdio`.")
+        (@arg STAGE_DATASET: --stage_dataset +takes_value
+         "(Optional) The path of a dataset in the guest to stage before the measured workload, to \
+          exclude cold-start effects. For memcached and redis, the dataset is loaded into the \
+          server; otherwise, it is read into the page cache. Staging time is recorded separately.")
+        (@arg ENFORCE_PINNING: --enforce_pinning
+         "(Optional) Fail if a workload's observed CPU affinity does not match the requested \
+          pinning, rather than just recording it.")
+        (@arg HOST_BOOT_PARAM: --host_boot_param +takes_value ... number_of_values(1)
+         {is_boot_param}
+         "(Optional) Boot the host with the given kernel parameter (e.g. `mitigations=off` or \
+          `transparent_hugepage=never`). Can be given multiple times.")
+        (@arg GUEST_BOOT_PARAM: --guest_boot_param +takes_value ... number_of_values(1)
+         {is_boot_param}
+         "(Optional) Boot the guest with the given kernel parameter (e.g. \
+          `numa_balancing=disable`). Can be given multiple times.")
+        (@arg BASELINE: --baseline
+         conflicts_with[spec17 GUEST_BOOT_PARAM DISABLE_ZSWAP ZSWAP_ZPOOL ZSWAP_MAX_POOL_PERCENT
+                        ZSWAP_COMPRESSOR MULTICORE_OFFSETTING SCREENSHOTS PERF_GUEST]
+         "(Optional) Run the workload directly on the host, without 0sim, a VM, or zswap, for \
+          comparison with simulation. The workload is pinned to the same number of cores. \
+          Requires the workload to be built on the host (e.g. `setup00000 --host_bmks`).")
+        (@arg DEV: --dev
+         "(Optional) Fast iteration mode for developing experiments: skip rebooting the host and \
+          setting up swapping and zswap if the host is still set up from the last run. Timing \
+          results are not reliable in this mode.")
     }
 }
 
@@ -83,7 +222,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
 
-    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<usize>().unwrap();
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
     let cores = sub_m.value_of("CORES").unwrap().parse::<usize>().unwrap();
 
     let workload = if sub_m.is_present("memcached") {
@@ -96,6 +235,16 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         Workload::TimeMmapTouch
     } else if sub_m.is_present("counter") {
         Workload::TimeMmapTouch
+    } else if sub_m.is_present("gapbs") {
+        Workload::Gapbs
+    } else if sub_m.is_present("spec17") {
+        Workload::Spec2017
+    } else if sub_m.is_present("mongo") {
+        Workload::Mongo
+    } else if sub_m.value_of("ycsb") == Some("memcached") {
+        Workload::YcsbMemcached
+    } else if sub_m.value_of("ycsb") == Some("redis") {
+        Workload::YcsbRedis
     } else {
         unreachable!();
     };
@@ -112,7 +261,26 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
     let size = sub_m
         .value_of("SIZE")
-        .map(|value| value.parse::<usize>().unwrap());
+        .map(|value| value.parse::<Size>().unwrap());
+    let mem_limit = sub_m
+        .value_of("MEM_LIMIT")
+        .map(|value| value.parse::<Size>().unwrap());
+    let antagonists: Vec<Antagonist> = sub_m
+        .values_of("ANTAGONIST")
+        .map(|values| {
+            values
+                .map(|value| value.parse::<Antagonist>().unwrap())
+                .collect()
+        })
+        .unwrap_or_default();
+    let host_boot_params: Vec<String> = sub_m
+        .values_of("HOST_BOOT_PARAM")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    let guest_boot_params: Vec<String> = sub_m
+        .values_of("GUEST_BOOT_PARAM")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
     let warmup = sub_m.is_present("WARMUP");
     let prefault = sub_m.is_present("PREFAULT");
 
@@ -123,19 +291,89 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         .value_of("DELAY")
         .map(|value| value.parse::<usize>().unwrap());
 
-    let disable_zswap = sub_m.is_present("DISABLE_ZSWAP");
+    let baseline = sub_m.is_present("BASELINE");
+    let mut zswap = ZswapConfig::from_args(sub_m);
+    if baseline {
+        zswap.enabled = false;
+    }
+
+    let gapbs_kernel = sub_m
+        .value_of("gapbs")
+        .map(|value| value.parse::<GapbsKernel>().unwrap());
+    let gapbs_scale = sub_m
+        .value_of("GAPBS_SCALE")
+        .map(|value| value.parse::<usize>().unwrap());
+
+    let spec17_benchmarks = sub_m.value_of("spec17").map(|value| {
+        value
+            .split(',')
+            .map(|bmk| bmk.trim().to_owned())
+            .collect::<Vec<_>>()
+    });
+
+    let ycsb = sub_m.is_present("ycsb");
+    let ycsb_records = sub_m
+        .value_of("YCSB_RECORDS")
+        .map(|value| value.parse::<usize>().unwrap());
+    let ycsb_ops = sub_m
+        .value_of("YCSB_OPS")
+        .map(|value| value.parse::<usize>().unwrap());
+    let ycsb_distribution = sub_m
+        .value_of("YCSB_DISTRIBUTION")
+        .unwrap_or("zipfian")
+        .parse::<YcsbDistribution>()
+        .unwrap();
+    let ycsb_mix = sub_m
+        .value_of("YCSB_MIX")
+        .unwrap_or("0.5,0.5,0")
+        .parse::<YcsbMix>()
+        .unwrap();
 
     let multicore_offsetting = sub_m.is_present("MULTICORE_OFFSETTING");
 
+    let screenshot_interval = sub_m
+        .value_of("SCREENSHOTS")
+        .map(|value| value.parse::<usize>().unwrap());
+
+    let stage_dataset = sub_m.value_of("STAGE_DATASET");
+
+    let telemetry_interval = sub_m
+        .value_of("TELEMETRY")
+        .map(|value| value.parse::<usize>().unwrap());
+
+    let sys_telemetry_interval = sub_m
+        .value_of("SYS_TELEMETRY")
+        .map(|value| value.parse::<usize>().unwrap());
+
+    let perf_mode = sub_m
+        .value_of("PERF")
+        .map(|value| value.parse::<PerfMode>().unwrap());
+    let perf_events: Vec<String> = sub_m
+        .value_of("PERF_EVENTS")
+        .map(|value| value.split(',').map(String::from).collect())
+        .unwrap_or_else(|| DEFAULT_PERF_EVENTS.iter().map(|&e| e.into()).collect());
+    let perf_duration = sub_m
+        .value_of("PERF_DURATION")
+        .map(|value| value.parse::<usize>().unwrap())
+        .unwrap_or(60);
+    let perf_guest = sub_m.is_present("PERF_GUEST");
+    let perf_report = sub_m.is_present("PERF_REPORT");
+
+    let enforce_pinning = sub_m.is_present("ENFORCE_PINNING");
+
+    let dev = sub_m.is_present("DEV");
+
     let ushell = SshShell::with_default_key(login.username, login.host)?;
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "bmk",
         * app: workload,
         exp: 0,
+        (baseline) baseline: baseline,
 
         * vm_size: vm_size,
         (cores > 1) cores: cores,
@@ -143,14 +381,40 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         prefault: prefault,
 
         (size.is_some()) size: size,
+        (mem_limit.is_some()) mem_limit: mem_limit,
+        antagonists: antagonists,
         calibrated: false,
         warmup: warmup,
 
-        (disable_zswap) disable_zswap: disable_zswap,
+        (!host_boot_params.is_empty()) host_boot_params: host_boot_params,
+        (!guest_boot_params.is_empty()) guest_boot_params: guest_boot_params,
+
+        (gapbs_kernel.is_some()) gapbs_kernel: gapbs_kernel,
+        gapbs_scale: gapbs_scale,
+        spec17_benchmarks: spec17_benchmarks,
+        ycsb_records: ycsb_records,
+        ycsb_ops: ycsb_ops,
+        ycsb_distribution: if ycsb { Some(ycsb_distribution) } else { None },
+        ycsb_mix: if ycsb { Some(ycsb_mix) } else { None },
 
         (multicore_offsetting) multicore_offsetting: multicore_offsetting,
 
-        zswap_max_pool_percent: 50,
+        screenshot_interval: screenshot_interval,
+        telemetry_interval: telemetry_interval,
+        sys_telemetry_interval: sys_telemetry_interval,
+        (perf_mode.is_some()) perf_mode: perf_mode,
+        perf_events: perf_events,
+        perf_duration: perf_duration,
+        perf_guest: perf_guest,
+        perf_report: perf_report,
+        stage_dataset: stage_dataset,
+        enforce_pinning: enforce_pinning,
+        (dev) dev: dev,
+        vm_backend: crate::common::exp_0sim::vm_backend(),
+        fresh_guest: crate::common::exp_0sim::fresh_guest(),
+        guest_kernel: crate::common::guest_kernel::get(),
+
+        zswap: zswap,
         (zerosim_drift_threshold.is_some()) zerosim_drift_threshold: zerosim_drift_threshold,
         (zerosim_delay.is_some()) zerosim_delay: zerosim_delay,
 
@@ -159,11 +423,17 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         local_git_hash: local_git_hash,
         remote_git_hash: remote_git_hash,
+        runner_invocation: crate::common::runner_invocation(),
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
-    run_inner(print_results_path, &login, settings)
+    // If the host reboots unexpectedly, mark the run invalid.
+    let results_file = settings.gen_file_name("results.json");
+    let res = run_inner(print_results_path, &login, settings);
+    crate::common::boot::invalidate_if_rebooted(&login, &results_file, res)
 }
 
 /// Run the experiment using the settings passed. Note that because the only thing we are passed
@@ -177,85 +447,172 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let workload = settings.get::<Workload>("app");
     let pattern = settings.get::<Option<TimeMmapTouchPattern>>("pattern");
-    let size = settings.get::<Option<usize>>("size");
+    let size = settings.get::<Option<Size>>("size");
+    let mem_limit = settings.get::<Option<Size>>("mem_limit");
+    let antagonists = settings.get::<Vec<Antagonist>>("antagonists");
     let warmup = settings.get::<bool>("warmup");
     let prefault = settings.get::<bool>("prefault");
+    let enforce_pinning = settings.get::<bool>("enforce_pinning");
     let calibrate = settings.get::<bool>("calibrated");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
     let zerosim_drift_threshold = settings.get::<Option<usize>>("zerosim_drift_threshold");
     let zerosim_delay = settings.get::<Option<usize>>("zerosim_delay");
-    let disable_zswap = settings.get::<bool>("disable_zswap");
     let multicore_offsetting = settings.get::<bool>("multicore_offsetting");
+    let screenshot_interval = settings.get::<Option<usize>>("screenshot_interval");
+    let telemetry_interval = settings.get::<Option<usize>>("telemetry_interval");
+    let sys_telemetry_interval = settings.get::<Option<usize>>("sys_telemetry_interval");
+    let perf_mode = settings.get::<Option<PerfMode>>("perf_mode");
+    let perf_events = settings.get::<Vec<String>>("perf_events");
+    let perf_duration = settings.get::<usize>("perf_duration");
+    let perf_guest = settings.get::<bool>("perf_guest");
+    let perf_report = settings.get::<bool>("perf_report");
+    let stage_dataset = settings.get::<Option<String>>("stage_dataset");
+    let gapbs_kernel = settings.get::<Option<GapbsKernel>>("gapbs_kernel");
+    let gapbs_scale = settings.get::<Option<usize>>("gapbs_scale");
+    let spec17_benchmarks = settings.get::<Option<Vec<String>>>("spec17_benchmarks");
+    let ycsb_records = settings.get::<Option<usize>>("ycsb_records");
+    let ycsb_ops = settings.get::<Option<usize>>("ycsb_ops");
+    let ycsb_distribution = settings.get::<Option<YcsbDistribution>>("ycsb_distribution");
+    let ycsb_mix = settings.get::<Option<YcsbMix>>("ycsb_mix");
+    let host_boot_params = settings
+        .get::<Vec<String>>("host_boot_params")
+        .iter()
+        .map(|param| param.parse::<BootParam>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let guest_boot_params = settings
+        .get::<Vec<String>>("guest_boot_params")
+        .iter()
+        .map(|param| param.parse::<BootParam>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let baseline = settings.get::<bool>("baseline");
+    let dev = settings.get::<bool>("dev");
+
+    // Set the boot parameters of the host before the initial reboot so that it boots with them.
+    // `start_vm` sets those of the guest.
+    let host_needs_reboot = crate::common::boot_params::set_host(
+        &SshShell::with_default_key(login.username, &login.host)?,
+        &host_boot_params,
+    )?;
+    crate::common::boot_params::set_guest(guest_boot_params);
+
+    // In dev mode, reuse the host as-is if it is still set up from the last run and doesn't need
+    // to be rebooted for its boot parameters.
+    let prepared = if dev && !host_needs_reboot {
+        connect_to_prepared_host(login)?
+    } else {
+        None
+    };
+    let already_prepared = prepared.is_some();
 
-    // Reboot
-    initial_reboot(&login)?;
+    let mut ushell = if let Some(ushell) = prepared {
+        ushell
+    } else {
+        // Reboot
+        if baseline {
+            initial_reboot_no_vagrant(login)?;
+        } else {
+            initial_reboot(&login)?;
+        }
 
-    // Connect to host
-    let mut ushell = connect_and_setup_host_only(&login)?;
+        // Connect to host
+        let ushell = connect_and_setup_host_only(login)?;
 
-    // Turn on SSDSWAP.
-    if !disable_zswap {
-        turn_on_ssdswap(&ushell)?;
-    }
+        // Turn on SSDSWAP.
+        if zswap.enabled {
+            turn_on_ssdswap(&ushell)?;
+        }
 
-    // Collect timers on VM
-    let mut timers = vec![];
+        ushell
+    };
 
-    // Start and connect to VM
-    let vshell = time!(
-        timers,
-        "Start VM",
-        start_vagrant(
-            &ushell,
-            &login.host,
-            vm_size,
-            cores,
-            /* fast */ true,
-            ZEROSIM_SKIP_HALT,
-            ZEROSIM_LAPIC_ADJUST,
-        )?
-    );
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00000");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
+
+    // Keep track of the memory and IO activity of each phase on the host.
+    let mut snapshots = PhaseSnapshots::new();
+
+    // Start and connect to VM. In baseline mode, the workload runs on the host instead, so the
+    // "guest" shell is just another shell on the host.
+    let vshell = if baseline {
+        SshShell::with_default_key(login.username, &login.host)?
+    } else {
+        time!(
+            timers,
+            "Start VM",
+            start_vagrant(
+                &ushell,
+                &login.host,
+                vm_size,
+                cores,
+                /* fast */ true,
+                ZEROSIM_SKIP_HALT,
+                ZEROSIM_LAPIC_ADJUST,
+            )?
+        )
+    };
 
     // Environment
-    if !disable_zswap {
-        ZeroSim::turn_on_zswap(&mut ushell)?;
+    if !baseline {
+        ZeroSim::zswap(&mut ushell, &zswap)?;
     }
 
-    if let Some(threshold) = zerosim_drift_threshold {
-        ZeroSim::threshold(&ushell, threshold)?;
-    }
-    if let Some(delay) = zerosim_delay {
-        ZeroSim::delay(&ushell, delay)?;
+    if !already_prepared {
+        save_host_fingerprint(&ushell)?;
     }
-    ZeroSim::multicore_offsetting(&ushell, multicore_offsetting)?;
-    if multicore_offsetting {
-        ZeroSim::sync_guest_tsc(&ushell)?;
+
+    if !baseline {
+        if let Some(threshold) = zerosim_drift_threshold {
+            ZeroSim::threshold(&ushell, threshold)?;
+        }
+        if let Some(delay) = zerosim_delay {
+            ZeroSim::delay(&ushell, delay)?;
+        }
+        ZeroSim::multicore_offsetting(&ushell, multicore_offsetting)?;
+        if multicore_offsetting {
+            ZeroSim::sync_guest_tsc(&ushell)?;
+        }
     }
 
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    // Make sure the boot parameters took effect, and record what the kernels actually booted with.
+    let cmdlines = Cmdlines::collect(
+        &ushell,
+        if baseline { None } else { Some(&vshell) },
+        &host_boot_params,
+    )?;
+
+    // Where the workload finds the research workspace and writes its results, and who runs it.
+    let (home, guest_results_dir, user) = if baseline {
+        (
+            get_user_home_dir(&ushell)?,
+            HOSTNAME_SHARED_RESULTS_DIR,
+            login.username,
+        )
+    } else {
+        ("/home/vagrant".to_owned(), VAGRANT_RESULTS_DIR, "vagrant")
+    };
+    let home = home.as_str();
 
-    let zerosim_exp_path = &dir!(
-        "/home/vagrant",
-        RESEARCH_WORKSPACE_PATH,
-        ZEROSIM_EXPERIMENTS_SUBMODULE
-    );
+    let zerosim_exp_path = &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_EXPERIMENTS_SUBMODULE);
 
     let size = if let Some(size) = size {
-        size // GB
+        size
     } else {
         // Get the amount of memory the guest thinks it has (in KB).
         let size = vshell
             .run(cmd!("grep MemAvailable /proc/meminfo | awk '{{print $2}}'").use_bash())?
             .stdout;
-        size.trim().parse::<usize>().unwrap() >> 20 // turn into GB
+        Size::from_kb(size.trim().parse::<usize>().unwrap())
     };
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -266,18 +623,29 @@ where
     let (output_file, params_file, time_file, sim_file) = settings.gen_standard_names();
     let params = serde_json::to_string(&settings)?;
 
-    vshell.run(cmd!(
-        "echo '{}' > {}",
-        escape_for_bash(&params),
-        dir!(VAGRANT_RESULTS_DIR, params_file)
-    ))?;
+    let sink = OutputSink::auto(&ushell, if baseline { None } else { Some(&vshell) })?;
+    sink.write(&params_file, &params)?;
+
+    // Keep the Vagrantfile the VM was started with for provenance.
+    if !baseline {
+        ushell.run(cmd!(
+            "cp {} {}",
+            dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY, "Vagrantfile"),
+            dir!(
+                HOSTNAME_SHARED_RESULTS_DIR,
+                settings.gen_file_name("Vagrantfile")
+            )
+        ))?;
+    }
 
+    crate::workloads::set_enforce_pinning(enforce_pinning);
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         //const WARM_UP_SIZE: usize = 50; // GB
         const WARM_UP_PATTERN: TimeMmapTouchPattern = TimeMmapTouchPattern::Zeros;
+        snapshots.begin(&ushell)?;
         time!(
             timers,
             "Warmup",
@@ -285,129 +653,503 @@ where
                 &vshell,
                 &TimeMmapTouchConfig {
                     exp_dir: zerosim_exp_path,
-                    pages: (size << 30) >> 12,
+                    pages: size.pages(),
                     pattern: WARM_UP_PATTERN,
                     prefault: false,
                     pf_time: None,
                     output_file: None,
-                    eager: false,
+                    opts: WorkloadOptions::default(),
                     pin_core: tctx.next(),
                 }
             )?
         );
+        snapshots.end(&ushell, "Warmup")?;
     }
 
     // We want to use rdtsc as the time source, so find the cpu freq:
     let freq = get_cpu_freq(&ushell)?;
 
-    // Run memcached or time_touch_mmap
-    match workload {
-        Workload::TimeMmapTouch => {
-            time!(
-                timers,
-                "Workload",
-                run_time_mmap_touch(
-                    &vshell,
-                    &TimeMmapTouchConfig {
-                        exp_dir: zerosim_exp_path,
-                        pages: (size << 30) >> 12,
-                        pattern: pattern.unwrap(),
-                        prefault: prefault,
-                        pf_time: None,
-                        output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                        eager: false,
-                        pin_core: tctx.next(),
-                    }
-                )?
-            );
-        }
+    // Start capturing screenshots of the guest console if needed.
+    let screenshots = if let Some(interval) = screenshot_interval {
+        Some(ConsoleScreenshots::start(
+            &ushell,
+            interval,
+            &dir!(
+                HOSTNAME_SHARED_RESULTS_DIR,
+                settings.gen_file_name("screenshots")
+            ),
+        )?)
+    } else {
+        None
+    };
 
-        Workload::Memcached => {
-            time!(
-                timers,
-                "Workload",
-                run_memcached_gen_data(
-                    &vshell,
-                    &MemcachedWorkloadConfig {
-                        user: "vagrant",
-                        exp_dir: zerosim_exp_path,
-                        memcached: &dir!(
-                            "/home/vagrant",
-                            RESEARCH_WORKSPACE_PATH,
-                            ZEROSIM_MEMCACHED_SUBMODULE
-                        ),
-                        server_size_mb: size << 10,
-                        wk_size_gb: size,
-                        freq: Some(freq),
-                        allow_oom: true,
-                        pf_time: None,
-                        output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                        eager: false,
-                        client_pin_core: tctx.next(),
-                        server_pin_core: None,
-                    }
-                )?
-            );
+    // Start sampling host power and temperature if needed.
+    let telemetry = if let Some(interval) = telemetry_interval {
+        Some(HostTelemetry::start(
+            &ushell,
+            interval,
+            &dir!(
+                HOSTNAME_SHARED_RESULTS_DIR,
+                settings.gen_file_name("telemetry.csv")
+            ),
+        )?)
+    } else {
+        None
+    };
+
+    // Start sampling the memory and CPU stats of the host and guest if needed.
+    let sys_telemetry = if let Some(interval) = sys_telemetry_interval {
+        Some((
+            SystemTelemetry::start(
+                &ushell,
+                interval,
+                &dir!(
+                    HOSTNAME_SHARED_RESULTS_DIR,
+                    settings.gen_file_name("sys_telemetry_host.csv")
+                ),
+            )?,
+            SystemTelemetry::start(
+                &vshell,
+                interval,
+                &dir!(
+                    guest_results_dir,
+                    settings.gen_file_name("sys_telemetry_guest.csv")
+                ),
+            )?,
+        ))
+    } else {
+        None
+    };
+
+    // Sample the guest TSC offset so that clock jumps show up in the sim output.
+    let offset_sampler = if baseline {
+        None
+    } else {
+        Some(GuestOffsetSampler::start(
+            &ushell,
+            &vshell,
+            GUEST_OFFSET_SAMPLE_INTERVAL,
+        )?)
+    };
+
+    // Stage the dataset into the guest page cache if needed. memcached and redis load it into the
+    // server after starting it instead (see below).
+    if let Some(dataset) = &stage_dataset {
+        match workload {
+            Workload::Memcached | Workload::Redis => {}
+            Workload::YcsbMemcached | Workload::YcsbRedis => {}
+            _ if !phases.should_run("Staging") => {}
+            _ => {
+                snapshots.begin(&ushell)?;
+                time!(
+                    timers,
+                    "Staging",
+                    run_stage_dataset(&vshell, dataset, StagingTarget::PageCache)?
+                );
+                snapshots.end(&ushell, "Staging")?;
+            }
         }
+    }
+
+    // Limit the memory of the workload if needed.
+    let cgroup = if let Some(limit) = mem_limit {
+        Some(MemoryCgroup::create(&vshell, WORKLOAD_CGROUP, limit)?)
+    } else {
+        None
+    };
+
+    // Start background antagonists if needed. They run until the workload finishes.
+    let antagonists = if antagonists.is_empty() {
+        None
+    } else {
+        Some(Antagonists::start(
+            &vshell,
+            &antagonists,
+            &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_MEMHOG_SUBMODULE),
+            &mut tctx,
+        )?)
+    };
 
-        Workload::Redis => {
-            time!(
-                timers,
-                "Start and Workload",
-                run_redis_gen_data(
-                    &vshell,
-                    &RedisWorkloadConfig {
-                        exp_dir: zerosim_exp_path,
-                        server_size_mb: size << 10,
-                        wk_size_gb: size,
-                        freq: Some(freq),
-                        pf_time: None,
-                        output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                        eager: false,
-                        client_pin_core: tctx.next(),
-                        server_pin_core: None,
-                        redis_conf: &dir!("/home/vagrant", RESEARCH_WORKSPACE_PATH, REDIS_CONF),
-                        nullfs: &dir!(
-                            "/home/vagrant",
-                            RESEARCH_WORKSPACE_PATH,
-                            ZEROSIM_NULLFS_SUBMODULE
-                        )
-                    }
-                )?
-                .wait_for_client()?
-            );
+    // Start `perf` if needed. In the guest, it gets its own core; on the host, it uses the last
+    // core so that it stays out of the way of the vcpus.
+    let (perf_shell, perf_results_dir) = if perf_guest {
+        (&vshell, guest_results_dir)
+    } else {
+        (&ushell, HOSTNAME_SHARED_RESULTS_DIR)
+    };
+    let perf = if let Some(mode) = perf_mode {
+        let pin_core = if perf_guest {
+            tctx.next()
+        } else {
+            crate::common::get_num_cores(&ushell)? - 1
+        };
+        Some(PerfSession::start(
+            perf_shell,
+            &PerfConfig {
+                perf: "perf",
+                mode,
+                events: &perf_events,
+                cpus: None,
+                interval_ms: if mode == PerfMode::Stat {
+                    Some(1000)
+                } else {
+                    None
+                },
+                delay_ms: None,
+                duration: perf_duration,
+                pin_core,
+                output_file: &dir!(perf_results_dir, settings.gen_file_name("perf")),
+            },
+        )?)
+    } else {
+        None
+    };
+
+    // The run time of each SPEC benchmark, if SPEC is run.
+    let mut spec17_results = None;
+
+    // Run memcached or time_touch_mmap
+    snapshots.begin(&ushell)?;
+
+    // The workload gets its own connection to the guest, so that it can be reconnected if it drops.
+    // Only this connection is reconnected: if the drop also breaks `ushell`, `vshell`, or the
+    // background loops using them, the run still fails after the workload.
+    //
+    // Each attempt starts from the same state: the same vCPU assignments, no servers left by the
+    // interrupted attempt, and no timings or snapshots of its phases. Each workload overwrites its
+    // output file, so it can just be rerun.
+    let mut wshell = SshShell::from_existing(&vshell)?;
+    let tctx_start = tctx.clone();
+    let timers_start = timers.num_phases();
+    let snapshots_start = snapshots.num_phases();
+    let mut attempt = 0;
+    with_reconnect(&mut wshell, "Workload", |vshell| {
+        if attempt > 0 {
+            tctx = tctx_start.clone();
+            timers.truncate(timers_start);
+            snapshots.truncate(snapshots_start);
+            snapshots.begin(&ushell)?;
+            kill_servers(vshell)?;
         }
+        attempt += 1;
 
-        Workload::MatrixMult2 => {
-            time!(
-                timers,
-                "Workload",
-                run_metis_matrix_mult(
-                    &vshell,
-                    &dir!(
-                        "/home/vagrant",
-                        RESEARCH_WORKSPACE_PATH,
-                        ZEROSIM_METIS_SUBMODULE
-                    ),
-                    ((size << 27) as f64).sqrt() as usize,
-                    /* eager */ false,
-                    &mut tctx,
-                )?
-                .1
-                .join()?
-            );
+        match workload {
+            _ if !phases.should_run("Workload") => {}
+
+            Workload::TimeMmapTouch => {
+                time!(
+                    timers,
+                    "Workload",
+                    run_time_mmap_touch(
+                        vshell,
+                        &TimeMmapTouchConfig {
+                            exp_dir: zerosim_exp_path,
+                            pages: size.pages(),
+                            pattern: pattern.unwrap(),
+                            prefault: prefault,
+                            pf_time: None,
+                            output_file: Some(&sink.path(&output_file)),
+                            opts: WorkloadOptions::default(),
+                            pin_core: tctx.next(),
+                        }
+                    )?
+                );
+            }
+
+            Workload::Memcached => {
+                let cfg = MemcachedWorkloadConfig {
+                    user,
+                    exp_dir: zerosim_exp_path,
+                    memcached: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_MEMCACHED_SUBMODULE),
+                    server_size: size,
+                    wk_size: size,
+                    freq: Some(freq),
+                    allow_oom: true,
+                    pf_time: None,
+                    output_file: Some(&sink.path(&output_file)),
+                    opts: WorkloadOptions {
+                        cgroup: cgroup.as_ref(),
+                        ..Default::default()
+                    },
+                    client_pin_core: tctx.next(),
+                    server_pin_core: None,
+                };
+
+                // If there is a dataset, it is loaded into the server as its own phase, so that
+                // it is not counted as part of the workload.
+                if let Some(dataset) = &stage_dataset {
+                    time!(timers, "Start and Staging", {
+                        start_memcached(vshell, &cfg)?;
+                        run_stage_dataset(vshell, dataset, StagingTarget::Memcached)?
+                    });
+                    snapshots.end(&ushell, "Start and Staging")?;
+                    snapshots.begin(&ushell)?;
+
+                    time!(
+                        timers,
+                        "Workload",
+                        run_memcached_gen_data_client(vshell, &cfg)?
+                    );
+                } else {
+                    time!(timers, "Workload", run_memcached_gen_data(vshell, &cfg)?);
+                }
+            }
+
+            Workload::Redis => {
+                let cfg = RedisWorkloadConfig {
+                    exp_dir: zerosim_exp_path,
+                    server_size: size,
+                    wk_size: size,
+                    freq: Some(freq),
+                    pf_time: None,
+                    output_file: Some(&sink.path(&output_file)),
+                    opts: WorkloadOptions {
+                        cgroup: cgroup.as_ref(),
+                        ..Default::default()
+                    },
+                    client_pin_core: tctx.next(),
+                    server_pin_core: None,
+                    tcp_port: None,
+                    redis_conf: &dir!(home, RESEARCH_WORKSPACE_PATH, REDIS_CONF),
+                    nullfs: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_NULLFS_SUBMODULE),
+                };
+
+                // As for memcached, a dataset is loaded into the server as its own phase.
+                if let Some(dataset) = &stage_dataset {
+                    let server = time!(timers, "Start and Staging", {
+                        let server = start_redis(vshell, &cfg)?;
+                        run_stage_dataset(vshell, dataset, StagingTarget::Redis)?;
+                        server
+                    });
+                    snapshots.end(&ushell, "Start and Staging")?;
+                    snapshots.begin(&ushell)?;
+
+                    time!(
+                        timers,
+                        "Workload",
+                        run_redis_gen_data_client(vshell, &cfg, server)?.wait_for_client()?
+                    );
+                } else {
+                    time!(
+                        timers,
+                        "Start and Workload",
+                        run_redis_gen_data(vshell, &cfg)?.wait_for_client()?
+                    );
+                }
+            }
+
+            Workload::MatrixMult2 => {
+                time!(
+                    timers,
+                    "Workload",
+                    run_metis_matrix_mult(
+                        vshell,
+                        &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_METIS_SUBMODULE),
+                        ((size.bytes() >> 3) as f64).sqrt() as usize,
+                        Some(&sink.path(&output_file)),
+                        &WorkloadOptions {
+                            cgroup: cgroup.as_ref(),
+                            ..Default::default()
+                        },
+                        &mut tctx,
+                    )?
+                    .join()?
+                );
+            }
+
+            Workload::Gapbs => {
+                time!(
+                    timers,
+                    "Workload",
+                    run_gapbs(
+                        vshell,
+                        &GapbsWorkloadConfig {
+                            gapbs: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_GAPBS_PATH),
+                            kernel: gapbs_kernel.unwrap(),
+                            scale: gapbs_scale.unwrap_or_else(|| gapbs_scale_for_size(size)),
+                            trials: GAPBS_TRIALS,
+                            pin_core: tctx.next(),
+                            output_file: Some(&sink.path(&output_file)),
+                            opts: WorkloadOptions::default(),
+                        }
+                    )?
+                );
+            }
+
+            Workload::Spec2017 => {
+                spec17_results = Some(time!(
+                    timers,
+                    "Workload",
+                    run_spec_2017(
+                        vshell,
+                        &Spec2017WorkloadConfig {
+                            spec_dir: SPEC_2017_GUEST_PATH,
+                            config: SPEC_2017_CONFIG,
+                            benchmarks: spec17_benchmarks.as_ref().unwrap(),
+                            input_size: "ref",
+                            pin_core: tctx.next(),
+                            output_file: Some(&sink.path(&output_file)),
+                            opts: WorkloadOptions::default(),
+                        }
+                    )?
+                ));
+            }
+
+            Workload::Mongo => {
+                time!(
+                    timers,
+                    "Workload",
+                    run_mongo_gen_data(
+                        vshell,
+                        &MongoWorkloadConfig {
+                            ycsb: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_YCSB_PATH),
+                            server_size: size,
+                            wk_size: size,
+                            output_file: Some(&sink.path(&output_file)),
+                            server_pin_core: None,
+                            client_pin_core: tctx.next(),
+                            opts: WorkloadOptions::default(),
+                        }
+                    )?
+                );
+            }
+
+            Workload::YcsbMemcached | Workload::YcsbRedis => {
+                // YCSB's default records are 10 fields of 100 bytes each.
+                let record_count = ycsb_records.unwrap_or(size.kb());
+
+                let memcached_cfg = MemcachedWorkloadConfig {
+                    user,
+                    exp_dir: zerosim_exp_path,
+                    memcached: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_MEMCACHED_SUBMODULE),
+                    server_size: size,
+                    wk_size: size,
+                    freq: None,
+                    allow_oom: true,
+                    pf_time: None,
+                    output_file: None,
+                    opts: WorkloadOptions {
+                        cgroup: cgroup.as_ref(),
+                        ..Default::default()
+                    },
+                    client_pin_core: 0,
+                    server_pin_core: None,
+                };
+                let redis_cfg = RedisWorkloadConfig {
+                    exp_dir: zerosim_exp_path,
+                    server_size: size,
+                    wk_size: size,
+                    freq: None,
+                    pf_time: None,
+                    output_file: None,
+                    opts: WorkloadOptions {
+                        cgroup: cgroup.as_ref(),
+                        ..Default::default()
+                    },
+                    client_pin_core: 0,
+                    server_pin_core: None,
+                    tcp_port: Some(YCSB_REDIS_PORT),
+                    redis_conf: &dir!(home, RESEARCH_WORKSPACE_PATH, REDIS_CONF),
+                    nullfs: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_NULLFS_SUBMODULE),
+                };
+                let server = if let Workload::YcsbMemcached = workload {
+                    YcsbServer::Memcached(&memcached_cfg)
+                } else {
+                    YcsbServer::Redis(&redis_cfg)
+                };
+
+                time!(
+                    timers,
+                    "Workload",
+                    run_ycsb(
+                        vshell,
+                        server,
+                        &YcsbWorkloadConfig {
+                            ycsb: &dir!(home, RESEARCH_WORKSPACE_PATH, ZEROSIM_YCSB_PATH),
+                            record_count,
+                            operation_count: ycsb_ops.unwrap_or(record_count),
+                            distribution: ycsb_distribution.unwrap(),
+                            mix: ycsb_mix.unwrap(),
+                            client_pin_core: tctx.next(),
+                            output_file: Some(&sink.path(&output_file)),
+                        }
+                    )?
+                );
+            }
         }
+
+        Ok(())
+    })?;
+
+    snapshots.end(&ushell, "Workload")?;
+
+    if let Some(antagonists) = antagonists {
+        antagonists.stop(&vshell)?;
     }
 
+    if let Some(perf) = perf {
+        let report_file = if perf_report {
+            Some(dir!(
+                perf_results_dir,
+                settings.gen_file_name("perf.report")
+            ))
+        } else {
+            None
+        };
+        perf.finish(perf_shell, report_file.as_deref())?;
+    }
+
+    let cgroup_stats = if let Some(cgroup) = &cgroup {
+        Some(cgroup.stats(&vshell)?)
+    } else {
+        None
+    };
+
     ushell.run(cmd!("date"))?;
 
-    vshell.run(cmd!(
-        "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
-        dir!(VAGRANT_RESULTS_DIR, time_file)
-    ))?;
+    if let Some(screenshots) = screenshots {
+        screenshots.stop()?;
+    }
+
+    if let Some(telemetry) = telemetry {
+        telemetry.stop()?;
+    }
+
+    if let Some((host_telemetry, guest_telemetry)) = sys_telemetry {
+        host_telemetry.stop()?;
+        guest_telemetry.stop()?;
+    }
+
+    if let Some(offset_sampler) = offset_sampler {
+        offset_sampler.stop()?;
+    }
+
+    sink.write(&time_file, timers.report())?;
+
+    let results = serde_json::json!({
+        "workloads": crate::workloads::recorded_invocations(),
+        "pinning": crate::workloads::recorded_pinning_checks(),
+        "output_checks": crate::workloads::recorded_output_checks(),
+        "staging": crate::workloads::recorded_stagings(),
+        "phase_resources": snapshots.phases(),
+        "spec17": spec17_results,
+        "cgroup": cgroup_stats,
+        "cmdline": cmdlines,
+    });
+    sink.write(
+        &settings.gen_file_name("results.json"),
+        &serde_json::to_string(&results)?,
+    )?;
+
+    if !baseline {
+        crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
+    }
+
+    if phases.is_partial() {
+        sink.write(&settings.gen_file_name("partial"), phases.report())?;
+    }
 
-    crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
+    crate::common::index::append(&ushell, &settings, &timers)?;
 
     if print_results_path {
         let glob = settings.gen_file_name("*");
@@ -11,14 +11,16 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        size::{is_vm_size, Size},
     },
     settings,
     workloads::{
         run_locality_mem_access, run_time_loop, run_time_mmap_touch, LocalityMemAccessConfig,
-        LocalityMemAccessMode, TimeMmapTouchConfig, TimeMmapTouchPattern,
+        LocalityMemAccessMode, TimeMmapTouchConfig, TimeMmapTouchPattern, WorkloadOptions,
     },
 };
 
@@ -52,8 +54,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         (@arg N: +required +takes_value {is_usize}
          "The number of iterations of the workload (e.g. 50000000), preferably \
           divisible by 8 for `locality_mem_access`")
-        (@arg VMSIZE: +takes_value {is_usize} -v --vm_size
-         "The number of GBs of the VM (defaults to 1024)")
+        (@arg VMSIZE: +takes_value {is_vm_size} -v --vm_size
+         "The size of the VM (e.g. 512G; a bare number is GB; defaults to 1024)")
         (@arg CORES: +takes_value {is_usize} -C --cores
          "The number of cores of the VM (defaults to 1)")
         (@arg WARMUP: -w --warmup
@@ -77,7 +79,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let n = sub_m.value_of("N").unwrap().parse::<usize>().unwrap();
     let vm_size = sub_m
         .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap());
+        .map(|value| value.parse::<Size>().unwrap());
     let cores = sub_m
         .value_of("CORES")
         .map(|value| value.parse::<usize>().unwrap());
@@ -86,7 +88,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let vm_size = if let Some(vm_size) = vm_size {
         vm_size
     } else {
-        VAGRANT_MEM
+        Size::from_gb(VAGRANT_MEM)
     };
 
     let cores = if let Some(cores) = cores {
@@ -113,6 +115,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: match workload {
@@ -130,7 +133,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         * vm_size: vm_size,
         cores: cores,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -140,6 +143,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         remote_research_settings: remote_research_settings,
 
+        deadline: deadline,
+
         // machine readable version for convenience
         workload_mr: workload,
     };
@@ -158,19 +163,22 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let warmup = settings.get::<bool>("warmup");
     let calibrate = settings.get::<bool>("calibrated");
     let n = settings.get::<usize>("n");
     let workload = settings.get::<Workload>("workload_mr");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
 
     // Reboot
     initial_reboot(&login)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00002");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Connect
     let (mut ushell, vshell) = time!(
@@ -186,8 +194,7 @@ where
     );
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     let zerosim_exp_path = &dir!(
         "/home/vagrant",
@@ -196,7 +203,7 @@ where
     );
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -216,7 +223,7 @@ where
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: TimeMmapTouchPattern = TimeMmapTouchPattern::Zeros;
         time!(
             timers,
@@ -230,7 +237,7 @@ where
                     prefault: false,
                     pf_time: None,
                     output_file: None,
-                    eager: false,
+                    opts: WorkloadOptions::default(),
                     pin_core: tctx.next(),
                 }
             )?
@@ -239,6 +246,8 @@ where
 
     // Then, run the actual experiment
     match workload {
+        _ if !phases.should_run("Workload") => {}
+
         Workload::TimeLoop => {
             time!(
                 timers,
@@ -248,7 +257,7 @@ where
                     zerosim_exp_path,
                     n,
                     &dir!(VAGRANT_RESULTS_DIR, output_file),
-                    /* eager */ false,
+                    &WorkloadOptions::default(),
                     &mut tctx,
                 )?
             );
@@ -267,7 +276,7 @@ where
                         n: n,
                         threads: None,
                         output_file: &dir!(VAGRANT_RESULTS_DIR, local_file),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     },
                 )?;
                 run_locality_mem_access(
@@ -278,7 +287,7 @@ where
                         n: n,
                         threads: None,
                         output_file: &dir!(VAGRANT_RESULTS_DIR, nonlocal_file),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     },
                 )?;
             });
@@ -297,7 +306,7 @@ where
                         n: n,
                         threads: Some(threads),
                         output_file: &dir!(VAGRANT_RESULTS_DIR, local_file),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     },
                 )?;
                 run_locality_mem_access(
@@ -308,7 +317,7 @@ where
                         n: n,
                         threads: Some(threads),
                         output_file: &dir!(VAGRANT_RESULTS_DIR, nonlocal_file),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     },
                 )?;
             });
@@ -319,12 +328,22 @@ where
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -17,13 +17,15 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        size::{is_size, is_vm_size, Size},
     },
     settings,
     setup00001::GUEST_SWAP_GBS,
-    workloads::{run_memcached_and_capture_thp, MemcachedWorkloadConfig},
+    workloads::{run_memcached_and_capture_thp, MemcachedWorkloadConfig, WorkloadOptions},
 };
 
 /// Interval at which to collect thp stats
@@ -43,12 +45,12 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg VMSIZE: +required +takes_value {is_usize}
-         "The number of GBs of the VM (e.g. 500)")
+        (@arg VMSIZE: +required +takes_value {is_vm_size}
+         "The size of the VM (e.g. 500G; a bare number is GB). Must be a multiple of 1GB.")
         (@arg CORES: -C --cores +takes_value {is_usize}
          "(Optional) The number of cores of the VM (defaults to 1)")
-        (@arg SIZE: -s --size +takes_value {is_usize}
-         "(Optional) The number of GBs of the workload (e.g. 500). Defaults to VMSIZE + 10")
+        (@arg SIZE: -s --size +takes_value {is_size}
+         "(Optional) The size of the workload (e.g. 500G or 512M; a bare number is GB). Defaults to VMSIZE + 10")
         (@arg CONTINUAL: --continual_compaction +takes_value {is_usize}
          "(Optional) Enables continual compaction via spurious failures of the given mode")
     }
@@ -60,16 +62,16 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         hostname: sub_m.value_of("HOSTNAME").unwrap(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
-    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<usize>().unwrap();
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
 
     let size = if let Some(size) = sub_m
         .value_of("SIZE")
-        .map(|value| value.parse::<usize>().unwrap())
+        .map(|value| value.parse::<Size>().unwrap())
     {
         size
     } else {
         // Just a bit smaller so we don't OOM
-        vm_size + GUEST_SWAP_GBS - 1
+        Size::from_gb(vm_size.gb() + GUEST_SWAP_GBS - 1)
     };
 
     let cores = if let Some(cores) = sub_m
@@ -89,6 +91,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "memcached_per_page_thp_ops",
@@ -101,7 +104,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         * vm_size: vm_size,
         cores: cores,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         transparent_hugepage_enabled: "always",
         transparent_hugepage_defrag: "always",
@@ -116,6 +119,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -132,11 +137,11 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
-    let size = settings.get::<usize>("size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
+    let size = settings.get::<Size>("size");
     let cores = settings.get::<usize>("cores");
     let calibrate = settings.get::<bool>("calibrated");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
     let transparent_hugepage_enabled = settings.get::<&str>("transparent_hugepage_enabled");
     let transparent_hugepage_defrag = settings.get::<&str>("transparent_hugepage_defrag");
     let transparent_hugepage_khugepaged_defrag =
@@ -150,8 +155,11 @@ where
     // Reboot
     initial_reboot(&login)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00003");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Connect
     let (mut ushell, vshell) = time!(
@@ -167,8 +175,7 @@ where
     );
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     // Mount guest swap space
     let research_settings = crate::common::get_remote_research_settings(&ushell)?;
@@ -183,7 +190,7 @@ where
     );
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -213,45 +220,57 @@ where
 
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
-    time!(
-        timers,
-        "Start and Workload",
-        run_memcached_and_capture_thp(
-            &vshell,
-            &MemcachedWorkloadConfig {
-                user: "vagrant",
-                exp_dir: zerosim_exp_path,
-                memcached: &dir!(
-                    "/home/vagrant",
-                    RESEARCH_WORKSPACE_PATH,
-                    ZEROSIM_MEMCACHED_SUBMODULE
-                ),
-                server_size_mb: size << 10,
-                wk_size_gb: size,
-                allow_oom: false,
-                output_file: Some(&dir!(VAGRANT_RESULTS_DIR, memcached_timing_file)),
-                eager: false,
-                client_pin_core: tctx.next(),
-                server_pin_core: None,
-                freq: None,
-                pf_time: None,
-            },
-            INTERVAL,
-            continual_compaction,
-            &dir!(VAGRANT_RESULTS_DIR, output_file),
-        )?
-    );
+    if phases.should_run("Workload") {
+        time!(
+            timers,
+            "Start and Workload",
+            run_memcached_and_capture_thp(
+                &vshell,
+                &MemcachedWorkloadConfig {
+                    user: "vagrant",
+                    exp_dir: zerosim_exp_path,
+                    memcached: &dir!(
+                        "/home/vagrant",
+                        RESEARCH_WORKSPACE_PATH,
+                        ZEROSIM_MEMCACHED_SUBMODULE
+                    ),
+                    server_size: size,
+                    wk_size: size,
+                    allow_oom: false,
+                    output_file: Some(&dir!(VAGRANT_RESULTS_DIR, memcached_timing_file)),
+                    opts: WorkloadOptions::default(),
+                    client_pin_core: tctx.next(),
+                    server_pin_core: None,
+                    freq: None,
+                    pf_time: None,
+                },
+                INTERVAL,
+                continual_compaction,
+                &dir!(VAGRANT_RESULTS_DIR, output_file),
+            )?
+        );
+    }
 
     ushell.run(cmd!("date"))?;
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
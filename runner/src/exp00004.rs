@@ -10,30 +10,30 @@ use spurs::{cmd, Execute, SshShell};
 use spurs_util::escape_for_bash;
 
 use crate::{
-    common::{exp_0sim::*, get_user_home_dir, output::OutputManager, paths::*},
+    common::{
+        deadline::PhaseGuard,
+        exp_0sim::*,
+        get_user_home_dir,
+        output::{OutputManager, Progress},
+        paths::*,
+        size::{is_size, Size},
+    },
     settings,
-    workloads::{run_memcached_and_capture_thp, MemcachedWorkloadConfig},
+    workloads::{run_memcached_and_capture_thp, MemcachedWorkloadConfig, WorkloadOptions},
 };
 
 /// Interval at which to collect thp stats
 const INTERVAL: usize = 60; // seconds
 
 pub fn cli_options() -> clap::App<'static, 'static> {
-    fn is_usize(s: String) -> Result<(), String> {
-        s.as_str()
-            .parse::<usize>()
-            .map(|_| ())
-            .map_err(|e| format!("{:?}", e))
-    }
-
     clap_app! { exp00004 =>
         (about: "Run experiment 00004. Requires `sudo`.")
         (@arg HOSTNAME: +required +takes_value
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg SIZE: +required +takes_value {is_usize}
-         "The number of GBs of the workload (e.g. 500)")
+        (@arg SIZE: +required +takes_value {is_size}
+         "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
     }
 }
 
@@ -43,12 +43,13 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         hostname: sub_m.value_of("HOSTNAME").unwrap(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
-    let size = sub_m.value_of("SIZE").unwrap().parse::<usize>().unwrap();
+    let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
 
     let ushell = SshShell::with_default_key(&login.username, &login.host)?;
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "memcached_thp_ops_per_page_bare_metal",
@@ -69,6 +70,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -85,7 +88,7 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let size = settings.get::<usize>("size");
+    let size = settings.get::<Size>("size");
     let transparent_hugepage_enabled = settings.get::<&str>("transparent_hugepage_enabled");
     let transparent_hugepage_defrag = settings.get::<&str>("transparent_hugepage_defrag");
     let transparent_hugepage_khugepaged_defrag =
@@ -108,8 +111,11 @@ where
         ZEROSIM_EXPERIMENTS_SUBMODULE
     );
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00004");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     let (output_file, params_file, time_file, _sim_file) = settings.gen_standard_names();
     let params = serde_json::to_string(&settings)?;
@@ -140,30 +146,32 @@ where
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Run workload
-    time!(
-        timers,
-        "Setup and Workload",
-        run_memcached_and_capture_thp(
-            &ushell,
-            &MemcachedWorkloadConfig {
-                user: login.username,
-                exp_dir: zerosim_exp_path,
-                memcached: &dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_MEMCACHED_SUBMODULE),
-                server_size_mb: size << 10,
-                wk_size_gb: size,
-                allow_oom: true,
-                output_file: None,
-                eager: false,
-                client_pin_core: tctx.next(),
-                server_pin_core: None,
-                freq: None,
-                pf_time: None,
-            },
-            INTERVAL,
-            /* continual_compaction */ None,
-            &dir!(setup00000::HOSTNAME_SHARED_RESULTS_DIR, output_file),
-        )?
-    );
+    if phases.should_run("Workload") {
+        time!(
+            timers,
+            "Setup and Workload",
+            run_memcached_and_capture_thp(
+                &ushell,
+                &MemcachedWorkloadConfig {
+                    user: login.username,
+                    exp_dir: zerosim_exp_path,
+                    memcached: &dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_MEMCACHED_SUBMODULE),
+                    server_size: size,
+                    wk_size: size,
+                    allow_oom: true,
+                    output_file: None,
+                    opts: WorkloadOptions::default(),
+                    client_pin_core: tctx.next(),
+                    server_pin_core: None,
+                    freq: None,
+                    pf_time: None,
+                },
+                INTERVAL,
+                /* continual_compaction */ None,
+                &dir!(setup00000::HOSTNAME_SHARED_RESULTS_DIR, output_file),
+            )?
+        );
+    }
 
     ushell.run(cmd!("date"))?;
 
@@ -171,10 +179,23 @@ where
 
     ushell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(setup00000::HOSTNAME_SHARED_RESULTS_DIR, time_file)
     ))?;
 
+    if phases.is_partial() {
+        ushell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(
+                setup00000::HOSTNAME_SHARED_RESULTS_DIR,
+                settings.gen_file_name("partial")
+            )
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -10,13 +10,16 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        size::{is_vm_size, Size},
     },
     settings,
     workloads::{
         run_nas_cg, run_time_mmap_touch, NasClass, TimeMmapTouchConfig, TimeMmapTouchPattern,
+        WorkloadOptions,
     },
 };
 
@@ -38,8 +41,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The length of time to run the workload in seconds.")
         (@arg WARMUP: -w --warmup
          "Pass this flag to warmup the VM before running the main workload.")
-        (@arg VMSIZE: +takes_value {is_usize}
-         "The number of GBs of the VM (defaults to 2048)")
+        (@arg VMSIZE: +takes_value {is_vm_size}
+         "The size of the VM (e.g. 512G; a bare number is GB; defaults to 2048)")
         (@arg CORES: +takes_value {is_usize} -C --cores
          "The number of cores of the VM (defaults to 1)")
     }
@@ -60,7 +63,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
     let vm_size = sub_m
         .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap());
+        .map(|value| value.parse::<Size>().unwrap());
     let cores = sub_m
         .value_of("CORES")
         .map(|value| value.parse::<usize>().unwrap());
@@ -70,7 +73,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         vm_size
     } else {
         // NAS class E is ~2TB
-        2048
+        Size::from_gb(2048)
     };
 
     let cores = if let Some(cores) = cores {
@@ -83,6 +86,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "nas_cg_class_e",
@@ -96,7 +100,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         duration: duration,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -105,6 +109,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -122,11 +128,11 @@ where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
     let duration = settings.get::<usize>("duration");
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let warmup = settings.get::<bool>("warmup");
     let calibrate = settings.get::<bool>("calibrated");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
 
     // Reboot
     initial_reboot(&login)?;
@@ -137,8 +143,11 @@ where
     // Turn on SSDSWAP.
     turn_on_ssdswap(&ushell)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00005");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Start and connect to VM
     let vshell = time!(
@@ -156,8 +165,7 @@ where
     );
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     let zerosim_exp_path = &dir!(
         "/home/vagrant",
@@ -171,7 +179,7 @@ where
     );
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -191,7 +199,7 @@ where
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: TimeMmapTouchPattern = TimeMmapTouchPattern::Zeros;
         time!(
             timers,
@@ -205,65 +213,78 @@ where
                     prefault: false,
                     pf_time: None,
                     output_file: None,
-                    eager: false,
+                    opts: WorkloadOptions::default(),
                     pin_core: tctx.next(),
                 }
             )?
         );
     }
 
-    // Record vmstat on guest
-    let vmstat_file = settings.gen_file_name("vmstat");
-    let (_shell, _vmstats_handle) = vshell.spawn(
-        cmd!(
-            "for (( c=1 ; c<={} ; c++ )) ; do \
-             cat /proc/vmstat >> {} ; sleep 1 ; done",
-            duration,
-            dir!(VAGRANT_RESULTS_DIR, vmstat_file)
-        )
-        .use_bash(),
-    )?;
-
-    // The workload takes a very long time, so we only use the first 2 hours (of wall-clock time).
-    // We start this thread that collects stats in the background and terminates after the given
-    // amount of time. We spawn the workload, but don't wait for it; rather, we wait for this task.
-    let zswapstats_file = settings.gen_file_name("zswapstats");
-    let (_shell, zswapstats_handle) = ushell.spawn(
-        cmd!(
-            "for (( c=1 ; c<={} ; c++ )) ; do \
-             sudo tail `sudo find  /sys/kernel/debug/zswap/ -type f`\
-             >> {} ; sleep 1 ; done",
-            duration,
-            dir!(HOSTNAME_SHARED_RESULTS_DIR, zswapstats_file)
-        )
-        .use_bash(),
-    )?;
-
-    time!(timers, "Background stats collection", {
-        let _ = run_nas_cg(
-            &vshell,
-            zerosim_bmk_path,
-            NasClass::F,
-            Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-            /* eager */ false,
-            &mut tctx,
+    if phases.should_run("Workload") {
+        // Record vmstat on guest
+        let vmstat_file = settings.gen_file_name("vmstat");
+        let (_shell, _vmstats_handle) = vshell.spawn(
+            cmd!(
+                "for (( c=1 ; c<={} ; c++ )) ; do \
+                 cat /proc/vmstat >> {} ; sleep 1 ; done",
+                duration,
+                dir!(VAGRANT_RESULTS_DIR, vmstat_file)
+            )
+            .use_bash(),
         )?;
 
-        std::thread::sleep(std::time::Duration::from_secs(duration as u64));
+        // The workload takes a very long time, so we only use the first 2 hours (of wall-clock
+        // time). We start this thread that collects stats in the background and terminates after
+        // the given amount of time. We spawn the workload, but don't wait for it; rather, we wait
+        // for this task.
+        let zswapstats_file = settings.gen_file_name("zswapstats");
+        let (_shell, zswapstats_handle) = ushell.spawn(
+            cmd!(
+                "for (( c=1 ; c<={} ; c++ )) ; do \
+                 sudo tail `sudo find  /sys/kernel/debug/zswap/ -type f`\
+                 >> {} ; sleep 1 ; done",
+                duration,
+                dir!(HOSTNAME_SHARED_RESULTS_DIR, zswapstats_file)
+            )
+            .use_bash(),
+        )?;
+
+        time!(timers, "Background stats collection", {
+            let _ = run_nas_cg(
+                &vshell,
+                zerosim_bmk_path,
+                NasClass::F,
+                Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
+                &WorkloadOptions::default(),
+                &mut tctx,
+            )?;
 
-        zswapstats_handle.join()?
-    });
+            std::thread::sleep(std::time::Duration::from_secs(duration as u64));
+
+            zswapstats_handle.join()?
+        });
+    }
 
     ushell.run(cmd!("date"))?;
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -9,7 +9,13 @@ use spurs::{cmd, Execute, SshShell};
 use spurs_util::escape_for_bash;
 
 use crate::{
-    common::{exp_0sim::*, output::OutputManager, paths::setup00000::*},
+    common::{
+        deadline::PhaseGuard,
+        exp_0sim::*,
+        output::{OutputManager, Progress},
+        paths::setup00000::*,
+        size::{is_vm_size, Size},
+    },
     settings,
 };
 
@@ -27,8 +33,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg VMSIZE: +takes_value {is_usize} +required
-         "The number of GBs of the VM")
+        (@arg VMSIZE: +takes_value {is_vm_size} +required
+         "The size of the VM (e.g. 512G; a bare number is GB)")
         (@arg CORES: +takes_value {is_usize} +required
          "The number of cores of the VM")
         (@group KTASK_DIV =>
@@ -49,7 +55,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         hostname: sub_m.value_of("HOSTNAME").unwrap(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
-    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<usize>().unwrap();
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
     let cores = sub_m.value_of("CORES").unwrap().parse::<usize>().unwrap();
     let ktask_div = sub_m.value_of("DIV").map(|s| s.parse::<usize>().unwrap());
 
@@ -57,6 +63,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: if ktask_div.is_some() { "ktask_boot_mem_init" } else { "boot_mem_init" },
@@ -74,6 +81,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -90,12 +99,15 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let ktask_div = settings.get::<Option<usize>>("ktask_div");
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00006");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // We first need to set the guest kernel boot param.
     if let Some(ktask_div) = ktask_div {
@@ -130,6 +142,36 @@ where
     // Connect
     let ushell = connect_and_setup_host_only(&login)?;
 
+    let (output_file, params_file, time_file, sim_file) = settings.gen_standard_names();
+    let params = serde_json::to_string(&settings)?;
+
+    // The instrumentation is collected while the VM boots, so if the deadline passes before then,
+    // there is nothing left to do but record the settings.
+    if !phases.should_run("Start VM") {
+        ushell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&params),
+            dir!(HOSTNAME_SHARED_RESULTS_DIR, params_file)
+        ))?;
+        ushell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(
+                HOSTNAME_SHARED_RESULTS_DIR,
+                settings.gen_file_name("partial")
+            )
+        ))?;
+
+        crate::common::index::append(&ushell, &settings, &timers)?;
+
+        if print_results_path {
+            let glob = settings.gen_file_name("*");
+            println!("RESULTS: {}", glob);
+        }
+
+        return Ok(());
+    }
+
     let vshell = time!(
         timers,
         "Start VM",
@@ -144,9 +186,6 @@ where
         )?
     );
 
-    let (output_file, params_file, time_file, sim_file) = settings.gen_standard_names();
-    let params = serde_json::to_string(&settings)?;
-
     vshell.run(cmd!(
         "echo '{}' > {}",
         escape_for_bash(&params),
@@ -162,12 +201,14 @@ where
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -13,15 +13,18 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
         get_cpu_freq,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        size::{is_vm_size, Size},
     },
     settings,
     workloads::{
         run_memcached_gen_data, run_memhog, run_metis_matrix_mult, run_mix, run_nas_cg,
         run_redis_gen_data, MemcachedWorkloadConfig, MemhogOptions, NasClass, RedisWorkloadConfig,
+        WorkloadOptions,
     },
 };
 
@@ -68,8 +71,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         )
         (@arg WARMUP: -w --warmup
          "Pass this flag to warmup the VM before running the main workload.")
-        (@arg VMSIZE: +takes_value {is_usize} --vm_size
-         "The number of GBs of the VM (defaults to 2048)")
+        (@arg VMSIZE: +takes_value {is_vm_size} --vm_size
+         "The size of the VM (e.g. 512G; a bare number is GB; defaults to 2048)")
         (@arg CORES: +takes_value {is_usize} -C --cores
          "The number of cores of the VM (defaults to 1)")
         (@arg EAGER_PAGING: --eager
@@ -107,12 +110,12 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
     let vm_size = if let Some(vm_size) = sub_m
         .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap())
+        .map(|value| value.parse::<Size>().unwrap())
     {
         vm_size
     } else {
         // NAS class F is ~2TB
-        2048
+        Size::from_gb(2048)
     };
 
     let cores = if let Some(cores) = sub_m
@@ -132,6 +135,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "fragmentation",
@@ -148,7 +152,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         stats_interval: interval,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -157,6 +161,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -175,12 +181,16 @@ where
 {
     let workload = settings.get::<Workload>("app");
     let interval = settings.get::<usize>("stats_interval");
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let calibrate = settings.get::<bool>("calibrated");
     let warmup = settings.get::<bool>("warmup");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
     let eager = settings.get::<bool>("eager");
+    let opts = WorkloadOptions {
+        eager,
+        ..Default::default()
+    };
 
     // Reboot
     initial_reboot(&login)?;
@@ -191,11 +201,14 @@ where
     // Turn on SSDSWAP.
     turn_on_ssdswap(&ushell)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00007");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     // Start and connect to VM
     let vshell = time!(
@@ -218,8 +231,6 @@ where
         .stdout;
     let size = size.trim().parse::<usize>().unwrap();
 
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
-
     let zerosim_exp_path = &dir!(
         "/home/vagrant",
         RESEARCH_WORKSPACE_PATH,
@@ -232,7 +243,7 @@ where
     );
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -256,7 +267,7 @@ where
     ))?;
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: &str = "-z";
         time!(
             timers,
@@ -305,6 +316,8 @@ where
 
     // Run the actual workload
     match workload {
+        _ if !phases.should_run("Workload") => {}
+
         Workload::Memcached => {
             time!(
                 timers,
@@ -319,13 +332,13 @@ where
                             RESEARCH_WORKSPACE_PATH,
                             ZEROSIM_MEMCACHED_SUBMODULE
                         ),
-                        server_size_mb: size >> 10,
-                        wk_size_gb: size >> 20,
+                        server_size: Size::from_kb(size),
+                        wk_size: Size::from_kb(size),
                         freq: Some(freq),
                         allow_oom: true,
                         pf_time: None,
                         output_file: None,
-                        eager: eager,
+                        opts: opts.clone(),
                         client_pin_core: tctx.next(),
                         server_pin_core: None,
                     }
@@ -345,10 +358,10 @@ where
                         ZEROSIM_METIS_SUBMODULE
                     ),
                     ((size << 7) as f64).sqrt() as usize,
-                    eager,
+                    Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
+                    &opts,
                     &mut tctx,
                 )?
-                .1
                 .join()?
             );
         }
@@ -361,14 +374,15 @@ where
                     &vshell,
                     &RedisWorkloadConfig {
                         exp_dir: zerosim_exp_path,
-                        server_size_mb: size >> 10,
-                        wk_size_gb: size >> 20,
+                        server_size: Size::from_kb(size),
+                        wk_size: Size::from_kb(size),
                         freq: Some(freq),
                         pf_time: None,
                         output_file: None,
-                        eager: eager,
+                        opts: opts.clone(),
                         client_pin_core: tctx.next(),
                         server_pin_core: None,
+                        tcp_port: None,
                         redis_conf: &dir!("/home/vagrant", RESEARCH_WORKSPACE_PATH, REDIS_CONF),
                         nullfs: &dir!(
                             "/home/vagrant",
@@ -388,7 +402,7 @@ where
                     zerosim_bmk_path,
                     NasClass::F,
                     Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                    eager,
+                    &opts,
                     &mut tctx,
                 )?;
 
@@ -408,7 +422,7 @@ where
                     Some(MEMHOG_R),
                     size,
                     MemhogOptions::PIN | MemhogOptions::DATA_OBLIV,
-                    eager,
+                    &opts,
                     &mut tctx,
                 )?
                 .1
@@ -439,7 +453,7 @@ where
                     &dir!("/home/vagrant", RESEARCH_WORKSPACE_PATH, REDIS_CONF,),
                     freq,
                     size >> 20,
-                    eager,
+                    &opts,
                     &mut tctx,
                 )?
             });
@@ -457,12 +471,22 @@ where
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
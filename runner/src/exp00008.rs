@@ -10,14 +10,16 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, setup00001::*, *},
+        size::{is_vm_size, Size},
     },
     settings,
     workloads::{
         run_memcached_gen_data, run_memhog, run_nas_cg, MemcachedWorkloadConfig, MemhogOptions,
-        NasClass,
+        NasClass, WorkloadOptions,
     },
 };
 
@@ -84,8 +86,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         )
         (@arg WARMUP: -w --warmup
          "Pass this flag to warmup the VM before running the main workload.")
-        (@arg VMSIZE: +takes_value {is_usize} --vm_size
-         "The number of GBs of the VM (defaults to 2048)")
+        (@arg VMSIZE: +takes_value {is_vm_size} --vm_size
+         "The size of the VM (e.g. 512G; a bare number is GB; defaults to 2048)")
         (@arg CORES: +takes_value {is_usize} -C --cores
          "The number of cores of the VM (defaults to 1)")
         (@arg FACTOR: +takes_value {is_isize} -f --factor
@@ -118,12 +120,12 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
     let vm_size = if let Some(vm_size) = sub_m
         .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap())
+        .map(|value| value.parse::<Size>().unwrap())
     {
         vm_size
     } else {
         // NAS class E is ~2TB
-        2048
+        Size::from_gb(2048)
     };
 
     let cores = if let Some(cores) = sub_m
@@ -150,6 +152,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: format!("swap_{}", workload.to_str()),
@@ -165,7 +168,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         stats_interval: interval,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -174,6 +177,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -192,12 +197,12 @@ where
 {
     let workload = Workload::from_str(&settings.get::<&str>("workload")[5..]);
     let interval = settings.get::<usize>("stats_interval");
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let factor = settings.get::<isize>("factor");
     let calibrate = settings.get::<bool>("calibrated");
     let warmup = settings.get::<bool>("warmup");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
 
     // Reboot
     initial_reboot(&login)?;
@@ -208,11 +213,14 @@ where
     // Turn on SSDSWAP.
     turn_on_ssdswap(&ushell)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00008");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     // Start and connect to VM
     let vshell = time!(
@@ -250,8 +258,6 @@ where
     // trigger OOM killer.
     let size = mem_avail + (8 * swap_avail / 10); // KB
 
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
-
     let zerosim_exp_path = &dir!(
         "/home/vagrant",
         RESEARCH_WORKSPACE_PATH,
@@ -264,7 +270,7 @@ where
     );
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -292,7 +298,7 @@ where
     }
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: &str = "-z";
         time!(
             timers,
@@ -361,6 +367,8 @@ where
 
     // Run the actual workload
     match workload {
+        _ if !phases.should_run("Workload") => {}
+
         Workload::Memcached => {
             // Start workload
             time!(
@@ -376,13 +384,13 @@ where
                             RESEARCH_WORKSPACE_PATH,
                             ZEROSIM_MEMCACHED_SUBMODULE
                         ),
-                        server_size_mb: size >> 10,
-                        wk_size_gb: size >> 20,
+                        server_size: Size::from_kb(size),
+                        wk_size: Size::from_kb(size),
                         freq: Some(freq),
                         allow_oom: false,
                         pf_time: None,
                         output_file: None,
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                         client_pin_core: tctx.next(),
                         server_pin_core: None,
                     }
@@ -397,7 +405,7 @@ where
                     zerosim_bmk_path,
                     NasClass::F,
                     Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                    /* eager */ false,
+                    &WorkloadOptions::default(),
                     &mut tctx,
                 )?;
 
@@ -419,7 +427,7 @@ where
                     Some(MEMHOG_R),
                     size,
                     MemhogOptions::empty(),
-                    /* eager */ false,
+                    &WorkloadOptions::default(),
                     &mut tctx,
                 )?
             );
@@ -437,12 +445,22 @@ where
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
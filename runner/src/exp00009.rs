@@ -10,16 +10,18 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
         get_cpu_freq,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        size::{is_size, is_vm_size, Size},
         KernelBaseConfigSource, KernelConfig, KernelPkgType, KernelSrc,
     },
     settings,
     workloads::{
         run_memcached_gen_data, run_time_mmap_touch, MemcachedWorkloadConfig, TimeMmapTouchConfig,
-        TimeMmapTouchPattern,
+        TimeMmapTouchPattern, WorkloadOptions,
     },
 };
 
@@ -37,8 +39,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg VMSIZE: +required +takes_value {is_usize}
-         "The number of GBs of the VM (e.g. 500)")
+        (@arg VMSIZE: +required +takes_value {is_vm_size}
+         "The size of the VM (e.g. 500G; a bare number is GB). Must be a multiple of 1GB.")
         (@arg CORES: +required +takes_value {is_usize}
          "The number of cores of the VM")
         (@group PATTERN =>
@@ -52,8 +54,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         (@arg PREFAULT: -p --prefault
          "Pass this flag to prefault memory before running the main workload \
          (ignored for memcached).")
-        (@arg SIZE: -s --size +takes_value {is_usize}
-         "The number of GBs of the workload (e.g. 500)")
+        (@arg SIZE: -s --size +takes_value {is_size}
+         "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
     }
 }
 
@@ -64,7 +66,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
 
-    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<usize>().unwrap();
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
     let cores = sub_m.value_of("CORES").unwrap().parse::<usize>().unwrap();
 
     let pattern = if sub_m.is_present("memcached") {
@@ -79,7 +81,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
     let size = sub_m
         .value_of("SIZE")
-        .map(|value| value.parse::<usize>().unwrap());
+        .map(|value| value.parse::<Size>().unwrap());
     let warmup = sub_m.is_present("WARMUP");
     let prefault = sub_m.is_present("PREFAULT");
 
@@ -87,6 +89,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: if pattern.is_some() {
@@ -105,7 +108,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         calibrated: false,
         warmup: warmup,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -114,6 +117,8 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -130,14 +135,14 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
     let cores = settings.get::<usize>("cores");
     let pattern = settings.get::<Option<TimeMmapTouchPattern>>("pattern");
-    let size = settings.get::<Option<usize>>("size");
+    let size = settings.get::<Option<Size>>("size");
     let warmup = settings.get::<bool>("warmup");
     let prefault = settings.get::<bool>("prefault");
     let calibrate = settings.get::<bool>("calibrated");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
 
     // Reboot
     initial_reboot(&login)?;
@@ -148,8 +153,11 @@ where
     // Turn on SSDSWAP.
     turn_on_ssdswap(&ushell)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00009");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Start and connect to VM
     let vshell = time!(
@@ -167,8 +175,7 @@ where
     );
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     let zerosim_exp_path = &dir!(
         "/home/vagrant",
@@ -186,17 +193,17 @@ where
     ushell.run(cmd!("make clean").cwd(tarball_path))?;
 
     let size = if let Some(size) = size {
-        size // GB
+        size
     } else {
         // Get the amount of memory the guest thinks it has (in KB).
         let size = vshell
             .run(cmd!("grep MemAvailable /proc/meminfo | awk '{{print $2}}'").use_bash())?
             .stdout;
-        size.trim().parse::<usize>().unwrap() >> 20 // turn into GB
+        Size::from_kb(size.trim().parse::<usize>().unwrap())
     };
 
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -216,7 +223,7 @@ where
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Warm up
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: TimeMmapTouchPattern = TimeMmapTouchPattern::Zeros;
         time!(
             timers,
@@ -225,12 +232,12 @@ where
                 &vshell,
                 &TimeMmapTouchConfig {
                     exp_dir: zerosim_exp_path,
-                    pages: (size << 30) >> 12,
+                    pages: size.pages(),
                     pattern: WARM_UP_PATTERN,
                     prefault: false,
                     pf_time: None,
                     output_file: None,
-                    eager: false,
+                    opts: WorkloadOptions::default(),
                     pin_core: tctx.next(),
                 }
             )?
@@ -269,62 +276,74 @@ where
     });
 
     // Run memcached or time_touch_mmap
-    if let Some(pattern) = pattern {
-        time!(
-            timers,
-            "Workload",
-            run_time_mmap_touch(
-                &vshell,
-                &TimeMmapTouchConfig {
-                    exp_dir: zerosim_exp_path,
-                    pages: (size << 30) >> 12,
-                    pattern: pattern,
-                    prefault: prefault,
-                    pf_time: None,
-                    output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                    eager: false,
-                    pin_core: tctx.next(),
-                }
-            )?
-        );
-    } else {
-        time!(
-            timers,
-            "Workload",
-            run_memcached_gen_data(
-                &vshell,
-                &MemcachedWorkloadConfig {
-                    user: "vagrant",
-                    exp_dir: zerosim_exp_path,
-                    memcached: &dir!(
-                        "/home/vagrant",
-                        RESEARCH_WORKSPACE_PATH,
-                        ZEROSIM_MEMCACHED_SUBMODULE
-                    ),
-                    server_size_mb: size << 10,
-                    wk_size_gb: size,
-                    freq: Some(freq),
-                    allow_oom: true,
-                    pf_time: None,
-                    output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                    eager: false,
-                    client_pin_core: tctx.next(),
-                    server_pin_core: None,
-                }
-            )?
-        );
+    if phases.should_run("Workload") {
+        if let Some(pattern) = pattern {
+            time!(
+                timers,
+                "Workload",
+                run_time_mmap_touch(
+                    &vshell,
+                    &TimeMmapTouchConfig {
+                        exp_dir: zerosim_exp_path,
+                        pages: size.pages(),
+                        pattern: pattern,
+                        prefault: prefault,
+                        pf_time: None,
+                        output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
+                        opts: WorkloadOptions::default(),
+                        pin_core: tctx.next(),
+                    }
+                )?
+            );
+        } else {
+            time!(
+                timers,
+                "Workload",
+                run_memcached_gen_data(
+                    &vshell,
+                    &MemcachedWorkloadConfig {
+                        user: "vagrant",
+                        exp_dir: zerosim_exp_path,
+                        memcached: &dir!(
+                            "/home/vagrant",
+                            RESEARCH_WORKSPACE_PATH,
+                            ZEROSIM_MEMCACHED_SUBMODULE
+                        ),
+                        server_size: size,
+                        wk_size: size,
+                        freq: Some(freq),
+                        allow_oom: true,
+                        pf_time: None,
+                        output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
+                        opts: WorkloadOptions::default(),
+                        client_pin_core: tctx.next(),
+                        server_pin_core: None,
+                    }
+                )?
+            );
+        }
     }
 
     ushell.run(cmd!("date"))?;
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -7,15 +7,24 @@ use clap::clap_app;
 use serde::{Deserialize, Serialize};
 
 use spurs::{cmd, Execute, SshShell};
-use spurs_util::escape_for_bash;
 
 use crate::{
-    common::{exp_0sim::*, get_cpu_freq, get_user_home_dir, output::OutputManager, paths::*},
+    common::{
+        deadline::PhaseGuard,
+        exp_0sim::*,
+        get_cpu_freq, get_user_home_dir,
+        output::{OutputManager, OutputSink, Progress},
+        paths::*,
+        reconnect::with_reconnect,
+        size::{is_size, Size},
+        snapshot::PhaseSnapshots,
+        telemetry::HostTelemetry,
+    },
     settings,
     workloads::{
-        run_locality_mem_access, run_memcached_gen_data, run_time_loop, run_time_mmap_touch,
-        LocalityMemAccessConfig, LocalityMemAccessMode, MemcachedWorkloadConfig,
-        TimeMmapTouchConfig, TimeMmapTouchPattern,
+        kill_servers, run_locality_mem_access, run_memcached_gen_data, run_time_loop,
+        run_time_mmap_touch, LocalityMemAccessConfig, LocalityMemAccessMode,
+        MemcachedWorkloadConfig, TimeMmapTouchConfig, TimeMmapTouchPattern, WorkloadOptions,
     },
 };
 
@@ -28,11 +37,11 @@ enum Workload {
         n: usize,
     },
     TimeMmapTouch {
-        size: usize,
+        size: Size,
         pattern: TimeMmapTouchPattern,
     },
     Memcached {
-        size: usize,
+        size: Size,
     },
 }
 
@@ -50,6 +59,16 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
+        (@arg TELEMETRY: --telemetry +takes_value {is_usize}
+         "(Optional) Sample the host's RAPL energy counters and temperatures every given number \
+          of seconds during the workload (to detect thermal throttling).")
+        (@arg ENFORCE_PINNING: --enforce_pinning
+         "(Optional) Fail if a workload's observed CPU affinity does not match the requested \
+          pinning, rather than just recording it.")
+        (@arg DEV: --dev
+         "(Optional) Fast iteration mode for developing experiments: skip rebooting the host and \
+          setting up swapping if the host is still set up from the last run. Timing results are \
+          not reliable in this mode.")
         (@subcommand time_loop =>
             (about: "Run the `time_loop` workload.")
             (@arg N: +required +takes_value {is_usize}
@@ -64,8 +83,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         )
         (@subcommand time_mmap_touch =>
             (about: "Run the `time_mmap_touch` workload.")
-            (@arg SIZE: +required +takes_value {is_usize}
-             "The number of GBs of the workload (e.g. 500)")
+            (@arg SIZE: +required +takes_value {is_size}
+             "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
             (@group PATTERN =>
                 (@attributes +required)
                 (@arg zeros: -z "Fill pages with zeros")
@@ -74,8 +93,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
         )
         (@subcommand memcached =>
             (about: "Run the `memcached` workload.")
-            (@arg SIZE: +required +takes_value {is_usize}
-             "The number of GBs of the workload (e.g. 500)")
+            (@arg SIZE: +required +takes_value {is_size}
+             "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
         )
     }
 }
@@ -90,7 +109,13 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
     let (workload, workload_name, n, size, pattern) = match sub_m.subcommand() {
         ("time_loop", Some(sub_m)) => {
             let n = sub_m.value_of("N").unwrap().parse::<usize>().unwrap();
-            (Workload::TimeLoop { n }, "time_loop", n, 0, None)
+            (
+                Workload::TimeLoop { n },
+                "time_loop",
+                n,
+                Size::from_bytes(0),
+                None,
+            )
         }
 
         ("locality_mem_access", Some(sub_m)) => {
@@ -99,13 +124,13 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
                 Workload::LocalityMemAccess { n },
                 "locality_mem_access",
                 n,
-                0,
+                Size::from_bytes(0),
                 None,
             )
         }
 
         ("time_mmap_touch", Some(sub_m)) => {
-            let size = sub_m.value_of("SIZE").unwrap().parse::<usize>().unwrap();
+            let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
 
             let pattern = if sub_m.is_present("zeros") {
                 TimeMmapTouchPattern::Zeros
@@ -123,7 +148,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         }
 
         ("memcached", Some(sub_m)) => {
-            let size = sub_m.value_of("SIZE").unwrap().parse::<usize>().unwrap();
+            let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
 
             (Workload::Memcached { size }, "memcached", 0, size, None)
         }
@@ -131,10 +156,19 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         _ => unreachable!(),
     };
 
+    let telemetry_interval = sub_m
+        .value_of("TELEMETRY")
+        .map(|value| value.parse::<usize>().unwrap());
+
+    let enforce_pinning = sub_m.is_present("ENFORCE_PINNING");
+
+    let dev = sub_m.is_present("DEV");
+
     let ushell = SshShell::with_default_key(login.username, login.host)?;
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: "bare_metal",
@@ -142,7 +176,7 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         exp: 10,
 
         (n > 0) n: n,
-        (size > 0) size: size,
+        (size.bytes() > 0) size: size,
         (pattern.is_some()) pattern: match pattern {
             Some(TimeMmapTouchPattern::Zeros) => "zeros",
             Some(TimeMmapTouchPattern::Counter) => "counter",
@@ -150,6 +184,9 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
         },
 
         workload_settings: workload,
+        telemetry_interval: telemetry_interval,
+        enforce_pinning: enforce_pinning,
+        (dev) dev: dev,
 
         transparent_hugepage_enabled: "always",
         transparent_hugepage_defrag: "always",
@@ -162,11 +199,17 @@ pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(),
 
         local_git_hash: local_git_hash,
         remote_git_hash: remote_git_hash,
+        runner_invocation: crate::common::runner_invocation(),
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
-    run_inner(print_results_path, &login, settings)
+    // If the host reboots unexpectedly, mark the run invalid.
+    let results_file = settings.gen_file_name("results.json");
+    let res = run_inner(print_results_path, &login, settings);
+    crate::common::boot::invalidate_if_rebooted(&login, &results_file, res)
 }
 
 /// Run the experiment using the settings passed. Note that because the only thing we are passed
@@ -181,12 +224,28 @@ where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
     let workload = settings.get::<Workload>("workload_settings");
+    let telemetry_interval = settings.get::<Option<usize>>("telemetry_interval");
+    let enforce_pinning = settings.get::<bool>("enforce_pinning");
+    let dev = settings.get::<bool>("dev");
+
+    // In dev mode, reuse the host as-is if it is still set up from the last run.
+    let prepared = if dev {
+        connect_to_prepared_host(login)?
+    } else {
+        None
+    };
 
-    // Reboot
-    initial_reboot_no_vagrant(&login)?;
+    let ushell = if let Some(ushell) = prepared {
+        ushell
+    } else {
+        // Reboot
+        initial_reboot_no_vagrant(&login)?;
 
-    // Connect
-    let ushell = connect_and_setup_host_only(&login)?;
+        // Connect
+        let ushell = connect_and_setup_host_only(&login)?;
+        save_host_fingerprint(&ushell)?;
+        ushell
+    };
 
     let user_home = &get_user_home_dir(&ushell)?;
     let zerosim_exp_path = &dir!(
@@ -195,140 +254,158 @@ where
         ZEROSIM_EXPERIMENTS_SUBMODULE
     );
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00010");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
+
+    // Keep track of the memory and IO activity of each phase on the host.
+    let mut snapshots = PhaseSnapshots::new();
 
     let (output_file, params_file, time_file, _sim_file) = settings.gen_standard_names();
     let params = serde_json::to_string(&settings)?;
 
-    ushell.run(cmd!(
-        "echo '{}' > {}",
-        escape_for_bash(&params),
-        dir!(
-            user_home.as_str(),
-            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-            params_file
-        )
-    ))?;
+    let sink = OutputSink::auto(&ushell, None)?;
+    sink.write(&params_file, &params)?;
+
+    let output_path = sink.path(&output_file);
+    let local_path = sink.path(&settings.gen_file_name("local"));
+    let nonlocal_path = sink.path(&settings.gen_file_name("nonlocal"));
+    let telemetry_path = sink.path(&settings.gen_file_name("telemetry.csv"));
+
+    crate::workloads::set_enforce_pinning(enforce_pinning);
 
     let cores = crate::common::get_num_cores(&ushell)?;
     let mut tctx = crate::workloads::TasksetCtx::new(cores);
 
     // Run the workload.
-    match workload {
-        Workload::TimeLoop { n } => {
-            time!(
-                timers,
-                "Workload",
-                run_time_loop(
-                    &ushell,
-                    zerosim_exp_path,
-                    n,
-                    &dir!(
-                        user_home.as_str(),
-                        setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-                        output_file
-                    ),
-                    /* eager */ false,
-                    &mut tctx,
-                )?
-            );
-        }
-
-        Workload::LocalityMemAccess { n } => {
-            let local_file = settings.gen_file_name("local");
-            let nonlocal_file = settings.gen_file_name("nonlocal");
-
-            time!(timers, "Workload", {
-                run_locality_mem_access(
-                    &ushell,
-                    &LocalityMemAccessConfig {
-                        exp_dir: zerosim_exp_path,
-                        locality: LocalityMemAccessMode::Local,
-                        n: n,
-                        threads: None,
-                        output_file: &dir!(
-                            user_home.as_str(),
-                            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-                            local_file
-                        ),
-                        eager: false,
-                    },
-                )?;
-                run_locality_mem_access(
-                    &ushell,
-                    &LocalityMemAccessConfig {
-                        exp_dir: zerosim_exp_path,
-                        locality: LocalityMemAccessMode::Random,
-                        n: n,
-                        threads: None,
-                        output_file: &dir!(
-                            user_home.as_str(),
-                            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-                            nonlocal_file
-                        ),
-                        eager: false,
-                    },
-                )?;
-            });
-        }
-
-        Workload::TimeMmapTouch { size, pattern } => {
-            time!(
-                timers,
-                "Workload",
-                run_time_mmap_touch(
-                    &ushell,
-                    &TimeMmapTouchConfig {
-                        exp_dir: zerosim_exp_path,
-                        pages: (size << 30) >> 12,
-                        pattern: pattern,
-                        prefault: false,
-                        pf_time: None,
-                        output_file: Some(&dir!(
-                            user_home.as_str(),
-                            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-                            output_file
-                        )),
-                        eager: false,
-                        pin_core: tctx.next(),
-                    }
-                )?
-            );
-        }
-
-        Workload::Memcached { size } => {
-            let freq = get_cpu_freq(&ushell)?;
-
-            time!(
-                timers,
-                "Workload",
-                run_memcached_gen_data(
-                    &ushell,
-                    &MemcachedWorkloadConfig {
-                        user: login.username,
-                        exp_dir: zerosim_exp_path,
-                        memcached: &dir!(
-                            user_home.as_str(),
-                            RESEARCH_WORKSPACE_PATH,
-                            ZEROSIM_MEMCACHED_SUBMODULE
-                        ),
-                        server_size_mb: size << 10,
-                        wk_size_gb: size,
-                        freq: Some(freq),
-                        allow_oom: true,
-                        pf_time: None,
-                        output_file: Some(&dir!(
-                            user_home.as_str(),
-                            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-                            output_file
-                        )),
-                        eager: false,
-                        client_pin_core: tctx.next(),
-                        server_pin_core: None,
-                    }
-                )?
-            );
+    //
+    // The workload writes its output directly to the results directory on the host, so if the
+    // connection drops, we can just rerun it. It gets its own connection, which is the only one
+    // that is reconnected. Each attempt uses the same cores and starts without any servers left by
+    // the interrupted attempt.
+    if phases.should_run("Workload") {
+        snapshots.begin(&ushell)?;
+
+        // Sample host power and temperature if needed.
+        let telemetry = if let Some(interval) = telemetry_interval {
+            Some(HostTelemetry::start(&ushell, interval, &telemetry_path)?)
+        } else {
+            None
+        };
+
+        let mut wshell = SshShell::from_existing(&ushell)?;
+        let tctx_start = tctx.clone();
+        let mut attempt = 0;
+        with_reconnect(&mut wshell, "Workload", |ushell| {
+            if attempt > 0 {
+                tctx = tctx_start.clone();
+                kill_servers(ushell)?;
+            }
+            attempt += 1;
+
+            match workload {
+                Workload::TimeLoop { n } => {
+                    time!(
+                        timers,
+                        "Workload",
+                        run_time_loop(
+                            ushell,
+                            zerosim_exp_path,
+                            n,
+                            &output_path,
+                            &WorkloadOptions::default(),
+                            &mut tctx,
+                        )?
+                    );
+                }
+
+                Workload::LocalityMemAccess { n } => {
+                    time!(timers, "Workload", {
+                        run_locality_mem_access(
+                            ushell,
+                            &LocalityMemAccessConfig {
+                                exp_dir: zerosim_exp_path,
+                                locality: LocalityMemAccessMode::Local,
+                                n: n,
+                                threads: None,
+                                output_file: &local_path,
+                                opts: WorkloadOptions::default(),
+                            },
+                        )?;
+                        run_locality_mem_access(
+                            ushell,
+                            &LocalityMemAccessConfig {
+                                exp_dir: zerosim_exp_path,
+                                locality: LocalityMemAccessMode::Random,
+                                n: n,
+                                threads: None,
+                                output_file: &nonlocal_path,
+                                opts: WorkloadOptions::default(),
+                            },
+                        )?;
+                    });
+                }
+
+                Workload::TimeMmapTouch { size, pattern } => {
+                    time!(
+                        timers,
+                        "Workload",
+                        run_time_mmap_touch(
+                            ushell,
+                            &TimeMmapTouchConfig {
+                                exp_dir: zerosim_exp_path,
+                                pages: size.pages(),
+                                pattern: pattern,
+                                prefault: false,
+                                pf_time: None,
+                                output_file: Some(&output_path),
+                                opts: WorkloadOptions::default(),
+                                pin_core: tctx.next(),
+                            }
+                        )?
+                    );
+                }
+
+                Workload::Memcached { size } => {
+                    let freq = get_cpu_freq(ushell)?;
+
+                    time!(
+                        timers,
+                        "Workload",
+                        run_memcached_gen_data(
+                            ushell,
+                            &MemcachedWorkloadConfig {
+                                user: login.username,
+                                exp_dir: zerosim_exp_path,
+                                memcached: &dir!(
+                                    user_home.as_str(),
+                                    RESEARCH_WORKSPACE_PATH,
+                                    ZEROSIM_MEMCACHED_SUBMODULE
+                                ),
+                                server_size: size,
+                                wk_size: size,
+                                freq: Some(freq),
+                                allow_oom: true,
+                                pf_time: None,
+                                output_file: Some(&output_path),
+                                opts: WorkloadOptions::default(),
+                                client_pin_core: tctx.next(),
+                                server_pin_core: None,
+                            }
+                        )?
+                    );
+                }
+            }
+
+            Ok(())
+        })?;
+
+        snapshots.end(&ushell, "Workload")?;
+
+        if let Some(telemetry) = telemetry {
+            telemetry.stop()?;
         }
     }
 
@@ -336,15 +413,26 @@ where
 
     ushell.run(cmd!("free -h"))?;
 
-    ushell.run(cmd!(
-        "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
-        dir!(
-            user_home.as_str(),
-            setup00000::HOSTNAME_SHARED_RESULTS_DIR,
-            time_file
-        )
-    ))?;
+    let sink = OutputSink::auto(&ushell, None)?;
+
+    sink.write(&time_file, timers.report())?;
+
+    let results = serde_json::json!({
+        "workloads": crate::workloads::recorded_invocations(),
+        "pinning": crate::workloads::recorded_pinning_checks(),
+        "output_checks": crate::workloads::recorded_output_checks(),
+        "phase_resources": snapshots.phases(),
+    });
+    sink.write(
+        &settings.gen_file_name("results.json"),
+        &serde_json::to_string(&results)?,
+    )?;
+
+    if phases.is_partial() {
+        sink.write(&settings.gen_file_name("partial"), phases.report())?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
 
     if print_results_path {
         let glob = settings.gen_file_name("*");
@@ -0,0 +1,150 @@
+//! Characterize the swap devices of the remote with `fio`, so that simulation results can be
+//! correlated with the performance of the storage backing them.
+//!
+//! Requires `setup00000`.
+
+use clap::clap_app;
+
+use spurs::SshShell;
+
+use crate::common::{
+    deadline::PhaseGuard,
+    exp_0sim::*,
+    output::{OutputManager, OutputSink, Progress},
+    size::{is_size, Size},
+    swap_profile::profile_swap_devices,
+};
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_usize(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<usize>()
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    clap_app! { exp00011 =>
+        (about: "Run experiment 00011. Requires `sudo`.")
+        (@arg HOSTNAME: +required +takes_value
+         "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
+        (@arg USERNAME: +required +takes_value
+         "The username on the remote (e.g. markm)")
+        (@arg SIZE: --size +takes_value {is_size}
+         "(Optional) The amount of each device to use (e.g. 4G; a bare number is GB). \
+          Default: 4G.")
+        (@arg RUNTIME: --runtime +takes_value {is_usize}
+         "(Optional) How long to run each fio job in seconds. Default: 60.")
+    }
+}
+
+pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let login = Login {
+        username: sub_m.value_of("USERNAME").unwrap(),
+        hostname: sub_m.value_of("HOSTNAME").unwrap(),
+        host: sub_m.value_of("HOSTNAME").unwrap(),
+    };
+
+    let size = sub_m
+        .value_of("SIZE")
+        .unwrap_or("4G")
+        .parse::<Size>()
+        .unwrap();
+    let runtime = sub_m
+        .value_of("RUNTIME")
+        .map(|value| value.parse::<usize>().unwrap())
+        .unwrap_or(60);
+
+    let ushell = SshShell::with_default_key(login.username, login.host)?;
+    let local_git_hash = crate::common::local_research_workspace_git_hash()?;
+    let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
+    let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
+
+    let settings = settings! {
+        * workload: "swap_profile",
+        exp: 11,
+
+        size: size,
+        runtime: runtime,
+
+        username: login.username,
+        * host: login.hostname,
+
+        local_git_hash: local_git_hash,
+        remote_git_hash: remote_git_hash,
+        runner_invocation: crate::common::runner_invocation(),
+
+        remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
+    };
+
+    run_inner(print_results_path, &login, settings)
+}
+
+/// Run the experiment using the settings passed. Note that because the only thing we are passed
+/// are the settings, we know that there is no information that is not recorded in the settings
+/// file.
+fn run_inner<A>(
+    print_results_path: bool,
+    login: &Login<A>,
+    settings: OutputManager,
+) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let size = settings.get::<Size>("size");
+    let runtime = settings.get::<usize>("runtime");
+
+    // Reboot so that nothing else is using the devices.
+    initial_reboot_no_vagrant(login)?;
+    let ushell = connect_and_setup_host_only(login)?;
+
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00011");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
+
+    let profiles = if phases.should_run("Profile swap devices") {
+        let profiles = time!(
+            timers,
+            "Profile swap devices",
+            profile_swap_devices(&ushell, size, runtime)?
+        );
+
+        // Profiling overwrites the devices, so turn swapping back on.
+        setup_swapping(&ushell)?;
+
+        profiles
+    } else {
+        vec![]
+    };
+
+    let (_output_file, params_file, time_file, _sim_file) = settings.gen_standard_names();
+
+    let sink = OutputSink::auto(&ushell, None)?;
+    sink.write(&params_file, &serde_json::to_string(&settings)?)?;
+    sink.write(&time_file, timers.report())?;
+
+    let results = serde_json::json!({
+        "swap_devices": profiles,
+    });
+    sink.write(
+        &settings.gen_file_name("results.json"),
+        &serde_json::to_string(&results)?,
+    )?;
+
+    if phases.is_partial() {
+        sink.write(&settings.gen_file_name("partial"), phases.report())?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
+    if print_results_path {
+        let glob = settings.gen_file_name("*");
+        println!("RESULTS: {}", glob);
+    }
+
+    Ok(())
+}
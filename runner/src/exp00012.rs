@@ -0,0 +1,411 @@
+//! Run the same workload in simulation across a sweep of guest core counts (1, 2, 4, ..., N) in
+//! one invocation. The host is set up once, and the VM is restarted with the next core count
+//! between points, rather than requiring a separate runner invocation (and host reboot) per point.
+//! The results of all points are consolidated into a single results file.
+//!
+//! Requires `setup00000` followed by `setup00001`.
+
+use clap::clap_app;
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+use crate::{
+    common::{
+        deadline::PhaseGuard,
+        exp_0sim::*,
+        output::{OutputManager, OutputSink, Progress},
+        paths::{setup00000::*, *},
+        size::{is_size, is_vm_size, Size},
+    },
+    workloads::{
+        run_locality_mem_access, run_memcached_gen_data, run_time_mmap_touch,
+        LocalityMemAccessConfig, LocalityMemAccessMode, MemcachedWorkloadConfig,
+        TimeMmapTouchConfig, TimeMmapTouchPattern, WorkloadOptions,
+    },
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+enum Workload {
+    TimeMmapTouch {
+        size: Size,
+        pattern: TimeMmapTouchPattern,
+    },
+    Memcached {
+        size: Size,
+    },
+    /// Runs with one thread per guest core.
+    LocalityMemAccess {
+        n: usize,
+    },
+}
+
+/// The results of a single point of the sweep.
+#[derive(Debug, Serialize)]
+struct SweepPoint {
+    /// The number of guest cores.
+    cores: usize,
+    /// How long the workload took, in milliseconds (not counting starting the VM).
+    workload_ms: u128,
+    /// The output files of the workload.
+    output_files: Vec<String>,
+    /// The sim file of the point (see `gen_standard_sim_output`).
+    sim_file: String,
+}
+
+/// Returns the core counts of a sweep up to `max_cores`: the powers of two below it, and
+/// `max_cores` itself.
+fn core_sweep(max_cores: usize) -> Vec<usize> {
+    let mut sweep: Vec<_> = (0..)
+        .map(|i| 1 << i)
+        .take_while(|&cores| cores < max_cores)
+        .collect();
+    sweep.push(max_cores);
+    sweep
+}
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_usize(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<usize>()
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn is_positive(s: String) -> Result<(), String> {
+        match s.as_str().parse::<usize>() {
+            Ok(0) => Err("must be at least 1".into()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    clap_app! { exp00012 =>
+        (about: "Run experiment 00012. Requires `sudo`.")
+        (@arg HOSTNAME: +required +takes_value
+         "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
+        (@arg USERNAME: +required +takes_value
+         "The username on the remote (e.g. markm)")
+        (@arg VMSIZE: +required +takes_value {is_vm_size}
+         "The size of the VM (e.g. 500G; a bare number is GB). Must be a multiple of 1GB.")
+        (@arg MAX_CORES: +required +takes_value {is_positive}
+         "The largest number of cores of the VM. The sweep runs the workload with 1, 2, 4, ... \
+          cores up to and including this number.")
+        (@arg ZSWAP_ZPOOL: --zswap_zpool +takes_value
+         "(Optional) The zpool allocator of zswap (e.g. zbud or z3fold; default: ztier).")
+        (@arg ZSWAP_MAX_POOL_PERCENT: --zswap_max_pool_percent +takes_value {is_usize}
+         "(Optional) Set the maximum percentage of memory that zswap can use (default: 50).")
+        (@arg ZSWAP_COMPRESSOR: --zswap_compressor +takes_value
+         "(Optional) The compression algorithm of zswap (e.g. lz4; default: the kernel's \
+          default).")
+        (@subcommand time_mmap_touch =>
+            (about: "Run the `time_mmap_touch` workload.")
+            (@arg SIZE: +required +takes_value {is_size}
+             "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
+            (@group PATTERN =>
+                (@attributes +required)
+                (@arg zeros: -z "Fill pages with zeros")
+                (@arg counter: -c "Fill pages with counter values")
+            )
+        )
+        (@subcommand memcached =>
+            (about: "Run the `memcached` workload.")
+            (@arg SIZE: +required +takes_value {is_size}
+             "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
+        )
+        (@subcommand locality_mem_access =>
+            (about: "Run the `locality_mem_access` workload with one thread per core.")
+            (@arg N: +required +takes_value {is_usize}
+             "The number of iterations of the workload (e.g. 50000000), preferably \
+              divisible by 8")
+        )
+    }
+}
+
+pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let login = Login {
+        username: sub_m.value_of("USERNAME").unwrap(),
+        hostname: sub_m.value_of("HOSTNAME").unwrap(),
+        host: sub_m.value_of("HOSTNAME").unwrap(),
+    };
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
+    let max_cores = sub_m
+        .value_of("MAX_CORES")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    let zswap = ZswapConfig::from_args(sub_m);
+
+    let (workload, workload_name) = match sub_m.subcommand() {
+        ("time_mmap_touch", Some(sub_m)) => {
+            let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
+
+            let pattern = if sub_m.is_present("zeros") {
+                TimeMmapTouchPattern::Zeros
+            } else {
+                TimeMmapTouchPattern::Counter
+            };
+
+            (Workload::TimeMmapTouch { size, pattern }, "time_mmap_touch")
+        }
+
+        ("memcached", Some(sub_m)) => {
+            let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
+
+            (Workload::Memcached { size }, "memcached")
+        }
+
+        ("locality_mem_access", Some(sub_m)) => {
+            let n = sub_m.value_of("N").unwrap().parse::<usize>().unwrap();
+
+            (Workload::LocalityMemAccess { n }, "locality_mem_access")
+        }
+
+        _ => unreachable!(),
+    };
+
+    let ushell = SshShell::with_default_key(login.username, login.host)?;
+    let local_git_hash = crate::common::local_research_workspace_git_hash()?;
+    let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
+    let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
+
+    let settings = settings! {
+        * workload: "core_sweep",
+        * app: workload_name,
+        exp: 12,
+
+        workload_settings: workload,
+
+        * vm_size: vm_size,
+        * max_cores: max_cores,
+        core_sweep: core_sweep(max_cores),
+
+        zswap: zswap,
+
+        username: login.username,
+        host: login.hostname,
+
+        local_git_hash: local_git_hash,
+        remote_git_hash: remote_git_hash,
+        runner_invocation: crate::common::runner_invocation(),
+
+        remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
+    };
+
+    run_inner(print_results_path, &login, settings)
+}
+
+/// Run the experiment using the settings passed. Note that because the only thing we are passed
+/// are the settings, we know that there is no information that is not recorded in the settings
+/// file.
+fn run_inner<A>(
+    print_results_path: bool,
+    login: &Login<A>,
+    settings: OutputManager,
+) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let workload = settings.get::<Workload>("workload_settings");
+    let vm_size = settings.get::<Size>("vm_size").gb();
+    let sweep = settings.get::<Vec<usize>>("core_sweep");
+    let zswap = settings.get::<ZswapConfig>("zswap");
+
+    // Reboot
+    initial_reboot(login)?;
+
+    // Connect to host
+    let mut ushell = connect_and_setup_host_only(login)?;
+    turn_on_ssdswap(&ushell)?;
+
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exp00012");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
+
+    let zerosim_exp_path = &dir!(
+        "/home/vagrant",
+        RESEARCH_WORKSPACE_PATH,
+        ZEROSIM_EXPERIMENTS_SUBMODULE
+    );
+
+    let mut points = vec![];
+
+    for (i, &cores) in sweep.iter().enumerate() {
+        if !phases.should_run(format!("Sweep point ({} cores)", cores)) {
+            continue;
+        }
+
+        println!("Core sweep: {} cores ({}/{})", cores, i + 1, sweep.len());
+
+        // Label the phases of each point separately, so that each gets its own timing and ETA.
+        let workload_phase = format!("Workload ({} cores)", cores);
+
+        // Start and connect to VM. This restarts the VM if it is already running.
+        let vshell = time!(
+            timers,
+            format!("Start VM ({} cores)", cores),
+            start_vagrant(
+                &ushell,
+                &login.host,
+                vm_size,
+                cores,
+                /* fast */ true,
+                ZEROSIM_SKIP_HALT,
+                ZEROSIM_LAPIC_ADJUST,
+            )?
+        );
+
+        // Environment
+        if i == 0 {
+            ZeroSim::zswap(&mut ushell, &zswap)?;
+        }
+
+        let point_file = |ext: &str| settings.gen_file_name(&format!("{}cores.{}", cores, ext));
+        let mut tctx = crate::workloads::TasksetCtx::new(cores);
+
+        let start = std::time::Instant::now();
+
+        let output_files = match workload {
+            Workload::TimeMmapTouch { size, pattern } => {
+                let output_file = point_file("out");
+
+                time!(
+                    timers,
+                    &workload_phase,
+                    run_time_mmap_touch(
+                        &vshell,
+                        &TimeMmapTouchConfig {
+                            exp_dir: zerosim_exp_path,
+                            pages: size.bytes() >> 12,
+                            pattern,
+                            prefault: false,
+                            pf_time: None,
+                            output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file.as_str())),
+                            opts: WorkloadOptions::default(),
+                            pin_core: tctx.next(),
+                        }
+                    )?
+                );
+
+                vec![output_file]
+            }
+
+            Workload::Memcached { size } => {
+                let output_file = point_file("out");
+
+                time!(
+                    timers,
+                    &workload_phase,
+                    run_memcached_gen_data(
+                        &vshell,
+                        &MemcachedWorkloadConfig {
+                            user: "vagrant",
+                            exp_dir: zerosim_exp_path,
+                            memcached: &dir!(
+                                "/home/vagrant",
+                                RESEARCH_WORKSPACE_PATH,
+                                ZEROSIM_MEMCACHED_SUBMODULE
+                            ),
+                            server_size: size,
+                            wk_size: size,
+                            freq: None,
+                            allow_oom: true,
+                            pf_time: None,
+                            output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file.as_str())),
+                            opts: WorkloadOptions::default(),
+                            client_pin_core: tctx.next(),
+                            server_pin_core: None,
+                        }
+                    )?
+                );
+
+                vec![output_file]
+            }
+
+            Workload::LocalityMemAccess { n } => {
+                let local_file = point_file("local");
+                let nonlocal_file = point_file("nonlocal");
+
+                time!(timers, &workload_phase, {
+                    run_locality_mem_access(
+                        &vshell,
+                        &LocalityMemAccessConfig {
+                            exp_dir: zerosim_exp_path,
+                            locality: LocalityMemAccessMode::Local,
+                            n,
+                            threads: Some(cores),
+                            output_file: &dir!(VAGRANT_RESULTS_DIR, local_file.as_str()),
+                            opts: WorkloadOptions::default(),
+                        },
+                    )?;
+                    run_locality_mem_access(
+                        &vshell,
+                        &LocalityMemAccessConfig {
+                            exp_dir: zerosim_exp_path,
+                            locality: LocalityMemAccessMode::Random,
+                            n,
+                            threads: Some(cores),
+                            output_file: &dir!(VAGRANT_RESULTS_DIR, nonlocal_file.as_str()),
+                            opts: WorkloadOptions::default(),
+                        },
+                    )?;
+                });
+
+                vec![local_file, nonlocal_file]
+            }
+        };
+
+        let workload_ms = start.elapsed().as_millis();
+
+        let sim_file = point_file("sim");
+        crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
+
+        points.push(SweepPoint {
+            cores,
+            workload_ms,
+            output_files,
+            sim_file,
+        });
+    }
+
+    ushell.run(cmd!("date"))?;
+
+    let (_output_file, params_file, time_file, _sim_file) = settings.gen_standard_names();
+
+    // The VM is restarted for every point, so write everything but the workload output via the
+    // host.
+    let sink = OutputSink::auto(&ushell, None)?;
+    sink.write(&params_file, &serde_json::to_string(&settings)?)?;
+    sink.write(&time_file, timers.report())?;
+
+    let results = serde_json::json!({
+        "points": points,
+        "workloads": crate::workloads::recorded_invocations(),
+        "pinning": crate::workloads::recorded_pinning_checks(),
+        "output_checks": crate::workloads::recorded_output_checks(),
+    });
+    sink.write(
+        &settings.gen_file_name("results.json"),
+        &serde_json::to_string(&results)?,
+    )?;
+
+    if phases.is_partial() {
+        sink.write(&settings.gen_file_name("partial"), phases.report())?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
+    if print_results_path {
+        let glob = settings.gen_file_name("*");
+        println!("RESULTS: {}", glob);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! Run a workload described by a TOML manifest on the remote machine in simulation and record its
+//! results. See `workloads::WorkloadManifest` for the format of the manifest.
+//!
+//! Requires `setup00000`.
+
+use std::collections::BTreeMap;
+
+use clap::clap_app;
+
+use spurs::{cmd, Execute, SshShell};
+
+use crate::{
+    common::{
+        deadline::PhaseGuard,
+        drift::{GuestOffsetSampler, GUEST_OFFSET_SAMPLE_INTERVAL},
+        exp_0sim::*,
+        output::{OutputManager, OutputSink, Progress},
+        paths::*,
+        size::{is_vm_size, Size},
+        snapshot::PhaseSnapshots,
+    },
+    workloads::{run_manifest_workload, ManifestRunContext, WorkloadManifest},
+};
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_usize(s: String) -> Result<(), String> {
+        s.as_str()
+            .parse::<usize>()
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    clap_app! { expcustom =>
+        (about: "Run a workload described by a manifest in simulation. Requires `sudo`.")
+        (@arg HOSTNAME: +required +takes_value
+         "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
+        (@arg USERNAME: +required +takes_value
+         "The username on the remote (e.g. markm)")
+        (@arg VMSIZE: +required +takes_value {is_vm_size}
+         "The size of the VM (e.g. 500G; a bare number is GB). Must be a multiple of 1GB.")
+        (@arg CORES: +required +takes_value {is_usize}
+         "The number of cores of the VM")
+        (@arg WORKLOAD: --workload +required +takes_value
+         "The path of the TOML manifest describing the workload (on this machine).")
+        (@arg PARAM: --param +takes_value ... number_of_values(1)
+         "(Optional) Override a parameter of the workload, given as KEY=VALUE. Can be given \
+          multiple times.")
+        (@arg SKIP_BUILD: --skip_build
+         "(Optional) Skip checking out submodules and running the build commands of the \
+          workload (e.g. if it is already built).")
+        (@arg DISABLE_ZSWAP: --disable_zswap
+         "(Optional; not recommended) Disable zswap, forcing the hypervisor to \
+         actually swap to disk")
+        (@arg ZSWAP_ZPOOL: --zswap_zpool +takes_value
+         "(Optional) The zpool allocator of zswap (e.g. zbud or z3fold; default: ztier).")
+        (@arg ZSWAP_MAX_POOL_PERCENT: --zswap_max_pool_percent +takes_value {is_usize}
+         "(Optional) Set the maximum percentage of memory that zswap can use (default: 50).")
+        (@arg ZSWAP_COMPRESSOR: --zswap_compressor +takes_value
+         "(Optional) The compression algorithm of zswap (e.g. lz4; default: the kernel's \
+          default).")
+    }
+}
+
+pub fn run(print_results_path: bool, sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let login = Login {
+        username: sub_m.value_of("USERNAME").unwrap(),
+        hostname: sub_m.value_of("HOSTNAME").unwrap(),
+        host: sub_m.value_of("HOSTNAME").unwrap(),
+    };
+
+    let vm_size = sub_m.value_of("VMSIZE").unwrap().parse::<Size>().unwrap();
+    let cores = sub_m.value_of("CORES").unwrap().parse::<usize>().unwrap();
+
+    let manifest = WorkloadManifest::load(sub_m.value_of("WORKLOAD").unwrap())?;
+
+    let mut params = manifest.params.clone();
+    for param in sub_m.values_of("PARAM").into_iter().flatten() {
+        let mut parts = param.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if params.contains_key(key) => {
+                params.insert(key.into(), value.into());
+            }
+            (Some(key), Some(_)) => {
+                return Err(failure::format_err!(
+                    "Workload {} has no parameter {:?}; add a default to its manifest",
+                    manifest.name,
+                    key
+                ));
+            }
+            _ => {
+                return Err(failure::format_err!(
+                    "Invalid parameter {:?}: expected KEY=VALUE",
+                    param
+                ));
+            }
+        }
+    }
+
+    let skip_build = sub_m.is_present("SKIP_BUILD");
+    let zswap = ZswapConfig::from_args(sub_m);
+
+    let ushell = SshShell::with_default_key(login.username, login.host)?;
+    let local_git_hash = crate::common::local_research_workspace_git_hash()?;
+    let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
+    let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
+
+    let settings = settings! {
+        * workload: "custom",
+        * app: manifest.name.clone(),
+        exp: "custom",
+
+        * vm_size: vm_size,
+        (cores > 1) cores: cores,
+        params: params,
+        manifest: manifest,
+        skip_build: skip_build,
+
+        vm_backend: crate::common::exp_0sim::vm_backend(),
+        fresh_guest: crate::common::exp_0sim::fresh_guest(),
+        guest_kernel: crate::common::guest_kernel::get(),
+
+        zswap: zswap,
+
+        username: login.username,
+        host: login.hostname,
+
+        local_git_hash: local_git_hash,
+        remote_git_hash: remote_git_hash,
+        runner_invocation: crate::common::runner_invocation(),
+
+        remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
+    };
+
+    // If the host reboots unexpectedly, mark the run invalid.
+    let results_file = settings.gen_file_name("results.json");
+    let res = run_inner(print_results_path, &login, settings);
+    crate::common::boot::invalidate_if_rebooted(&login, &results_file, res)
+}
+
+/// Run the experiment using the settings passed. Note that because the only thing we are passed
+/// are the settings, we know that there is no information that is not recorded in the settings
+/// file.
+fn run_inner<A>(
+    print_results_path: bool,
+    login: &Login<A>,
+    settings: OutputManager,
+) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let vm_size = settings.get::<Size>("vm_size").gb();
+    let cores = settings.get::<usize>("cores");
+    let manifest = settings.get::<WorkloadManifest>("manifest");
+    let params = settings.get::<BTreeMap<String, String>>("params");
+    let skip_build = settings.get::<bool>("skip_build");
+    let zswap = settings.get::<ZswapConfig>("zswap");
+
+    // Reboot
+    initial_reboot(login)?;
+
+    // Connect to host
+    let mut ushell = connect_and_setup_host_only(login)?;
+
+    // Turn on SSDSWAP.
+    if zswap.enabled {
+        turn_on_ssdswap(&ushell)?;
+    }
+
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("expcustom");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
+
+    // Keep track of the memory and IO activity of each phase on the host.
+    let mut snapshots = PhaseSnapshots::new();
+
+    // Check out and build the workload on the host. The workspace is shared with the guest, so it
+    // is then available there too.
+    if !skip_build {
+        time!(timers, "Build", {
+            for submodule in manifest.submodules.iter() {
+                ushell.run(
+                    cmd!("git submodule update --init --recursive -- {}", submodule)
+                        .cwd(RESEARCH_WORKSPACE_PATH),
+                )?;
+            }
+            for command in manifest.build.iter() {
+                ushell.run(cmd!("{}", command).use_bash().cwd(RESEARCH_WORKSPACE_PATH))?;
+            }
+        });
+    }
+
+    // Start and connect to VM
+    let vshell = time!(
+        timers,
+        "Start VM",
+        start_vagrant(
+            &ushell,
+            &login.host,
+            vm_size,
+            cores,
+            /* fast */ true,
+            ZEROSIM_SKIP_HALT,
+            ZEROSIM_LAPIC_ADJUST,
+        )?
+    );
+
+    // Environment
+    ZeroSim::zswap(&mut ushell, &zswap)?;
+
+    save_host_fingerprint(&ushell)?;
+
+    let (output_file, params_file, time_file, sim_file) = settings.gen_standard_names();
+    let settings_json = serde_json::to_string(&settings)?;
+
+    let sink = OutputSink::auto(&ushell, Some(&vshell))?;
+    sink.write(&params_file, &settings_json)?;
+
+    let mut tctx = crate::workloads::TasksetCtx::new(cores);
+
+    // Render the command line of the workload.
+    let workspace = dir!("/home/vagrant", RESEARCH_WORKSPACE_PATH);
+    let output_path = sink.path(&output_file);
+    let command = manifest.render_run(&ManifestRunContext {
+        workspace: &workspace,
+        output_file: &output_path,
+        outputs: manifest
+            .outputs
+            .iter()
+            .map(|suffix| (suffix.clone(), sink.path(&settings.gen_file_name(suffix))))
+            .collect(),
+        params: &params,
+        cores,
+        vm_size,
+        pin_core: tctx.next(),
+    })?;
+
+    // Run the workload, sampling the guest TSC offset so that clock jumps show up in the sim
+    // output.
+    let offset_sampler = GuestOffsetSampler::start(&ushell, &vshell, GUEST_OFFSET_SAMPLE_INTERVAL)?;
+    snapshots.begin(&ushell)?;
+
+    if phases.should_run("Workload") {
+        time!(
+            timers,
+            "Workload",
+            run_manifest_workload(&vshell, &workspace, command)?
+        );
+    }
+
+    snapshots.end(&ushell, "Workload")?;
+    offset_sampler.stop()?;
+
+    ushell.run(cmd!("date"))?;
+
+    sink.write(&time_file, timers.report())?;
+
+    let results = serde_json::json!({
+        "workloads": crate::workloads::recorded_invocations(),
+        "phase_resources": snapshots.phases(),
+    });
+    sink.write(
+        &settings.gen_file_name("results.json"),
+        &serde_json::to_string(&results)?,
+    )?;
+
+    crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
+
+    if phases.is_partial() {
+        sink.write(&settings.gen_file_name("partial"), phases.report())?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
+    if print_results_path {
+        let glob = settings.gen_file_name("*");
+        println!("RESULTS: {}", glob);
+    }
+
+    Ok(())
+}
@@ -10,15 +10,21 @@ use spurs_util::escape_for_bash;
 
 use crate::{
     common::{
+        deadline::PhaseGuard,
         exp_0sim::*,
-        output::OutputManager,
+        output::{OutputManager, Progress},
         paths::{setup00000::*, *},
+        perf::{PerfConfig, PerfMode, PerfSession, DEFAULT_PERF_EVENTS},
+        size::{is_size, is_vm_size, Size},
+        tracing::{
+            ZerosimTrace, ZerosimTraceConfig, DEFAULT_TRACE_BUFFER_SIZE, DEFAULT_TRACE_INTERVAL,
+        },
     },
     settings,
     workloads::{
         run_locality_mem_access, run_memcached_gen_data, run_time_mmap_touch,
         LocalityMemAccessConfig, LocalityMemAccessMode, MemcachedWorkloadConfig,
-        TimeMmapTouchConfig, TimeMmapTouchPattern,
+        TimeMmapTouchConfig, TimeMmapTouchPattern, WorkloadOptions,
     },
 };
 
@@ -70,8 +76,8 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
         (@arg USERNAME: +required +takes_value
          "The username on the remote (e.g. markm)")
-        (@arg SIZE: +required +takes_value {is_usize}
-         "The number of GBs of the workload (e.g. 500)")
+        (@arg SIZE: +required +takes_value {is_size}
+         "The size of the workload (e.g. 500G or 512M; a bare number is GB)")
         (@group PATTERN =>
             (@attributes +required)
             (@arg zeros: -z "Fill pages with zeros")
@@ -80,14 +86,16 @@ pub fn cli_options() -> clap::App<'static, 'static> {
             (@arg locality: -l "Run the locality test workload")
             (@arg hibench_wordcount: -b "Run HiBench Wordcount")
         )
-        (@arg VMSIZE: +takes_value {is_usize} -v --vm_size
-         "The number of GBs of the VM (defaults to 1024) (e.g. 500)")
+        (@arg VMSIZE: +takes_value {is_vm_size} -v --vm_size
+         "The size of the VM (e.g. 500G; a bare number is GB; defaults to 1024)")
         (@arg CORES: +takes_value {is_usize} -C --cores
          "The number of cores of the VM (defaults to 1)")
         (@arg WARMUP: -w --warmup
          "Pass this flag to warmup the VM before running the main workload.")
         (@arg PFTIME: +takes_value {is_usize} --pftime
          "Pass this flag to set the pf_time value for the workload.")
+        (@arg PERF: --perf
+         "Pass this flag to measure the host with `perf stat` during the workload.")
     }
 }
 
@@ -97,7 +105,7 @@ pub fn run(print_results_path: bool, sub_m: &ArgMatches<'_>) -> Result<(), failu
         hostname: sub_m.value_of("HOSTNAME").unwrap(),
         host: sub_m.value_of("HOSTNAME").unwrap(),
     };
-    let size = sub_m.value_of("SIZE").unwrap().parse::<usize>().unwrap();
+    let size = sub_m.value_of("SIZE").unwrap().parse::<Size>().unwrap();
     let workload = if sub_m.is_present("memcached") {
         Workload::Memcached
     } else if sub_m.is_present("zeros") {
@@ -113,16 +121,17 @@ pub fn run(print_results_path: bool, sub_m: &ArgMatches<'_>) -> Result<(), failu
     };
     let vm_size = sub_m
         .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap());
+        .map(|value| value.parse::<Size>().unwrap());
     let cores = sub_m
         .value_of("CORES")
         .map(|value| value.parse::<usize>().unwrap());
     let warmup = sub_m.is_present("WARMUP");
+    let perf = sub_m.is_present("PERF");
 
     let vm_size = if let Some(vm_size) = vm_size {
         vm_size
     } else {
-        VAGRANT_MEM
+        Size::from_gb(VAGRANT_MEM)
     };
 
     let cores = if let Some(cores) = cores {
@@ -139,6 +148,7 @@ pub fn run(print_results_path: bool, sub_m: &ArgMatches<'_>) -> Result<(), failu
     let local_git_hash = crate::common::local_research_workspace_git_hash()?;
     let remote_git_hash = crate::common::research_workspace_git_hash(&ushell)?;
     let remote_research_settings = crate::common::get_remote_research_settings(&ushell)?;
+    let deadline = crate::common::deadline::get();
 
     let settings = settings! {
         * workload: workload.to_str(),
@@ -153,11 +163,12 @@ pub fn run(print_results_path: bool, sub_m: &ArgMatches<'_>) -> Result<(), failu
         calibrated: false,
         warmup: warmup,
         pf_time: pf_time,
+        perf: perf,
 
         * vm_size: vm_size,
         cores: cores,
 
-        zswap_max_pool_percent: 50,
+        zswap: ZswapConfig::default(),
 
         username: login.username,
         host: login.hostname,
@@ -166,6 +177,8 @@ pub fn run(print_results_path: bool, sub_m: &ArgMatches<'_>) -> Result<(), failu
         remote_git_hash: remote_git_hash,
 
         remote_research_settings: remote_research_settings,
+
+        deadline: deadline,
     };
 
     run_inner(print_results_path, &login, settings)
@@ -182,15 +195,16 @@ fn run_inner<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
-    let vm_size = settings.get::<usize>("vm_size");
-    let size = settings.get::<usize>("size");
+    let vm_size = settings.get::<Size>("vm_size").gb();
+    let size = settings.get::<Size>("size");
     let cores = settings.get::<usize>("cores");
     let pattern = settings.get::<Option<TimeMmapTouchPattern>>("pattern");
     let workload = Workload::from_str(settings.get::<&str>("workload"), pattern);
     let warmup = settings.get::<bool>("warmup");
     let calibrate = settings.get::<bool>("calibrated");
-    let zswap_max_pool_percent = settings.get::<usize>("zswap_max_pool_percent");
+    let zswap = settings.get::<ZswapConfig>("zswap");
     let pf_time = settings.get::<Option<u64>>("pf_time");
+    let perf = settings.get::<bool>("perf");
 
     // Reboot
     initial_reboot(&login)?;
@@ -201,8 +215,11 @@ where
     // Turn on SSDSWAP.
     turn_on_ssdswap(&ushell)?;
 
-    // Collect timers on VM
-    let mut timers = vec![];
+    // Announce and time the phases of the experiment.
+    let mut timers = Progress::new("exptmp");
+
+    // Keep track of phases skipped due to the deadline.
+    let mut phases = PhaseGuard::new();
 
     // Start and connect to VM
     let vshell = time!(
@@ -220,16 +237,13 @@ where
     );
 
     // Environment
-    ZeroSim::turn_on_zswap(&mut ushell)?;
-    ZeroSim::zswap_max_pool_percent(&ushell, zswap_max_pool_percent)?;
+    ZeroSim::zswap(&mut ushell, &zswap)?;
 
     let zerosim_path = &dir!("/home/vagrant", RESEARCH_WORKSPACE_PATH,);
     let zerosim_exp_path = &dir!(zerosim_path, ZEROSIM_EXPERIMENTS_SUBMODULE);
 
-    // let zerosim_path_host = &dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_KERNEL_SUBMODULE);
-
     // Calibrate
-    if calibrate {
+    if calibrate && phases.should_run("Calibrate") {
         time!(
             timers,
             "Calibrate",
@@ -250,7 +264,7 @@ where
 
     // Warm up
     //const WARM_UP_SIZE: usize = 50; // GB
-    if warmup {
+    if warmup && phases.should_run("Warmup") {
         const WARM_UP_PATTERN: TimeMmapTouchPattern = TimeMmapTouchPattern::Zeros;
         time!(
             timers,
@@ -259,12 +273,12 @@ where
                 &vshell,
                 &TimeMmapTouchConfig {
                     exp_dir: zerosim_exp_path,
-                    pages: (size << 30) >> 12,
+                    pages: size.pages(),
                     pattern: WARM_UP_PATTERN,
                     prefault: false,
                     pf_time: None,
                     output_file: None,
-                    eager: false,
+                    opts: WorkloadOptions::default(),
                     pin_core: tctx.next(),
                 }
             )?
@@ -274,22 +288,45 @@ where
     // We want to use rdtsc as the time source, so find the cpu freq:
     let freq = crate::common::get_cpu_freq(&ushell)?;
 
+    // If requested, measure host stats on core 0 with `perf stat` for `duration` seconds while the
+    // workload runs, pinning `perf` to `pin_core`.
+    let perf_events: Vec<String> = DEFAULT_PERF_EVENTS.iter().map(|&e| e.into()).collect();
+    let start_perf = |name: &str, pin_core, delay_ms, duration| {
+        if !perf {
+            return Ok(None);
+        }
+
+        PerfSession::start(
+            &ushell,
+            &PerfConfig {
+                perf: "perf",
+                mode: PerfMode::Stat,
+                events: &perf_events,
+                cpus: Some("0"),
+                interval_ms: Some(1000),
+                delay_ms,
+                duration,
+                pin_core,
+                output_file: &dir!(HOSTNAME_SHARED_RESULTS_DIR, settings.gen_file_name(name)),
+            },
+        )
+        .map(Some)
+    };
+    let finish_perf = |session: Option<PerfSession>| -> Result<(), failure::Error> {
+        if let Some(session) = session {
+            session.finish(&ushell, None)?;
+        }
+        Ok(())
+    };
+
     // Run the workload
     match workload {
+        _ if !phases.should_run("Workload") => {}
+
         Workload::Zeros | Workload::Counter => {
             let pattern = pattern.unwrap();
 
-            // const PERF_MEASURE_TIME: usize = 960; // seconds
-            // let perf_output_early = settings.gen_file_name("perfdata0");
-            // let spawn_handle0 = ushell.spawn(cmd!(
-            //     "sudo taskset -c 3 {}/tools/perf/perf stat -C 0 -I 1000 \
-            //      -e 'cycles,cache-misses,dTLB-load-misses,dTLB-store-misses,\
-            //      page-faults,context-switches,vmscan:*,kvm:*' -o {} sleep {}",
-            //     zerosim_path_host,
-            //     dir!(HOSTNAME_SHARED_RESULTS_DIR,
-            //     perf_output_early),
-            //     PERF_MEASURE_TIME,
-            // ))?;
+            let perf0 = start_perf("perfdata0", 3, None, 960)?;
 
             // Then, run the actual experiment
             time!(
@@ -299,48 +336,27 @@ where
                     &vshell,
                     &TimeMmapTouchConfig {
                         exp_dir: zerosim_exp_path,
-                        pages: (size << 30) >> 12,
+                        pages: size.pages(),
                         pattern: pattern,
                         prefault: false,
                         pf_time: pf_time,
                         output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                         pin_core: tctx.next(),
                     }
                 )?
             );
 
-            // let _ = spawn_handle0.join()?;
+            finish_perf(perf0)?;
         }
         Workload::Memcached => {
-            // // Measure host stats with perf while the workload is running. We measure at the beginning
-            // // of the workload and later in the workload after the "cliff".
-            // const PERF_MEASURE_TIME: usize = 50; // seconds
-            // const PERF_LATE_DELAY_MS: usize = 85 * 1000; // ms
-
-            // let perf_output_early = settings.gen_file_name("perfdata0");
-            // let perf_output_late = settings.gen_file_name("perfdata1");
-
-            // let spawn_handle0 = ushell.spawn(cmd!(
-            //     "sudo taskset -c 2 {}/tools/perf/perf stat -C 0 -I 1000 \
-            //      -e 'cycles,cache-misses,dTLB-load-misses,dTLB-store-misses,\
-            //      page-faults,context-switches,vmscan:*,kvm:*' -o {} sleep {}",
-            //     zerosim_path_host,
-            //     dir!(HOSTNAME_SHARED_RESULTS_DIR,
-            //     perf_output_early),
-            //     PERF_MEASURE_TIME,
-            // ))?;
-
-            // let spawn_handle1 = ushell.spawn(cmd!(
-            //     "sudo taskset -c 2 {}/tools/perf/perf stat -C 0 -I 1000 -D {} \
-            //      -e 'cycles,cache-misses,dTLB-load-misses,dTLB-store-misses,\
-            //      page-faults,context-switches,vmscan:*,kvm:*' -o {} sleep {}",
-            //     zerosim_path_host,
-            //     PERF_LATE_DELAY_MS,
-            //     dir!(HOSTNAME_SHARED_RESULTS_DIR,
-            //     perf_output_late),
-            //     PERF_MEASURE_TIME,
-            // ))?;
+            // Measure host stats with perf while the workload is running. We measure at the beginning
+            // of the workload and later in the workload after the "cliff".
+            const PERF_MEASURE_TIME: usize = 50; // seconds
+            const PERF_LATE_DELAY_MS: usize = 85 * 1000; // ms
+
+            let perf0 = start_perf("perfdata0", 2, None, PERF_MEASURE_TIME)?;
+            let perf1 = start_perf("perfdata1", 2, Some(PERF_LATE_DELAY_MS), PERF_MEASURE_TIME)?;
 
             time!(
                 timers,
@@ -355,46 +371,44 @@ where
                             RESEARCH_WORKSPACE_PATH,
                             ZEROSIM_MEMCACHED_SUBMODULE
                         ),
-                        server_size_mb: size << 10,
-                        wk_size_gb: size,
+                        server_size: size,
+                        wk_size: size,
                         freq: Some(freq),
                         allow_oom: true,
                         pf_time: pf_time,
                         output_file: Some(&dir!(VAGRANT_RESULTS_DIR, output_file)),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                         client_pin_core: tctx.next(),
                         server_pin_core: None,
                     }
                 )?
             );
 
-            // let _ = spawn_handle0.join()?;
-            // let _ = spawn_handle1.join()?;
+            finish_perf(perf0)?;
+            finish_perf(perf1)?;
         }
         Workload::Locality => {
-            // const PERF_MEASURE_TIME: usize = 960; // seconds
-
-            // let perf_output_early = settings.gen_file_name("perfdata0");
-            // let spawn_handle0 = ushell.spawn(cmd!(
-            //     "sudo taskset -c 3 {}/tools/perf/perf stat -C 0 -I 1000 \
-            //      -e 'cycles,cache-misses,dTLB-load-misses,dTLB-store-misses,\
-            //      page-faults,context-switches,vmscan:*,kvm:*' -o {} sleep {}",
-            //     zerosim_path_host,
-            //     dir!(HOSTNAME_SHARED_RESULTS_DIR,
-            //     perf_output_early),
-            //     PERF_MEASURE_TIME,
-            // ))?;
-
-            let trace_output_local = settings.gen_file_name("tracelocal");
-            let trace_output_nonlocal = settings.gen_file_name("tracenonlocal");
-            let (_shell, spawn_handle0) = ushell.spawn(cmd!(
-                "sudo taskset -c 3 {}/target/release/zerosim-trace trace {} {} {} -t {}",
-                dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_TRACE_SUBMODULE),
-                500,     // interval
-                100_000, // buffer size
-                dir!(HOSTNAME_SHARED_RESULTS_DIR, trace_output_local),
-                pf_time.unwrap(),
-            ))?;
+            let perf0 = start_perf("perfdata0", 3, None, 960)?;
+
+            // Trace each workload on the host for `pf_time` seconds.
+            let start_trace = |name: &str| {
+                ZerosimTrace::start(
+                    &ushell,
+                    &ZerosimTraceConfig {
+                        interval: DEFAULT_TRACE_INTERVAL,
+                        buffer_size: DEFAULT_TRACE_BUFFER_SIZE,
+                        output_file: &dir!(
+                            HOSTNAME_SHARED_RESULTS_DIR,
+                            settings.gen_file_name(name)
+                        ),
+                        timeout: pf_time,
+                        pin_core: 3,
+                        compress: false,
+                    },
+                )
+            };
+
+            let trace = start_trace("tracelocal")?;
 
             let output_local = settings.gen_file_name("local");
             let output_nonlocal = settings.gen_file_name("nonlocal");
@@ -413,21 +427,14 @@ where
                         n: LOCALITY_N,
                         threads: None,
                         output_file: &dir!(VAGRANT_RESULTS_DIR, output_local),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     }
                 )?
             );
 
-            let _ = spawn_handle0.join()?;
+            trace.stop(&ushell)?;
 
-            let (_shell, spawn_handle0) = ushell.spawn(cmd!(
-                "sudo taskset -c 3 {}/target/release/zerosim-trace trace {} {} {} -t {}",
-                dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_TRACE_SUBMODULE),
-                500,     // interval
-                100_000, // buffer size
-                dir!(HOSTNAME_SHARED_RESULTS_DIR, trace_output_nonlocal),
-                pf_time.unwrap(),
-            ))?;
+            let trace = start_trace("tracenonlocal")?;
 
             time!(
                 timers,
@@ -440,12 +447,14 @@ where
                         n: LOCALITY_N,
                         threads: None,
                         output_file: &dir!(VAGRANT_RESULTS_DIR, output_nonlocal),
-                        eager: false,
+                        opts: WorkloadOptions::default(),
                     }
                 )?
             );
 
-            let _ = spawn_handle0.join()?;
+            trace.stop(&ushell)?;
+
+            finish_perf(perf0)?;
         }
 
         Workload::HiBenchWordcount => {
@@ -475,12 +484,22 @@ where
 
     vshell.run(cmd!(
         "echo -e '{}' > {}",
-        crate::common::timings_str(timers.as_slice()),
+        timers.report(),
         dir!(VAGRANT_RESULTS_DIR, time_file)
     ))?;
 
     crate::common::exp_0sim::gen_standard_sim_output(&sim_file, &ushell, &vshell)?;
 
+    if phases.is_partial() {
+        vshell.run(cmd!(
+            "echo {} > {}",
+            escape_for_bash(&phases.report()),
+            dir!(VAGRANT_RESULTS_DIR, settings.gen_file_name("partial"))
+        ))?;
+    }
+
+    crate::common::index::append(&ushell, &settings, &timers)?;
+
     if print_results_path {
         let glob = settings.gen_file_name("*");
         println!("RESULTS: {}", glob);
@@ -15,7 +15,20 @@ mod setup00002;
 
 mod manual;
 
+// Packaging results for artifact evaluation.
+mod bundle;
+
+// Comparing the results of several batches of experiments.
+mod compare;
+
+// Querying the index of runs in a results directory.
+mod results;
+
+// Acquiring machines from cloud providers.
+mod provider;
+
 // Experiment routines
+mod expcustom;
 mod exptmp;
 
 mod exp00000;
@@ -28,8 +41,21 @@ mod exp00007;
 mod exp00008;
 mod exp00009;
 mod exp00010;
+mod exp00011;
+mod exp00012;
+
+/// The long names of global options that take a value.
+const GLOBAL_VALUE_OPTIONS: &[&str] = &[
+    "config",
+    "deadline",
+    "reconnect",
+    "vm_backend",
+    "guest_kernel",
+];
 
 fn run() -> Result<(), failure::Error> {
+    let args = common::config::expand_config(std::env::args().collect(), GLOBAL_VALUE_OPTIONS)?;
+
     let matches = clap::App::new("runner")
         .about(
             "This program runs different routines remotely. Which routine is chosen by passing \
@@ -40,11 +66,87 @@ fn run() -> Result<(), failure::Error> {
                 .long("print_results_path")
                 .help("(For experiments) Print the results path as the last line of output."),
         )
+        .arg(
+            clap::Arg::with_name("CONFIG")
+                .long("config")
+                .takes_value(true)
+                .help(
+                    "Read the parameters of the subcommand from the given TOML file. Flags given \
+                     on the command line override the file.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("DEADLINE")
+                .long("deadline")
+                .takes_value(true)
+                .validator(|s| {
+                    common::deadline::parse_duration(&s)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help(
+                    "(For experiments) Skip any remaining phases once the given amount of time \
+                     (e.g. 90m, 4h, 1h30m) has passed, collecting whatever results exist and \
+                     marking the run as partial.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("RECONNECT")
+                .long("reconnect")
+                .takes_value(true)
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .help(
+                    "(For experiments) If the workload's SSH connection drops, reconnect it and \
+                     restart the workload, up to the given number of times. Other connections \
+                     are not reconnected.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("VM_BACKEND")
+                .long("vm_backend")
+                .takes_value(true)
+                .possible_values(common::exp_0sim::VmBackend::NAMES)
+                .help(
+                    "(For experiments) How to start the VM (default: vagrant). `qemu` defines a \
+                     libvirt domain directly, bypassing vagrant, but still boots from the disk \
+                     image created by vagrant during setup.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("FRESH_GUEST")
+                .long("fresh_guest")
+                .help(
+                    "(For experiments) Restore the guest disk from the snapshot taken at the end \
+                     of setup before starting the VM, discarding any state left by previous runs.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("GUEST_KERNEL")
+                .long("guest_kernel")
+                .takes_value(true)
+                .validator(|s| {
+                    s.parse::<common::guest_kernel::GuestKernel>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help(
+                    "(For experiments) Run the given kernel in the guest: either a version (e.g. \
+                     5.4.1), which is built on the host if needed, or the path of a kernel RPM on \
+                     the host. It is installed in the guest and booted before the experiment.",
+                ),
+        )
         .subcommand(setup00000::cli_options())
         .subcommand(setup00001::cli_options())
         .subcommand(setup00002::cli_options())
         .subcommand(manual::cli_options())
+        .subcommand(bundle::cli_options())
+        .subcommand(compare::cli_options())
+        .subcommand(results::cli_options())
+        .subcommand(provider::aws::cli_options())
+        .subcommand(provider::cloudlab::cli_options())
+        .subcommand(provider::gcp::cli_options())
         .subcommand(exptmp::cli_options())
+        .subcommand(expcustom::cli_options())
         .subcommand(exp00000::cli_options())
         .subcommand(exp00002::cli_options())
         .subcommand(exp00003::cli_options())
@@ -55,12 +157,32 @@ fn run() -> Result<(), failure::Error> {
         .subcommand(exp00008::cli_options())
         .subcommand(exp00009::cli_options())
         .subcommand(exp00010::cli_options())
+        .subcommand(exp00011::cli_options())
+        .subcommand(exp00012::cli_options())
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .setting(clap::AppSettings::DisableVersion)
-        .get_matches();
+        .get_matches_from(args);
 
     let print_results_path = matches.is_present("PRINT_RESULTS_PATH");
 
+    if let Some(deadline) = matches.value_of("DEADLINE") {
+        common::deadline::set(common::deadline::parse_duration(deadline)?);
+    }
+
+    if let Some(reconnect) = matches.value_of("RECONNECT") {
+        common::reconnect::set_max_reconnects(reconnect.parse().unwrap());
+    }
+
+    if let Some(backend) = matches.value_of("VM_BACKEND") {
+        common::exp_0sim::set_vm_backend(backend.parse()?);
+    }
+
+    common::exp_0sim::set_fresh_guest(matches.is_present("FRESH_GUEST"));
+
+    if let Some(kernel) = matches.value_of("GUEST_KERNEL") {
+        common::guest_kernel::set(Some(kernel.parse()?));
+    }
+
     match matches.subcommand() {
         ("setup00000", Some(sub_m)) => setup00000::run(sub_m),
         ("setup00001", Some(sub_m)) => setup00001::run(sub_m),
@@ -68,7 +190,18 @@ fn run() -> Result<(), failure::Error> {
 
         ("manual", Some(sub_m)) => manual::run(sub_m),
 
+        ("bundle", Some(sub_m)) => bundle::run(sub_m),
+
+        ("compare", Some(sub_m)) => compare::run(sub_m),
+
+        ("results", Some(sub_m)) => results::run(sub_m),
+
+        ("aws", Some(sub_m)) => provider::aws::run(sub_m),
+        ("cloudlab", Some(sub_m)) => provider::cloudlab::run(sub_m),
+        ("gcp", Some(sub_m)) => provider::gcp::run(sub_m),
+
         ("exptmp", Some(sub_m)) => exptmp::run(print_results_path, sub_m),
+        ("expcustom", Some(sub_m)) => expcustom::run(print_results_path, sub_m),
 
         ("exp00000", Some(sub_m)) => exp00000::run(print_results_path, sub_m),
         ("exp00002", Some(sub_m)) => exp00002::run(print_results_path, sub_m),
@@ -80,6 +213,8 @@ fn run() -> Result<(), failure::Error> {
         ("exp00008", Some(sub_m)) => exp00008::run(print_results_path, sub_m),
         ("exp00009", Some(sub_m)) => exp00009::run(print_results_path, sub_m),
         ("exp00010", Some(sub_m)) => exp00010::run(print_results_path, sub_m),
+        ("exp00011", Some(sub_m)) => exp00011::run(print_results_path, sub_m),
+        ("exp00012", Some(sub_m)) => exp00012::run(print_results_path, sub_m),
 
         _ => {
             unreachable!();
@@ -3,6 +3,14 @@
 //! with stuff installed and prepare the environment for an experiment (e.g. setting scaling
 //! governor)".
 //!
+//! It can also run an arbitrary command on the host, the guest, or both, on one or many machines
+//! (e.g. `runner manual --hosts_file hosts --parallel --exec 'uptime' --on both`), collecting the
+//! output and exit status of each.
+//!
+//! With `--vms`, it can instead start several named VMs on the same host (e.g. `runner manual
+//! --vms victim:64G:4 --vms antagonist:16G:2 ...`), each with its own vCPUs pinned to their own
+//! host CPUs.
+//!
 //! NOTE: This should not be used for real experiments. Just for testing and prototyping.
 
 use clap::{clap_app, ArgMatches};
@@ -11,11 +19,12 @@ use spurs::{cmd, Execute, SshShell};
 
 use crate::common::{
     exp_0sim::{
-        initial_reboot, set_kernel_printk_level, set_perf_scaling_gov, setup_swapping,
-        start_vagrant, turn_on_ssdswap, ZeroSim, VAGRANT_CORES, VAGRANT_MEM, ZEROSIM_LAPIC_ADJUST,
-        ZEROSIM_SKIP_HALT,
+        connect_to_vagrant_as_user, initial_reboot, set_kernel_printk_level, set_perf_scaling_gov,
+        setup_swapping, start_vagrant, start_vms, turn_on_ssdswap, VmConfig, ZeroSim, ZswapConfig,
+        VAGRANT_CORES, VAGRANT_MEM, ZEROSIM_LAPIC_ADJUST, ZEROSIM_SKIP_HALT,
     },
     paths::*,
+    size::{is_vm_size, Size},
     Login,
 };
 
@@ -27,12 +36,30 @@ pub fn cli_options() -> clap::App<'static, 'static> {
             .map_err(|e| format!("{:?}", e))
     }
 
+    fn is_named_vm(s: String) -> Result<(), String> {
+        parse_named_vm(&s).map(|_| ()).map_err(|e| e.to_string())
+    }
+
     clap_app! { manual =>
         (about: "Perform some (non-strict) subset of the setup for an experiment. Requires `sudo`.")
-        (@arg HOSTNAME: +required +takes_value
+        (@arg HOSTNAME: +takes_value required_unless[HOSTS_FILE]
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
-        (@arg USERNAME: +required +takes_value
+        (@arg USERNAME: +takes_value required_unless[HOSTS_FILE]
          "The username on the remote (e.g. markm)")
+        (@arg HOSTS_FILE: --hosts_file +takes_value conflicts_with[HOSTNAME]
+         "(Optional) Instead of a single remote, do everything on each remote listed in the \
+          given file, one `HOSTNAME USERNAME` pair per line.")
+        (@arg PARALLEL: --parallel requires[HOSTS_FILE]
+         "(Optional) With --hosts_file, handle all remotes concurrently rather than one at a time.")
+        (@arg EXEC: --exec +takes_value
+         "(Optional) Run the given command (with bash) after everything else and report its \
+          exit status.")
+        (@arg ON: --on +takes_value possible_values(&["host", "guest", "both"]) requires[EXEC]
+         "(Optional) Where to run the --exec command: on the host, in the guest, or both \
+          (defaults to host). The guest must already be running (or use --vm).")
+        (@arg OUTPUT_DIR: --output_dir +takes_value requires[EXEC]
+         "(Optional) Save the output of the --exec command to a file per remote in the given \
+          local directory, rather than just printing it.")
         (@arg REBOOT: --reboot
          "(Optional) If present, reboots the host machine.")
         (@arg SWAP: --setup_swap
@@ -46,12 +73,17 @@ pub fn cli_options() -> clap::App<'static, 'static> {
          "(Optional) If present, turn on ssdswap.")
         (@arg VM: --vm
          "(Optional) Start the vagrant VM. Use other flags to set VM memory and vCPUS.")
-        (@arg VMSIZE: --vm_size +takes_value {is_usize}
-         "(Only valid with --vm) The number of GBs of the VM (defaults to 1024) (e.g. 500)")
+        (@arg VMSIZE: --vm_size +takes_value {is_vm_size}
+         "(Only valid with --vm) The size of the VM (e.g. 500G; a bare number is GB; defaults to 1024)")
         (@arg VMCORES: --vm_cores +takes_value {is_usize}
          "(Only valid with --vm) The number of cores of the VM (defaults to 1)")
+        (@arg VMS: --vms +takes_value ... number_of_values(1) {is_named_vm} conflicts_with[VM]
+         "(Optional) Start several VMs on the host instead of one, given as NAME:SIZE:CORES \
+          (e.g. victim:64G:4). Can be given multiple times. The vCPUs of each VM are pinned to \
+          their own host CPUs. Requires `--vm_backend qemu`. With `--on guest`, the --exec \
+          command runs as root in each VM.")
         (@arg DISABLETSC: --disable_tsc
-         "(Only valid with --vm) Disable TSC offsetting during boot to speed it up.")
+         "(Only valid with --vm or --vms) Disable TSC offsetting during boot to speed it up.")
         (@arg ZSWAP: --zswap +takes_value {is_usize}
          "(Optional) Turn on zswap with the given `max_pool_percent`")
         (@arg DRIFT_THRESHOLD: --drift_thresh +takes_value {is_usize}
@@ -65,39 +97,260 @@ pub fn cli_options() -> clap::App<'static, 'static> {
     }
 }
 
+/// Where to run the command given with `--exec`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ExecTarget {
+    Host,
+    Guest,
+    Both,
+}
+
+/// All flags/options that apply to each remote.
+#[derive(Clone, Debug)]
+struct ManualOptions {
+    reboot: bool,
+    swap: bool,
+    perfgov: bool,
+    printk: Option<usize>,
+    ssdswap: bool,
+    vm: bool,
+    vm_size: Option<Size>,
+    vm_cores: Option<usize>,
+    /// The name, size, and number of cores of each VM to start with `--vms`.
+    vms: Vec<(String, Size, usize)>,
+    disable_tsc: bool,
+    zswap: Option<usize>,
+    zerosim_drift_threshold: Option<usize>,
+    zerosim_delay: Option<usize>,
+    disable_ept: bool,
+    update_exp: bool,
+    exec: Option<String>,
+    exec_on: ExecTarget,
+    output_dir: Option<String>,
+}
+
+/// The outcome of running the `--exec` command on one remote.
+#[derive(Debug)]
+struct ExecResult {
+    hostname: String,
+    /// `host`, `guest`, or the name of the VM given with `--vms`.
+    target: String,
+    exit_code: i32,
+}
+
+/// Printed after the `--exec` command so that its exit status can be found in the output.
+const EXIT_STATUS_MARKER: &str = "runner-manual-exit-status:";
+
+/// Parse a VM given with `--vms` as `NAME:SIZE:CORES` (e.g. `victim:64G:4`).
+fn parse_named_vm(s: &str) -> Result<(String, Size, usize), failure::Error> {
+    let parts: Vec<_> = s.split(':').collect();
+    match parts.as_slice() {
+        [name, size, cores] if !name.is_empty() => {
+            is_vm_size((*size).to_owned()).map_err(failure::err_msg)?;
+            let cores = cores
+                .parse::<usize>()
+                .map_err(|e| failure::format_err!("Invalid number of cores {:?}: {}", cores, e))?;
+            Ok(((*name).to_owned(), size.parse()?, cores))
+        }
+        _ => Err(failure::format_err!(
+            "Expected NAME:SIZE:CORES (e.g. victim:64G:4), found {:?}",
+            s
+        )),
+    }
+}
+
 pub fn run(sub_m: &ArgMatches<'_>) -> Result<(), failure::Error> {
     // Read all flags/options
+    let hosts = if let Some(hosts_file) = sub_m.value_of("HOSTS_FILE") {
+        crate::common::parse_hosts_file(&std::fs::read_to_string(hosts_file)?)?
+    } else {
+        vec![(
+            sub_m.value_of("HOSTNAME").unwrap().to_owned(),
+            sub_m.value_of("USERNAME").unwrap().to_owned(),
+        )]
+    };
+    let parallel = sub_m.is_present("PARALLEL");
+
+    let opts = ManualOptions {
+        reboot: sub_m.is_present("REBOOT"),
+        swap: sub_m.is_present("SWAP"),
+        perfgov: sub_m.is_present("PERF"),
+        printk: sub_m
+            .value_of("PRINTK")
+            .map(|value| value.parse::<usize>().unwrap()),
+        ssdswap: sub_m.is_present("SSDSWAP"),
+        vm: sub_m.is_present("VM"),
+        vm_size: sub_m
+            .value_of("VMSIZE")
+            .map(|value| value.parse::<Size>().unwrap()),
+        vm_cores: sub_m
+            .value_of("VMCORES")
+            .map(|value| value.parse::<usize>().unwrap()),
+        vms: sub_m
+            .values_of("VMS")
+            .map(|values| values.map(|vm| parse_named_vm(vm).unwrap()).collect())
+            .unwrap_or_default(),
+        disable_tsc: sub_m.is_present("DISABLETSC"),
+        zswap: sub_m
+            .value_of("ZSWAP")
+            .map(|value| value.parse::<usize>().unwrap()),
+        zerosim_drift_threshold: sub_m
+            .value_of("DRIFT_THRESHOLD")
+            .map(|value| value.parse::<usize>().unwrap()),
+        zerosim_delay: sub_m
+            .value_of("DELAY")
+            .map(|value| value.parse::<usize>().unwrap()),
+        disable_ept: sub_m.is_present("DISABLE_EPT"),
+        update_exp: sub_m.is_present("UPDATE_EXP"),
+        exec: sub_m.value_of("EXEC").map(Into::into),
+        exec_on: match sub_m.value_of("ON") {
+            None | Some("host") => ExecTarget::Host,
+            Some("guest") => ExecTarget::Guest,
+            Some("both") => ExecTarget::Both,
+            _ => unreachable!(),
+        },
+        output_dir: sub_m.value_of("OUTPUT_DIR").map(Into::into),
+    };
+
+    if let Some(output_dir) = &opts.output_dir {
+        std::fs::create_dir_all(output_dir)?;
+    }
+
+    // With a single remote, just report any error directly.
+    if hosts.len() == 1 {
+        let (hostname, username) = &hosts[0];
+        let results = run_on_host(hostname, username, &opts)?;
+        return report(&results, &[]);
+    }
+
+    let outcomes: Vec<(String, Result<Vec<ExecResult>, failure::Error>)> = if parallel {
+        let handles: Vec<_> = hosts
+            .into_iter()
+            .map(|(hostname, username)| {
+                let opts = opts.clone();
+                let thread_hostname = hostname.clone();
+                let handle =
+                    std::thread::spawn(move || run_on_host(&thread_hostname, &username, &opts));
+                (hostname, handle)
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(hostname, handle)| {
+                let res = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(failure::format_err!("thread panicked")));
+                (hostname, res)
+            })
+            .collect()
+    } else {
+        hosts
+            .iter()
+            .map(|(hostname, username)| (hostname.clone(), run_on_host(hostname, username, &opts)))
+            .collect()
+    };
+
+    let mut results = vec![];
+    let mut errors = vec![];
+    for (hostname, outcome) in outcomes.into_iter() {
+        match outcome {
+            Ok(host_results) => results.extend(host_results),
+            Err(err) => errors.push((hostname, err)),
+        }
+    }
+
+    report(&results, &errors)
+}
+
+/// Print the exit status of the `--exec` command on each remote and any errors. Returns an error
+/// if anything failed.
+fn report(
+    results: &[ExecResult],
+    errors: &[(String, failure::Error)],
+) -> Result<(), failure::Error> {
+    for result in results.iter() {
+        println!(
+            "{} ({}): exit status {}",
+            result.hostname, result.target, result.exit_code
+        );
+    }
+    for (hostname, err) in errors.iter() {
+        println!("{}: error: {}", hostname, err);
+    }
+
+    let failed = results.iter().filter(|r| r.exit_code != 0).count() + errors.len();
+    if failed > 0 {
+        Err(failure::format_err!(
+            "{} of {} remote commands failed",
+            failed,
+            results.len() + errors.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Run `command` with `shell` and return its exit status. The output is printed and, if
+/// `output_dir` is given, saved to a file named after the remote and target.
+fn exec(
+    shell: &SshShell,
+    hostname: &str,
+    target: &str,
+    command: &str,
+    output_dir: Option<&str>,
+) -> Result<ExecResult, failure::Error> {
+    let out = shell
+        .run(cmd!("( {} ) 2>&1 ; echo \"{}$?\"", command, EXIT_STATUS_MARKER).use_bash())?
+        .stdout;
+
+    let marker = out
+        .rfind(EXIT_STATUS_MARKER)
+        .ok_or_else(|| failure::format_err!("No exit status in output of {:?}", command))?;
+    let output = &out[..marker];
+    let exit_code = out[marker + EXIT_STATUS_MARKER.len()..].trim().parse()?;
+
+    if let Some(output_dir) = output_dir {
+        let file = format!("{}-{}.out", hostname.replace(':', "_"), target);
+        std::fs::write(std::path::Path::new(output_dir).join(file), output)?;
+    }
+
+    Ok(ExecResult {
+        hostname: hostname.into(),
+        target: target.into(),
+        exit_code,
+    })
+}
+
+/// Do everything requested in `opts` on the given remote.
+fn run_on_host(
+    hostname: &str,
+    username: &str,
+    opts: &ManualOptions,
+) -> Result<Vec<ExecResult>, failure::Error> {
     let login = Login {
-        username: sub_m.value_of("USERNAME").unwrap(),
-        hostname: sub_m.value_of("HOSTNAME").unwrap(),
-        host: sub_m.value_of("HOSTNAME").unwrap(),
+        username,
+        hostname,
+        host: hostname,
     };
-    let reboot = sub_m.is_present("REBOOT");
-    let swap = sub_m.is_present("SWAP");
-    let perfgov = sub_m.is_present("PERF");
-    let printk = sub_m
-        .value_of("PRINTK")
-        .map(|value| value.parse::<usize>().unwrap());
-    let ssdswap = sub_m.is_present("SSDSWAP");
-    let vm = sub_m.is_present("VM");
-    let vm_size = sub_m
-        .value_of("VMSIZE")
-        .map(|value| value.parse::<usize>().unwrap());
-    let vm_cores = sub_m
-        .value_of("VMCORES")
-        .map(|value| value.parse::<usize>().unwrap());
-    let disable_tsc = sub_m.is_present("DISABLETSC");
-    let zswap = sub_m
-        .value_of("ZSWAP")
-        .map(|value| value.parse::<usize>().unwrap());
-    let zerosim_drift_threshold = sub_m
-        .value_of("DRIFT_THRESHOLD")
-        .map(|value| value.parse::<usize>().unwrap());
-    let zerosim_delay = sub_m
-        .value_of("DELAY")
-        .map(|value| value.parse::<usize>().unwrap());
-    let disable_ept = sub_m.is_present("DISABLE_EPT");
-    let update_exp = sub_m.is_present("UPDATE_EXP");
+    let ManualOptions {
+        reboot,
+        swap,
+        perfgov,
+        printk,
+        ssdswap,
+        vm,
+        vm_size,
+        vm_cores,
+        vms,
+        disable_tsc,
+        zswap,
+        zerosim_drift_threshold,
+        zerosim_delay,
+        disable_ept,
+        update_exp,
+        ..
+    } = opts.clone();
 
     // Reboot
     if reboot {
@@ -148,7 +401,7 @@ pub fn run(sub_m: &ArgMatches<'_>) -> Result<(), failure::Error> {
     // Boot VM
     if vm {
         let vm_size = if let Some(vm_size) = vm_size {
-            vm_size
+            vm_size.gb()
         } else {
             VAGRANT_MEM
         };
@@ -171,10 +424,39 @@ pub fn run(sub_m: &ArgMatches<'_>) -> Result<(), failure::Error> {
         )?;
     }
 
+    // Boot several named VMs, each pinned to the host CPUs after those of the previous VMs.
+    let vshells = if !vms.is_empty() {
+        let mut first_cpu = 0;
+        let configs: Vec<_> = vms
+            .iter()
+            .map(|(name, size, cores)| {
+                let config = VmConfig::new(size.gb(), *cores).pin_from(first_cpu);
+                first_cpu += cores;
+                (name.as_str(), config)
+            })
+            .collect();
+
+        start_vms(
+            &ushell,
+            &login.host,
+            &configs,
+            disable_tsc,
+            ZEROSIM_SKIP_HALT,
+            ZEROSIM_LAPIC_ADJUST,
+        )?
+    } else {
+        Default::default()
+    };
+
     // Turn on zswap
     if let Some(max_pool_percent) = zswap {
-        ZeroSim::turn_on_zswap(&mut ushell)?;
-        ZeroSim::zswap_max_pool_percent(&ushell, max_pool_percent)?;
+        ZeroSim::zswap(
+            &mut ushell,
+            &ZswapConfig {
+                max_pool_percent,
+                ..ZswapConfig::default()
+            },
+        )?;
     }
 
     // Set D and delta
@@ -192,5 +474,47 @@ pub fn run(sub_m: &ArgMatches<'_>) -> Result<(), failure::Error> {
         ushell.run(cmd!("~/.cargo/bin/cargo build --release").cwd(zerosim_exp_path_host))?;
     }
 
-    Ok(())
+    // Run the given command
+    let mut results = vec![];
+    if let Some(command) = &opts.exec {
+        let output_dir = opts.output_dir.as_deref();
+
+        if opts.exec_on != ExecTarget::Guest {
+            results.push(exec(&ushell, hostname, "host", command, output_dir)?);
+        }
+
+        if opts.exec_on != ExecTarget::Host && !vshells.is_empty() {
+            for (name, vshell) in vshells.iter() {
+                results.push(exec(vshell, hostname, name, command, output_dir)?);
+            }
+        } else if opts.exec_on != ExecTarget::Host {
+            let vshell = connect_to_vagrant_as_user(login.host)?;
+            results.push(exec(&vshell, hostname, "guest", command, output_dir)?);
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn named_vm() {
+        let (name, size, cores) = parse_named_vm("victim:64G:4").unwrap();
+        assert_eq!(name, "victim");
+        assert_eq!(size, Size::from_gb(64));
+        assert_eq!(cores, 4);
+
+        for s in &[
+            "victim:64G",
+            ":64G:4",
+            "victim:512M:4",
+            "victim:64G:four",
+            "a:1:2:3",
+        ] {
+            assert!(parse_named_vm(s).is_err(), "{:?} should not parse", s);
+        }
+    }
 }
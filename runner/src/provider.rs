@@ -0,0 +1,129 @@
+//! Acquiring machines to run experiments on from cloud providers, rather than using hosts that
+//! were provisioned by hand.
+//!
+//! Each provider implements `Provider`, which allocates machines, waits until they are ready, and
+//! tears them down again. `acquire` then waits until the machines accept SSH connections and
+//! appends their addresses to a hosts file (see `common::parse_hosts_file`), which can be passed
+//! to `setup00000 --hosts_file`. Experiments take the addresses from there as usual, so they don't
+//! depend on where the machines came from.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use spurs::{cmd, Execute, SshShell};
+
+pub mod aws;
+pub mod cloudlab;
+pub mod gcp;
+
+/// How long to wait between attempts to connect to a machine that is still booting.
+const SSH_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for machines to accept SSH connections once they are ready.
+const SSH_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A source of machines to run experiments on. A provider is configured with what to allocate
+/// when it is created, so that `acquire` can drive any of them the same way.
+pub trait Provider {
+    /// A handle to the machines allocated by one call to `allocate` (e.g. a set of instances or a
+    /// CloudLab experiment).
+    type Allocation;
+
+    /// Allocate machines. They are not necessarily ready yet; see `wait_ready`.
+    fn allocate(&self) -> Result<Self::Allocation, failure::Error>;
+
+    /// Wait until the machines of `allocation` are booted and have addresses.
+    fn wait_ready(&self, allocation: &mut Self::Allocation) -> Result<(), failure::Error>;
+
+    /// Returns the `host:port` addresses to SSH to the machines of a ready `allocation` at.
+    fn address(&self, allocation: &Self::Allocation) -> Result<Vec<String>, failure::Error>;
+
+    /// Release the machines of `allocation`.
+    fn teardown(&self, allocation: &Self::Allocation) -> Result<(), failure::Error>;
+
+    /// The user to log in to the machines as.
+    fn username(&self) -> &str;
+}
+
+/// Allocate machines from `provider`, wait until they accept SSH connections, and append them to
+/// `hosts_file`. Returns the allocation and the addresses of the machines. If the machines don't
+/// become usable, they are torn down again rather than left running.
+pub fn acquire<P: Provider>(
+    provider: &P,
+    hosts_file: &str,
+) -> Result<(P::Allocation, Vec<String>), failure::Error> {
+    let mut allocation = provider.allocate()?;
+
+    let hosts = provider
+        .wait_ready(&mut allocation)
+        .and_then(|()| provider.address(&allocation))
+        .and_then(|hosts| {
+            if hosts.is_empty() {
+                return Err(failure::format_err!("No machines were allocated"));
+            }
+
+            for host in hosts.iter() {
+                println!("Waiting for {} to accept SSH connections...", host);
+                wait_for_ssh(provider.username(), host, SSH_TIMEOUT)?;
+            }
+
+            Ok(hosts)
+        });
+
+    let hosts = match hosts {
+        Ok(hosts) => hosts,
+        Err(err) => {
+            println!("Tearing down the machines: {}", err);
+            if let Err(teardown_err) = provider.teardown(&allocation) {
+                println!("Failed to tear down the machines: {}", teardown_err);
+            }
+            return Err(err);
+        }
+    };
+
+    append_to_hosts_file(hosts_file, provider.username(), &hosts)?;
+
+    Ok((allocation, hosts))
+}
+
+/// Wait until `username@host` accepts SSH connections with the default key, or until `timeout`
+/// has passed.
+fn wait_for_ssh(username: &str, host: &str, timeout: Duration) -> Result<(), failure::Error> {
+    let start = Instant::now();
+
+    loop {
+        let res = SshShell::with_default_key(username, host)
+            .and_then(|shell| shell.run(cmd!("whoami")).map(|_| ()));
+
+        match res {
+            Ok(()) => return Ok(()),
+            Err(err) if start.elapsed() > timeout => {
+                return Err(failure::format_err!(
+                    "{} did not accept SSH connections within {:?}: {}",
+                    host,
+                    timeout,
+                    err
+                ));
+            }
+            Err(_) => std::thread::sleep(SSH_RETRY_INTERVAL),
+        }
+    }
+}
+
+/// Append a `HOSTNAME USERNAME` line for each of `hosts` to `hosts_file`, creating it if needed.
+fn append_to_hosts_file(
+    hosts_file: &str,
+    username: &str,
+    hosts: &[String],
+) -> Result<(), failure::Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(hosts_file)?;
+
+    for host in hosts.iter() {
+        writeln!(file, "{} {}", host, username)?;
+    }
+
+    Ok(())
+}
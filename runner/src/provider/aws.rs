@@ -0,0 +1,490 @@
+//! Launching and terminating AWS EC2 instances to run experiments on.
+//!
+//! This drives the `aws` command line tool on the local machine, which must be installed and
+//! configured with credentials (e.g. with `aws configure`). Instances are tagged with
+//! `RUNNER_TAG` when they are launched, so that `runner aws list` and `runner aws terminate --all`
+//! only touch instances launched by the runner. `Ec2Launch` launches instances for
+//! `provider::acquire`, which appends them to a hosts file that can be passed to
+//! `setup00000 --hosts_file --aws`.
+
+use std::process::Command;
+
+use clap::clap_app;
+
+use failure::ResultExt;
+
+use serde::Serialize;
+
+use super::Provider;
+
+/// The key of the tag marking instances launched by the runner. Its value is the name given at
+/// launch.
+const RUNNER_TAG: &str = "zerosim-runner";
+
+/// The default user of the AMIs we use (RHEL and Amazon Linux).
+const DEFAULT_USERNAME: &str = "ec2-user";
+
+/// The default size of the root volume in GB. The default of most AMIs (8GB) is not enough to
+/// build kernels and hold the VM image.
+const DEFAULT_ROOT_VOLUME_GB: usize = 100;
+
+/// The default hosts file that launched instances are appended to.
+const DEFAULT_HOSTS_FILE: &str = "aws-hosts";
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_positive(s: String) -> Result<(), String> {
+        match s.as_str().parse::<usize>() {
+            Ok(0) => Err("must be positive".into()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    clap_app! { aws =>
+        (about: "Launch, list, and terminate AWS EC2 instances to run experiments on. Requires \
+                 the `aws` command line tool to be configured locally.")
+        (@setting SubcommandRequiredElseHelp)
+        (@arg REGION: --region +takes_value
+         "(Optional) The AWS region (defaults to that of the `aws` configuration).")
+        (@subcommand launch =>
+            (about: "Launch instances, wait until they accept SSH connections, and append them \
+                     to a hosts file for `setup00000 --hosts_file`.")
+            (@arg NAME: +required +takes_value
+             "A name for the instances, used to find them later (e.g. markm-exp00000).")
+            (@arg TYPE: +required +takes_value
+             "The instance type (e.g. m5.metal). 0sim needs KVM, so this must be a bare-metal \
+              type.")
+            (@arg AMI: +required +takes_value
+             "The id of the AMI to boot (e.g. a RHEL 7 AMI).")
+            (@arg KEY_NAME: +required +takes_value
+             "The name of the EC2 key pair to log in with. Its private key must be the default \
+              SSH key locally.")
+            (@arg COUNT: --count +takes_value {is_positive}
+             "(Optional) The number of instances to launch (default: 1).")
+            (@arg SPOT: --spot
+             "(Optional) Launch spot instances rather than on-demand ones. They are cheaper, but \
+              may be terminated by AWS at any time.")
+            (@arg ROOT_VOLUME_GB: --root_volume_gb +takes_value {is_positive}
+             "(Optional) The size of the root volume in GB (default: 100).")
+            (@arg SECURITY_GROUP: --security_group +takes_value
+             "(Optional) The id of the security group of the instances. It must allow SSH.")
+            (@arg SUBNET: --subnet +takes_value
+             "(Optional) The id of the subnet to launch the instances in.")
+            (@arg USERNAME: --username +takes_value
+             "(Optional) The user to log in as (default: ec2-user).")
+            (@arg HOSTS_FILE: --hosts_file +takes_value
+             "(Optional) The hosts file to append the instances to (default: aws-hosts).")
+        )
+        (@subcommand list =>
+            (about: "List the instances launched by the runner that are not terminated.")
+            (@arg NAME: --name +takes_value
+             "(Optional) Only list the instances with the given name.")
+        )
+        (@subcommand terminate =>
+            (about: "Terminate instances launched by the runner.")
+            (@group WHICH =>
+                (@attributes +required)
+                (@arg ID: +takes_value ... "The ids of the instances to terminate.")
+                (@arg NAME: --name +takes_value
+                 "Terminate all instances with the given name.")
+                (@arg ALL: --all "Terminate all instances launched by the runner.")
+            )
+        )
+    }
+}
+
+/// Whether to launch on-demand or spot instances.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Market {
+    OnDemand,
+    Spot,
+}
+
+/// The instances to launch.
+pub struct LaunchConfig<'a> {
+    /// The name of the instances, recorded in the `RUNNER_TAG` tag.
+    pub name: &'a str,
+
+    /// The instance type (e.g. `m5.metal`).
+    pub instance_type: &'a str,
+
+    /// The id of the AMI to boot.
+    pub ami: &'a str,
+
+    /// The name of the EC2 key pair to log in with.
+    pub key_name: &'a str,
+
+    /// The number of instances to launch.
+    pub count: usize,
+
+    /// Whether to launch on-demand or spot instances.
+    pub market: Market,
+
+    /// The size of the root volume in GB.
+    pub root_volume_gb: usize,
+
+    /// The id of the security group of the instances, or the default one if `None`.
+    pub security_group: Option<&'a str>,
+
+    /// The id of the subnet to launch the instances in, or the default one if `None`.
+    pub subnet: Option<&'a str>,
+}
+
+/// An EC2 instance launched by the runner.
+#[derive(Clone, Debug, Serialize)]
+pub struct Instance {
+    /// The id of the instance (e.g. `i-0123456789abcdef0`).
+    pub id: String,
+
+    /// The name given at launch.
+    pub name: String,
+
+    /// The instance type.
+    pub instance_type: String,
+
+    /// The state of the instance (e.g. `pending`, `running`, or `terminated`).
+    pub state: String,
+
+    /// Whether this is a spot instance.
+    pub spot: bool,
+
+    /// The public DNS name of the instance (or its public IP address if it has no DNS name), once
+    /// it has one.
+    pub address: Option<String>,
+}
+
+impl Instance {
+    /// Parse an instance from the output of `aws ec2 describe-instances` or `run-instances`.
+    fn from_json(json: &serde_json::Value) -> Self {
+        let string = |value: &serde_json::Value| {
+            value
+                .as_str()
+                .filter(|s| !s.is_empty())
+                .map(ToOwned::to_owned)
+        };
+
+        let name = json["Tags"]
+            .as_array()
+            .and_then(|tags| tags.iter().find(|tag| tag["Key"] == RUNNER_TAG))
+            .and_then(|tag| string(&tag["Value"]))
+            .unwrap_or_default();
+
+        Instance {
+            id: string(&json["InstanceId"]).unwrap_or_default(),
+            name,
+            instance_type: string(&json["InstanceType"]).unwrap_or_default(),
+            state: string(&json["State"]["Name"]).unwrap_or_default(),
+            spot: json["InstanceLifecycle"] == "spot",
+            address: string(&json["PublicDnsName"]).or_else(|| string(&json["PublicIpAddress"])),
+        }
+    }
+}
+
+/// A client for EC2 in one region.
+pub struct Ec2 {
+    /// The region, or that of the `aws` configuration if `None`.
+    region: Option<String>,
+}
+
+impl Ec2 {
+    pub fn new(region: Option<&str>) -> Self {
+        Ec2 {
+            region: region.map(Into::into),
+        }
+    }
+
+    /// Run `aws ec2` with the given arguments and return its JSON output (`Null` if there is
+    /// none).
+    fn aws(&self, args: &[&str]) -> Result<serde_json::Value, failure::Error> {
+        let mut command = Command::new("aws");
+        command.arg("ec2").args(args).args(["--output", "json"]);
+        if let Some(region) = &self.region {
+            command.args(["--region", region.as_str()]);
+        }
+
+        let output = command
+            .output()
+            .context("running the `aws` command line tool; is it installed?")?;
+
+        if !output.status.success() {
+            return Err(failure::format_err!(
+                "`aws ec2 {}` failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            Ok(serde_json::Value::Null)
+        } else {
+            Ok(serde_json::from_str(&stdout)?)
+        }
+    }
+
+    /// Launch instances and return them. They are not necessarily running yet; see
+    /// `wait_running`.
+    pub fn launch(&self, cfg: &LaunchConfig<'_>) -> Result<Vec<Instance>, failure::Error> {
+        let count = cfg.count.to_string();
+        let tags = format!(
+            "ResourceType=instance,Tags=[{{Key={},Value={}}}]",
+            RUNNER_TAG, cfg.name
+        );
+        let block_devices = serde_json::json!([{
+            "DeviceName": "/dev/sda1",
+            "Ebs": { "VolumeSize": cfg.root_volume_gb, "DeleteOnTermination": true },
+        }])
+        .to_string();
+
+        let mut args = vec![
+            "run-instances",
+            "--image-id",
+            cfg.ami,
+            "--instance-type",
+            cfg.instance_type,
+            "--key-name",
+            cfg.key_name,
+            "--count",
+            count.as_str(),
+            "--tag-specifications",
+            tags.as_str(),
+            "--block-device-mappings",
+            block_devices.as_str(),
+        ];
+        if cfg.market == Market::Spot {
+            args.extend(&[
+                "--instance-market-options",
+                r#"{"MarketType":"spot","SpotOptions":{"SpotInstanceType":"one-time"}}"#,
+            ]);
+        }
+        if let Some(security_group) = cfg.security_group {
+            args.extend(&["--security-group-ids", security_group]);
+        }
+        if let Some(subnet) = cfg.subnet {
+            args.extend(&["--subnet-id", subnet]);
+        }
+
+        let output = self.aws(&args)?;
+
+        Ok(output["Instances"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Instance::from_json)
+            .collect())
+    }
+
+    /// Wait until the given instances are running, and return them.
+    pub fn wait_running(&self, ids: &[String]) -> Result<Vec<Instance>, failure::Error> {
+        let mut args = vec!["wait", "instance-running", "--instance-ids"];
+        args.extend(ids.iter().map(String::as_str));
+        self.aws(&args)?;
+
+        self.describe(ids)
+    }
+
+    /// Returns the given instances.
+    pub fn describe(&self, ids: &[String]) -> Result<Vec<Instance>, failure::Error> {
+        let mut args = vec!["describe-instances", "--instance-ids"];
+        args.extend(ids.iter().map(String::as_str));
+
+        Ok(Self::instances(&self.aws(&args)?))
+    }
+
+    /// Returns the instances launched by the runner (with the given name, if any) that are not
+    /// terminated.
+    pub fn list(&self, name: Option<&str>) -> Result<Vec<Instance>, failure::Error> {
+        let tag_filter = match name {
+            Some(name) => format!("Name=tag:{},Values={}", RUNNER_TAG, name),
+            None => format!("Name=tag-key,Values={}", RUNNER_TAG),
+        };
+
+        let output = self.aws(&[
+            "describe-instances",
+            "--filters",
+            tag_filter.as_str(),
+            "Name=instance-state-name,Values=pending,running,stopping,stopped",
+        ])?;
+
+        Ok(Self::instances(&output))
+    }
+
+    /// Terminate the given instances.
+    pub fn terminate(&self, ids: &[String]) -> Result<(), failure::Error> {
+        let mut args = vec!["terminate-instances", "--instance-ids"];
+        args.extend(ids.iter().map(String::as_str));
+        self.aws(&args)?;
+
+        Ok(())
+    }
+
+    /// Returns the instances in the output of `aws ec2 describe-instances`.
+    fn instances(output: &serde_json::Value) -> Vec<Instance> {
+        output["Reservations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|reservation| reservation["Instances"].as_array())
+            .flatten()
+            .map(Instance::from_json)
+            .collect()
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let ec2 = Ec2::new(sub_m.value_of("REGION"));
+
+    match sub_m.subcommand() {
+        ("launch", Some(sub_m)) => launch(&ec2, sub_m),
+
+        ("list", Some(sub_m)) => {
+            for instance in ec2.list(sub_m.value_of("NAME"))?.iter() {
+                print_instance(instance);
+            }
+            Ok(())
+        }
+
+        ("terminate", Some(sub_m)) => {
+            let ids: Vec<String> = if let Some(ids) = sub_m.values_of("ID") {
+                ids.map(String::from).collect()
+            } else {
+                ec2.list(sub_m.value_of("NAME"))?
+                    .into_iter()
+                    .map(|instance| instance.id)
+                    .collect()
+            };
+
+            if ids.is_empty() {
+                println!("No instances to terminate.");
+                return Ok(());
+            }
+
+            ec2.terminate(&ids)?;
+            for id in ids.iter() {
+                println!("Terminated {}", id);
+            }
+
+            Ok(())
+        }
+
+        _ => unreachable!(),
+    }
+}
+
+/// Launches EC2 instances as a `Provider`.
+pub struct Ec2Launch<'a> {
+    pub ec2: &'a Ec2,
+
+    /// The instances to launch.
+    pub cfg: LaunchConfig<'a>,
+
+    /// The user to log in to the instances as.
+    pub username: &'a str,
+}
+
+impl Provider for Ec2Launch<'_> {
+    type Allocation = Vec<Instance>;
+
+    fn allocate(&self) -> Result<Self::Allocation, failure::Error> {
+        let instances = self.ec2.launch(&self.cfg)?;
+        println!(
+            "Launched {}. Waiting for them to run...",
+            instances
+                .iter()
+                .map(|instance| instance.id.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+
+        Ok(instances)
+    }
+
+    fn wait_ready(&self, instances: &mut Self::Allocation) -> Result<(), failure::Error> {
+        let ids: Vec<String> = instances
+            .iter()
+            .map(|instance| instance.id.clone())
+            .collect();
+        *instances = self.ec2.wait_running(&ids)?;
+
+        Ok(())
+    }
+
+    fn address(&self, instances: &Self::Allocation) -> Result<Vec<String>, failure::Error> {
+        instances
+            .iter()
+            .map(|instance| match &instance.address {
+                Some(address) => Ok(format!("{}:22", address)),
+                None => Err(failure::format_err!(
+                    "Instance {} has no public address; is the subnet public?",
+                    instance.id
+                )),
+            })
+            .collect()
+    }
+
+    fn teardown(&self, instances: &Self::Allocation) -> Result<(), failure::Error> {
+        let ids: Vec<String> = instances
+            .iter()
+            .map(|instance| instance.id.clone())
+            .collect();
+        self.ec2.terminate(&ids)
+    }
+
+    fn username(&self) -> &str {
+        self.username
+    }
+}
+
+/// Launch instances, wait until they accept SSH connections, and append them to the hosts file.
+fn launch(ec2: &Ec2, sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let username = sub_m.value_of("USERNAME").unwrap_or(DEFAULT_USERNAME);
+    let hosts_file = sub_m.value_of("HOSTS_FILE").unwrap_or(DEFAULT_HOSTS_FILE);
+
+    let cfg = LaunchConfig {
+        name: sub_m.value_of("NAME").unwrap(),
+        instance_type: sub_m.value_of("TYPE").unwrap(),
+        ami: sub_m.value_of("AMI").unwrap(),
+        key_name: sub_m.value_of("KEY_NAME").unwrap(),
+        count: sub_m
+            .value_of("COUNT")
+            .map(|value| value.parse::<usize>().unwrap())
+            .unwrap_or(1),
+        market: if sub_m.is_present("SPOT") {
+            Market::Spot
+        } else {
+            Market::OnDemand
+        },
+        root_volume_gb: sub_m
+            .value_of("ROOT_VOLUME_GB")
+            .map(|value| value.parse::<usize>().unwrap())
+            .unwrap_or(DEFAULT_ROOT_VOLUME_GB),
+        security_group: sub_m.value_of("SECURITY_GROUP"),
+        subnet: sub_m.value_of("SUBNET"),
+    };
+
+    let name = cfg.name;
+    let (instances, _) = super::acquire(&Ec2Launch { ec2, cfg, username }, hosts_file)?;
+
+    for instance in instances.iter() {
+        print_instance(instance);
+    }
+    println!(
+        "Added the instances to {}. Set them up with `runner setup00000 --hosts_file {} --aws`, \
+         and terminate them with `runner aws terminate --name {}` when done.",
+        hosts_file, hosts_file, name
+    );
+
+    Ok(())
+}
+
+fn print_instance(instance: &Instance) {
+    println!(
+        "{} {} {} {}{} {}",
+        instance.id,
+        instance.name,
+        instance.instance_type,
+        instance.state,
+        if instance.spot { " (spot)" } else { "" },
+        instance.address.as_deref().unwrap_or("-"),
+    );
+}
@@ -0,0 +1,491 @@
+//! Instantiating CloudLab profiles to get machines to run experiments on.
+//!
+//! This talks to the XML-RPC API of the Emulab portal that CloudLab runs on (the same API that
+//! CloudLab's `portal-tools` use), with `curl` on the local machine. Requests are authenticated
+//! with the SSL certificate from "Download Credentials" in the CloudLab web UI, which must be
+//! decrypted first (e.g. `openssl rsa -in cloudlab.pem -out key.pem` and appending the
+//! certificate). `ProfileInstance` instantiates a profile for `provider::acquire`, which takes
+//! the hostnames of its nodes from the manifest of the experiment, so they no longer need to be
+//! copied from the web UI.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use clap::clap_app;
+
+use failure::ResultExt;
+
+use super::Provider;
+
+/// The XML-RPC server of the Emulab portal.
+const XMLRPC_SERVER: &str = "https://boss.emulab.net:3069/usr/testbed";
+
+/// The version of the XML-RPC API we speak.
+const XMLRPC_VERSION: &str = "0.1";
+
+/// Where `portal-tools` expects the decrypted certificate, relative to the home directory.
+const DEFAULT_CERT: &str = ".ssl/emulab.pem";
+
+/// The default duration of new experiments in hours.
+pub const DEFAULT_DURATION_HOURS: usize = 16;
+
+/// How long to wait for an experiment to become ready, and how often to check.
+const READY_TIMEOUT: Duration = Duration::from_secs(3600);
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_positive(s: String) -> Result<(), String> {
+        match s.as_str().parse::<usize>() {
+            Ok(0) => Err("must be positive".into()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    clap_app! { cloudlab =>
+        (about: "Instantiate, extend, and terminate CloudLab experiments to run experiments on. \
+                 Requires `curl` and a decrypted CloudLab certificate locally.")
+        (@setting SubcommandRequiredElseHelp)
+        (@arg PROJECT: +required +takes_value
+         "The CloudLab project of the experiment (e.g. SuperSecretProject).")
+        (@arg CERT: --cert +takes_value
+         "(Optional) The path of the decrypted CloudLab certificate (default: ~/.ssl/emulab.pem).")
+        (@subcommand start =>
+            (about: "Instantiate a profile, wait until its nodes accept SSH connections, and \
+                     append them to a hosts file for `setup00000 --hosts_file`.")
+            (@arg PROFILE: +required +takes_value
+             "The profile to instantiate, as `PROJECT,NAME` or just `NAME` for a profile of the \
+              project.")
+            (@arg NAME: +required +takes_value
+             "The name of the new experiment.")
+            (@arg USERNAME: +required +takes_value
+             "The CloudLab username to log in to the nodes as.")
+            (@arg HOURS: --hours +takes_value {is_positive}
+             "(Optional) The duration of the experiment in hours (default: 16).")
+            (@arg CLUSTER: --cluster +takes_value
+             "(Optional) The URN of the cluster to instantiate the profile on (e.g. \
+              urn:publicid:IDN+wisc.cloudlab.us+authority+cm).")
+            (@arg HOSTS_FILE: --hosts_file +takes_value
+             "(Optional) The hosts file to append the nodes to (default: cloudlab-<NAME>-hosts).")
+        )
+        (@subcommand hosts =>
+            (about: "Print the hostnames of the nodes of an experiment.")
+            (@arg NAME: +required +takes_value "The name of the experiment.")
+        )
+        (@subcommand extend =>
+            (about: "Extend an experiment.")
+            (@arg NAME: +required +takes_value "The name of the experiment.")
+            (@arg HOURS: +required +takes_value {is_positive}
+             "The number of hours to extend the experiment by.")
+            (@arg REASON: --reason +takes_value
+             "(Optional) Why the extension is needed. Long extensions must be justified.")
+        )
+        (@subcommand terminate =>
+            (about: "Terminate an experiment.")
+            (@arg NAME: +required +takes_value "The name of the experiment.")
+        )
+    }
+}
+
+/// A client for the CloudLab portal, acting on the experiments of one project.
+pub struct CloudLab {
+    /// The project of the experiments.
+    project: String,
+
+    /// The path of the decrypted certificate.
+    cert: String,
+}
+
+impl CloudLab {
+    /// Create a client for the experiments of `project`, authenticating with the certificate at
+    /// `cert` (or `DEFAULT_CERT` if `None`).
+    pub fn new(project: &str, cert: Option<&str>) -> Result<Self, failure::Error> {
+        let cert = match cert {
+            Some(cert) => cert.to_owned(),
+            None => {
+                let home = std::env::var("HOME").context("finding the home directory")?;
+                dir!(home.as_str(), DEFAULT_CERT)
+            }
+        };
+
+        Ok(CloudLab {
+            project: project.into(),
+            cert,
+        })
+    }
+
+    /// The name of the experiment `name` as the API expects it.
+    fn experiment(&self, name: &str) -> String {
+        format!("{},{}", self.project, name)
+    }
+
+    /// Call the XML-RPC method `portal.<method>` with the given arguments and return the value it
+    /// returns. Returns an error if the method fails.
+    fn call(&self, method: &str, args: &[(&str, &str)]) -> Result<String, failure::Error> {
+        let members: String = args
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "<member><name>{}</name><value><string>{}</string></value></member>",
+                    name,
+                    xml_escape(value)
+                )
+            })
+            .collect();
+        let request = format!(
+            "<?xml version=\"1.0\"?><methodCall><methodName>portal.{}</methodName><params>\
+             <param><value><string>{}</string></value></param>\
+             <param><value><struct>{}</struct></value></param>\
+             </params></methodCall>",
+            method, XMLRPC_VERSION, members
+        );
+
+        let mut curl = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--insecure"])
+            .args(["--cert", &self.cert])
+            .args(["-H", "Content-Type: text/xml", "--data-binary", "@-"])
+            .arg(XMLRPC_SERVER)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("running `curl`; is it installed?")?;
+
+        {
+            use std::io::Write;
+            let stdin = curl.stdin.as_mut().expect("stdin is piped");
+            stdin.write_all(request.as_bytes())?;
+        }
+
+        let output = curl.wait_with_output()?;
+        if !output.status.success() {
+            return Err(failure::format_err!(
+                "CloudLab request portal.{} failed: {}",
+                method,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let response = String::from_utf8_lossy(&output.stdout);
+
+        if response.contains("<fault>") {
+            return Err(failure::format_err!(
+                "CloudLab request portal.{} failed: {}",
+                method,
+                xmlrpc_member(&response, "faultString").unwrap_or_default()
+            ));
+        }
+
+        // Emulab wraps the result in a struct with a return code and any error output.
+        let code = xmlrpc_member(&response, "code").unwrap_or_default();
+        if code.trim() != "0" {
+            return Err(failure::format_err!(
+                "CloudLab request portal.{} failed with code {}: {}",
+                method,
+                code,
+                xmlrpc_member(&response, "output").unwrap_or_default()
+            ));
+        }
+
+        Ok(xmlrpc_member(&response, "value").unwrap_or_default())
+    }
+
+    /// Instantiate `profile` (`PROJECT,NAME`, or just `NAME` for a profile of the project) as the
+    /// experiment `name` for `hours` hours, optionally on the cluster with URN `cluster`. The
+    /// experiment is not necessarily ready yet; see `wait_ready`.
+    pub fn start(
+        &self,
+        profile: &str,
+        name: &str,
+        hours: usize,
+        cluster: Option<&str>,
+    ) -> Result<(), failure::Error> {
+        let profile = if profile.contains(',') {
+            profile.to_owned()
+        } else {
+            format!("{},{}", self.project, profile)
+        };
+        let hours = hours.to_string();
+
+        let mut args = vec![
+            ("proj", self.project.as_str()),
+            ("profile", profile.as_str()),
+            ("name", name),
+            ("duration", hours.as_str()),
+        ];
+        if let Some(cluster) = cluster {
+            args.push(("aggregate", cluster));
+        }
+
+        self.call("startExperiment", &args)?;
+
+        Ok(())
+    }
+
+    /// Returns the status of the experiment `name` (e.g. `provisioning`, `ready`, or `failed`).
+    pub fn status(&self, name: &str) -> Result<String, failure::Error> {
+        let experiment = self.experiment(name);
+        let status = self.call(
+            "experimentStatus",
+            &[("experiment", experiment.as_str()), ("asjson", "1")],
+        )?;
+
+        // Older portals ignore `asjson` and return a `Status: ...` line instead.
+        let status = match serde_json::from_str::<serde_json::Value>(&status) {
+            Ok(json) => json["status"].as_str().unwrap_or_default().to_owned(),
+            Err(_) => status
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with("Status:"))
+                .map(|line| &line["Status:".len()..])
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+        };
+
+        Ok(status.to_lowercase())
+    }
+
+    /// Wait until the experiment `name` is ready. Returns an error if it fails or doesn't become
+    /// ready in time.
+    pub fn wait_ready(&self, name: &str) -> Result<(), failure::Error> {
+        let start = Instant::now();
+
+        loop {
+            match self.status(name)?.as_str() {
+                "ready" => return Ok(()),
+                "failed" | "terminated" => {
+                    return Err(failure::format_err!("CloudLab experiment {} failed", name));
+                }
+                status if start.elapsed() > READY_TIMEOUT => {
+                    return Err(failure::format_err!(
+                        "CloudLab experiment {} is not ready after {:?} (status: {})",
+                        name,
+                        READY_TIMEOUT,
+                        status
+                    ));
+                }
+                status => {
+                    println!("Experiment {} is {}...", name, status);
+                    std::thread::sleep(READY_POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    /// Returns the hostnames of the nodes of the experiment `name`, from its manifests.
+    pub fn hosts(&self, name: &str) -> Result<Vec<String>, failure::Error> {
+        let experiment = self.experiment(name);
+        let manifests = self.call(
+            "experimentManifests",
+            &[("experiment", experiment.as_str())],
+        )?;
+
+        // The manifests are returned as a JSON object mapping each cluster to its manifest.
+        let manifests: Vec<String> = match serde_json::from_str::<serde_json::Value>(&manifests) {
+            Ok(serde_json::Value::Object(manifests)) => manifests
+                .values()
+                .filter_map(|manifest| manifest.as_str().map(ToOwned::to_owned))
+                .collect(),
+            _ => vec![manifests],
+        };
+
+        let mut hosts: Vec<String> = manifests
+            .iter()
+            .flat_map(|manifest| xml_attr_values(manifest, "host", "name"))
+            .collect();
+        hosts.sort();
+        hosts.dedup();
+
+        Ok(hosts)
+    }
+
+    /// Extend the experiment `name` by `hours` hours, giving `reason` for the extension.
+    pub fn extend(&self, name: &str, hours: usize, reason: &str) -> Result<(), failure::Error> {
+        let experiment = self.experiment(name);
+        let hours = hours.to_string();
+
+        self.call(
+            "extendExperiment",
+            &[
+                ("experiment", experiment.as_str()),
+                ("wanted", hours.as_str()),
+                ("reason", reason),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Terminate the experiment `name`, releasing its nodes.
+    pub fn terminate(&self, name: &str) -> Result<(), failure::Error> {
+        let experiment = self.experiment(name);
+        self.call(
+            "terminateExperiment",
+            &[("experiment", experiment.as_str())],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Instantiates a CloudLab profile as a `Provider`. The allocation is the name of the
+/// experiment.
+pub struct ProfileInstance<'a> {
+    pub cloudlab: &'a CloudLab,
+
+    /// The profile to instantiate (see `CloudLab::start`).
+    pub profile: &'a str,
+
+    /// The name of the new experiment.
+    pub name: &'a str,
+
+    /// The duration of the experiment in hours.
+    pub hours: usize,
+
+    /// The URN of the cluster to instantiate the profile on, or any cluster if `None`.
+    pub cluster: Option<&'a str>,
+
+    /// The CloudLab user to log in to the nodes as.
+    pub username: &'a str,
+}
+
+impl Provider for ProfileInstance<'_> {
+    type Allocation = String;
+
+    fn allocate(&self) -> Result<Self::Allocation, failure::Error> {
+        self.cloudlab
+            .start(self.profile, self.name, self.hours, self.cluster)?;
+        println!(
+            "Started CloudLab experiment {}. Waiting for it to be ready...",
+            self.name
+        );
+
+        Ok(self.name.to_owned())
+    }
+
+    fn wait_ready(&self, name: &mut Self::Allocation) -> Result<(), failure::Error> {
+        self.cloudlab.wait_ready(name)
+    }
+
+    fn address(&self, name: &Self::Allocation) -> Result<Vec<String>, failure::Error> {
+        Ok(self
+            .cloudlab
+            .hosts(name)?
+            .into_iter()
+            .map(|host| format!("{}:22", host))
+            .collect())
+    }
+
+    fn teardown(&self, name: &Self::Allocation) -> Result<(), failure::Error> {
+        self.cloudlab.terminate(name)
+    }
+
+    fn username(&self) -> &str {
+        self.username
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let cloudlab = CloudLab::new(sub_m.value_of("PROJECT").unwrap(), sub_m.value_of("CERT"))?;
+
+    match sub_m.subcommand() {
+        ("start", Some(sub_m)) => {
+            let name = sub_m.value_of("NAME").unwrap();
+            let default_hosts_file = format!("cloudlab-{}-hosts", name);
+            let hosts_file = sub_m.value_of("HOSTS_FILE").unwrap_or(&default_hosts_file);
+
+            let (_, hosts) = super::acquire(
+                &ProfileInstance {
+                    cloudlab: &cloudlab,
+                    profile: sub_m.value_of("PROFILE").unwrap(),
+                    name,
+                    hours: sub_m
+                        .value_of("HOURS")
+                        .map(|value| value.parse::<usize>().unwrap())
+                        .unwrap_or(DEFAULT_DURATION_HOURS),
+                    cluster: sub_m.value_of("CLUSTER"),
+                    username: sub_m.value_of("USERNAME").unwrap(),
+                },
+                hosts_file,
+            )?;
+
+            for host in hosts.iter() {
+                println!("{}", host);
+            }
+            println!(
+                "Added the nodes to {}. Set them up with `runner setup00000 --hosts_file {}`.",
+                hosts_file, hosts_file
+            );
+
+            Ok(())
+        }
+
+        ("hosts", Some(sub_m)) => {
+            for host in cloudlab.hosts(sub_m.value_of("NAME").unwrap())?.iter() {
+                println!("{}", host);
+            }
+            Ok(())
+        }
+
+        ("extend", Some(sub_m)) => cloudlab.extend(
+            sub_m.value_of("NAME").unwrap(),
+            sub_m.value_of("HOURS").unwrap().parse::<usize>().unwrap(),
+            sub_m.value_of("REASON").unwrap_or("Running experiments."),
+        ),
+
+        ("terminate", Some(sub_m)) => cloudlab.terminate(sub_m.value_of("NAME").unwrap()),
+
+        _ => unreachable!(),
+    }
+}
+
+/// Escape `s` for use as text in XML.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Undo `xml_escape` and the other predefined XML entities.
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the scalar value of the first struct member called `name` in an XML-RPC response, or
+/// `None` if there is none.
+fn xmlrpc_member(response: &str, name: &str) -> Option<String> {
+    let start = response.find(&format!("<name>{}</name>", name))?;
+    let rest = &response[start..];
+    let value = &rest[rest.find("<value>")? + "<value>".len()..];
+    let value = &value[..value.find("</value>")?];
+
+    // Strip the type tag (e.g. `<string>` or `<int>`), if any.
+    let value = if value.starts_with('<') {
+        let inner = &value[value.find('>')? + 1..];
+        &inner[..inner.rfind("</").unwrap_or(inner.len())]
+    } else {
+        value
+    };
+
+    Some(xml_unescape(value))
+}
+
+/// Returns the values of attribute `attr` of each `element` element in `xml`.
+fn xml_attr_values(xml: &str, element: &str, attr: &str) -> Vec<String> {
+    let open = format!("<{} ", element);
+    let attr = format!("{}=\"", attr);
+
+    xml.match_indices(&open)
+        .filter_map(|(start, _)| {
+            let tag = &xml[start..];
+            let tag = &tag[..tag.find('>')?];
+            let value = &tag[tag.find(&attr)? + attr.len()..];
+            Some(value[..value.find('"')?].to_owned())
+        })
+        .collect()
+}
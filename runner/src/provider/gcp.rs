@@ -0,0 +1,518 @@
+//! Creating and deleting Google Compute Engine instances to run experiments on.
+//!
+//! This drives the `gcloud` command line tool on the local machine, which must be installed and
+//! logged in (e.g. with `gcloud init`). 0sim needs KVM, so instances are created with nested
+//! virtualization enabled. They are labeled with `RUNNER_LABEL` so that `runner gcp list` and
+//! `runner gcp delete --all` only touch instances created by the runner, and the local default
+//! SSH public key is added to their metadata for the given user. `GceCreate` creates instances
+//! for `provider::acquire`, which appends them to a hosts file that can be passed to
+//! `setup00000 --hosts_file`.
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use clap::clap_app;
+
+use failure::ResultExt;
+
+use serde::Serialize;
+
+use super::Provider;
+
+/// The key of the label marking instances created by the runner. Its value is the name given at
+/// creation.
+const RUNNER_LABEL: &str = "zerosim-runner";
+
+/// The default image to boot. `setup00000` supports CentOS.
+const DEFAULT_IMAGE_FAMILY: &str = "centos-7";
+const DEFAULT_IMAGE_PROJECT: &str = "centos-cloud";
+
+/// The default size of the boot disk in GB. The default (10GB) is not enough to build kernels and
+/// hold the VM image.
+const DEFAULT_BOOT_DISK_GB: usize = 100;
+
+/// The default hosts file that created instances are appended to.
+const DEFAULT_HOSTS_FILE: &str = "gcp-hosts";
+
+/// The local SSH public key to log in with, relative to the home directory. Its private key must
+/// be the default SSH key.
+const SSH_PUBLIC_KEY: &str = ".ssh/id_rsa.pub";
+
+/// How long to wait for instances to be running, and how often to check.
+const RUNNING_TIMEOUT: Duration = Duration::from_secs(600);
+const RUNNING_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    fn is_positive(s: String) -> Result<(), String> {
+        match s.as_str().parse::<usize>() {
+            Ok(0) => Err("must be positive".into()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    clap_app! { gcp =>
+        (about: "Create, list, and delete Google Compute Engine instances to run experiments on. \
+                 Requires the `gcloud` command line tool to be configured locally.")
+        (@setting SubcommandRequiredElseHelp)
+        (@arg PROJECT: --project +takes_value
+         "(Optional) The GCP project (defaults to that of the `gcloud` configuration).")
+        (@arg ZONE: --zone +takes_value
+         "(Optional) The zone of the instances (defaults to that of the `gcloud` \
+          configuration).")
+        (@subcommand create =>
+            (about: "Create instances, wait until they accept SSH connections, and append them \
+                     to a hosts file for `setup00000 --hosts_file`.")
+            (@arg NAME: +required +takes_value
+             "A name for the instances, used to find them later. The instances are called \
+              NAME-0, NAME-1, etc., so it must be lowercase letters, digits, and dashes \
+              (e.g. markm-exp00000).")
+            (@arg MACHINE_TYPE: +required +takes_value
+             "The machine type (e.g. n1-highmem-16). It must support nested virtualization.")
+            (@arg USERNAME: +required +takes_value
+             "The user to create on the instances and log in as (e.g. markm).")
+            (@arg COUNT: --count +takes_value {is_positive}
+             "(Optional) The number of instances to create (default: 1).")
+            (@arg PREEMPTIBLE: --preemptible
+             "(Optional) Create preemptible instances. They are cheaper, but may be stopped by \
+              GCP at any time.")
+            (@arg IMAGE_FAMILY: --image_family +takes_value
+             "(Optional) The image family to boot (default: centos-7).")
+            (@arg IMAGE_PROJECT: --image_project +takes_value
+             "(Optional) The project of the image family (default: centos-cloud).")
+            (@arg BOOT_DISK_GB: --boot_disk_gb +takes_value {is_positive}
+             "(Optional) The size of the boot disk in GB (default: 100).")
+            (@arg HOSTS_FILE: --hosts_file +takes_value
+             "(Optional) The hosts file to append the instances to (default: gcp-hosts).")
+        )
+        (@subcommand list =>
+            (about: "List the instances created by the runner.")
+            (@arg NAME: --name +takes_value
+             "(Optional) Only list the instances with the given name.")
+        )
+        (@subcommand delete =>
+            (about: "Delete instances created by the runner.")
+            (@group WHICH =>
+                (@attributes +required)
+                (@arg INSTANCE: +takes_value ... "The instances to delete (e.g. markm-exp00000-0).")
+                (@arg NAME: --name +takes_value
+                 "Delete all instances with the given name.")
+                (@arg ALL: --all "Delete all instances created by the runner.")
+            )
+        )
+    }
+}
+
+/// The instances to create.
+pub struct CreateConfig<'a> {
+    /// The name of the instances, recorded in the `RUNNER_LABEL` label.
+    pub name: &'a str,
+
+    /// The machine type (e.g. `n1-highmem-16`).
+    pub machine_type: &'a str,
+
+    /// The number of instances to create.
+    pub count: usize,
+
+    /// Whether to create preemptible instances.
+    pub preemptible: bool,
+
+    /// The image family to boot and its project.
+    pub image_family: &'a str,
+    pub image_project: &'a str,
+
+    /// The size of the boot disk in GB.
+    pub boot_disk_gb: usize,
+
+    /// The user to create on the instances with the local SSH public key.
+    pub username: &'a str,
+}
+
+/// A GCE instance created by the runner.
+#[derive(Clone, Debug, Serialize)]
+pub struct Instance {
+    /// The name of the instance (e.g. `markm-exp00000-0`).
+    pub id: String,
+
+    /// The name given at creation.
+    pub name: String,
+
+    /// The machine type.
+    pub machine_type: String,
+
+    /// The zone of the instance.
+    pub zone: String,
+
+    /// The status of the instance (e.g. `PROVISIONING`, `RUNNING`, or `TERMINATED`).
+    pub status: String,
+
+    /// Whether this is a preemptible instance.
+    pub preemptible: bool,
+
+    /// The external IP address of the instance, once it has one.
+    pub address: Option<String>,
+}
+
+impl Instance {
+    /// Parse an instance from the JSON output of `gcloud compute instances`.
+    fn from_json(json: &serde_json::Value) -> Self {
+        let string = |value: &serde_json::Value| {
+            value
+                .as_str()
+                .filter(|s| !s.is_empty())
+                .map(ToOwned::to_owned)
+        };
+
+        // Zones and machine types are given as URLs.
+        let last_segment = |value: &serde_json::Value| {
+            string(value)
+                .and_then(|url| url.rsplit('/').next().map(ToOwned::to_owned))
+                .unwrap_or_default()
+        };
+
+        Instance {
+            id: string(&json["name"]).unwrap_or_default(),
+            name: string(&json["labels"][RUNNER_LABEL]).unwrap_or_default(),
+            machine_type: last_segment(&json["machineType"]),
+            zone: last_segment(&json["zone"]),
+            status: string(&json["status"]).unwrap_or_default(),
+            preemptible: json["scheduling"]["preemptible"] == true,
+            address: string(&json["networkInterfaces"][0]["accessConfigs"][0]["natIP"]),
+        }
+    }
+}
+
+/// A client for GCE in one project and zone.
+pub struct Gce {
+    /// The project, or that of the `gcloud` configuration if `None`.
+    project: Option<String>,
+
+    /// The zone, or that of the `gcloud` configuration if `None`.
+    zone: Option<String>,
+}
+
+impl Gce {
+    pub fn new(project: Option<&str>, zone: Option<&str>) -> Self {
+        Gce {
+            project: project.map(Into::into),
+            zone: zone.map(Into::into),
+        }
+    }
+
+    /// Run `gcloud compute instances` with the given arguments and return its JSON output (`Null`
+    /// if there is none). Callers pass the zone themselves, since `list` takes zones differently.
+    fn gcloud(&self, args: &[&str]) -> Result<serde_json::Value, failure::Error> {
+        let mut command = Command::new("gcloud");
+        command
+            .args(["compute", "instances"])
+            .args(args)
+            .args(["--format", "json"]);
+        if let Some(project) = &self.project {
+            command.args(["--project", project.as_str()]);
+        }
+
+        let output = command
+            .output()
+            .context("running the `gcloud` command line tool; is it installed?")?;
+
+        if !output.status.success() {
+            return Err(failure::format_err!(
+                "`gcloud compute instances {}` failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            Ok(serde_json::Value::Null)
+        } else {
+            Ok(serde_json::from_str(&stdout)?)
+        }
+    }
+
+    /// Create instances and return them. They are not necessarily running yet; see
+    /// `wait_running`.
+    pub fn create(&self, cfg: &CreateConfig<'_>) -> Result<Vec<Instance>, failure::Error> {
+        let home = std::env::var("HOME").context("finding the home directory")?;
+        let key_file = dir!(home.as_str(), SSH_PUBLIC_KEY);
+        let key = std::fs::read_to_string(&key_file)
+            .with_context(|_| format!("reading the SSH public key {}", key_file))?;
+
+        let names: Vec<String> = (0..cfg.count)
+            .map(|i| format!("{}-{}", cfg.name, i))
+            .collect();
+        let labels = format!("{}={}", RUNNER_LABEL, cfg.name);
+        let metadata = format!("ssh-keys={}:{}", cfg.username, key.trim());
+        let boot_disk_size = format!("{}GB", cfg.boot_disk_gb);
+
+        let mut args = vec!["create"];
+        args.extend(names.iter().map(String::as_str));
+        args.extend(&[
+            "--machine-type",
+            cfg.machine_type,
+            "--image-family",
+            cfg.image_family,
+            "--image-project",
+            cfg.image_project,
+            "--boot-disk-size",
+            boot_disk_size.as_str(),
+            "--enable-nested-virtualization",
+            "--labels",
+            labels.as_str(),
+            "--metadata",
+            metadata.as_str(),
+        ]);
+        if cfg.preemptible {
+            args.push("--preemptible");
+        }
+        if let Some(zone) = &self.zone {
+            args.extend(&["--zone", zone.as_str()]);
+        }
+
+        let output = self.gcloud(&args)?;
+
+        Ok(output
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Instance::from_json)
+            .collect())
+    }
+
+    /// Wait until the given instances are running and have an external address, and return them.
+    pub fn wait_running(&self, instances: &[Instance]) -> Result<Vec<Instance>, failure::Error> {
+        let start = Instant::now();
+
+        loop {
+            let current: Vec<Instance> = self
+                .list(None)?
+                .into_iter()
+                .filter(|current| instances.iter().any(|instance| instance.id == current.id))
+                .collect();
+
+            let not_running: Vec<&str> = instances
+                .iter()
+                .filter(|instance| {
+                    !current.iter().any(|current| {
+                        current.id == instance.id
+                            && current.status == "RUNNING"
+                            && current.address.is_some()
+                    })
+                })
+                .map(|instance| instance.id.as_str())
+                .collect();
+
+            if not_running.is_empty() {
+                return Ok(current);
+            } else if start.elapsed() > RUNNING_TIMEOUT {
+                return Err(failure::format_err!(
+                    "Instances {} are not running after {:?}",
+                    not_running.join(" "),
+                    RUNNING_TIMEOUT
+                ));
+            }
+
+            std::thread::sleep(RUNNING_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the instances created by the runner (with the given name, if any).
+    pub fn list(&self, name: Option<&str>) -> Result<Vec<Instance>, failure::Error> {
+        let filter = match name {
+            Some(name) => format!("labels.{}={}", RUNNER_LABEL, name),
+            None => format!("labels.{}:*", RUNNER_LABEL),
+        };
+
+        // Instances are listed in all zones unless one is given.
+        let mut args = vec!["list", "--filter", filter.as_str()];
+        if let Some(zone) = &self.zone {
+            args.extend(&["--zones", zone.as_str()]);
+        }
+
+        let output = self.gcloud(&args)?;
+
+        Ok(output
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Instance::from_json)
+            .collect())
+    }
+
+    /// Delete the given instances.
+    pub fn delete(&self, instances: &[Instance]) -> Result<(), failure::Error> {
+        // Instances can only be deleted one zone at a time.
+        let mut zones: Vec<&str> = instances
+            .iter()
+            .map(|instance| instance.zone.as_str())
+            .collect();
+        zones.sort();
+        zones.dedup();
+
+        for zone in zones.into_iter() {
+            let mut args = vec!["delete", "--quiet", "--zone", zone];
+            args.extend(
+                instances
+                    .iter()
+                    .filter(|instance| instance.zone == zone)
+                    .map(|instance| instance.id.as_str()),
+            );
+
+            self.gcloud(&args)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates GCE instances as a `Provider`.
+pub struct GceCreate<'a> {
+    pub gce: &'a Gce,
+
+    /// The instances to create.
+    pub cfg: CreateConfig<'a>,
+}
+
+impl Provider for GceCreate<'_> {
+    type Allocation = Vec<Instance>;
+
+    fn allocate(&self) -> Result<Self::Allocation, failure::Error> {
+        let instances = self.gce.create(&self.cfg)?;
+        println!(
+            "Created {}. Waiting for them to run...",
+            instances
+                .iter()
+                .map(|instance| instance.id.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+
+        Ok(instances)
+    }
+
+    fn wait_ready(&self, instances: &mut Self::Allocation) -> Result<(), failure::Error> {
+        *instances = self.gce.wait_running(instances)?;
+
+        Ok(())
+    }
+
+    fn address(&self, instances: &Self::Allocation) -> Result<Vec<String>, failure::Error> {
+        instances
+            .iter()
+            .map(|instance| match &instance.address {
+                Some(address) => Ok(format!("{}:22", address)),
+                None => Err(failure::format_err!(
+                    "Instance {} has no external address",
+                    instance.id
+                )),
+            })
+            .collect()
+    }
+
+    fn teardown(&self, instances: &Self::Allocation) -> Result<(), failure::Error> {
+        self.gce.delete(instances)
+    }
+
+    fn username(&self) -> &str {
+        self.cfg.username
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let gce = Gce::new(sub_m.value_of("PROJECT"), sub_m.value_of("ZONE"));
+
+    match sub_m.subcommand() {
+        ("create", Some(sub_m)) => create(&gce, sub_m),
+
+        ("list", Some(sub_m)) => {
+            for instance in gce.list(sub_m.value_of("NAME"))?.iter() {
+                print_instance(instance);
+            }
+            Ok(())
+        }
+
+        ("delete", Some(sub_m)) => {
+            let instances: Vec<Instance> = if let Some(ids) = sub_m.values_of("INSTANCE") {
+                let ids: Vec<&str> = ids.collect();
+                gce.list(None)?
+                    .into_iter()
+                    .filter(|instance| ids.contains(&instance.id.as_str()))
+                    .collect()
+            } else {
+                gce.list(sub_m.value_of("NAME"))?
+            };
+
+            if instances.is_empty() {
+                println!("No instances to delete.");
+                return Ok(());
+            }
+
+            gce.delete(&instances)?;
+            for instance in instances.iter() {
+                println!("Deleted {}", instance.id);
+            }
+
+            Ok(())
+        }
+
+        _ => unreachable!(),
+    }
+}
+
+/// Create instances, wait until they accept SSH connections, and append them to the hosts file.
+fn create(gce: &Gce, sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let hosts_file = sub_m.value_of("HOSTS_FILE").unwrap_or(DEFAULT_HOSTS_FILE);
+
+    let cfg = CreateConfig {
+        name: sub_m.value_of("NAME").unwrap(),
+        machine_type: sub_m.value_of("MACHINE_TYPE").unwrap(),
+        count: sub_m
+            .value_of("COUNT")
+            .map(|value| value.parse::<usize>().unwrap())
+            .unwrap_or(1),
+        preemptible: sub_m.is_present("PREEMPTIBLE"),
+        image_family: sub_m
+            .value_of("IMAGE_FAMILY")
+            .unwrap_or(DEFAULT_IMAGE_FAMILY),
+        image_project: sub_m
+            .value_of("IMAGE_PROJECT")
+            .unwrap_or(DEFAULT_IMAGE_PROJECT),
+        boot_disk_gb: sub_m
+            .value_of("BOOT_DISK_GB")
+            .map(|value| value.parse::<usize>().unwrap())
+            .unwrap_or(DEFAULT_BOOT_DISK_GB),
+        username: sub_m.value_of("USERNAME").unwrap(),
+    };
+
+    let name = cfg.name;
+    let (instances, _) = super::acquire(&GceCreate { gce, cfg }, hosts_file)?;
+
+    for instance in instances.iter() {
+        print_instance(instance);
+    }
+    println!(
+        "Added the instances to {}. Set them up with `runner setup00000 --hosts_file {}`, and \
+         delete them with `runner gcp delete --name {}` when done.",
+        hosts_file, hosts_file, name
+    );
+
+    Ok(())
+}
+
+fn print_instance(instance: &Instance) {
+    println!(
+        "{} {} {} {} {}{} {}",
+        instance.id,
+        instance.name,
+        instance.machine_type,
+        instance.zone,
+        instance.status,
+        if instance.preemptible {
+            " (preemptible)"
+        } else {
+            ""
+        },
+        instance.address.as_deref().unwrap_or("-"),
+    );
+}
@@ -0,0 +1,160 @@
+//! Query the index of runs in a local results directory (see `common::index`).
+
+use std::path::Path;
+
+use clap::clap_app;
+
+use crate::common::index::{IndexRecord, INDEX_FILE};
+
+pub fn cli_options() -> clap::App<'static, 'static> {
+    clap_app! { results =>
+        (about: "Query the index of runs in a results directory.")
+        (@setting SubcommandRequiredElseHelp)
+        (@subcommand ls =>
+            (about: "List all runs in the index.")
+            (@arg RESULTS_DIR: +required +takes_value
+             "The local results directory containing the index.")
+        )
+        (@subcommand find =>
+            (about: "List the runs in the index matching all of the given conditions.")
+            (@arg RESULTS_DIR: +required +takes_value
+             "The local results directory containing the index.")
+            (@arg EXPERIMENT: --experiment +takes_value
+             "Only runs of the given experiment (e.g. exp00000).")
+            (@arg HOST: --host +takes_value
+             "Only runs on the given remote.")
+            (@arg GIT_HASH: --git_hash +takes_value
+             "Only runs whose local or remote workspace git hash starts with the given prefix.")
+            (@arg SETTINGS_HASH: --settings_hash +takes_value
+             "Only runs with the given settings hash.")
+            (@arg SETTING: --setting +takes_value ... number_of_values(1)
+             "Only runs whose setting KEY has value VALUE, given as KEY=VALUE (e.g. \
+              cores=4). The value is compared with the JSON value of the setting from the \
+              run's params file, without quotes for strings. Can be given multiple times.")
+            (@arg SUSPECT: --suspect
+             "Only runs marked suspect because of time-drift anomalies.")
+            (@arg FILES: --files
+             "Print the output files of the matching runs, one per line, rather than a summary.")
+        )
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    match sub_m.subcommand() {
+        ("ls", Some(sub_m)) => {
+            let records = read_index(sub_m)?;
+            print_summary(records.iter());
+            Ok(())
+        }
+
+        ("find", Some(sub_m)) => find(sub_m),
+
+        _ => unreachable!(),
+    }
+}
+
+fn read_index(sub_m: &clap::ArgMatches<'_>) -> Result<Vec<IndexRecord>, failure::Error> {
+    let results_dir = Path::new(sub_m.value_of("RESULTS_DIR").unwrap());
+    crate::common::index::read(&results_dir.join(INDEX_FILE))
+}
+
+fn find(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    let results_dir = Path::new(sub_m.value_of("RESULTS_DIR").unwrap());
+    let records = read_index(sub_m)?;
+
+    let experiment = sub_m.value_of("EXPERIMENT");
+    let host = sub_m.value_of("HOST");
+    let git_hash = sub_m.value_of("GIT_HASH");
+    let settings_hash = sub_m.value_of("SETTINGS_HASH");
+    let suspect = sub_m.is_present("SUSPECT");
+    let settings = sub_m
+        .values_of("SETTING")
+        .map(|settings| {
+            settings
+                .map(|setting| {
+                    let mut parts = setting.splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(key), Some(value)) => Ok((key, value)),
+                        _ => Err(failure::format_err!(
+                            "Invalid setting {:?}: expected KEY=VALUE",
+                            setting
+                        )),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut matches = vec![];
+    for record in records.iter() {
+        let matched = experiment.is_none_or(|e| record.experiment == e)
+            && host.is_none_or(|h| record.host.as_deref() == Some(h))
+            && git_hash.is_none_or(|g| {
+                record
+                    .local_git_hash
+                    .iter()
+                    .chain(record.remote_git_hash.iter())
+                    .any(|hash| hash.starts_with(g))
+            })
+            && settings_hash.is_none_or(|s| record.settings_hash == s)
+            && (!suspect || record.suspect);
+
+        if !matched {
+            continue;
+        }
+
+        if !settings.is_empty() {
+            // Settings are not in the index, so look them up in the params file of the run.
+            let params =
+                crate::common::output::read_params_file(&results_dir.join(&record.params_file));
+            let params = match params {
+                Ok(params) => params,
+                Err(e) => {
+                    println!("WARNING: skipping {}: {}", record.params_file, e);
+                    continue;
+                }
+            };
+
+            let all_match = settings.iter().all(|(key, value)| {
+                params.get(*key).is_some_and(|v| match v {
+                    serde_json::Value::String(s) => s == value,
+                    v => v.to_string() == *value,
+                })
+            });
+            if !all_match {
+                continue;
+            }
+        }
+
+        matches.push(record);
+    }
+
+    if sub_m.is_present("FILES") {
+        for record in matches.iter() {
+            for file in record.output_files.iter() {
+                println!("{}", file);
+            }
+        }
+    } else {
+        print_summary(matches.into_iter());
+    }
+
+    Ok(())
+}
+
+/// Print a one-line summary of each of the given records. Suspect runs are marked with a `!`.
+fn print_summary<'a>(records: impl Iterator<Item = &'a IndexRecord>) {
+    for record in records {
+        println!(
+            "{}  {:<8}  {:<40}  {:>8.0}s  {}  {}{}",
+            record.timestamp,
+            record.experiment,
+            record.host.as_deref().unwrap_or("-"),
+            record.duration_secs,
+            record.settings_hash,
+            record.params_file,
+            if record.suspect { "  !" } else { "" },
+        );
+    }
+}
@@ -12,6 +12,7 @@ use failure::ResultExt;
 use spurs::{cmd, Execute, SshShell};
 
 use crate::common::{
+    distro::Distro,
     exp_0sim::*,
     get_user_home_dir,
     paths::{setup00000::*, *},
@@ -20,6 +21,8 @@ use crate::common::{
 
 const VAGRANT_RPM_URL: &str =
     "https://releases.hashicorp.com/vagrant/2.1.5/vagrant_2.1.5_x86_64.rpm";
+const VAGRANT_DEB_URL: &str =
+    "https://releases.hashicorp.com/vagrant/2.1.5/vagrant_2.1.5_x86_64.deb";
 
 const QEMU_TARBALL: &str = "https://download.qemu.org/qemu-4.0.0.tar.xz";
 const QEMU_TARBALL_NAME: &str = "qemu-4.0.0.tar.xz";
@@ -27,14 +30,43 @@ const QEMU_TARBALL_NAME: &str = "qemu-4.0.0.tar.xz";
 const HADOOP_VERSION: &str = "3.1.3";
 const SPARK_VERSION: &str = "2.4.4";
 
+const YCSB_VERSION: &str = "0.17.0";
+
+const GAPBS_REPO: &str = "https://github.com/sbeamer/gapbs.git";
+
+const MONGODB_VERSION: &str = "4.2";
+
 pub fn cli_options() -> clap::App<'static, 'static> {
     clap_app! { setup00000 =>
-        (about: "Sets up the given _centos_ test machine for use with vagrant. Requires `sudo`.")
-        (@arg HOSTNAME: +required +takes_value
+        (about: "Sets up the given CentOS or Ubuntu test machine for use with vagrant. Requires `sudo`.")
+        (@arg HOSTNAME: +takes_value required_unless[HOSTS_FILE CLOUDLAB_PROFILE]
          "The domain name of the remote (e.g. c240g2-031321.wisc.cloudlab.us:22)")
-        (@arg USERNAME: +required +takes_value
+        (@arg USERNAME: +takes_value required_unless[HOSTS_FILE CLOUDLAB_PROFILE]
          "The username on the remote (e.g. markm)")
 
+        (@arg HOSTS_FILE: +takes_value --hosts_file conflicts_with[HOSTNAME]
+         "(Optional) Instead of a single remote, run the setup concurrently on each remote listed \
+          in the given file, one `HOSTNAME USERNAME` pair per line. The output for each remote \
+          goes to its own log file.")
+        (@arg LOG_DIR: +takes_value --log_dir
+         "(Optional) The directory for the per-remote log files with --hosts_file or \
+          --cloudlab_profile (defaults to setup00000-logs).")
+
+        (@arg CLOUDLAB_PROFILE: +takes_value --cloudlab_profile
+         conflicts_with[HOSTNAME HOSTS_FILE] requires[CLOUDLAB_PROJECT CLOUDLAB_USERNAME]
+         "(Optional) Instead of a given remote, instantiate the given CloudLab profile and set up \
+          each of its nodes, as with --hosts_file. Requires `curl` and a decrypted CloudLab \
+          certificate locally (see `runner cloudlab`).")
+        (@arg CLOUDLAB_PROJECT: +takes_value --cloudlab_project requires[CLOUDLAB_PROFILE]
+         "The CloudLab project to instantiate the profile in.")
+        (@arg CLOUDLAB_USERNAME: +takes_value --cloudlab_username requires[CLOUDLAB_PROFILE]
+         "The CloudLab username to log in to the nodes as.")
+        (@arg CLOUDLAB_EXPERIMENT: +takes_value --cloudlab_experiment requires[CLOUDLAB_PROFILE]
+         "(Optional) The name of the new CloudLab experiment (defaults to zerosim-MMDDhhmm).")
+        (@arg CLOUDLAB_CERT: +takes_value --cloudlab_cert requires[CLOUDLAB_PROFILE]
+         "(Optional) The path of the decrypted CloudLab certificate \
+          (defaults to ~/.ssl/emulab.pem).")
+
         (@arg PROXY: +takes_value --proxy
          "(Optional) set up the VM to use the given proxy. Leave off the protocol \
          (e.g. squid.cs.wisc.edu:3128)")
@@ -91,11 +123,19 @@ pub fn cli_options() -> clap::App<'static, 'static> {
 
         (@arg GUEST_KERNEL: --guest_kernel
          "(Optional) Build and install a guest kernel")
+        (@arg GUEST_KERNEL_PATCHES: --guest_kernel_patches +takes_value requires[GUEST_KERNEL]
+         "(Optional) A directory on the remote containing a series of patches (e.g. from \
+          `git format-patch`) to apply to the guest kernel source before building it. The \
+          patches are applied in the order of a quilt-style `series` file, if there is one, or \
+          else the *.patch and *.diff files in order of name.")
 
         (@arg GUEST_BMKS: --guest_bmks
          "(Optional) Build and install a guest benchmarks")
         (@arg HADOOP: --hadoop
          "(Optional) set up hadoop stack on VM.")
+        (@arg SPEC_2017: --spec_2017 +takes_value
+         "(Optional) The path of a SPEC CPU 2017 tarball on the remote (e.g. of the contents of \
+          the ISO). SPEC is installed in the guest and the rate benchmarks are built.")
     }
 }
 
@@ -147,14 +187,29 @@ where
 
     /// Compile and install Linux 5.1.4 on the guest.
     guest_kernel: bool,
+    /// Apply the patches in the given directory to the guest kernel before building.
+    guest_kernel_patches: Option<&'a str>,
 
     /// Compile and install guest bmks.
     guest_bmks: bool,
     /// Set up the Hadoop on the guest.
     setup_hadoop: bool,
+    /// Install SPEC CPU 2017 on the guest from the given tarball on the host.
+    spec_2017: Option<&'a str>,
 }
 
 pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    if let Some(hosts_file) = sub_m.value_of("HOSTS_FILE") {
+        let log_dir = sub_m.value_of("LOG_DIR").unwrap_or(DEFAULT_LOG_DIR);
+        return run_on_hosts(hosts_file, log_dir);
+    }
+
+    if let Some(profile) = sub_m.value_of("CLOUDLAB_PROFILE") {
+        let log_dir = sub_m.value_of("LOG_DIR").unwrap_or(DEFAULT_LOG_DIR);
+        let hosts_file = start_cloudlab_experiment(sub_m, profile)?;
+        return run_on_hosts(&hosts_file, log_dir);
+    }
+
     let login = Login {
         username: sub_m.value_of("USERNAME").unwrap(),
         hostname: sub_m.value_of("HOSTNAME").unwrap(),
@@ -186,11 +241,14 @@ pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
     let create_vm = sub_m.is_present("CREATE_VM");
 
     let guest_kernel = sub_m.is_present("GUEST_KERNEL");
+    let guest_kernel_patches = sub_m.value_of("GUEST_KERNEL_PATCHES");
 
     let setup_hadoop = sub_m.is_present("HADOOP");
 
     let guest_bmks = sub_m.is_present("GUEST_BMKS");
 
+    let spec_2017 = sub_m.value_of("SPEC_2017");
+
     let cfg = SetupConfig {
         login,
         aws,
@@ -209,8 +267,10 @@ pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
         destroy_existing_vm,
         create_vm,
         guest_kernel,
+        guest_kernel_patches,
         guest_bmks,
         setup_hadoop,
+        spec_2017,
     };
 
     validate_options(&cfg)?;
@@ -218,6 +278,206 @@ pub fn run(sub_m: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
     run_inner(cfg)
 }
 
+/// The default directory for per-remote log files with `--hosts_file`.
+const DEFAULT_LOG_DIR: &str = "setup00000-logs";
+
+/// How often to print the progress of each remote with `--hosts_file`.
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Instantiate a CloudLab profile as a new experiment and wait until its nodes accept SSH
+/// connections. Returns the path of a new hosts file listing the nodes.
+fn start_cloudlab_experiment(
+    sub_m: &clap::ArgMatches<'_>,
+    profile: &str,
+) -> Result<String, failure::Error> {
+    use crate::provider::{acquire, cloudlab};
+
+    let cloudlab = cloudlab::CloudLab::new(
+        sub_m.value_of("CLOUDLAB_PROJECT").unwrap(),
+        sub_m.value_of("CLOUDLAB_CERT"),
+    )?;
+
+    let name = sub_m
+        .value_of("CLOUDLAB_EXPERIMENT")
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| {
+            format!(
+                "zerosim-{}",
+                chrono::offset::Local::now().format("%m%d%H%M")
+            )
+        });
+    let hosts_file = format!("cloudlab-{}-hosts", name);
+
+    let (_, hosts) = acquire(
+        &cloudlab::ProfileInstance {
+            cloudlab: &cloudlab,
+            profile,
+            name: &name,
+            hours: cloudlab::DEFAULT_DURATION_HOURS,
+            cluster: None,
+            username: sub_m.value_of("CLOUDLAB_USERNAME").unwrap(),
+        },
+        &hosts_file,
+    )?;
+
+    println!(
+        "CloudLab experiment {} is ready with {} nodes (listed in {}). Extend or terminate it \
+         with `runner cloudlab`.",
+        name,
+        hosts.len(),
+        hosts_file
+    );
+
+    Ok(hosts_file)
+}
+
+/// Returns the arguments to pass to a runner that sets up a single remote: the arguments of this
+/// runner, minus the `--hosts_file`, `--log_dir`, and CloudLab options. Global options are kept,
+/// except for `--config`, which has already been expanded.
+fn single_host_args() -> (Vec<String>, Vec<String>) {
+    let args = crate::common::runner_invocation();
+    let subcommand = args
+        .iter()
+        .position(|arg| arg == "setup00000")
+        .expect("setup00000 not in arguments");
+
+    let strip = |args: &[String], opts: &[&str]| {
+        let mut stripped = vec![];
+        let mut skip = false;
+        for arg in args.iter() {
+            if skip {
+                skip = false;
+            } else if opts.contains(&arg.as_str()) {
+                skip = true;
+            } else if !opts.iter().any(|opt| arg.starts_with(&format!("{}=", opt))) {
+                stripped.push(arg.clone());
+            }
+        }
+        stripped
+    };
+
+    (
+        strip(&args[1..subcommand], &["--config"]),
+        strip(
+            &args[subcommand + 1..],
+            &[
+                "--hosts_file",
+                "--log_dir",
+                "--cloudlab_profile",
+                "--cloudlab_project",
+                "--cloudlab_username",
+                "--cloudlab_experiment",
+                "--cloudlab_cert",
+            ],
+        ),
+    )
+}
+
+/// Returns the last non-empty line of the given log file, if any, shortened for display.
+fn last_log_line(log: &std::path::Path) -> String {
+    const MAX_LEN: usize = 80;
+
+    let contents = std::fs::read_to_string(log).unwrap_or_default();
+    let line = contents
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+
+    line.chars().take(MAX_LEN).collect()
+}
+
+/// Run the setup concurrently on each remote in `hosts_file`. Each remote is set up by a separate
+/// runner process, so that its output can go to its own log file in `log_dir`. The progress of
+/// each remote is printed periodically until all of them finish.
+fn run_on_hosts(hosts_file: &str, log_dir: &str) -> Result<(), failure::Error> {
+    use console::style;
+
+    let hosts = crate::common::parse_hosts_file(&std::fs::read_to_string(hosts_file)?)?;
+    let (global_args, setup_args) = single_host_args();
+    let exe = std::env::current_exe()?;
+
+    std::fs::create_dir_all(log_dir)?;
+
+    let mut children = vec![];
+    for (hostname, username) in hosts.iter() {
+        let log = std::path::Path::new(log_dir).join(format!("{}.log", hostname.replace(':', "_")));
+        let out = std::fs::File::create(&log)?;
+        let err = out.try_clone()?;
+
+        let child = Command::new(&exe)
+            .args(&global_args)
+            .arg("setup00000")
+            .arg(hostname)
+            .arg(username)
+            .args(&setup_args)
+            .stdout(out)
+            .stderr(err)
+            .spawn()?;
+
+        println!("Started setup of {} (log: {})", hostname, log.display());
+
+        children.push((hostname, log, child, None));
+    }
+
+    loop {
+        let mut running = 0;
+
+        println!("== setup00000 progress ==");
+        for (hostname, log, child, status) in children.iter_mut() {
+            if status.is_none() {
+                *status = child.try_wait()?;
+            }
+
+            match status {
+                None => {
+                    running += 1;
+                    println!(
+                        "{}: {} {}",
+                        hostname,
+                        style("running").yellow(),
+                        last_log_line(log)
+                    );
+                }
+                Some(status) if status.success() => {
+                    println!("{}: {}", hostname, style("done").green());
+                }
+                Some(status) => {
+                    println!(
+                        "{}: {} ({}; see {})",
+                        hostname,
+                        style("failed").red().bold(),
+                        status,
+                        log.display()
+                    );
+                }
+            }
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        std::thread::sleep(PROGRESS_INTERVAL);
+    }
+
+    let failed: Vec<_> = children
+        .iter()
+        .filter(|(_, _, _, status)| !status.map(|s| s.success()).unwrap_or(false))
+        .map(|(hostname, _, _, _)| hostname.as_str())
+        .collect();
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failure::format_err!(
+            "Setup failed on: {}",
+            failed.join(", ")
+        ))
+    }
+}
+
 /// Check that the set of flags passed satisfies dependencies and is non-contradictory.
 fn validate_options<A>(cfg: &SetupConfig<'_, A>) -> Result<(), failure::Error>
 where
@@ -269,7 +529,7 @@ where
     let (vrshell, vushell) = if cfg.create_vm {
         // Create the VM and install dependencies for the benchmarks/simulator.
         init_vm(&mut ushell, &cfg)?
-    } else if cfg.guest_kernel || cfg.setup_hadoop || cfg.guest_bmks {
+    } else if cfg.guest_kernel || cfg.setup_hadoop || cfg.guest_bmks || cfg.spec_2017.is_some() {
         // Start vagrant (that already exists)
         let vrshell = start_vagrant(
             &ushell,
@@ -298,17 +558,32 @@ where
     // Disable TSC offsetting for performance
     ZeroSim::tsc_offsetting(&ushell, false)?;
 
-    install_guest_dependencies(&vrshell, &vushell)?;
+    // The guest kernel is built on the host, which takes a long time, so do it in the background
+    // while we set up the rest of the guest.
+    let guest_kernel_build = if cfg.guest_kernel {
+        Some(spawn_guest_kernel_build(&vushell, &cfg)?)
+    } else {
+        None
+    };
 
-    if cfg.guest_kernel {
-        install_guest_kernel(&ushell, &vrshell, &vushell)?;
-    }
+    install_guest_dependencies(&vrshell, &vushell)?;
 
     // Install benchmarks.
     if cfg.guest_bmks || cfg.setup_hadoop {
         install_guest_benchmarks(&ushell, &vushell, &vrshell, &cfg)?;
     }
 
+    if let Some(tarball) = cfg.spec_2017 {
+        install_spec_2017(&ushell, &vushell, &vrshell, tarball)?;
+    }
+
+    if let Some(guest_kernel_build) = guest_kernel_build {
+        let kernel_rpm = guest_kernel_build
+            .join()
+            .expect("guest kernel build thread panicked")?;
+        install_guest_kernel(&vrshell, &kernel_rpm)?;
+    }
+
     // Make sure the TSC is marked as a reliable clock source in the guest.
     set_kernel_boot_param(&vrshell, "tsc", Some("reliable"))?;
 
@@ -317,6 +592,9 @@ where
     ushell.run(cmd!("sync"))?;
     let _ = vrshell.run(cmd!("sudo poweroff")); // This will give a TCP error for obvious reasons
 
+    // Snapshot the freshly set up guest so that experiments can start from it (`--fresh_guest`).
+    snapshot_guest_disk(&ushell)?;
+
     Ok(())
 }
 
@@ -347,26 +625,28 @@ fn install_host_dependencies<A>(
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
+    let distro = Distro::detect(ushell)?;
+
     // Install a bunch of stuff
-    ushell.run(cmd!("sudo yum group install -y 'Development Tools'"))?;
+    ushell.run(distro.install_dev_tools())?;
 
     if cfg.aws {
         // This installs the qemu-kvm package, which we don't want on machines where we will run VMs.
-        ushell.run(spurs_util::centos::yum_install(&["libguestfs-tools-c"]))?;
+        ushell.run(distro.install(&["libguestfs-tools-c"]))?;
     } else {
         with_shell! { ushell =>
-            spurs_util::centos::yum_install(&[
+            distro.install(&[
                 "libunwind-devel",
                 "centos-release-scl",
                 "libfdt-devel",
             ]),
 
-            spurs_util::centos::yum_install(&["devtoolset-7"]),
+            distro.install(&["devtoolset-7"]),
         }
     }
 
     with_shell! { ushell =>
-        spurs_util::centos::yum_install(&[
+        distro.install(&[
             "vim",
             "git",
             "libxslt-devel",
@@ -403,23 +683,24 @@ where
             "java-1.8.0-openjdk",
             "maven",
             "redis",
+            "fio",
             "perf", // for debugging
             "wget",
             "libevent",
             "libevent-devel",
             "firewalld",
+            "nfs-utils",
         ]),
 
         // Add user to libvirt group after installing
         spurs_util::add_to_group("libvirt"),
     }
 
-    let installed = ushell
-        .run(cmd!("yum list installed vagrant | grep -q vagrant"))
-        .is_ok();
-
-    if !installed {
-        ushell.run(cmd!("sudo yum -y install {}", VAGRANT_RPM_URL))?;
+    if !distro.is_installed(ushell, "vagrant") {
+        ushell.run(distro.install_url(match distro {
+            Distro::CentOS => VAGRANT_RPM_URL,
+            Distro::Debian => VAGRANT_DEB_URL,
+        }))?;
     }
 
     let installed = ushell
@@ -457,7 +738,8 @@ where
     }
 
     ushell.run(cmd!(
-        "sudo chown qemu:kvm /usr/local/bin/qemu-system-x86_64"
+        "sudo chown {}:kvm /usr/local/bin/qemu-system-x86_64",
+        distro.qemu_user()
     ))?;
 
     // Make sure libvirtd can run the qemu binary
@@ -586,6 +868,7 @@ where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
     let user_home = &get_user_home_dir(&ushell)?;
+    let distro = Distro::detect(ushell)?;
 
     // clone the research workspace and build/install the 0sim kernel.
     if let Some(git_branch) = cfg.git_branch {
@@ -632,35 +915,56 @@ where
                 extra_options: &config_set,
             },
             Some(&crate::common::gen_local_version(git_branch, &git_hash)),
-            KernelPkgType::Rpm,
+            distro.kernel_pkg_type(),
         )?;
 
-        // Get name of RPM by looking for most recent file.
-        let kernel_rpm = ushell
-            .run(
-                cmd!(
-                    "basename `ls -Art {}/rpmbuild/RPMS/x86_64/ | grep -v headers | tail -n 1`",
-                    user_home
-                )
-                .use_bash(),
-            )?
-            .stdout;
-        let kernel_rpm = kernel_rpm.trim();
+        match distro {
+            Distro::CentOS => {
+                // Get name of RPM by looking for most recent file.
+                let kernel_rpm = ushell
+                    .run(
+                        cmd!(
+                            "basename `ls -Art {}/rpmbuild/RPMS/x86_64/ | grep -v headers | tail -n 1`",
+                            user_home
+                        )
+                        .use_bash(),
+                    )?
+                    .stdout;
+                let kernel_rpm = kernel_rpm.trim();
+
+                ushell.run(
+                    cmd!(
+                        "sudo rpm -ivh --force {}/rpmbuild/RPMS/x86_64/{}",
+                        user_home,
+                        kernel_rpm
+                    )
+                    .use_bash(),
+                )?;
+            }
 
-        ushell.run(
-            cmd!(
-                "sudo rpm -ivh --force {}/rpmbuild/RPMS/x86_64/{}",
-                user_home,
-                kernel_rpm
-            )
-            .use_bash(),
-        )?;
+            Distro::Debian => {
+                // `bindeb-pkg` puts the packages in the parent directory of the kernel source. Get
+                // the name of the image package by looking for the most recent one.
+                let kernel_deb = ushell
+                    .run(
+                        cmd!(
+                            "ls -Art {}/../linux-image-*.deb | grep -v dbg | tail -n 1",
+                            kernel_path
+                        )
+                        .use_bash(),
+                    )?
+                    .stdout;
+                let kernel_deb = kernel_deb.trim();
+
+                ushell.run(cmd!("sudo dpkg -i {}", kernel_deb))?;
+            }
+        }
 
         // Build cpupower
         ushell.run(cmd!("make").cwd(&format!("{}/tools/power/cpupower/", kernel_path)))?;
 
         // update grub to choose this entry (new kernel) by default
-        ushell.run(cmd!("sudo grub2-set-default 0"))?;
+        ushell.run(distro.set_default_kernel_to_newest())?;
     }
 
     Ok(())
@@ -790,6 +1094,17 @@ where
         cmd!("make"),
     }
 
+    // GAP benchmark suite (graph analytics)
+    let gapbs_path = dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_GAPBS_PATH);
+    if ushell.run(cmd!("test -d {}", gapbs_path)).is_err() {
+        ushell.run(cmd!("git clone {} {}", GAPBS_REPO, gapbs_path))?;
+    }
+    if cfg.aws {
+        ushell.run(cmd!("make").cwd(&gapbs_path))?;
+    } else {
+        ushell.run(cmd!("(source /opt/rh/devtoolset-7/enable ; make )").cwd(&gapbs_path))?;
+    }
+
     // Eager paging scripts/programs
     ushell.run(cmd!("make").cwd(&dir!(
         RESEARCH_WORKSPACE_PATH,
@@ -814,7 +1129,11 @@ where
     ushell.run(cmd!("mkdir -p images/"))?;
     ushell.run(cmd!("chmod +x ."))?;
     ushell.run(cmd!("chmod +x images/"))?;
-    ushell.run(cmd!("sudo chown {}:qemu images/", cfg.login.username))?;
+    ushell.run(cmd!(
+        "sudo chown {}:{} images/",
+        cfg.login.username,
+        Distro::detect(ushell)?.qemu_group()
+    ))?;
 
     crate::common::service(&ushell, "libvirtd", ServiceAction::Start)?;
 
@@ -840,19 +1159,8 @@ where
     // Disable TSC offsetting so that setup runs faster
     ZeroSim::tsc_offsetting(&ushell, false)?;
 
-    // Disable firewalld if it is running because it causes VM issues. When we do that, we need to
-    // reastart libvirtd.
-    let firewall_up = ushell.run(cmd!("sudo firewall-cmd --state")).is_ok(); // returns 252 if not running
-    if firewall_up {
-        ushell.run(cmd!("sudo firewall-cmd --permanent --add-service=nfs"))?;
-        ushell.run(cmd!("sudo firewall-cmd --permanent --add-service=rpc-bind"))?;
-        ushell.run(cmd!("sudo firewall-cmd --permanent --add-service=mountd"))?;
-        ushell.run(cmd!("sudo firewall-cmd --reload"))?;
-        crate::common::service(&ushell, "firewalld", ServiceAction::Disable)?;
-    }
-
-    // Make sure libvirtd is running.
-    crate::common::service(&ushell, "libvirtd", ServiceAction::Restart)?;
+    // Disable firewalld and make sure libvirtd is running.
+    crate::common::network::prepare_host(ushell)?;
 
     Ok(())
 }
@@ -882,10 +1190,8 @@ where
     // Create the VM and add our ssh key to it.
     let vagrant_path = &dir!(RESEARCH_WORKSPACE_PATH, VAGRANT_SUBDIRECTORY);
 
-    ushell.run(cmd!("cp Vagrantfile.bk Vagrantfile").cwd(vagrant_path))?;
-    crate::common::gen_new_vagrantdomain(&ushell)?;
-
-    gen_vagrantfile(&ushell, 20, 1)?;
+    let domain = crate::common::gen_new_vagrantdomain(ushell)?;
+    write_vagrantfile(ushell, &domain, &VmConfig::new(20, 1))?;
 
     // Make sure to turn off skip_halt and lapic_adjust
     ZeroSim::skip_halt(&ushell, false)?;
@@ -1038,6 +1344,7 @@ fn install_guest_dependencies(
         "libevent-devel",
         "numactl-devel",
         "fuse-devel",
+        "fio", // for the IO antagonist
     ]))?;
 
     install_rust(vrshell)?;
@@ -1046,34 +1353,83 @@ fn install_guest_dependencies(
     Ok(())
 }
 
-/// Install a recent kernel on the guest.
+/// Start building a recent guest kernel on the host in the background. If `patch_dir` is given,
+/// the patches in it are applied to the kernel source before building. The build uses its own
+/// SSH connection to the host, so the caller is free to keep setting up the guest in the
+/// meantime. The returned handle yields the name of the RPM, which is placed in the shared
+/// directory, ready to be installed with `install_guest_kernel`.
 ///
-/// We will compile on the host and copy the config and the RPM through the shared directory.
-fn install_guest_kernel(
-    ushell: &SshShell,
-    vrshell: &SshShell,
+/// We compile on the host and copy the config and the RPM through the shared directory.
+fn spawn_guest_kernel_build<A>(
     vushell: &SshShell,
-) -> Result<(), failure::Error> {
-    let user_home = &get_user_home_dir(&ushell)?;
-
+    cfg: &SetupConfig<'_, A>,
+) -> Result<std::thread::JoinHandle<Result<String, failure::Error>>, failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
     let guest_config = vushell
         .run(cmd!("ls -1 /boot/config-* | head -n1").use_bash())?
         .stdout;
     let guest_config = guest_config.trim();
     vushell.run(cmd!("cp {} {}", guest_config, VAGRANT_SHARED_DIR))?;
 
-    let guest_config_base_name = std::path::Path::new(guest_config).file_name().unwrap();
+    let guest_config_base_name = std::path::Path::new(guest_config)
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let username = cfg.login.username.to_owned();
+    let hostname = cfg.login.hostname.to_owned();
+    let patch_dir = cfg.guest_kernel_patches.map(String::from);
+
+    Ok(std::thread::spawn(move || {
+        let ushell = SshShell::with_default_key(&username, &hostname)?;
+        build_guest_kernel(
+            &ushell,
+            KERNEL_RECENT_TARBALL,
+            &guest_config_base_name,
+            patch_dir,
+        )
+    }))
+}
+
+/// Build a kernel for the guest on the host from the source tarball at the URL `tarball` (e.g.
+/// `KERNEL_RECENT_TARBALL`) using the guest config with the given name in the shared directory.
+/// Returns the name of the RPM, which is copied to the shared directory.
+pub fn build_guest_kernel(
+    ushell: &SshShell,
+    tarball: &str,
+    guest_config_base_name: &str,
+    patch_dir: Option<String>,
+) -> Result<String, failure::Error> {
+    let user_home = &get_user_home_dir(&ushell)?;
+
+    let tarball_name = tarball
+        .rsplit('/')
+        .next()
+        .expect("rsplit returns at least one item");
+
+    ushell.run(cmd!("wget -N {}", tarball))?;
+    let source = if let Some(patch_dir) = patch_dir {
+        KernelSrc::Patched {
+            base_tarball: tarball_name.into(),
+            patch_dir,
+        }
+    } else {
+        KernelSrc::Tar {
+            tarball_path: tarball_name.into(),
+        }
+    };
 
-    ushell.run(cmd!("wget {}", KERNEL_RECENT_TARBALL))?;
     crate::common::build_kernel(
         &ushell,
-        KernelSrc::Tar {
-            tarball_path: KERNEL_RECENT_TARBALL_NAME.into(),
-        },
+        source,
         KernelConfig {
             base_config: KernelBaseConfigSource::Path(dir!(
                 HOSTNAME_SHARED_DIR,
-                guest_config_base_name.to_str().unwrap()
+                guest_config_base_name
             )),
             extra_options: &[
                 // disable spectre/meltdown mitigations
@@ -1087,12 +1443,18 @@ fn install_guest_kernel(
         KernelPkgType::Rpm,
     )?;
 
-    // Get name of RPM by looking for most recent file.
+    // Get name of RPM by looking for most recent file of the right version. Other kernels (e.g.
+    // the host kernel) may be built into the same directory.
+    let kernel_version = tarball_name
+        .trim_start_matches("linux-")
+        .trim_end_matches(".tar.xz");
     let kernel_rpm = ushell
         .run(
             cmd!(
-                "basename `ls -Art {}/rpmbuild/RPMS/x86_64/ | grep -v headers | tail -n 1`",
-                user_home
+                "basename `ls -Art {}/rpmbuild/RPMS/x86_64/ | grep -v headers | \
+                 grep 'kernel-{}-' | tail -n 1`",
+                user_home,
+                kernel_version,
             )
             .use_bash(),
         )?
@@ -1109,6 +1471,11 @@ fn install_guest_kernel(
         .use_bash(),
     )?;
 
+    Ok(kernel_rpm.into())
+}
+
+/// Install the given kernel RPM from the shared directory on the guest and make it the default.
+pub fn install_guest_kernel(vrshell: &SshShell, kernel_rpm: &str) -> Result<(), failure::Error> {
     vrshell.run(cmd!(
         "rpm -ivh --force {}",
         dir!(VAGRANT_SHARED_DIR, kernel_rpm)
@@ -1134,6 +1501,12 @@ where
         vm_setup_hadoop(ushell, vushell, vrshell, HADOOP_VERSION, SPARK_VERSION)?;
     }
 
+    // YCSB. It is downloaded on the host, since the workspace is shared with the guest.
+    if cfg.guest_bmks {
+        download_ycsb(ushell, YCSB_VERSION)?;
+        install_mongodb(vrshell, MONGODB_VERSION)?;
+    }
+
     // Create a mountpoint for nullfs
     vushell.run(cmd!("sudo mkdir -p /mnt/nullfs"))?;
     vushell.run(cmd!("sudo chmod 777 /mnt/nullfs"))?;
@@ -1141,6 +1514,93 @@ where
     Ok(())
 }
 
+/// Install SPEC CPU 2017 in the guest from the given tarball on the host and build the rate
+/// benchmarks. SPEC is not freely available, so the user has to provide the tarball.
+///
+/// The benchmarks are built with the `Example-gcc-linux-x86` config that comes with SPEC, which
+/// uses the devtoolset-7 compiler, since the CentOS 7 one is too old.
+fn install_spec_2017(
+    ushell: &SshShell,
+    vushell: &SshShell,
+    vrshell: &SshShell,
+    tarball: &str,
+) -> Result<(), failure::Error> {
+    const INSTALLER_DIR: &str = "/tmp/spec2017-installer";
+
+    vrshell.run(spurs_util::centos::yum_install(&["centos-release-scl"]))?;
+    vrshell.run(spurs_util::centos::yum_install(&["devtoolset-7"]))?;
+
+    // Copy the tarball to the guest through the shared directory and install SPEC.
+    ushell.run(cmd!("cp {} {}/spec2017.tar", tarball, HOSTNAME_SHARED_DIR))?;
+
+    with_shell! { vushell =>
+        cmd!("rm -rf {0} {1} && mkdir -p {0}", INSTALLER_DIR, SPEC_2017_GUEST_PATH),
+        cmd!("tar xf {}/spec2017.tar -C {}", VAGRANT_SHARED_DIR, INSTALLER_DIR),
+        cmd!("./install.sh -f -d {}", SPEC_2017_GUEST_PATH).cwd(INSTALLER_DIR),
+        cmd!("rm -rf {}", INSTALLER_DIR),
+    }
+
+    ushell.run(cmd!("rm {}/spec2017.tar", HOSTNAME_SHARED_DIR))?;
+
+    // Build the benchmarks so that the build is not part of any measurements.
+    with_shell! { vushell in SPEC_2017_GUEST_PATH =>
+        cmd!(
+            "cp config/Example-gcc-linux-x86.cfg config/{}.cfg",
+            SPEC_2017_CONFIG
+        ),
+        cmd!(
+            "source shrc && runcpu --config={} --action=build intrate fprate",
+            SPEC_2017_CONFIG
+        )
+        .use_bash(),
+    }
+
+    Ok(())
+}
+
+/// Install the given version of MongoDB in the guest from the official yum repository. The
+/// service is not enabled; workloads start `mongod` themselves.
+fn install_mongodb(vrshell: &SshShell, version: &str) -> Result<(), failure::Error> {
+    let repo = format!(
+        "[mongodb-org-{0}]\n\
+         name=MongoDB Repository\n\
+         baseurl=https://repo.mongodb.org/yum/redhat/$releasever/mongodb-org/{0}/x86_64/\n\
+         gpgcheck=1\n\
+         enabled=1\n\
+         gpgkey=https://www.mongodb.org/static/pgp/server-{0}.asc\n",
+        version
+    );
+
+    vrshell.run(cmd!(
+        "echo {} > /etc/yum.repos.d/mongodb-org-{}.repo",
+        spurs_util::escape_for_bash(&repo),
+        version
+    ))?;
+    vrshell.run(spurs_util::centos::yum_install(&["mongodb-org"]))?;
+    vrshell.run(cmd!("systemctl disable mongod"))?;
+
+    Ok(())
+}
+
+/// Download and untar the given version of YCSB as `ZEROSIM_YCSB_PATH` in the research
+/// workspace, deleting anything that was previously there.
+fn download_ycsb(ushell: &SshShell, version: &str) -> Result<(), failure::Error> {
+    let ycsb_path = dir!(RESEARCH_WORKSPACE_PATH, ZEROSIM_YCSB_PATH);
+
+    with_shell! { ushell =>
+        cmd!(
+            "wget -O /tmp/ycsb.tgz \
+             https://github.com/brianfrankcooper/YCSB/releases/download/{0}/ycsb-{0}.tar.gz",
+            version
+        ),
+        cmd!("tar xvzf /tmp/ycsb.tgz"),
+        cmd!("rm -rf {}", ycsb_path),
+        cmd!("mv ycsb-{} {}", version, ycsb_path),
+    }
+
+    Ok(())
+}
+
 /// Set up hadoop and hibench in the guest.
 fn vm_setup_hadoop(
     ushell: &SshShell,
@@ -1,10 +1,498 @@
 //! Common workloads.
 
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
 use bitflags::bitflags;
 
 use serde::{Deserialize, Serialize};
 
 use spurs::{cmd, Execute, SshError, SshShell, SshSpawnHandle};
+use spurs_util::escape_for_bash;
+
+use crate::common::{background::LoopMarker, cgroups::MemoryCgroup, size::Size};
+
+/// A single workload command line run on a remote, as recorded in the results of an experiment.
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkloadInvocation {
+    /// The exact command line passed to the shell, after all formatting.
+    pub command: String,
+    /// The directory the command was run in, if any.
+    pub cwd: Option<String>,
+    /// The exit status of the command, or `None` if the command was spawned in the background or
+    /// could not be run at all.
+    pub exit_code: Option<i32>,
+}
+
+thread_local! {
+    /// All workload invocations so far.
+    static INVOCATIONS: RefCell<Vec<WorkloadInvocation>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns all workload invocations so far, in the order they were run.
+pub fn recorded_invocations() -> Vec<WorkloadInvocation> {
+    INVOCATIONS.with(|invocations| invocations.borrow().clone())
+}
+
+fn record_invocation(command: String, cwd: Option<&str>, exit_code: Option<i32>) {
+    INVOCATIONS.with(|invocations| {
+        invocations.borrow_mut().push(WorkloadInvocation {
+            command,
+            cwd: cwd.map(Into::into),
+            exit_code,
+        })
+    });
+}
+
+/// Run the workload command line `command` (in `cwd`, if any) on the remote and record it along
+/// with its exit status. If `allow_error` is true, a non-zero exit status is recorded but is not
+/// treated as an error. `pipefail` is set, so a failure anywhere in a pipeline (e.g. `... | tee`)
+/// is reported rather than only the exit status of the last command.
+fn run_recorded(
+    shell: &SshShell,
+    cwd: Option<&str>,
+    command: String,
+    allow_error: bool,
+) -> Result<(), SshError> {
+    let cmd = cmd!("set -o pipefail; {}", command).use_bash();
+    let cmd = if let Some(cwd) = cwd {
+        cmd.cwd(cwd)
+    } else {
+        cmd
+    };
+
+    let res = shell.run(cmd);
+
+    let exit_code = match &res {
+        Ok(_) => Some(0),
+        Err(SshError::NonZeroExit { exit, .. }) => Some(*exit),
+        Err(_) => None,
+    };
+    record_invocation(command, cwd, exit_code);
+
+    match res {
+        Err(SshError::NonZeroExit { .. }) if allow_error => Ok(()),
+        res => res.map(|_| ()),
+    }
+}
+
+/// Spawn the workload command line `command` (in `cwd`, if any) on the remote and record it. The
+/// exit status is not known, since the command runs in the background.
+fn spawn_recorded(
+    shell: &SshShell,
+    cwd: Option<&str>,
+    command: String,
+) -> Result<(SshShell, SshSpawnHandle), SshError> {
+    let cmd = cmd!("{}", command).use_bash();
+    let cmd = if let Some(cwd) = cwd {
+        cmd.cwd(cwd)
+    } else {
+        cmd
+    };
+
+    let handle = shell.spawn(cmd)?;
+    record_invocation(command, cwd, None);
+
+    Ok(handle)
+}
+
+/// Where to stage a dataset before the measured phase of an experiment.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum StagingTarget {
+    /// Read every file under the dataset path so that it is in the page cache.
+    PageCache,
+    /// Send the dataset to a running `memcached` server on `localhost:11211`. The dataset must be a
+    /// file of memcached text-protocol commands, which should use `noreply`.
+    Memcached,
+    /// Send the dataset to a running `redis` server on `/tmp/redis.sock` via `redis-cli --pipe`.
+    /// The dataset must be a file of redis commands in the mass-insertion format.
+    Redis,
+}
+
+/// A dataset staged before the measured phase, as recorded in the results of an experiment.
+#[derive(Clone, Debug, Serialize)]
+pub struct Staging {
+    /// The path of the dataset on the remote.
+    pub dataset: String,
+    /// Where the dataset was staged.
+    pub target: StagingTarget,
+    /// How long staging took, in milliseconds.
+    pub duration_ms: u128,
+}
+
+thread_local! {
+    /// All datasets staged so far.
+    static STAGINGS: RefCell<Vec<Staging>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns all datasets staged so far, in the order they were staged.
+pub fn recorded_stagings() -> Vec<Staging> {
+    STAGINGS.with(|stagings| stagings.borrow().clone())
+}
+
+/// Stage the dataset at `dataset` on the remote into `target` and record how long it took. This
+/// lets an experiment exclude cold-start effects from its measured phase deliberately, while still
+/// reporting the cost of population separately. For `Memcached` and `Redis`, the server must
+/// already be running.
+pub fn run_stage_dataset(
+    shell: &SshShell,
+    dataset: &str,
+    target: StagingTarget,
+) -> Result<(), failure::Error> {
+    let command = match target {
+        StagingTarget::PageCache => {
+            format!("find {} -type f -exec cat {{}} + > /dev/null", dataset)
+        }
+        StagingTarget::Memcached => format!("cat {} > /dev/tcp/localhost/11211", dataset),
+        StagingTarget::Redis => format!(
+            "redis-cli -s /tmp/redis.sock --pipe < {} > /dev/null",
+            dataset
+        ),
+    };
+
+    let start = std::time::Instant::now();
+    run_recorded(shell, None, command, false)?;
+    let duration_ms = start.elapsed().as_millis();
+
+    STAGINGS.with(|stagings| {
+        stagings.borrow_mut().push(Staging {
+            dataset: dataset.into(),
+            target,
+            duration_ms,
+        })
+    });
+
+    Ok(())
+}
+
+/// The CPU and memory affinity of a workload process as observed while it ran, as recorded in the
+/// results of an experiment.
+#[derive(Clone, Debug, Serialize)]
+pub struct PinningCheck {
+    /// The name of the process.
+    pub process: String,
+    /// The core the process was supposed to be pinned to.
+    pub requested_core: usize,
+    /// The NUMA node of the requested core, or `None` if it could not be determined. The process
+    /// must be allowed to allocate memory on this node.
+    pub requested_node: Option<usize>,
+    /// The `Cpus_allowed_list` of the process, or `None` if the process was not found.
+    pub cpus_allowed: Option<String>,
+    /// The `Mems_allowed_list` of the process, or `None` if the process was not found.
+    pub mems_allowed: Option<String>,
+}
+
+/// Returns true if the kernel-formatted list `list` (e.g. `0-3,8`, as in `Mems_allowed_list`)
+/// contains `n`.
+fn list_contains(list: &str, n: usize) -> bool {
+    list.split(',').any(|range| {
+        let mut bounds = range.trim().splitn(2, '-').map(str::parse::<usize>);
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(only)), None) => only == n,
+            (Some(Ok(lo)), Some(Ok(hi))) => lo <= n && n <= hi,
+            _ => false,
+        }
+    })
+}
+
+thread_local! {
+    /// All pinning checks so far.
+    static PINNING_CHECKS: RefCell<Vec<PinningCheck>> = const { RefCell::new(Vec::new()) };
+
+    /// If true, a workload whose affinity does not match the requested pinning is an error.
+    static ENFORCE_PINNING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns all pinning checks so far, in the order they were made.
+pub fn recorded_pinning_checks() -> Vec<PinningCheck> {
+    PINNING_CHECKS.with(|checks| checks.borrow().clone())
+}
+
+/// If `enforce` is true, workloads will return an error if their observed CPU affinity does not
+/// match the requested pinning. Otherwise, mismatches are only recorded and reported.
+pub fn set_enforce_pinning(enforce: bool) {
+    ENFORCE_PINNING.with(|e| e.set(enforce));
+}
+
+/// A check of the CPU affinity of a workload process that is in progress. `taskset` can be
+/// silently overridden (e.g. by cgroups or NUMA policy), so we read the affinity of the process
+/// from `/proc/<pid>/status` while it runs.
+///
+/// Start the check just before (or just after) starting the workload, and `finish` it afterwards.
+struct PinningVerifier {
+    process: String,
+    requested_core: usize,
+    handle: (SshShell, SshSpawnHandle),
+}
+
+impl PinningVerifier {
+    /// Start polling for a process called `process` (in a separate shell) to check that it is
+    /// pinned to `core`.
+    fn start(shell: &SshShell, process: &str, core: usize) -> Result<Self, SshError> {
+        // `pgrep -x` matches against the command name, which the kernel truncates to 15 chars.
+        let comm: String = process.chars().take(15).collect();
+
+        // Poll for up to 60s. The affinity is set by `taskset` before it execs the workload, so
+        // once the process has the right name, its affinity is final.
+        let handle = shell.spawn(
+            cmd!(
+                "for i in $(seq 600) ; do \
+                    pid=$(pgrep -n -x {}) && \
+                    grep -E '^(Cpus|Mems)_allowed_list' /proc/$pid/status && \
+                    exit 0 ; \
+                    sleep 0.1 ; \
+                 done",
+                comm
+            )
+            .use_bash()
+            .allow_error(),
+        )?;
+
+        Ok(PinningVerifier {
+            process: process.into(),
+            requested_core: core,
+            handle,
+        })
+    }
+
+    /// Wait for the check to complete and record the result. Returns an error if the affinity
+    /// does not match and pinning is being enforced.
+    fn finish(self) -> Result<(), failure::Error> {
+        let (shell, handle) = self.handle;
+        let out = handle.join()?;
+
+        // The NUMA node of a core is the `nodeN` entry in its sysfs directory.
+        let requested_node = shell
+            .run(
+                cmd!(
+                    "ls -d /sys/devices/system/cpu/cpu{}/node*",
+                    self.requested_core
+                )
+                .allow_error(),
+            )?
+            .stdout
+            .trim()
+            .rsplit("node")
+            .next()
+            .and_then(|node| node.parse().ok());
+
+        let field = |name: &str| {
+            out.stdout
+                .lines()
+                .find(|line| line.starts_with(name))
+                .and_then(|line| line.split(':').nth(1))
+                .map(|value| value.trim().to_owned())
+        };
+
+        let check = PinningCheck {
+            process: self.process,
+            requested_core: self.requested_core,
+            requested_node,
+            cpus_allowed: field("Cpus_allowed_list"),
+            mems_allowed: field("Mems_allowed_list"),
+        };
+
+        let mismatch = match (&check.cpus_allowed, &check.mems_allowed) {
+            (Some(cpus), _) if *cpus != check.requested_core.to_string() => Some(format!(
+                "is not pinned to core {} (Cpus_allowed_list: {})",
+                check.requested_core, cpus
+            )),
+            (Some(_), Some(mems)) => match check.requested_node {
+                Some(node) if !list_contains(mems, node) => Some(format!(
+                    "cannot allocate memory on node {} of core {} (Mems_allowed_list: {})",
+                    node, check.requested_core, mems
+                )),
+                _ => None,
+            },
+            (Some(_), None) => None,
+            (None, _) => {
+                println!(
+                    "WARNING: unable to check the pinning of {}: process not found",
+                    check.process
+                );
+                None
+            }
+        };
+
+        let err = mismatch.map(|mismatch| {
+            println!("WARNING: {} {}", check.process, mismatch);
+            failure::format_err!("{} {}", check.process, mismatch)
+        });
+
+        PINNING_CHECKS.with(|checks| checks.borrow_mut().push(check));
+
+        match err {
+            Some(err) if ENFORCE_PINNING.with(|e| e.get()) => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A check of the output of a workload. The exit status of a workload doesn't catch everything: a
+/// client that dies partway through (or is allowed to fail, e.g. with `allow_oom`) can leave
+/// truncated output that looks like a successful run.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum OutputValidator {
+    /// The output must contain a line containing the given string.
+    Contains(&'static str),
+    /// The output must contain at least the given number of lines matching the given extended
+    /// regular expression.
+    MinMatches(&'static str, usize),
+}
+
+/// A lower bound on the number of requests `memcached_gen_data` and `redis_gen_data` make per GB
+/// of workload. Values are at most 1MB (memcached's maximum item size), so filling a GB takes at
+/// least 1024 requests.
+const GEN_DATA_MIN_REQUESTS_PER_GB: usize = 1024;
+
+/// The output of the `memcached_gen_data` and `redis_gen_data` workloads has one latency per line.
+/// A `wk_gb` GB workload must record at least `GEN_DATA_MIN_REQUESTS_PER_GB` latencies per GB. If
+/// the workload may stop early (e.g. a memcached server that is allowed to OOM), only the first GB
+/// is required.
+pub fn gen_data_validator(wk_gb: usize, may_stop_early: bool) -> OutputValidator {
+    let gb = if may_stop_early { 1 } else { wk_gb };
+    OutputValidator::MinMatches("^[0-9]+$", gb * GEN_DATA_MIN_REQUESTS_PER_GB)
+}
+
+/// NAS CG verifies its own results at the end of the run.
+pub const NAS_CG_VALIDATOR: OutputValidator = OutputValidator::Contains("VERIFICATION SUCCESSFUL");
+
+/// Metis prints nothing useful in quiet mode, so `run_metis_matrix_mult` prints a marker once
+/// `matrix_mult2` exits successfully.
+pub const METIS_VALIDATOR: OutputValidator = OutputValidator::Contains("matrix_mult2 done");
+
+/// `time_loop` and `time_mmap_touch` print the elapsed time once they finish.
+pub const TIMING_VALIDATOR: OutputValidator = OutputValidator::MinMatches("[0-9]+", 1);
+
+/// The GAP benchmark suite prints the average time of the trials once they all complete.
+pub const GAPBS_VALIDATOR: OutputValidator = OutputValidator::Contains("Average Time:");
+
+/// YCSB prints the overall run time once all operations complete.
+pub const YCSB_VALIDATOR: OutputValidator = OutputValidator::Contains("[OVERALL], RunTime(ms)");
+
+/// The result of a check of the output of a workload, as recorded in the results of an experiment.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputCheck {
+    /// The name of the workload.
+    pub workload: String,
+    /// The file containing the output of the workload on the remote.
+    pub output_file: String,
+    /// The check that was made.
+    pub validator: OutputValidator,
+    /// The number of lines of the output that matched.
+    pub matches: usize,
+    /// Whether the output passed the check.
+    pub passed: bool,
+}
+
+thread_local! {
+    /// All output checks so far.
+    static OUTPUT_CHECKS: RefCell<Vec<OutputCheck>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns all output checks so far, in the order they were made.
+pub fn recorded_output_checks() -> Vec<OutputCheck> {
+    OUTPUT_CHECKS.with(|checks| checks.borrow().clone())
+}
+
+/// Check the output of `workload` in `output_file` on the remote and record the result. Returns an
+/// error if the check fails. If `output_file` is `None` (i.e. the output went to `/dev/null`),
+/// there is nothing to check.
+///
+/// The workload routines in this module check the output of workloads that they wait for.
+/// Workloads that are spawned in the background are checked when their `SpawnedWorkload` (or
+/// `RedisWorkloadHandles`) is joined.
+pub fn validate_output(
+    shell: &SshShell,
+    workload: &str,
+    output_file: Option<&str>,
+    validator: OutputValidator,
+) -> Result<(), failure::Error> {
+    let output_file = match output_file {
+        Some(output_file) => output_file,
+        None => return Ok(()),
+    };
+
+    let (flags, pattern, required) = match validator {
+        OutputValidator::Contains(pattern) => ("-F", pattern, 1),
+        OutputValidator::MinMatches(pattern, required) => ("-E", pattern, required),
+    };
+
+    // `grep -c` exits with an error if nothing matches or the file doesn't exist. In either case,
+    // it prints nothing useful, so we count 0 matches.
+    let matches = shell
+        .run(
+            cmd!(
+                "grep -c {} -- {} {}",
+                flags,
+                escape_for_bash(pattern),
+                output_file
+            )
+            .allow_error(),
+        )?
+        .stdout
+        .trim()
+        .parse()
+        .unwrap_or(0);
+
+    let check = OutputCheck {
+        workload: workload.into(),
+        output_file: output_file.into(),
+        validator,
+        matches,
+        passed: matches >= required,
+    };
+
+    let err = if check.passed {
+        None
+    } else {
+        println!(
+            "WARNING: the output of {} in {} is invalid: {:?} matched {} lines",
+            check.workload, check.output_file, check.validator, check.matches
+        );
+
+        Some(failure::format_err!(
+            "The output of {} in {} failed validation ({:?} matched {} lines); \
+             the workload probably did not complete",
+            check.workload,
+            check.output_file,
+            check.validator,
+            check.matches
+        ))
+    };
+
+    OUTPUT_CHECKS.with(|checks| checks.borrow_mut().push(check));
+
+    match err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// A workload spawned in the background. Its output is checked when it is joined.
+pub struct SpawnedWorkload {
+    pub shell: SshShell,
+    pub spawn_handle: SshSpawnHandle,
+    /// The name of the workload.
+    workload: String,
+    /// The file to which the workload writes its output, if any.
+    output_file: Option<String>,
+    /// The check to make once the workload finishes.
+    validator: OutputValidator,
+}
+
+impl SpawnedWorkload {
+    /// Wait for the workload to finish and check its output.
+    pub fn join(self) -> Result<(), failure::Error> {
+        self.spawn_handle.join()?;
+        validate_output(
+            &self.shell,
+            &self.workload,
+            self.output_file.as_deref(),
+            self.validator,
+        )
+    }
+}
 
 /// Set the apriori paging process using Swapnil's program. Requires `sudo`.
 ///
@@ -30,6 +518,7 @@ pub fn vagrant_setup_apriori_paging_process(shell: &SshShell, prog: &str) -> Res
 }
 
 /// Keeps track of which guest vCPUs have been assigned.
+#[derive(Clone, Debug)]
 pub struct TasksetCtx {
     /// The total number of vCPUs.
     ncores: usize,
@@ -53,6 +542,71 @@ impl TasksetCtx {
     }
 }
 
+/// Options that apply to any workload, so that cross-cutting knobs only need to be plumbed through
+/// once. For workloads with a client and a server (e.g. memcached), the options apply to the
+/// server, since that is the process whose memory we are interested in.
+#[derive(Clone, Debug, Default)]
+pub struct WorkloadOptions<'s> {
+    /// Run the workload with eager paging (only in VM).
+    pub eager: bool,
+    /// Restrict the workload to the given cores. Processes of the workload that are pinned to a
+    /// particular core (e.g. `pin_core`) stay on that core.
+    pub pin_cores: Option<Vec<usize>>,
+    /// Run the workload with the given `nice` value. Negative values require the workload to run
+    /// as root.
+    pub nice: Option<i32>,
+    /// Run the workload in the given memory cgroup.
+    pub cgroup: Option<&'s MemoryCgroup>,
+    /// Environment variables to set for the workload.
+    pub env: Vec<(String, String)>,
+}
+
+impl WorkloadOptions<'_> {
+    /// Set up eager paging for the given process if needed. Requires `sudo`.
+    pub fn setup_eager_paging(&self, shell: &SshShell, prog: &str) -> Result<(), SshError> {
+        if self.eager {
+            vagrant_setup_apriori_paging_process(shell, prog)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a prefix for the command line of the workload that applies the options other than
+    /// `eager`. For commands run with `sudo`, the prefix goes after `sudo`. Only the first command
+    /// of a pipeline is affected.
+    pub fn command_prefix(&self) -> String {
+        let mut prefix = vec![];
+
+        if let Some(cgroup) = self.cgroup {
+            prefix.push(cgroup.exec_prefix());
+        }
+        if !self.env.is_empty() {
+            prefix.push(format!(
+                "env {}",
+                self.env
+                    .iter()
+                    .map(|(var, value)| format!("{}={}", var, escape_for_bash(value)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+        if let Some(nice) = self.nice {
+            prefix.push(format!("nice -n {}", nice));
+        }
+        if let Some(pin_cores) = &self.pin_cores {
+            prefix.push(format!(
+                "taskset -c {}",
+                pin_cores
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ));
+        }
+
+        prefix.join(" ")
+    }
+}
+
 /// The different patterns supported by the `time_mmap_touch` workload.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum TimeMmapTouchPattern {
@@ -79,8 +633,8 @@ pub struct TimeMmapTouchConfig<'s> {
     pub prefault: bool,
     /// Specifies the page fault time if TSC offsetting is to try to account for it.
     pub pf_time: Option<u64>,
-    /// Indicates whether the workload should be run with eager paging (only in VM).
-    pub eager: bool,
+    /// Options for the workload (e.g. eager paging).
+    pub opts: WorkloadOptions<'s>,
 }
 
 /// Run the `time_mmap_touch` workload on the remote `shell`. Requires `sudo`.
@@ -93,13 +647,16 @@ pub fn run_time_mmap_touch(
         TimeMmapTouchPattern::Zeros => "-z",
     };
 
-    if cfg.eager {
-        vagrant_setup_apriori_paging_process(shell, "time_mmap_touch")?;
-    }
+    cfg.opts.setup_eager_paging(shell, "time_mmap_touch")?;
+
+    let pinning = PinningVerifier::start(shell, "time_mmap_touch", cfg.pin_core)?;
 
-    shell.run(
-        cmd!(
-            "sudo taskset -c {} ./target/release/time_mmap_touch {} {} {} {} > {}",
+    run_recorded(
+        shell,
+        Some(cfg.exp_dir),
+        format!(
+            "sudo {} taskset -c {} ./target/release/time_mmap_touch {} {} {} {} > {}",
+            cfg.opts.command_prefix(),
             cfg.pin_core,
             cfg.pages,
             pattern,
@@ -110,11 +667,14 @@ pub fn run_time_mmap_touch(
                 "".into()
             },
             cfg.output_file.unwrap_or("/dev/null")
-        )
-        .cwd(cfg.exp_dir)
-        .use_bash(),
+        ),
+        /* allow_error */ false,
     )?;
 
+    pinning.finish()?;
+
+    validate_output(shell, "time_mmap_touch", cfg.output_file, TIMING_VALIDATOR)?;
+
     Ok(())
 }
 
@@ -127,8 +687,8 @@ pub struct MemcachedWorkloadConfig<'s> {
 
     /// The user to run the `memcached` server as.
     pub user: &'s str,
-    /// The size of `memcached` server in MB.
-    pub server_size_mb: usize,
+    /// The size of `memcached` server.
+    pub server_size: Size,
     /// Specifies whether the memcached server is allowed to OOM.
     pub allow_oom: bool,
 
@@ -137,8 +697,8 @@ pub struct MemcachedWorkloadConfig<'s> {
     /// The core number that the workload client is pinned to.
     pub client_pin_core: usize,
 
-    /// The size of the workload in GB.
-    pub wk_size_gb: usize,
+    /// The size of the workload.
+    pub wk_size: Size,
     /// The file to which the workload will write its output. If `None`, then `/dev/null` is used.
     pub output_file: Option<&'s str>,
 
@@ -146,8 +706,8 @@ pub struct MemcachedWorkloadConfig<'s> {
     pub freq: Option<usize>,
     /// Specifies the page fault time if TSC offsetting is to try to account for it.
     pub pf_time: Option<u64>,
-    /// Indicates whether the workload should be run with eager paging.
-    pub eager: bool,
+    /// Options for the memcached server (e.g. eager paging or a memory cgroup).
+    pub opts: WorkloadOptions<'s>,
 }
 
 /// Start a `memcached` server in daemon mode as the given user with the given amount of memory.
@@ -157,40 +717,65 @@ pub struct MemcachedWorkloadConfig<'s> {
 /// behaviors. memcached uses a large amount of the memory you give it for bookkeeping, rather
 /// than user data, so OOM will almost certainly happen.
 ///
-/// `eager` indicates whether the workload should be run with eager paging (only in VM).
+/// `cfg.opts` apply to the server.
 pub fn start_memcached(
     shell: &SshShell,
     cfg: &MemcachedWorkloadConfig<'_>,
 ) -> Result<(), failure::Error> {
-    if cfg.eager {
-        vagrant_setup_apriori_paging_process(shell, "memcached")?;
-    }
+    cfg.opts.setup_eager_paging(shell, "memcached")?;
 
     // We need to update the system vma limit because malloc may cause it to be hit for
     // large-memory systems.
     shell.run(cmd!("sudo sysctl -w vm.max_map_count={}", 1_000_000_000))?;
 
+    let prefix = cfg.opts.command_prefix();
+
     if let Some(server_pin_core) = cfg.server_pin_core {
         shell.run(cmd!(
-            "taskset -c {} {}/memcached {} -m {} -d -u {} -f 1.11",
+            "{} taskset -c {} {}/memcached {} -m {} -d -u {} -f 1.11",
+            prefix,
             server_pin_core,
             cfg.memcached,
             if cfg.allow_oom { "-M" } else { "" },
-            cfg.server_size_mb,
+            cfg.server_size.mb(),
             cfg.user
         ))?
     } else {
         shell.run(cmd!(
-            "{}/memcached {} -m {} -d -u {} -f 1.11",
+            "{} {}/memcached {} -m {} -d -u {} -f 1.11",
+            prefix,
             cfg.memcached,
             if cfg.allow_oom { "-M" } else { "" },
-            cfg.server_size_mb,
+            cfg.server_size.mb(),
             cfg.user
         ))?
     };
     Ok(())
 }
 
+/// Kill any memcached, redis, or mongod servers left running by an earlier, interrupted attempt at
+/// a workload, so that the workload can be rerun from the beginning.
+pub fn kill_servers(shell: &SshShell) -> Result<(), failure::Error> {
+    for server in &["memcached", "redis-server", "mongod"] {
+        shell.run(cmd!("sudo pkill -x {}", server).allow_error())?;
+    }
+    Ok(())
+}
+
+/// Returns `size` in GB, rounded down, for the workload clients that only take a number of GB.
+/// `headroom_gb` is subtracted from the result (e.g. to leave room for the server's overhead).
+fn size_gb(size: Size, headroom_gb: usize, client: &str) -> Result<usize, failure::Error> {
+    match size.gb().checked_sub(headroom_gb) {
+        Some(gb) if gb > 0 => Ok(gb),
+        _ => Err(failure::format_err!(
+            "{} needs at least {}GB, not {}",
+            client,
+            headroom_gb + 1,
+            size
+        )),
+    }
+}
+
 /// Run the `memcached_gen_data` workload.
 pub fn run_memcached_gen_data(
     shell: &SshShell,
@@ -199,32 +784,51 @@ pub fn run_memcached_gen_data(
     // Start server
     start_memcached(&shell, cfg)?;
 
+    run_memcached_gen_data_client(shell, cfg)
+}
+
+/// Run the `memcached_gen_data` workload against a server already started with `start_memcached`
+/// (e.g. so that a dataset can be staged into the server first).
+pub fn run_memcached_gen_data_client(
+    shell: &SshShell,
+    cfg: &MemcachedWorkloadConfig<'_>,
+) -> Result<(), failure::Error> {
+    // Leave 1GB of headroom to avoid an OOM.
+    let wk_gb = size_gb(cfg.wk_size, 1, "memcached_gen_data")?;
+
     // Run workload
-    let cmd = cmd!(
-        "taskset -c {} ./target/release/memcached_gen_data localhost:11211 {} {} {} | tee {}",
-        cfg.client_pin_core,
-        cfg.wk_size_gb - 1, // Avoid a OOM
-        if let Some(freq) = cfg.freq {
-            format!("--freq {}", freq)
-        } else {
-            "".into()
-        },
-        if let Some(pf_time) = cfg.pf_time {
-            format!("--pftime {}", pf_time)
-        } else {
-            "".into()
-        },
-        cfg.output_file.unwrap_or("/dev/null")
-    )
-    .cwd(cfg.exp_dir);
+    let pinning = PinningVerifier::start(shell, "memcached_gen_data", cfg.client_pin_core)?;
 
-    let cmd = if cfg.allow_oom {
-        cmd.allow_error()
-    } else {
-        cmd
-    };
+    run_recorded(
+        shell,
+        Some(cfg.exp_dir),
+        format!(
+            "taskset -c {} ./target/release/memcached_gen_data localhost:11211 {} {} {} | tee {}",
+            cfg.client_pin_core,
+            wk_gb,
+            if let Some(freq) = cfg.freq {
+                format!("--freq {}", freq)
+            } else {
+                "".into()
+            },
+            if let Some(pf_time) = cfg.pf_time {
+                format!("--pftime {}", pf_time)
+            } else {
+                "".into()
+            },
+            cfg.output_file.unwrap_or("/dev/null")
+        ),
+        cfg.allow_oom,
+    )?;
 
-    shell.run(cmd)?;
+    pinning.finish()?;
+
+    validate_output(
+        shell,
+        "memcached_gen_data",
+        cfg.output_file,
+        gen_data_validator(wk_gb, cfg.allow_oom),
+    )?;
 
     Ok(())
 }
@@ -253,29 +857,28 @@ pub fn run_memcached_and_capture_thp(
     }
 
     // Run workload
-    let cmd = cmd!(
-        "taskset -c {} ./target/release/memcached_and_capture_thp localhost:11211 {} {} {} {} | tee {}",
-        cfg.client_pin_core,
-        cfg.wk_size_gb,
-        interval,
-        cfg.output_file.unwrap_or("/dev/null"),
-        if continual_compaction.is_some() {
-            "--continual_compaction"
-        } else {
-            ""
-        },
-        output_file
-    )
-    .cwd(cfg.exp_dir)
-    .use_bash();
+    let pinning = PinningVerifier::start(shell, "memcached_and_capture_thp", cfg.client_pin_core)?;
 
-    let cmd = if cfg.allow_oom {
-        cmd.allow_error()
-    } else {
-        cmd
-    };
+    run_recorded(
+        shell,
+        Some(cfg.exp_dir),
+        format!(
+            "taskset -c {} ./target/release/memcached_and_capture_thp localhost:11211 {} {} {} {} | tee {}",
+            cfg.client_pin_core,
+            cfg.wk_size.gb(),
+            interval,
+            cfg.output_file.unwrap_or("/dev/null"),
+            if continual_compaction.is_some() {
+                "--continual_compaction"
+            } else {
+                ""
+            },
+            output_file
+        ),
+        cfg.allow_oom,
+    )?;
 
-    shell.run(cmd)?;
+    pinning.finish()?;
 
     Ok(())
 }
@@ -289,40 +892,269 @@ pub enum NasClass {
 
 /// Start the NAS CG workload. It must already be compiled. This workload takes a really long time,
 /// so we start it in a spawned shell and return the join handle rather than waiting for the
-/// workload to return.
+/// workload to return. Joining the handle waits for CG to finish and checks that it verified its
+/// results. Experiments that stop measuring after a fixed time should not join it, since CG has
+/// not verified anything yet.
 ///
 /// - `zerosim_bmk_path` is the path to the `bmks` directory of `0sim-workspace`.
 /// - `output_file` is the file to which the workload will write its output. If `None`, then
 ///   `/dev/null` is used.
-/// - `eager` indicates whether the workload should be run with eager paging (only in VM).
+/// - `opts` are the options for the workload (e.g. eager paging).
 pub fn run_nas_cg(
     shell: &SshShell,
     zerosim_bmk_path: &str,
     class: NasClass,
     output_file: Option<&str>,
-    eager: bool,
+    opts: &WorkloadOptions<'_>,
     tctx: &mut TasksetCtx,
-) -> Result<(SshShell, SshSpawnHandle), failure::Error> {
+) -> Result<SpawnedWorkload, failure::Error> {
     let class = match class {
         NasClass::E => "E",
         NasClass::F => "F",
     };
 
-    if eager {
-        vagrant_setup_apriori_paging_process(shell, &format!("cg.{}.x", class))?;
-    }
+    opts.setup_eager_paging(shell, &format!("cg.{}.x", class))?;
 
-    let handle = shell.spawn(
-        cmd!(
-            "taskset -c {} ./bin/cg.{}.x > {}",
-            tctx.next(),
+    let core = tctx.next();
+
+    let (cg_shell, spawn_handle) = spawn_recorded(
+        shell,
+        Some(&format!("{}/NPB3.4/NPB3.4-OMP", zerosim_bmk_path)),
+        format!(
+            "{} taskset -c {} ./bin/cg.{}.x > {}",
+            opts.command_prefix(),
+            core,
             class,
             output_file.unwrap_or("/dev/null")
-        )
-        .cwd(&format!("{}/NPB3.4/NPB3.4-OMP", zerosim_bmk_path)),
+        ),
     )?;
 
-    Ok(handle)
+    PinningVerifier::start(shell, &format!("cg.{}.x", class), core)?.finish()?;
+
+    Ok(SpawnedWorkload {
+        shell: cg_shell,
+        spawn_handle,
+        workload: format!("cg.{}.x", class),
+        output_file: output_file.map(Into::into),
+        validator: NAS_CG_VALIDATOR,
+    })
+}
+
+/// The graph kernels of the GAP benchmark suite that we support.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum GapbsKernel {
+    /// Breadth-first search.
+    Bfs,
+    /// PageRank.
+    PageRank,
+}
+
+impl GapbsKernel {
+    /// The names of the kernels, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &["bfs", "pr"];
+
+    /// The name of the kernel's binary, which is also the name of the process.
+    fn binary(self) -> &'static str {
+        match self {
+            GapbsKernel::Bfs => "bfs",
+            GapbsKernel::PageRank => "pr",
+        }
+    }
+}
+
+impl std::str::FromStr for GapbsKernel {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bfs" => Ok(GapbsKernel::Bfs),
+            "pr" => Ok(GapbsKernel::PageRank),
+            _ => Err(failure::format_err!("Unknown GAP kernel {:?}", s)),
+        }
+    }
+}
+
+/// Settings for a run of a GAP benchmark suite kernel.
+pub struct GapbsWorkloadConfig<'s> {
+    /// The path of the GAP benchmark suite on the remote. It must already be compiled.
+    pub gapbs: &'s str,
+
+    /// The kernel to run.
+    pub kernel: GapbsKernel,
+    /// The scale of the synthetic Kronecker graph, which has `2^scale` vertices and an average
+    /// degree of 16. See `gapbs_scale_for_size`.
+    pub scale: usize,
+    /// The number of times to run the kernel on the graph.
+    pub trials: usize,
+
+    /// The core number that the workload is pinned to.
+    pub pin_core: usize,
+
+    /// The file to which the workload will write its output. If `None`, then `/dev/null` is used.
+    pub output_file: Option<&'s str>,
+    /// Options for the workload (e.g. eager paging).
+    pub opts: WorkloadOptions<'s>,
+}
+
+/// Returns the largest Kronecker graph scale for which the GAP benchmark suite uses at most `size`
+/// of memory. Building a graph with `n` vertices (and `16n` edges) takes roughly `256n` bytes at
+/// its peak.
+pub fn gapbs_scale_for_size(size: Size) -> usize {
+    let vertices = size.bytes() / 256;
+    assert!(vertices > 0);
+    (std::mem::size_of::<usize>() * 8) - 1 - vertices.leading_zeros() as usize
+}
+
+/// Run a GAP benchmark suite kernel on a synthetic Kronecker graph. The graph is generated in
+/// memory, so the workload accesses memory in a pointer-chasing, poorly-compressible pattern. The
+/// kernel is run single-threaded, like our other workloads.
+pub fn run_gapbs(shell: &SshShell, cfg: &GapbsWorkloadConfig<'_>) -> Result<(), failure::Error> {
+    let binary = cfg.kernel.binary();
+
+    cfg.opts.setup_eager_paging(shell, binary)?;
+
+    let pinning = PinningVerifier::start(shell, binary, cfg.pin_core)?;
+
+    run_recorded(
+        shell,
+        Some(cfg.gapbs),
+        format!(
+            "OMP_NUM_THREADS=1 {} taskset -c {} ./{} -g {} -n {} | tee {}",
+            cfg.opts.command_prefix(),
+            cfg.pin_core,
+            binary,
+            cfg.scale,
+            cfg.trials,
+            cfg.output_file.unwrap_or("/dev/null")
+        ),
+        /* allow_error */ false,
+    )?;
+
+    pinning.finish()?;
+
+    validate_output(shell, binary, cfg.output_file, GAPBS_VALIDATOR)?;
+
+    Ok(())
+}
+
+/// Settings for a run of SPEC CPU 2017 benchmarks.
+pub struct Spec2017WorkloadConfig<'s> {
+    /// The path of the SPEC installation on the remote. The benchmarks must already be built.
+    pub spec_dir: &'s str,
+    /// The name of the SPEC config with which the benchmarks were built.
+    pub config: &'s str,
+
+    /// The benchmarks to run (e.g. `505.mcf_r`).
+    pub benchmarks: &'s [String],
+    /// The input size (`test`, `train`, or `ref`).
+    pub input_size: &'s str,
+
+    /// The core number that the benchmarks are pinned to.
+    pub pin_core: usize,
+
+    /// The file to which the SPEC CSV report of the run is copied. If `None`, then it is not
+    /// copied.
+    pub output_file: Option<&'s str>,
+    /// Options for the benchmarks. Eager paging is not supported, since `runcpu` runs each
+    /// benchmark as a differently named process.
+    pub opts: WorkloadOptions<'s>,
+}
+
+/// Run the given SPEC CPU 2017 benchmarks once each with base tuning, pinned to a single core.
+/// Returns the run time of each benchmark in seconds, parsed from the SPEC report. Returns an
+/// error if any of the benchmarks did not complete.
+pub fn run_spec_2017(
+    shell: &SshShell,
+    cfg: &Spec2017WorkloadConfig<'_>,
+) -> Result<BTreeMap<String, f64>, failure::Error> {
+    // `runcpu` runs each benchmark in a child process, which inherits the affinity.
+    let pinning = PinningVerifier::start(shell, "runcpu", cfg.pin_core)?;
+
+    run_recorded(
+        shell,
+        Some(cfg.spec_dir),
+        format!(
+            "source shrc && {} taskset -c {} runcpu --config={} --size={} --iterations=1 \
+             --tune=base --copies=1 --noreportable --output_format=csv {}",
+            cfg.opts.command_prefix(),
+            cfg.pin_core,
+            cfg.config,
+            cfg.input_size,
+            cfg.benchmarks.join(" ")
+        ),
+        /* allow_error */ false,
+    )?;
+
+    pinning.finish()?;
+
+    // The report of this run is the newest one.
+    let report = shell
+        .run(
+            cmd!("ls -t result/*.csv | head -n 1")
+                .use_bash()
+                .cwd(cfg.spec_dir),
+        )?
+        .stdout;
+    let report = report.trim();
+
+    if let Some(output_file) = cfg.output_file {
+        shell.run(cmd!("cp {} {}", report, output_file).cwd(cfg.spec_dir))?;
+    }
+
+    let csv = shell.run(cmd!("cat {}", report).cwd(cfg.spec_dir))?.stdout;
+    let results = parse_spec_2017_csv(&csv);
+
+    let missing: Vec<_> = cfg
+        .benchmarks
+        .iter()
+        .filter(|benchmark| !results.contains_key(benchmark.as_str()))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(failure::format_err!(
+            "SPEC CPU 2017 benchmarks did not complete: {} (see {})",
+            missing.join(", "),
+            report
+        ));
+    }
+
+    Ok(results)
+}
+
+/// Parse the run time of each benchmark from the "Selected Results Table" of a SPEC CPU 2017 CSV
+/// report. Benchmarks that did not complete (and so have no run time) are left out.
+pub fn parse_spec_2017_csv(csv: &str) -> BTreeMap<String, f64> {
+    let split = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|field| field.trim().trim_matches('"').to_owned())
+            .collect()
+    };
+
+    let mut lines = csv
+        .lines()
+        .skip_while(|line| !line.starts_with("\"Selected Results Table\""))
+        .skip(1);
+
+    let header = match lines.next() {
+        Some(header) => split(header),
+        None => return BTreeMap::new(),
+    };
+    let run_time = match header
+        .iter()
+        .position(|field| field.starts_with("Est. Base Run Time") || field == "Base Run Time")
+    {
+        Some(i) => i,
+        None => return BTreeMap::new(),
+    };
+
+    lines
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let fields = split(line);
+            let time = fields.get(run_time)?.parse().ok()?;
+            Some((fields[0].clone(), time))
+        })
+        .collect()
 }
 
 bitflags! {
@@ -341,45 +1173,167 @@ bitflags! {
 /// - `r` is the number of times to call `memhog`, not the value of `-r`. `-r` is always passed a
 ///   value of `1`. If `None`, then run indefinitely.
 /// - `size_kb` is the number of kilobytes to mmap and touch.
-/// - `eager` indicates whether the workload should be run with eager paging (only in VM).
+/// - `flags` are the `memhog` options to use.
+/// - `opts` are the options for the workload (e.g. eager paging).
 pub fn run_memhog(
     shell: &SshShell,
     exp_dir: &str,
     r: Option<usize>,
     size_kb: usize,
-    opts: MemhogOptions,
-    eager: bool,
+    flags: MemhogOptions,
+    opts: &WorkloadOptions<'_>,
     tctx: &mut TasksetCtx,
-) -> Result<(SshShell, SshSpawnHandle), SshError> {
-    if eager {
-        vagrant_setup_apriori_paging_process(shell, "memhog")?;
-    }
+) -> Result<(SshShell, SshSpawnHandle), failure::Error> {
+    opts.setup_eager_paging(shell, "memhog")?;
+
+    let core = tctx.next();
 
-    shell.spawn(cmd!(
-        "{} ; do \
-         LD_LIBRARY_PATH={} taskset -c {} {}/memhog -r1 {}k {} {} > /dev/null ; \
+    let handle = spawn_recorded(
+        shell,
+        None,
+        format!(
+            "{} ; do \
+         LD_LIBRARY_PATH={} {} taskset -c {} {}/memhog -r1 {}k {} {} > /dev/null ; \
          done; \
          echo memhog done ;",
-        if let Some(r) = r {
-            format!("for i in `seq {}`", r)
-        } else {
-            "while [ 1 ]".into()
-        },
-        exp_dir,
-        tctx.next(),
-        exp_dir,
-        size_kb,
-        if opts.contains(MemhogOptions::PIN) {
-            "-p"
-        } else {
-            ""
-        },
-        if opts.contains(MemhogOptions::DATA_OBLIV) {
-            "-o"
-        } else {
-            ""
-        },
-    ))
+            if let Some(r) = r {
+                format!("for i in `seq {}`", r)
+            } else {
+                "while [ 1 ]".into()
+            },
+            exp_dir,
+            opts.command_prefix(),
+            core,
+            exp_dir,
+            size_kb,
+            if flags.contains(MemhogOptions::PIN) {
+                "-p"
+            } else {
+                ""
+            },
+            if flags.contains(MemhogOptions::DATA_OBLIV) {
+                "-o"
+            } else {
+                ""
+            },
+        ),
+    )?;
+
+    PinningVerifier::start(shell, "memhog", core)?.finish()?;
+
+    Ok(handle)
+}
+
+/// A background workload that interferes with the primary workload of an experiment.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Antagonist {
+    /// Repeatedly touch the given amount of memory with `memhog`.
+    MemHog(Size),
+    /// Spin on a core.
+    CpuSpin,
+    /// Do random 4KB direct reads and writes to a file of the given size with `fio`.
+    IoThrash(Size),
+}
+
+impl std::str::FromStr for Antagonist {
+    type Err = failure::Error;
+
+    /// Parse an antagonist given as `memhog:SIZE`, `cpu`, or `io:SIZE` (e.g. `memhog:4G`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let size = |size: Option<&str>| {
+            size.ok_or_else(|| failure::format_err!("Antagonist {:?} needs a size", s))?
+                .parse::<Size>()
+        };
+
+        match parts.next() {
+            Some("memhog") => Ok(Antagonist::MemHog(size(parts.next())?)),
+            Some("cpu") => Ok(Antagonist::CpuSpin),
+            Some("io") => Ok(Antagonist::IoThrash(size(parts.next())?)),
+            _ => Err(failure::format_err!(
+                "Unknown antagonist {:?}: expected memhog:SIZE, cpu, or io:SIZE",
+                s
+            )),
+        }
+    }
+}
+
+/// Background antagonists running on the remote, each in its own shell and pinned to its own core.
+/// They run until `stop` is called, or until they are dropped (e.g. because the workload failed).
+pub struct Antagonists {
+    /// Keeps the antagonists running.
+    marker: LoopMarker,
+
+    handles: Vec<(SshShell, SshSpawnHandle)>,
+}
+
+impl Antagonists {
+    /// Start the given antagonists in the background. `numactl_dir` is the path of the `numactl`
+    /// benchmark directory, which contains `memhog`. The IO antagonist requires `fio`.
+    pub fn start(
+        shell: &SshShell,
+        antagonists: &[Antagonist],
+        numactl_dir: &str,
+        tctx: &mut TasksetCtx,
+    ) -> Result<Self, failure::Error> {
+        let marker = LoopMarker::create(shell, "antagonists")?;
+        let io_file = Self::io_file(&marker);
+
+        let mut handles = vec![];
+
+        for antagonist in antagonists.iter() {
+            let core = tctx.next();
+
+            // Each antagonist is a loop that runs until the marker is removed, so they stop
+            // shortly after `stop` is called.
+            let body = match antagonist {
+                Antagonist::MemHog(size) => format!(
+                    "LD_LIBRARY_PATH={0} {0}/memhog -r1 {1}k > /dev/null",
+                    numactl_dir,
+                    size.kb()
+                ),
+                Antagonist::CpuSpin => ":".into(),
+                Antagonist::IoThrash(size) => format!(
+                    "fio --name=antagonist --filename={} --size={} --rw=randrw --bs=4k \
+                     --direct=1 --time_based --runtime=10 > /dev/null",
+                    io_file,
+                    size.bytes()
+                ),
+            };
+
+            handles.push(spawn_recorded(
+                shell,
+                None,
+                format!(
+                    "taskset -c {} bash -c 'while [ -e {} ] ; do {} ; done' ; \
+                     echo antagonist done",
+                    core,
+                    marker.path(),
+                    body
+                ),
+            )?);
+        }
+
+        Ok(Antagonists { marker, handles })
+    }
+
+    /// The file that the IO antagonist reads and writes, which is unique to this run like `marker`.
+    fn io_file(marker: &LoopMarker) -> String {
+        format!("{}.fio", marker.path())
+    }
+
+    /// Stop all antagonists and wait for them to exit.
+    pub fn stop(mut self, shell: &SshShell) -> Result<(), failure::Error> {
+        self.marker.remove()?;
+
+        for (_shell, handle) in self.handles.drain(..) {
+            handle.join()?;
+        }
+
+        shell.run(cmd!("rm -f {}", Self::io_file(&self.marker)))?;
+
+        Ok(())
+    }
 }
 
 /// Run the `time_loop` microbenchmark on the remote.
@@ -387,30 +1341,37 @@ pub fn run_memhog(
 /// - `exp_dir` is the path of the 0sim-experiments submodule.
 /// - `n` is the number of times to loop.
 /// - `output_file` is the location to put the output.
-/// - `eager` indicates whether the workload should be run with eager paging (only in VM).
+/// - `opts` are the options for the workload (e.g. eager paging).
 pub fn run_time_loop(
     shell: &SshShell,
     exp_dir: &str,
     n: usize,
     output_file: &str,
-    eager: bool,
+    opts: &WorkloadOptions<'_>,
     tctx: &mut TasksetCtx,
 ) -> Result<(), failure::Error> {
-    if eager {
-        vagrant_setup_apriori_paging_process(shell, "time_loop")?;
-    }
+    opts.setup_eager_paging(shell, "time_loop")?;
 
-    shell.run(
-        cmd!(
-            "sudo taskset -c {} ./target/release/time_loop {} > {}",
-            tctx.next(),
+    let core = tctx.next();
+    let pinning = PinningVerifier::start(shell, "time_loop", core)?;
+
+    run_recorded(
+        shell,
+        Some(exp_dir),
+        format!(
+            "sudo {} taskset -c {} ./target/release/time_loop {} > {}",
+            opts.command_prefix(),
+            core,
             n,
             output_file
-        )
-        .cwd(exp_dir)
-        .use_bash(),
+        ),
+        /* allow_error */ false,
     )?;
 
+    pinning.finish()?;
+
+    validate_output(shell, "time_loop", Some(output_file), TIMING_VALIDATOR)?;
+
     Ok(())
 }
 
@@ -439,8 +1400,8 @@ pub struct LocalityMemAccessConfig<'s> {
     /// The location to write the output for the workload.
     pub output_file: &'s str,
 
-    /// Turn on eager paging.
-    pub eager: bool,
+    /// Options for the workload (e.g. eager paging).
+    pub opts: WorkloadOptions<'s>,
 }
 
 /// Run the `locality_mem_access` workload on the remote of the given number of iterations.
@@ -448,7 +1409,7 @@ pub struct LocalityMemAccessConfig<'s> {
 /// If `threads` is `None`, a single-threaded workload is run. Otherwise, a multithreaded workload
 /// is run. The workload does its own CPU affinity assignments.
 ///
-/// `eager` should only be used in a VM.
+/// Eager paging should only be used in a VM.
 pub fn run_locality_mem_access(
     shell: &SshShell,
     cfg: &LocalityMemAccessConfig<'_>,
@@ -458,13 +1419,14 @@ pub fn run_locality_mem_access(
         LocalityMemAccessMode::Random => "-n",
     };
 
-    if cfg.eager {
-        vagrant_setup_apriori_paging_process(shell, "locality_mem_access")?;
-    }
+    cfg.opts.setup_eager_paging(shell, "locality_mem_access")?;
 
-    shell.run(
-        cmd!(
-            "time sudo ./target/release/locality_mem_access {} {} {} > {}",
+    run_recorded(
+        shell,
+        Some(cfg.exp_dir),
+        format!(
+            "time sudo {} ./target/release/locality_mem_access {} {} {} > {}",
+            cfg.opts.command_prefix(),
             locality,
             cfg.n,
             if let Some(threads) = cfg.threads {
@@ -473,9 +1435,8 @@ pub fn run_locality_mem_access(
                 "".into()
             },
             cfg.output_file,
-        )
-        .cwd(cfg.exp_dir)
-        .use_bash(),
+        ),
+        /* allow_error */ false,
     )?;
 
     Ok(())
@@ -486,11 +1447,22 @@ pub struct RedisWorkloadHandles {
     pub server_spawn_handle: SshSpawnHandle,
     pub client_shell: SshShell,
     pub client_spawn_handle: SshSpawnHandle,
+    /// The file to which the client writes its output, if any.
+    pub output_file: Option<String>,
+    /// The check to make once the client finishes.
+    pub validator: OutputValidator,
 }
 
 impl RedisWorkloadHandles {
+    /// Wait for the client to finish and check its output.
     pub fn wait_for_client(self) -> Result<(), failure::Error> {
         self.client_spawn_handle.join()?;
+        validate_output(
+            &self.client_shell,
+            "redis_gen_data",
+            self.output_file.as_deref(),
+            self.validator,
+        )?;
         Ok(())
     }
 }
@@ -504,13 +1476,17 @@ pub struct RedisWorkloadConfig<'s> {
     /// The path of the `redis.conf` file on the remote.
     pub redis_conf: &'s str,
 
-    /// The size of `redis` server in MB.
-    pub server_size_mb: usize,
-    /// The size of the workload in GB.
-    pub wk_size_gb: usize,
+    /// The size of `redis` server.
+    pub server_size: Size,
+    /// The size of the workload.
+    pub wk_size: Size,
     /// The file to which the workload will write its output. If `None`, then `/dev/null` is used.
     pub output_file: Option<&'s str>,
 
+    /// The TCP port the redis server listens on, if any, in addition to the unix socket. Only
+    /// needed for clients that cannot use a unix socket (e.g. YCSB).
+    pub tcp_port: Option<u16>,
+
     /// The core number that the redis server is pinned to, if any.
     pub server_pin_core: Option<usize>,
     /// The core number that the workload client is pinned to.
@@ -520,8 +1496,8 @@ pub struct RedisWorkloadConfig<'s> {
     pub freq: Option<usize>,
     /// Specifies the page fault time if TSC offsetting is to try to account for it.
     pub pf_time: Option<u64>,
-    /// Indicates whether the workload should be run with eager paging.
-    pub eager: bool,
+    /// Options for the redis server (e.g. eager paging or a memory cgroup).
+    pub opts: WorkloadOptions<'s>,
 }
 
 /// Spawn a `redis` server in a new shell with the given amount of memory and set some important
@@ -534,7 +1510,7 @@ pub struct RedisWorkloadConfig<'s> {
 ///     - delete any existing RDB files.
 ///     - set up a nullfs to use for the snapshot directory
 ///
-/// `eager` should only be used in a VM.
+/// `cfg.opts` apply to the server. Eager paging should only be used in a VM.
 ///
 /// Returns the spawned shell.
 pub fn start_redis(
@@ -544,9 +1520,7 @@ pub fn start_redis(
     // Set overcommit
     shell.run(cmd!("echo 1 | sudo tee /proc/sys/vm/overcommit_memory"))?;
 
-    if cfg.eager {
-        vagrant_setup_apriori_paging_process(shell, "redis-server")?;
-    }
+    cfg.opts.setup_eager_paging(shell, "redis-server")?;
 
     // Delete any previous database
     shell.run(cmd!("rm -f /tmp/dump.rdb"))?;
@@ -558,15 +1532,23 @@ pub fn start_redis(
     shell.run(cmd!("nohup {}/nullfs /mnt/nullfs", cfg.nullfs))?;
     shell.run(cmd!("sudo chmod 777 /mnt/nullfs"))?;
 
-    // Start the redis server
+    // Start the redis server. Arguments after the config file override it.
+    let port = if let Some(port) = cfg.tcp_port {
+        format!("--port {}", port)
+    } else {
+        "".into()
+    };
+    let prefix = cfg.opts.command_prefix();
     let handle = if let Some(server_pin_core) = cfg.server_pin_core {
         shell.spawn(cmd!(
-            "taskset -c {} redis-server {}",
+            "{} taskset -c {} redis-server {} {}",
+            prefix,
             server_pin_core,
-            cfg.redis_conf
+            cfg.redis_conf,
+            port
         ))?
     } else {
-        shell.spawn(cmd!("redis-server {}", cfg.redis_conf))?
+        shell.spawn(cmd!("{} redis-server {} {}", prefix, cfg.redis_conf, port))?
     };
 
     // Wait for server to start
@@ -584,7 +1566,7 @@ pub fn start_redis(
     // - save snapshots every 300 seconds if >= 1 key changed to the file /tmp/dump.rdb
     with_shell! { shell =>
         cmd!("redis-cli -s /tmp/redis.sock CONFIG SET maxmemory-policy allkeys-random"),
-        cmd!("redis-cli -s /tmp/redis.sock CONFIG SET maxmemory {}mb", cfg.server_size_mb),
+        cmd!("redis-cli -s /tmp/redis.sock CONFIG SET maxmemory {}mb", cfg.server_size.mb()),
 
         cmd!("redis-cli -s /tmp/redis.sock CONFIG SET save \"{} 1\"", REDIS_SNAPSHOT_FREQ_SECS),
     }
@@ -598,15 +1580,32 @@ pub fn run_redis_gen_data(
     cfg: &RedisWorkloadConfig<'_>,
 ) -> Result<RedisWorkloadHandles, failure::Error> {
     // Start server
-    let (server_shell, server_spawn_handle) = start_redis(&shell, cfg)?;
+    let server = start_redis(shell, cfg)?;
+
+    run_redis_gen_data_client(shell, cfg, server)
+}
+
+/// Run the `redis_gen_data` workload against a server already started with `start_redis` (e.g. so
+/// that a dataset can be staged into the server first). `server` is what `start_redis` returned.
+pub fn run_redis_gen_data_client(
+    shell: &SshShell,
+    cfg: &RedisWorkloadConfig<'_>,
+    server: (SshShell, SshSpawnHandle),
+) -> Result<RedisWorkloadHandles, failure::Error> {
+    let wk_gb = size_gb(cfg.wk_size, 0, "redis_gen_data")?;
+    let (server_shell, server_spawn_handle) = server;
 
     // Run workload
-    let (client_shell, client_spawn_handle) = shell.spawn(
-        cmd!(
+    let pinning = PinningVerifier::start(shell, "redis_gen_data", cfg.client_pin_core)?;
+
+    let (client_shell, client_spawn_handle) = spawn_recorded(
+        shell,
+        Some(cfg.exp_dir),
+        format!(
             "taskset -c {} ./target/release/redis_gen_data unix:/tmp/redis.sock \
              {} {} {} | tee {} ; echo redis_gen_data done",
             cfg.client_pin_core,
-            cfg.wk_size_gb,
+            wk_gb,
             if let Some(freq) = cfg.freq {
                 format!("--freq {}", freq)
             } else {
@@ -618,18 +1617,312 @@ pub fn run_redis_gen_data(
                 "".into()
             },
             cfg.output_file.unwrap_or("/dev/null")
-        )
-        .cwd(cfg.exp_dir),
+        ),
     )?;
 
+    pinning.finish()?;
+
     Ok(RedisWorkloadHandles {
         server_shell,
         server_spawn_handle,
         client_shell,
         client_spawn_handle,
+        output_file: cfg.output_file.map(Into::into),
+        validator: gen_data_validator(wk_gb, false),
     })
 }
 
+/// The data directory of the `mongod` server.
+const MONGO_DB_PATH: &str = "/tmp/mongodb";
+
+/// The cgroup in which the `mongod` server runs, to cap its memory usage.
+const MONGO_CGROUP: &str = "mongod";
+
+/// Every setting of the mongodb workload.
+pub struct MongoWorkloadConfig<'s> {
+    /// The path of the YCSB installation on the remote, which generates the load.
+    pub ycsb: &'s str,
+
+    /// The size of the `mongod` server. Unless `opts.cgroup` is set, its memory usage (including
+    /// the page cache) is capped to this amount using a memory cgroup.
+    pub server_size: Size,
+    /// The size of the workload.
+    pub wk_size: Size,
+    /// The file to which the workload will write its output, including latency percentiles. If
+    /// `None`, then `/dev/null` is used.
+    pub output_file: Option<&'s str>,
+
+    /// The core number that the mongodb server is pinned to, if any.
+    pub server_pin_core: Option<usize>,
+    /// The core number that the workload client is pinned to.
+    pub client_pin_core: usize,
+
+    /// Options for the mongodb server (e.g. eager paging).
+    pub opts: WorkloadOptions<'s>,
+}
+
+/// Start a `mongod` server in a fresh data directory, in a memory cgroup limited to the server
+/// size (or in `cfg.opts.cgroup`, if set). Usually this is called indirectly through one of the
+/// other workload routines. Requires `sudo`.
+///
+/// `cfg.opts` apply to the server. Eager paging should only be used in a VM.
+pub fn start_mongo(shell: &SshShell, cfg: &MongoWorkloadConfig<'_>) -> Result<(), failure::Error> {
+    cfg.opts.setup_eager_paging(shell, "mongod")?;
+
+    // Delete any previous database
+    shell.run(cmd!("sudo rm -rf {}", MONGO_DB_PATH))?;
+    shell.run(cmd!("mkdir -p {}", MONGO_DB_PATH))?;
+
+    // Cap the memory of the server.
+    let cgroup;
+    let prefix = if cfg.opts.cgroup.is_some() {
+        cfg.opts.command_prefix()
+    } else {
+        cgroup = MemoryCgroup::create(shell, MONGO_CGROUP, cfg.server_size)?;
+        WorkloadOptions {
+            cgroup: Some(&cgroup),
+            ..cfg.opts.clone()
+        }
+        .command_prefix()
+    };
+
+    // mongod sizes its cache based on the memory of the whole machine, not the cgroup, so size it
+    // explicitly. By default, it uses half of the memory.
+    let cache_gb = (cfg.server_size.mb() as f64 / 1024.0 / 2.0).max(0.25);
+
+    // Start the server. With `--fork`, this returns once the server is ready for connections.
+    shell.run(cmd!(
+        "{} {} mongod --fork --logpath /tmp/mongod.log --dbpath {} \
+         --bind_ip 127.0.0.1 --wiredTigerCacheSizeGB {:.2}",
+        prefix,
+        if let Some(server_pin_core) = cfg.server_pin_core {
+            format!("taskset -c {}", server_pin_core)
+        } else {
+            "".into()
+        },
+        MONGO_DB_PATH,
+        cache_gb,
+    ))?;
+
+    Ok(())
+}
+
+/// Run the `mongo_gen_data` workload: start a `mongod` server and insert 1KB records into it with
+/// YCSB until the workload size is reached, recording the latency of the inserts.
+pub fn run_mongo_gen_data(
+    shell: &SshShell,
+    cfg: &MongoWorkloadConfig<'_>,
+) -> Result<(), failure::Error> {
+    // Start server
+    start_mongo(shell, cfg)?;
+
+    // Run workload. YCSB's default records are 10 fields of 100 bytes each.
+    let pinning = PinningVerifier::start(shell, "java", cfg.client_pin_core)?;
+
+    run_recorded(
+        shell,
+        Some(cfg.ycsb),
+        format!(
+            "taskset -c {} ./bin/ycsb.sh load mongodb -s -P workloads/workloada \
+             -p mongodb.url=mongodb://127.0.0.1:27017/ycsb -p recordcount={} \
+             -p measurementtype=hdrhistogram -p hdrhistogram.percentiles={} | tee {}",
+            cfg.client_pin_core,
+            cfg.wk_size.kb(),
+            YCSB_PERCENTILES,
+            cfg.output_file.unwrap_or("/dev/null")
+        ),
+        /* allow_error */ false,
+    )?;
+
+    pinning.finish()?;
+
+    validate_output(shell, "mongo_gen_data", cfg.output_file, YCSB_VALIDATOR)?;
+
+    Ok(())
+}
+
+/// The request distribution of a YCSB workload.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum YcsbDistribution {
+    Uniform,
+    Zipfian,
+    Latest,
+}
+
+impl YcsbDistribution {
+    /// The names of the distributions, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &["uniform", "zipfian", "latest"];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            YcsbDistribution::Uniform => "uniform",
+            YcsbDistribution::Zipfian => "zipfian",
+            YcsbDistribution::Latest => "latest",
+        }
+    }
+}
+
+impl std::str::FromStr for YcsbDistribution {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(YcsbDistribution::Uniform),
+            "zipfian" => Ok(YcsbDistribution::Zipfian),
+            "latest" => Ok(YcsbDistribution::Latest),
+            _ => Err(failure::format_err!("Unknown YCSB distribution {:?}", s)),
+        }
+    }
+}
+
+/// The proportions of reads, updates, and inserts in a YCSB workload. They should add up to 1.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct YcsbMix {
+    pub read: f64,
+    pub update: f64,
+    pub insert: f64,
+}
+
+impl std::str::FromStr for YcsbMix {
+    type Err = failure::Error;
+
+    /// Parse a mix given as `READ,UPDATE,INSERT` (e.g. `0.95,0.05,0`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| failure::format_err!("Invalid YCSB mix {:?}: {}", s, e))?;
+
+        match parts.as_slice() {
+            &[read, update, insert] if (read + update + insert - 1.0).abs() < 1e-6 => Ok(YcsbMix {
+                read,
+                update,
+                insert,
+            }),
+            _ => Err(failure::format_err!(
+                "Invalid YCSB mix {:?}: expected READ,UPDATE,INSERT adding up to 1",
+                s
+            )),
+        }
+    }
+}
+
+/// The server that a YCSB workload drives, along with its configuration. The server is started by
+/// `run_ycsb`.
+pub enum YcsbServer<'s> {
+    /// Only the server settings of the config are used.
+    Memcached(&'s MemcachedWorkloadConfig<'s>),
+    /// Only the server settings of the config are used. `tcp_port` must be set, since YCSB cannot
+    /// connect to a unix socket.
+    Redis(&'s RedisWorkloadConfig<'s>),
+}
+
+/// Every setting of the YCSB workload.
+pub struct YcsbWorkloadConfig<'s> {
+    /// The path of the YCSB installation on the remote.
+    pub ycsb: &'s str,
+
+    /// The number of records to load before the measured run.
+    pub record_count: usize,
+    /// The number of operations in the measured run.
+    pub operation_count: usize,
+    /// The request distribution of the measured run.
+    pub distribution: YcsbDistribution,
+    /// The operation mix of the measured run.
+    pub mix: YcsbMix,
+
+    /// The core number that the workload client is pinned to.
+    pub client_pin_core: usize,
+
+    /// The file to which the workload will write its output, including latency percentiles. If
+    /// `None`, then `/dev/null` is used.
+    pub output_file: Option<&'s str>,
+}
+
+/// The latency percentiles that YCSB reports for each operation type.
+const YCSB_PERCENTILES: &str = "50,90,95,99,99.9,99.99";
+
+/// Start the given server, load it with `record_count` records using YCSB, and then run the
+/// measured YCSB workload against it. Only the output of the measured run goes to the output file.
+pub fn run_ycsb(
+    shell: &SshShell,
+    server: YcsbServer<'_>,
+    cfg: &YcsbWorkloadConfig<'_>,
+) -> Result<(), failure::Error> {
+    // Start server
+    let (binding, connection, _redis) = match server {
+        YcsbServer::Memcached(mcfg) => {
+            start_memcached(shell, mcfg)?;
+            (
+                "memcached",
+                "-p memcached.hosts=localhost:11211".to_owned(),
+                None,
+            )
+        }
+        YcsbServer::Redis(rcfg) => {
+            let port = rcfg
+                .tcp_port
+                .ok_or_else(|| failure::format_err!("YCSB needs redis to listen on a TCP port"))?;
+            let redis = start_redis(shell, rcfg)?;
+            (
+                "redis",
+                format!("-p redis.host=127.0.0.1 -p redis.port={}", port),
+                Some(redis),
+            )
+        }
+    };
+
+    // The core workload properties, shared by the load and run phases.
+    let properties = format!(
+        "-P workloads/workloada {} -p recordcount={} -p operationcount={} \
+         -p requestdistribution={} -p readproportion={} -p updateproportion={} \
+         -p insertproportion={} -p scanproportion=0 -p readmodifywriteproportion=0 \
+         -p measurementtype=hdrhistogram -p hdrhistogram.percentiles={}",
+        connection,
+        cfg.record_count,
+        cfg.operation_count,
+        cfg.distribution.as_str(),
+        cfg.mix.read,
+        cfg.mix.update,
+        cfg.mix.insert,
+        YCSB_PERCENTILES,
+    );
+
+    // Load
+    run_recorded(
+        shell,
+        Some(cfg.ycsb),
+        format!(
+            "taskset -c {} ./bin/ycsb.sh load {} -s {}",
+            cfg.client_pin_core, binding, properties
+        ),
+        /* allow_error */ false,
+    )?;
+
+    // Run workload
+    let pinning = PinningVerifier::start(shell, "java", cfg.client_pin_core)?;
+
+    run_recorded(
+        shell,
+        Some(cfg.ycsb),
+        format!(
+            "taskset -c {} ./bin/ycsb.sh run {} -s {} | tee {}",
+            cfg.client_pin_core,
+            binding,
+            properties,
+            cfg.output_file.unwrap_or("/dev/null")
+        ),
+        /* allow_error */ false,
+    )?;
+
+    pinning.finish()?;
+
+    validate_output(shell, "ycsb", cfg.output_file, YCSB_VALIDATOR)?;
+
+    Ok(())
+}
+
 /// Run the metis matrix multiply workload with the given matrix dimensions (square matrix). This
 /// workload takes a really long time, so we start it in a spawned shell and return the join handle
 /// rather than waiting for the workload to return.
@@ -642,26 +1935,43 @@ pub fn run_redis_gen_data(
 ///
 /// - `bmk_dir` is the path to the `Metis` directory in the workspace on the remote.
 /// - `dim` is the dimension of the matrix (one side), which is assumed to be square.
-/// - `eager` indicates whether the workload should be run with eager paging (only in VM).
+/// - `output_file` is the file to which the workload will write its output. If `None`, then
+///   `/dev/null` is used.
+/// - `opts` are the options for the workload (e.g. eager paging or a memory cgroup).
 pub fn run_metis_matrix_mult(
     shell: &SshShell,
     bmk_dir: &str,
     dim: usize,
-    eager: bool,
+    output_file: Option<&str>,
+    opts: &WorkloadOptions<'_>,
     tctx: &mut TasksetCtx,
-) -> Result<(SshShell, SshSpawnHandle), SshError> {
-    if eager {
-        vagrant_setup_apriori_paging_process(shell, "matrix_mult2")?;
-    }
+) -> Result<SpawnedWorkload, failure::Error> {
+    opts.setup_eager_paging(shell, "matrix_mult2")?;
 
-    shell.spawn(
-        cmd!(
-            "taskset -c {} ./obj/matrix_mult2 -q -o -l {} ; echo matrix_mult2 done ;",
-            tctx.next(),
-            dim
-        )
-        .cwd(bmk_dir),
-    )
+    let core = tctx.next();
+
+    let (metis_shell, spawn_handle) = spawn_recorded(
+        shell,
+        Some(bmk_dir),
+        format!(
+            "{{ {} taskset -c {} ./obj/matrix_mult2 -q -o -l {} && \
+             echo matrix_mult2 done ; }} > {}",
+            opts.command_prefix(),
+            core,
+            dim,
+            output_file.unwrap_or("/dev/null")
+        ),
+    )?;
+
+    PinningVerifier::start(shell, "matrix_mult2", core)?.finish()?;
+
+    Ok(SpawnedWorkload {
+        shell: metis_shell,
+        spawn_handle,
+        workload: "matrix_mult2".into(),
+        output_file: output_file.map(Into::into),
+        validator: METIS_VALIDATOR,
+    })
 }
 
 /// Run the mix workload which consists of splitting memory between
@@ -680,7 +1990,8 @@ pub fn run_metis_matrix_mult(
 /// - `redis_conf` is the path to the `redis.conf` file on the remote.
 /// - `freq` is the _host_ CPU frequency in MHz.
 /// - `size_gb` is the total amount of memory of the mix workload in GB.
-/// - `eager` indicates whether the workload should be run with eager paging.
+/// - `opts` are the options for the metis and memhog sub-workloads. The redis server always uses
+///   eager paging.
 pub fn run_mix(
     shell: &SshShell,
     exp_dir: &str,
@@ -690,7 +2001,7 @@ pub fn run_mix(
     redis_conf: &str,
     freq: usize,
     size_gb: usize,
-    eager: bool,
+    opts: &WorkloadOptions<'_>,
     tctx: &mut TasksetCtx,
 ) -> Result<(), failure::Error> {
     let redis_handles = run_redis_gen_data(
@@ -698,20 +2009,24 @@ pub fn run_mix(
         &RedisWorkloadConfig {
             exp_dir,
             nullfs: nullfs_dir,
-            server_size_mb: (size_gb << 10) / 3,
-            wk_size_gb: size_gb / 3,
+            server_size: Size::from_mb((size_gb << 10) / 3),
+            wk_size: Size::from_gb(size_gb / 3),
             freq: Some(freq),
             pf_time: None,
             output_file: None,
-            eager: true,
+            opts: WorkloadOptions {
+                eager: true,
+                ..opts.clone()
+            },
             client_pin_core: tctx.next(),
             server_pin_core: None,
             redis_conf,
+            tcp_port: None,
         },
     )?;
 
     let matrix_dim = (((size_gb / 3) << 27) as f64).sqrt() as usize;
-    let _metis_handle = run_metis_matrix_mult(shell, metis_dir, matrix_dim, eager, tctx)?;
+    let _metis_handle = run_metis_matrix_mult(shell, metis_dir, matrix_dim, None, opts, tctx)?;
 
     let _memhog_handles = run_memhog(
         shell,
@@ -719,7 +2034,7 @@ pub fn run_mix(
         None,
         (size_gb << 20) / 3,
         MemhogOptions::PIN | MemhogOptions::DATA_OBLIV,
-        eager,
+        opts,
         tctx,
     )?;
 
@@ -728,3 +2043,174 @@ pub fn run_mix(
 
     Ok(())
 }
+
+/// A workload described by a TOML manifest rather than by code, so that new workloads can be
+/// added without editing the runner. `expcustom` runs such workloads. For example:
+///
+/// ```toml
+/// name = "stream"
+/// submodules = ["bmks/stream"]
+/// build = ["make -C bmks/stream"]
+/// run = "taskset -c {{ pin_core }} bmks/stream/stream_c.exe -n {{ params.ntimes }} > {{ output_file }}"
+/// outputs = ["stream.log"]
+///
+/// [params]
+/// ntimes = "10"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkloadManifest {
+    /// The name of the workload, which is recorded as the `app` setting of the experiment.
+    pub name: String,
+
+    /// Submodules of the research workspace (relative to its root) that the workload needs.
+    #[serde(default)]
+    pub submodules: Vec<String>,
+
+    /// Commands to build the workload, run on the host in the root of the research workspace.
+    /// Since the workspace is shared with the guest, the results are also available there.
+    #[serde(default)]
+    pub build: Vec<String>,
+
+    /// A template of the command that runs the workload, run in the guest in the root of the
+    /// research workspace. See `WorkloadManifest::render_run` for the available variables.
+    pub run: String,
+
+    /// Suffixes of additional output files the workload produces (e.g. `stream.log`). Each gets
+    /// a path in the results directory, available to the `run` template as `outputs[suffix]`.
+    #[serde(default)]
+    pub outputs: Vec<String>,
+
+    /// Default values of the parameters of the workload, available to the `run` template as
+    /// `params.<name>`. These can be overridden on the command line.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+/// The values of the variables available to the `run` template of a `WorkloadManifest`.
+#[derive(Debug, Serialize)]
+pub struct ManifestRunContext<'s> {
+    /// The path of the research workspace in the guest.
+    pub workspace: &'s str,
+    /// The path of the main output file of the workload.
+    pub output_file: &'s str,
+    /// The paths of the additional output files, keyed by suffix.
+    pub outputs: BTreeMap<String, String>,
+    /// The parameters of the workload, after applying overrides.
+    pub params: &'s BTreeMap<String, String>,
+    /// The number of cores of the VM.
+    pub cores: usize,
+    /// The size of the VM in GB.
+    pub vm_size: usize,
+    /// The core to pin the workload to, if the template wants to.
+    pub pin_core: usize,
+}
+
+impl WorkloadManifest {
+    /// Read and parse the manifest at the given path on the local machine.
+    pub fn load(path: &str) -> Result<Self, failure::Error> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            failure::format_err!("Unable to read workload manifest {}: {}", path, e)
+        })?;
+        toml::from_str(&contents)
+            .map_err(|e| failure::format_err!("Invalid workload manifest {}: {}", path, e))
+    }
+
+    /// Render the `run` template with the given variables. Fails if the template refers to a
+    /// variable that does not exist (e.g. a misspelled parameter).
+    pub fn render_run(&self, ctx: &ManifestRunContext<'_>) -> Result<String, failure::Error> {
+        let context = tera::Context::from_serialize(ctx)
+            .map_err(|e| failure::format_err!("Unable to build template context: {}", e))?;
+        tera::Tera::one_off(&self.run, &context, false).map_err(|e| {
+            failure::format_err!("Unable to render run command of {}: {}", self.name, e)
+        })
+    }
+}
+
+/// Run the (already rendered) command line of a manifest workload on the remote in `workspace`.
+pub fn run_manifest_workload(
+    shell: &SshShell,
+    workspace: &str,
+    command: String,
+) -> Result<(), failure::Error> {
+    run_recorded(
+        shell,
+        Some(workspace),
+        command,
+        /* allow_error */ false,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kernel_lists() {
+        assert!(list_contains("0", 0));
+        assert!(!list_contains("0", 1));
+        assert!(list_contains("0-3,8", 2));
+        assert!(list_contains("0-3,8", 8));
+        assert!(!list_contains("0-3,8", 5));
+        assert!(!list_contains("", 0));
+    }
+
+    #[test]
+    fn spec_2017_csv() {
+        let csv = "\
+\"Full Results Table\"
+Benchmark,\"Base # Copies\",\"Est. Base Run Time\"
+505.mcf_r,1,999
+
+\"Selected Results Table\"
+Benchmark,\"Base # Copies\",\"Est. Base Run Time\",\"Est. Base Ratio\"
+505.mcf_r,1,311.5,5.19
+\"520.omnetpp_r\",1,402,3.26
+523.xalancbmk_r,1,,
+
+Other,1,2,3
+";
+        let results = parse_spec_2017_csv(csv);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results["505.mcf_r"], 311.5);
+        assert_eq!(results["520.omnetpp_r"], 402.0);
+    }
+
+    #[test]
+    fn spec_2017_csv_without_results() {
+        assert!(parse_spec_2017_csv("").is_empty());
+        assert!(parse_spec_2017_csv("\"Selected Results Table\"\nBenchmark,Foo\na,1\n").is_empty());
+    }
+
+    #[test]
+    fn parse_ycsb_mix() {
+        let mix: YcsbMix = "0.95, 0.05, 0".parse().unwrap();
+        assert_eq!((mix.read, mix.update, mix.insert), (0.95, 0.05, 0.0));
+
+        for s in &["", "1", "0.5,0.5", "0.5,0.4,0", "0.5,0.5,0,0", "a,b,c"] {
+            assert!(s.parse::<YcsbMix>().is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn parse_antagonist() {
+        match "memhog:4G".parse().unwrap() {
+            Antagonist::MemHog(size) => assert_eq!(size, Size::from_gb(4)),
+            other => panic!("unexpected {:?}", other),
+        }
+        match "cpu".parse().unwrap() {
+            Antagonist::CpuSpin => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match "io:512M".parse().unwrap() {
+            Antagonist::IoThrash(size) => assert_eq!(size, Size::from_mb(512)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        for s in &["", "memhog", "memhog:", "memhog:lots", "io", "disk:1G"] {
+            assert!(s.parse::<Antagonist>().is_err(), "{:?} should not parse", s);
+        }
+    }
+}